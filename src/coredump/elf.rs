@@ -0,0 +1,230 @@
+// ELF core files (ET_CORE)
+// each PT_LOAD saves a mapping of the process, the notes of the PT_NOTE segment hold the
+// registers of every thread (NT_PRSTATUS), the files mapped in the process (NT_FILE) and its
+// auxiliary vector (NT_AUXV)
+// the kernel only saves the first page of the mapped ELF files (bit 4 of coredump_filter, set by
+// default): the images are found and identified from it, their code stays in the files on disk
+
+use crate::elf::note::{NT_AUXV, NT_FILE, NT_PRSTATUS, Note};
+use crate::elf::{ET_CORE, Elf, PT_LOAD};
+use crate::macho::reader::{self, Reader};
+use crate::unwind::{CpuFamily, Registers};
+
+use super::{CoreError, CoreImage, CoreMemory, CoreRegion, CoreThread};
+
+// owner of the notes written by the kernel
+pub const CORE_NOTE: &str = "CORE";
+
+// a_type of the auxiliary vector entries, from <elf.h>
+pub const AT_NULL: u64 = 0;
+pub const AT_SYSINFO_EHDR: u64 = 33;
+
+/// Name given to the vDSO, the image the kernel maps in every process without a file.
+pub const VDSO_NAME: &str = "[vdso]";
+
+// the 64-bit elf_prstatus starts with the signal info, the pending and held signals, then the
+// ids and the times before the general registers
+const PRSTATUS_PID_OFFSET: usize = 32;
+const PRSTATUS_REGS_OFFSET: usize = 112;
+// size in words of the x86_64 user_regs_struct and of the arm64 user_pt_regs
+const X86_64_USER_REGS: usize = 27;
+const ARM64_USER_REGS: usize = 34;
+
+/// A file mapped in the dumped process, from the NT_FILE note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMapping {
+    pub start: u64,
+    pub end: u64,
+    /// Offset in the file of the first mapped byte.
+    pub offset: u64,
+    pub path: String,
+}
+
+/// A parsed ELF core file.
+#[derive(Debug, Clone)]
+pub struct ElfCore<'a> {
+    pub binary: Elf<'a>,
+    pub memory: CoreMemory<'a>,
+    pub threads: Vec<CoreThread>,
+    pub mappings: Vec<FileMapping>,
+    /// `(a_type, a_val)` entries of the auxiliary vector, `AT_NULL` excluded.
+    pub auxv: Vec<(u64, u64)>,
+    pub images: Vec<CoreImage>,
+}
+
+impl<'a> ElfCore<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<ElfCore<'a>, CoreError> {
+        let binary = Elf::parse(data)?;
+        if binary.e_type != ET_CORE {
+            return Err(CoreError::NotACore {
+                filetype: binary.e_type as u32,
+            });
+        }
+        let machine = binary.machine;
+        // the register layouts read below are the 64-bit ones
+        let family = CpuFamily::from_elf_machine(machine)
+            .filter(|_| binary.is_64)
+            .ok_or(CoreError::UnsupportedMachine { machine })?;
+
+        let mut regions = Vec::new();
+        for ph in binary
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+        {
+            regions.push(CoreRegion {
+                address: ph.vaddr,
+                size: ph.memsz,
+                data: reader::slice64(data, ph.offset, ph.filesz, "segment")?,
+            });
+        }
+        let memory = CoreMemory::new(regions);
+
+        let mut threads = Vec::new();
+        let mut mappings = Vec::new();
+        let mut auxv = Vec::new();
+        // the "LINUX" notes hold the floating point and extended registers, not needed here
+        for note in binary.notes()?.iter().filter(|note| note.name == CORE_NOTE) {
+            match note.n_type {
+                NT_PRSTATUS => threads.push(prstatus(note, family, &binary)?),
+                NT_FILE => mappings = file_mappings(note, &binary)?,
+                NT_AUXV => auxv = auxv_entries(note, &binary)?,
+                _ => {}
+            }
+        }
+
+        let mut core = ElfCore {
+            binary,
+            memory,
+            threads,
+            mappings,
+            auxv,
+            images: Vec::new(),
+        };
+        core.images = core.find_images();
+        Ok(core)
+    }
+
+    /// Value of an entry of the auxiliary vector.
+    pub fn auxv_value(&self, a_type: u64) -> Option<u64> {
+        self.auxv
+            .iter()
+            .find(|(entry_type, _)| *entry_type == a_type)
+            .map(|(_, value)| *value)
+    }
+
+    /// The ELF files mapped from their start, and the vDSO.
+    fn find_images(&self) -> Vec<CoreImage> {
+        let mut images = Vec::new();
+        for mapping in self.mappings.iter().filter(|mapping| mapping.offset == 0) {
+            // the other mapped files have no header in the core
+            if let Some(header) = self.image_header(mapping.start) {
+                images.push(CoreImage {
+                    path: Some(mapping.path.clone()),
+                    load_address: mapping.start,
+                    uuid: None,
+                    build_id: header.build_id().map(<[u8]>::to_vec),
+                });
+            }
+        }
+        if let Some(address) = self.auxv_value(AT_SYSINFO_EHDR)
+            && let Some(header) = self.image_header(address)
+        {
+            images.push(CoreImage {
+                path: Some(VDSO_NAME.to_string()),
+                load_address: address,
+                uuid: None,
+                build_id: header.build_id().map(<[u8]>::to_vec),
+            });
+        }
+        images.sort_by_key(|image| image.load_address);
+        images.dedup_by_key(|image| image.load_address);
+        images
+    }
+
+    /// The ELF header and program headers saved at `address`, `None` when there is no image
+    /// header there.
+    fn image_header(&self, address: u64) -> Option<Elf<'a>> {
+        let bytes = self.memory.bytes_at(address)?;
+        Elf::parse_mapped(bytes).ok()
+    }
+
+    /// Copy of an image saved whole in the core, section headers included, like the vDSO.
+    pub fn rebuild_image(&self, address: u64) -> Result<Vec<u8>, CoreError> {
+        let bytes = self
+            .memory
+            .bytes_at(address)
+            .ok_or(CoreError::ImageNotInCore {
+                address,
+                reason: "its header is not in the core",
+            })?;
+        // the file ends with the section headers, they are only there if the whole file was
+        // mapped and saved
+        if Elf::parse(bytes).is_err() {
+            return Err(CoreError::ImageNotInCore {
+                address,
+                reason: "only its first page is in the core",
+            });
+        }
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Id and registers of the thread of an NT_PRSTATUS note.
+fn prstatus(note: &Note, family: CpuFamily, binary: &Elf) -> Result<CoreThread, CoreError> {
+    let mut r = Reader::at(note.desc, PRSTATUS_PID_OFFSET, binary.endian, "prstatus");
+    let pid = r.u32()?;
+    r.seek(PRSTATUS_REGS_OFFSET);
+    let count = match family {
+        CpuFamily::X86_64 => X86_64_USER_REGS,
+        CpuFamily::Arm64 => ARM64_USER_REGS,
+    };
+    let regs = (0..count).map(|_| r.u64()).collect::<Result<Vec<_>, _>>()?;
+    let registers = match family {
+        CpuFamily::X86_64 => Registers::from_x86_64_user_regs(&regs),
+        CpuFamily::Arm64 => Registers::from_arm64_thread_state(&regs),
+    };
+    Ok(CoreThread {
+        id: pid as u64,
+        registers,
+    })
+}
+
+// the count and the page size, the start, end and offset in pages of each mapping, then the
+// paths of the mappings
+fn file_mappings(note: &Note, binary: &Elf) -> Result<Vec<FileMapping>, CoreError> {
+    let is_64 = binary.is_64;
+    let mut r = Reader::new(note.desc, binary.endian, "file note");
+    let count = r.word(is_64)?;
+    let page_size = r.word(is_64)?;
+    let mut ranges = Vec::new();
+    for _ in 0..count {
+        ranges.push((r.word(is_64)?, r.word(is_64)?, r.word(is_64)?));
+    }
+    let mut mappings = Vec::with_capacity(ranges.len());
+    for (start, end, page) in ranges {
+        mappings.push(FileMapping {
+            start,
+            end,
+            offset: page.wrapping_mul(page_size),
+            path: r.cstr()?.to_string(),
+        });
+    }
+    Ok(mappings)
+}
+
+// pairs of words, up to AT_NULL
+fn auxv_entries(note: &Note, binary: &Elf) -> Result<Vec<(u64, u64)>, CoreError> {
+    let is_64 = binary.is_64;
+    let mut r = Reader::new(note.desc, binary.endian, "auxv");
+    let mut entries = Vec::new();
+    while !r.is_empty() {
+        let a_type = r.word(is_64)?;
+        let value = r.word(is_64)?;
+        if a_type == AT_NULL {
+            break;
+        }
+        entries.push((a_type, value));
+    }
+    Ok(entries)
+}
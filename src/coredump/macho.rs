@@ -0,0 +1,529 @@
+// Mach-O core files (MH_CORE)
+// each LC_SEGMENT_64 saves a VM region of the process, each LC_THREAD the registers of a thread
+// the images loaded in the process are listed by the LC_NOTEs debuggers write, or else found by
+// looking for mach headers at the start of the pages of the saved memory
+
+use crate::macho::header::{
+    MH_BUNDLE, MH_CORE, MH_DYLIB, MH_DYLIB_IN_CACHE, MH_DYLINKER, MH_EXECUTE, MH_MAGIC,
+    MH_MAGIC_64, MachHeader,
+};
+use crate::macho::load_command::{
+    self, LC_ID_DYLIB, LC_ID_DYLINKER, LC_SEGMENT, LC_SEGMENT_64, LC_UUID, NoteCommand, ThreadState,
+};
+use crate::macho::reader::{self, Endian, Reader};
+use crate::macho::{LoadCommand, MachO, MachOError};
+use crate::unwind::{CpuFamily, Memory, Registers};
+
+use super::{CoreError, CoreImage, CoreMemory, CoreRegion, CoreThread};
+
+// thread state flavors of <mach/arm/thread_status.h> and <mach/i386/thread_status.h>
+pub const ARM_THREAD_STATE: u32 = 1;
+pub const ARM_THREAD_STATE64: u32 = 6;
+pub const X86_THREAD_STATE64: u32 = 4;
+pub const X86_THREAD_STATE: u32 = 7;
+
+// owners of the LC_NOTEs listing images, written by lldb and by the kernel
+pub const ALL_IMAGE_INFOS_NOTE: &str = "all image infos";
+pub const LOAD_BINARY_NOTE: &str = "load binary";
+
+// the notes use all ones for unknown addresses
+const UNKNOWN_ADDRESS: u64 = u64::MAX;
+// headers are page aligned, 4K is the smallest page size of the supported systems
+const SCAN_ALIGNMENT: usize = 0x1000;
+// images rebuilt from the memory of the core are allocated whole
+const MAX_REBUILT_IMAGE_SIZE: u64 = 1 << 30;
+
+/// A parsed Mach-O core file.
+#[derive(Debug, Clone)]
+pub struct MachCore<'a> {
+    pub binary: MachO<'a>,
+    pub memory: CoreMemory<'a>,
+    pub threads: Vec<CoreThread>,
+    pub images: Vec<CoreImage>,
+}
+
+impl<'a> MachCore<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<MachCore<'a>, CoreError> {
+        let binary = MachO::parse(data)?;
+        if binary.header.filetype != MH_CORE {
+            return Err(CoreError::NotACore {
+                filetype: binary.header.filetype,
+            });
+        }
+        let cputype = binary.header.cputype;
+        let family =
+            CpuFamily::from_cputype(cputype).ok_or(CoreError::UnsupportedCpu { cputype })?;
+
+        let mut regions = Vec::new();
+        for segment in binary.segments() {
+            regions.push(CoreRegion {
+                address: segment.vmaddr,
+                size: segment.vmsize,
+                data: reader::slice64(data, segment.fileoff, segment.filesize, "segment")?,
+            });
+        }
+        let memory = CoreMemory::new(regions);
+
+        let mut threads = Vec::new();
+        for lc in &binary.load_commands {
+            let LoadCommand::Thread(states) = lc else {
+                continue;
+            };
+            if let Some(registers) = states
+                .iter()
+                .find_map(|state| thread_registers(family, state, binary.endian))
+            {
+                threads.push(CoreThread {
+                    id: threads.len() as u64,
+                    registers,
+                });
+            }
+        }
+
+        let mut core = MachCore {
+            binary,
+            memory,
+            threads,
+            images: Vec::new(),
+        };
+        core.images = core.find_images()?;
+        Ok(core)
+    }
+
+    /// The images listed by the notes, or the ones whose header is in the saved memory.
+    fn find_images(&self) -> Result<Vec<CoreImage>, CoreError> {
+        let mut images = Vec::new();
+        for lc in &self.binary.load_commands {
+            let LoadCommand::Note(note) = lc else {
+                continue;
+            };
+            match note.data_owner.as_str() {
+                ALL_IMAGE_INFOS_NOTE => images.extend(self.all_image_infos(note)?),
+                LOAD_BINARY_NOTE => images.extend(self.load_binary(note)?),
+                _ => {}
+            }
+        }
+        if images.is_empty() {
+            images = self.scan_headers();
+        }
+        // fill what the notes leave out from the headers in memory
+        for image in &mut images {
+            if let Some(header) = self.image_header(image.load_address) {
+                image.uuid = image.uuid.or(header.uuid);
+                if image.path.is_none() {
+                    image.path = header.path;
+                }
+            }
+        }
+        images.sort_by_key(|image| image.load_address);
+        images.dedup_by_key(|image| image.load_address);
+        Ok(images)
+    }
+
+    // all_image_infos_header then an array of image_entry, from lldb's ObjectFileMachO
+    fn all_image_infos(&self, note: &NoteCommand) -> Result<Vec<CoreImage>, CoreError> {
+        let data = self.binary.data();
+        let mut r = Reader::at(
+            data,
+            note.offset as usize,
+            self.binary.endian,
+            "image infos",
+        );
+        let _version = r.u32()?;
+        let count = r.u32()?;
+        let entries_offset = r.u64()?;
+        let entry_size = r.u32()? as u64;
+        let mut images = Vec::new();
+        for i in 0..count as u64 {
+            let offset = entries_offset + i * entry_size;
+            let mut entry = Reader::at(data, offset as usize, self.binary.endian, "image info");
+            let path_offset = entry.u64()?;
+            let uuid = uuid_from(entry.take(16)?);
+            let mut load_address = entry.u64()?;
+            let segments_offset = entry.u64()?;
+            let segment_count = entry.u32()?;
+            // images known by the addresses of their segments only are loaded at their __TEXT
+            if load_address == UNKNOWN_ADDRESS {
+                let mut segments = Reader::at(
+                    data,
+                    segments_offset as usize,
+                    self.binary.endian,
+                    "image info segments",
+                );
+                for _ in 0..segment_count {
+                    let name = segments.name16()?;
+                    let vmaddr = segments.u64()?;
+                    let _unused = segments.u64()?;
+                    if name == "__TEXT" {
+                        load_address = vmaddr;
+                    }
+                }
+            }
+            if load_address == UNKNOWN_ADDRESS {
+                continue;
+            }
+            images.push(CoreImage {
+                path: (path_offset != UNKNOWN_ADDRESS)
+                    .then(|| reader::cstr(data, path_offset as usize))
+                    .flatten()
+                    .map(str::to_string),
+                load_address,
+                uuid,
+                build_id: None,
+            });
+        }
+        Ok(images)
+    }
+
+    // version, uuid, address, slide then the name
+    fn load_binary(&self, note: &NoteCommand) -> Result<Vec<CoreImage>, CoreError> {
+        let data = reader::slice64(self.binary.data(), note.offset, note.size, "load binary")?;
+        let mut r = Reader::new(data, self.binary.endian, "load binary");
+        let _version = r.u32()?;
+        let uuid = uuid_from(r.take(16)?);
+        let address = r.u64()?;
+        let _slide = r.u64()?;
+        let name = r.cstr().ok().filter(|name| !name.is_empty());
+        // without an address the header is looked for by its UUID
+        let load_address = match address {
+            UNKNOWN_ADDRESS => {
+                let found = self
+                    .scan_headers()
+                    .into_iter()
+                    .find(|image| uuid.is_some() && image.uuid == uuid);
+                match found {
+                    Some(image) => image.load_address,
+                    None => return Ok(Vec::new()),
+                }
+            }
+            address => address,
+        };
+        Ok(vec![CoreImage {
+            path: name.map(str::to_string),
+            load_address,
+            uuid,
+            build_id: None,
+        }])
+    }
+
+    /// Images whose mach header starts a page of the saved memory.
+    pub fn scan_headers(&self) -> Vec<CoreImage> {
+        let mut images = Vec::new();
+        for region in self.memory.regions() {
+            for offset in (0..region.data.len()).step_by(SCAN_ALIGNMENT) {
+                let magic = region.data[offset..]
+                    .get(..4)
+                    .map(|magic| u32::from_le_bytes(magic.try_into().unwrap()));
+                if !matches!(magic, Some(MH_MAGIC | MH_MAGIC_64)) {
+                    continue;
+                }
+                let address = region.address + offset as u64;
+                if let Some(header) = self.image_header(address) {
+                    images.push(CoreImage {
+                        path: header.path,
+                        load_address: address,
+                        uuid: header.uuid,
+                        build_id: None,
+                    });
+                }
+            }
+        }
+        images
+    }
+
+    /// UUID and name of the image whose header is at `address`, `None` when there is no
+    /// image header there.
+    fn image_header(&self, address: u64) -> Option<ImageHeader> {
+        let (header, endian, commands) = self.load_commands_at(address).ok()?;
+        if !matches!(
+            header.filetype,
+            MH_EXECUTE | MH_DYLIB | MH_DYLINKER | MH_BUNDLE
+        ) {
+            return None;
+        }
+        let raw = load_command::parse_load_commands(
+            commands,
+            header.size(),
+            header.ncmds,
+            header.sizeofcmds,
+            endian,
+        )
+        .ok()?;
+        // only the commands that do not point to the rest of the image, which is not there
+        let mut image = ImageHeader {
+            path: None,
+            uuid: None,
+        };
+        for lc in raw
+            .iter()
+            .filter(|lc| matches!(lc.cmd, LC_UUID | LC_ID_DYLIB | LC_ID_DYLINKER))
+        {
+            match LoadCommand::parse(lc, commands, endian).ok()? {
+                LoadCommand::Uuid(uuid) => image.uuid = Some(uuid),
+                LoadCommand::IdDylib(dylib) => image.path = Some(dylib.name.to_string()),
+                LoadCommand::IdDylinker(name) => image.path = Some(name.to_string()),
+                _ => {}
+            }
+        }
+        Some(image)
+    }
+
+    // the header and load commands of the image at `address`
+    fn load_commands_at(&self, address: u64) -> Result<(MachHeader, Endian, &'a [u8]), CoreError> {
+        let start = self
+            .memory
+            .bytes_at(address)
+            .ok_or(CoreError::ImageNotInCore {
+                address,
+                reason: "the header is not in the core",
+            })?;
+        let (header, endian) = MachHeader::parse(start)?;
+        let size = header.size() + header.sizeofcmds as usize;
+        let commands = reader::slice(start, 0, size, "load commands")?;
+        Ok((header, endian, commands))
+    }
+
+    /// Copies the segments of the image at `address` from the memory of the core back to their
+    /// place in the file, which gives an image the parsers read like the original one as long
+    /// as the process did not modify it.
+    ///
+    /// Pages missing from the core are left zeroed.
+    pub fn rebuild_image(&self, address: u64) -> Result<Vec<u8>, CoreError> {
+        let not_in_core = |reason| CoreError::ImageNotInCore { address, reason };
+        let (header, endian, commands) = self.load_commands_at(address)?;
+        if header.flags & MH_DYLIB_IN_CACHE != 0 {
+            return Err(not_in_core("the image is part of the dyld shared cache"));
+        }
+        let raw = load_command::parse_load_commands(
+            commands,
+            header.size(),
+            header.ncmds,
+            header.sizeofcmds,
+            endian,
+        )?;
+        let mut segments = Vec::new();
+        for lc in raw
+            .iter()
+            .filter(|lc| matches!(lc.cmd, LC_SEGMENT | LC_SEGMENT_64))
+        {
+            let is_64 = lc.cmd == LC_SEGMENT_64;
+            let mut r = lc.body(endian);
+            let name = r.name16()?;
+            let vmaddr = r.word(is_64)?;
+            let _vmsize = r.word(is_64)?;
+            let fileoff = r.word(is_64)?;
+            let filesize = r.word(is_64)?;
+            segments.push((name, vmaddr, fileoff, filesize));
+        }
+        let text = segments
+            .iter()
+            .find(|(name, ..)| name == "__TEXT")
+            .ok_or(not_in_core("the image has no __TEXT segment"))?;
+        let slide = address.wrapping_sub(text.1);
+        let size = segments
+            .iter()
+            .map(|(_, _, fileoff, filesize)| fileoff.saturating_add(*filesize))
+            .max()
+            .unwrap_or(0);
+        if size > MAX_REBUILT_IMAGE_SIZE {
+            return Err(not_in_core("the segments are too large"));
+        }
+        let mut image = vec![0u8; size as usize];
+        for (_, vmaddr, fileoff, filesize) in &segments {
+            let start = vmaddr.wrapping_add(slide);
+            // page by page, so a page missing from the core leaves the others readable
+            for page in (0..*filesize).step_by(SCAN_ALIGNMENT) {
+                let length = (filesize - page).min(SCAN_ALIGNMENT as u64) as usize;
+                let offset = (fileoff + page) as usize;
+                let _ = self
+                    .memory
+                    .read(start + page, &mut image[offset..offset + length]);
+            }
+        }
+        MachO::parse(&image).map_err(|_| not_in_core("the rebuilt image does not parse"))?;
+        Ok(image)
+    }
+}
+
+// what the header of an image in memory tells about it
+struct ImageHeader {
+    path: Option<String>,
+    uuid: Option<[u8; 16]>,
+}
+
+fn uuid_from(bytes: &[u8]) -> Option<[u8; 16]> {
+    let uuid: [u8; 16] = bytes.try_into().ok()?;
+    // all zeroes when unknown
+    (uuid != [0; 16]).then_some(uuid)
+}
+
+/// Registers of a thread state of a core of `family`, `None` for the flavors without the
+/// general purpose registers.
+fn thread_registers(family: CpuFamily, state: &ThreadState, endian: Endian) -> Option<Registers> {
+    let mut r = Reader::new(state.state, endian, "thread state");
+    // the generic flavors wrap the state of the actual flavor
+    let flavor = match (family, state.flavor) {
+        (CpuFamily::Arm64, ARM_THREAD_STATE) | (CpuFamily::X86_64, X86_THREAD_STATE) => {
+            let flavor = r.u32().ok()?;
+            let _count = r.u32().ok()?;
+            flavor
+        }
+        (_, flavor) => flavor,
+    };
+    let (words, from_state): (usize, fn(&[u64]) -> Registers) = match (family, flavor) {
+        (CpuFamily::Arm64, ARM_THREAD_STATE64) => (33, Registers::from_arm64_thread_state),
+        (CpuFamily::X86_64, X86_THREAD_STATE64) => (17, Registers::from_x86_64_thread_state),
+        _ => return None,
+    };
+    let values = (0..words)
+        .map(|_| r.u64())
+        .collect::<Result<Vec<_>, MachOError>>()
+        .ok()?;
+    Some(from_state(&values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::cpu::{CPU_TYPE_ARM64, CPU_TYPE_POWERPC64};
+    use crate::macho::load_command::{LC_NOTE, LC_THREAD};
+    use crate::macho::tests::{UUID, image};
+    use crate::unwind::registers::ARM64_PC;
+
+    // the executable is mapped 0x10000 above its preferred address
+    const LOAD_ADDRESS: u64 = 0x1_0001_0000;
+    const STATE_WORDS: u64 = 33;
+
+    fn command(cmd: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = cmd.to_le_bytes().to_vec();
+        data.extend((payload.len() as u32 + 8).to_le_bytes());
+        data.extend(payload);
+        data
+    }
+
+    fn arm64_state(pc: u64) -> Vec<u8> {
+        (0..STATE_WORDS)
+            .flat_map(|reg| if reg == ARM64_PC as u64 { pc } else { reg }.to_le_bytes())
+            .collect()
+    }
+
+    /// A core with the image of `crate::macho::tests::image` in its memory, two threads, one
+    /// with the generic flavor, and a "load binary" note naming the image when `with_note`.
+    fn core_file(cputype: u32, with_note: bool) -> Vec<u8> {
+        let executable = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let image_offset = 0x400u64;
+        let note_offset = 0x300u64;
+
+        let mut segment = vec![0; 16];
+        for value in [LOAD_ADDRESS, 0x1000, image_offset, executable.len() as u64] {
+            segment.extend(value.to_le_bytes());
+        }
+        segment.extend([3u32, 3, 0, 0].iter().flat_map(|v| v.to_le_bytes()));
+        let mut thread = ARM_THREAD_STATE64.to_le_bytes().to_vec();
+        thread.extend((STATE_WORDS as u32 * 2).to_le_bytes());
+        thread.extend(arm64_state(LOAD_ADDRESS + 0x10));
+        let mut generic = ARM_THREAD_STATE.to_le_bytes().to_vec();
+        generic.extend((STATE_WORDS as u32 * 2 + 2).to_le_bytes());
+        generic.extend(thread.clone());
+        let mut commands = command(LC_SEGMENT_64, &segment);
+        commands.extend(command(LC_THREAD, &thread));
+        commands.extend(command(LC_THREAD, &generic));
+
+        let mut note_data = 1u32.to_le_bytes().to_vec();
+        note_data.extend(UUID);
+        note_data.extend(LOAD_ADDRESS.to_le_bytes());
+        note_data.extend(0x10000u64.to_le_bytes());
+        note_data.extend(b"/usr/bin/test\0");
+        if with_note {
+            let mut note = b"load binary\0\0\0\0\0".to_vec();
+            note.extend(note_offset.to_le_bytes());
+            note.extend((note_data.len() as u64).to_le_bytes());
+            commands.extend(command(LC_NOTE, &note));
+        }
+
+        let ncmds = if with_note { 4 } else { 3 };
+        let mut data = Vec::new();
+        for value in [
+            MH_MAGIC_64,
+            cputype,
+            0,
+            MH_CORE,
+            ncmds,
+            commands.len() as u32,
+            0,
+            0,
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(commands);
+        data.resize(note_offset as usize, 0);
+        data.extend(note_data);
+        data.resize(image_offset as usize, 0);
+        data.extend(executable);
+        data
+    }
+
+    #[test]
+    fn threads_and_images_of_a_core() {
+        let data = core_file(CPU_TYPE_ARM64, false);
+        let core = MachCore::parse(&data).unwrap();
+        assert_eq!(core.threads.len(), 2);
+        for (i, thread) in core.threads.iter().enumerate() {
+            assert_eq!(thread.id, i as u64);
+            assert_eq!(thread.registers.pc(), Some(LOAD_ADDRESS + 0x10));
+            assert_eq!(thread.registers.get(3), Some(3));
+        }
+        // found by its header in the saved memory
+        assert_eq!(
+            core.images,
+            [CoreImage {
+                path: None,
+                load_address: LOAD_ADDRESS,
+                uuid: Some(UUID),
+                build_id: None
+            }]
+        );
+        let mut magic = [0; 4];
+        assert!(core.memory.read(LOAD_ADDRESS, &mut magic));
+        assert_eq!(u32::from_le_bytes(magic), MH_MAGIC_64);
+        assert!(!core.memory.read(LOAD_ADDRESS + 126, &mut magic));
+
+        // named by the note
+        let data = core_file(CPU_TYPE_ARM64, true);
+        let core = MachCore::parse(&data).unwrap();
+        assert_eq!(core.images[0].path.as_deref(), Some("/usr/bin/test"));
+        assert_eq!(core.images[0].uuid, Some(UUID));
+    }
+
+    #[test]
+    fn images_are_rebuilt_from_the_memory() {
+        let data = core_file(CPU_TYPE_ARM64, false);
+        let core = MachCore::parse(&data).unwrap();
+        let rebuilt = core.rebuild_image(LOAD_ADDRESS).unwrap();
+        assert_eq!(rebuilt, image(CPU_TYPE_ARM64, true, Endian::Little));
+        assert_eq!(
+            core.rebuild_image(LOAD_ADDRESS + 0x2000).unwrap_err(),
+            CoreError::ImageNotInCore {
+                address: LOAD_ADDRESS + 0x2000,
+                reason: "the header is not in the core"
+            }
+        );
+    }
+
+    #[test]
+    fn rejected_files() {
+        assert_eq!(
+            MachCore::parse(&core_file(CPU_TYPE_POWERPC64, false)).unwrap_err(),
+            CoreError::UnsupportedCpu {
+                cputype: CPU_TYPE_POWERPC64
+            }
+        );
+        let executable = image(CPU_TYPE_ARM64, true, Endian::Little);
+        assert_eq!(
+            MachCore::parse(&executable).unwrap_err(),
+            CoreError::NotACore {
+                filetype: MH_EXECUTE
+            }
+        );
+    }
+}
@@ -0,0 +1,142 @@
+// core files
+// a core file holds the memory of a process and the registers of its threads when it was
+// dumped, which is everything the unwinder reads from a live process
+// the threads are unwound and symbolized offline, on any host
+
+pub mod elf;
+pub mod macho;
+
+pub use elf::ElfCore;
+pub use macho::MachCore;
+
+use std::fmt;
+
+use crate::elf::ElfError;
+use crate::macho::MachOError;
+use crate::unwind::{Memory, Registers};
+
+/// Every way a core file can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreError {
+    /// The file is an image, not a core file.
+    NotACore { filetype: u32 },
+    /// The threads are of an architecture the unwinder does not know.
+    UnsupportedCpu { cputype: u32 },
+    /// The threads of an ELF core are of a machine the unwinder does not know.
+    UnsupportedMachine { machine: u16 },
+    /// An image cannot be rebuilt from the memory of the core.
+    ImageNotInCore { address: u64, reason: &'static str },
+    /// The core, or an image in its memory, is malformed.
+    Read(MachOError),
+    /// The ELF core, or an image in its memory, is malformed.
+    Elf(ElfError),
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoreError::NotACore { filetype } => {
+                write!(f, "file type {filetype:#x} is not a core file")
+            }
+            CoreError::UnsupportedCpu { cputype } => {
+                write!(f, "cannot unwind the threads of cpu type {cputype:#x}")
+            }
+            CoreError::ImageNotInCore { address, reason } => {
+                write!(f, "cannot rebuild the image at {address:#x}: {reason}")
+            }
+            CoreError::UnsupportedMachine { machine } => {
+                write!(f, "cannot unwind the threads of ELF machine {machine}")
+            }
+            CoreError::Read(e) => write!(f, "{e}"),
+            CoreError::Elf(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+impl From<MachOError> for CoreError {
+    fn from(e: MachOError) -> CoreError {
+        CoreError::Read(e)
+    }
+}
+
+impl From<ElfError> for CoreError {
+    fn from(e: ElfError) -> CoreError {
+        CoreError::Elf(e)
+    }
+}
+
+/// A range of the memory of the process saved in the core.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoreRegion<'a> {
+    pub address: u64,
+    /// Size of the range in the process, the bytes past `data` were not saved.
+    pub size: u64,
+    pub data: &'a [u8],
+}
+
+/// The memory saved in a core file, read like the memory of the live process.
+#[derive(Debug, Clone, Default)]
+pub struct CoreMemory<'a> {
+    // sorted by address
+    regions: Vec<CoreRegion<'a>>,
+}
+
+impl<'a> CoreMemory<'a> {
+    pub fn new(mut regions: Vec<CoreRegion<'a>>) -> CoreMemory<'a> {
+        regions.sort_by_key(|region| region.address);
+        CoreMemory { regions }
+    }
+
+    pub fn regions(&self) -> &[CoreRegion<'a>] {
+        &self.regions
+    }
+
+    /// Bytes saved from `address` to the end of its region.
+    pub fn bytes_at(&self, address: u64) -> Option<&'a [u8]> {
+        let i = self
+            .regions
+            .partition_point(|region| region.address <= address);
+        let region = self.regions[..i].last()?;
+        let offset = usize::try_from(address - region.address).ok()?;
+        region.data.get(offset..).filter(|bytes| !bytes.is_empty())
+    }
+}
+
+impl Memory for CoreMemory<'_> {
+    fn read(&self, address: u64, buf: &mut [u8]) -> bool {
+        // a read may span adjacent regions
+        let mut done = 0;
+        while done < buf.len() {
+            let Some(bytes) = self.bytes_at(address + done as u64) else {
+                return false;
+            };
+            let size = bytes.len().min(buf.len() - done);
+            buf[done..done + size].copy_from_slice(&bytes[..size]);
+            done += size;
+        }
+        true
+    }
+}
+
+/// A thread of the dumped process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreThread {
+    /// Thread id when the core records one, else the index of the thread.
+    pub id: u64,
+    pub registers: Registers,
+}
+
+/// An image loaded in the dumped process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoreImage {
+    /// Path of the image when the core names it.
+    pub path: Option<String>,
+    /// Address the image header is mapped at.
+    pub load_address: u64,
+    /// LC_UUID of a Mach-O image.
+    pub uuid: Option<[u8; 16]>,
+    /// GNU build-id of an ELF image.
+    pub build_id: Option<Vec<u8>>,
+}
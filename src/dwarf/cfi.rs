@@ -0,0 +1,917 @@
+// call frame information (.eh_frame, __eh_frame and .debug_frame)
+// CIEs hold the instructions shared by several functions, each FDE covers one address range and
+// adds its own instructions; running them up to an address gives the rules to find the caller
+
+use super::{DwarfError, PointerBases, read_encoded_pointer};
+use crate::macho::reader::{Endian, Reader};
+
+pub const DW_CFA_ADVANCE_LOC: u8 = 0x40;
+pub const DW_CFA_OFFSET: u8 = 0x80;
+pub const DW_CFA_RESTORE: u8 = 0xc0;
+pub const DW_CFA_NOP: u8 = 0x00;
+pub const DW_CFA_SET_LOC: u8 = 0x01;
+pub const DW_CFA_ADVANCE_LOC1: u8 = 0x02;
+pub const DW_CFA_ADVANCE_LOC2: u8 = 0x03;
+pub const DW_CFA_ADVANCE_LOC4: u8 = 0x04;
+pub const DW_CFA_OFFSET_EXTENDED: u8 = 0x05;
+pub const DW_CFA_RESTORE_EXTENDED: u8 = 0x06;
+pub const DW_CFA_UNDEFINED: u8 = 0x07;
+pub const DW_CFA_SAME_VALUE: u8 = 0x08;
+pub const DW_CFA_REGISTER: u8 = 0x09;
+pub const DW_CFA_REMEMBER_STATE: u8 = 0x0a;
+pub const DW_CFA_RESTORE_STATE: u8 = 0x0b;
+pub const DW_CFA_DEF_CFA: u8 = 0x0c;
+pub const DW_CFA_DEF_CFA_REGISTER: u8 = 0x0d;
+pub const DW_CFA_DEF_CFA_OFFSET: u8 = 0x0e;
+pub const DW_CFA_DEF_CFA_EXPRESSION: u8 = 0x0f;
+pub const DW_CFA_EXPRESSION: u8 = 0x10;
+pub const DW_CFA_OFFSET_EXTENDED_SF: u8 = 0x11;
+pub const DW_CFA_DEF_CFA_SF: u8 = 0x12;
+pub const DW_CFA_DEF_CFA_OFFSET_SF: u8 = 0x13;
+pub const DW_CFA_VAL_OFFSET: u8 = 0x14;
+pub const DW_CFA_VAL_OFFSET_SF: u8 = 0x15;
+pub const DW_CFA_VAL_EXPRESSION: u8 = 0x16;
+// DW_CFA_GNU_window_save on SPARC, toggles the return address signing state on arm64
+pub const DW_CFA_AARCH64_NEGATE_RA_STATE: u8 = 0x2d;
+pub const DW_CFA_GNU_ARGS_SIZE: u8 = 0x2e;
+pub const DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED: u8 = 0x2f;
+
+/// Which flavour of call frame section is parsed, they differ in how CIEs are found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfiKind {
+    EhFrame,
+    DebugFrame,
+}
+
+/// A call frame section and where it is mapped.
+#[derive(Debug, Clone, Copy)]
+pub struct CfiSection<'a> {
+    pub kind: CfiKind,
+    pub data: &'a [u8],
+    /// Address of the section in the file, used for the pc relative pointers.
+    pub address: u64,
+    pub endian: Endian,
+    pub address_size: u8,
+}
+
+/// Common information entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cie<'a> {
+    pub offset: usize,
+    pub version: u8,
+    pub augmentation: String,
+    pub address_size: u8,
+    pub code_alignment: u64,
+    pub data_alignment: i64,
+    pub return_address_register: u16,
+    pub fde_encoding: u8,
+    pub lsda_encoding: u8,
+    /// Address of the personality routine, or of the pointer to it when encoded indirectly.
+    pub personality: Option<u64>,
+    pub signal_frame: bool,
+    pub instructions: &'a [u8],
+}
+
+/// Frame description entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fde<'a> {
+    pub offset: usize,
+    pub cie: Cie<'a>,
+    pub initial_location: u64,
+    pub address_range: u64,
+    pub lsda: Option<u64>,
+    pub instructions: &'a [u8],
+}
+
+/// How to compute the canonical frame address, the value of the stack pointer at the call site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CfaRule<'a> {
+    RegisterOffset { register: u16, offset: i64 },
+    Expression(&'a [u8]),
+}
+
+/// How to recover the value a register had in the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterRule<'a> {
+    Undefined,
+    SameValue,
+    /// Saved at CFA + offset.
+    Offset(i64),
+    /// The value is CFA + offset.
+    ValOffset(i64),
+    /// Saved in another register.
+    Register(u16),
+    /// Saved at the address computed by the expression, the CFA is pushed first.
+    Expression(&'a [u8]),
+    /// The value is computed by the expression, the CFA is pushed first.
+    ValExpression(&'a [u8]),
+}
+
+/// Rules valid for the addresses `start..end` of a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnwindRow<'a> {
+    pub start: u64,
+    pub end: u64,
+    pub cfa: CfaRule<'a>,
+    pub registers: Vec<(u16, RegisterRule<'a>)>,
+}
+
+impl<'a> UnwindRow<'a> {
+    pub fn rule(&self, register: u16) -> Option<RegisterRule<'a>> {
+        self.registers
+            .iter()
+            .find(|(reg, _)| *reg == register)
+            .map(|(_, rule)| *rule)
+    }
+
+    fn set_rule(&mut self, register: u16, rule: RegisterRule<'a>) {
+        match self.registers.iter_mut().find(|(reg, _)| *reg == register) {
+            Some(slot) => slot.1 = rule,
+            None => self.registers.push((register, rule)),
+        }
+    }
+}
+
+/// Length and CIE id of an entry, with a reader positioned after them.
+struct EntryHeader<'a> {
+    reader: Reader<'a>,
+    end: usize,
+    /// Offset of the CIE id, the CIE pointers of .eh_frame are relative to it.
+    id_offset: usize,
+    id: u64,
+    is_cie: bool,
+}
+
+/// One entry of a call frame section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CfiEntry<'a> {
+    Cie(Cie<'a>),
+    Fde(Fde<'a>),
+    /// The zero length entry ending an .eh_frame section.
+    Terminator,
+}
+
+/// Address range of an FDE and its offset in the section, sorted by address in `CfiIndex`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdeRange {
+    pub start: u64,
+    pub end: u64,
+    pub offset: usize,
+}
+
+/// FDEs of a section sorted by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfiIndex {
+    ranges: Vec<FdeRange>,
+}
+
+impl CfiIndex {
+    pub fn lookup(&self, address: u64) -> Option<&FdeRange> {
+        let i = self.ranges.partition_point(|r| r.start <= address);
+        let range = self.ranges.get(i.checked_sub(1)?)?;
+        (address < range.end).then_some(range)
+    }
+}
+
+impl<'a> CfiSection<'a> {
+    pub fn new(
+        kind: CfiKind,
+        data: &'a [u8],
+        address: u64,
+        endian: Endian,
+        address_size: u8,
+    ) -> Self {
+        CfiSection {
+            kind,
+            data,
+            address,
+            endian,
+            address_size,
+        }
+    }
+
+    /// Reads the length and the CIE id of the entry at `offset`, `None` for a terminator.
+    /// Returns the header with the offset of the next entry.
+    fn entry_header(&self, offset: usize) -> Result<(Option<EntryHeader<'a>>, usize), DwarfError> {
+        let mut r = Reader::at(self.data, offset, self.endian, "call frame entry");
+        let mut length = r.u32()? as u64;
+        let is_64 = length == 0xffff_ffff;
+        if is_64 {
+            length = r.u64()?;
+        }
+        if length == 0 {
+            return Ok((None, r.offset()));
+        }
+        let start = r.offset();
+        let end = usize::try_from(length)
+            .ok()
+            .and_then(|length| start.checked_add(length))
+            .filter(|end| *end <= self.data.len())
+            .ok_or(crate::macho::MachOError::OutOfBounds {
+                what: "call frame entry",
+                offset,
+                size: length as usize,
+            })?;
+        // limit the reader to the entry so the instructions end with it
+        let entry = &self.data[..end];
+        let mut r = Reader::at(entry, start, self.endian, "call frame entry");
+        let id_offset = r.offset();
+        let id = if is_64 { r.u64()? } else { r.u32()? as u64 };
+        let is_cie = match self.kind {
+            CfiKind::EhFrame => id == 0,
+            CfiKind::DebugFrame => id == if is_64 { u64::MAX } else { 0xffff_ffff },
+        };
+        let header = EntryHeader {
+            reader: r,
+            end,
+            id_offset,
+            id,
+            is_cie,
+        };
+        Ok((Some(header), end))
+    }
+
+    /// Parses the entry at `offset` and returns it with the offset of the next entry.
+    pub fn entry_at(&self, offset: usize) -> Result<(CfiEntry<'a>, usize), DwarfError> {
+        let (header, next) = self.entry_header(offset)?;
+        let Some(header) = header else {
+            return Ok((CfiEntry::Terminator, next));
+        };
+        if header.is_cie {
+            let cie = self.parse_cie(offset, header.reader, header.end)?;
+            return Ok((CfiEntry::Cie(cie), next));
+        }
+
+        let cie_offset = match self.kind {
+            // relative to the CIE pointer itself, pointing backwards
+            CfiKind::EhFrame => usize::try_from(header.id)
+                .ok()
+                .and_then(|id| header.id_offset.checked_sub(id)),
+            // an offset from the start of the section, it may point anywhere
+            CfiKind::DebugFrame => usize::try_from(header.id).ok(),
+        };
+        let cie_offset = cie_offset.ok_or(DwarfError::InvalidCiePointer {
+            fde: offset,
+            cie: usize::MAX,
+        })?;
+        let cie = self.cie_at(cie_offset, offset)?;
+        let fde = self.parse_fde(offset, header.reader, header.end, cie)?;
+        Ok((CfiEntry::Fde(fde), next))
+    }
+
+    /// The CIE at `offset` the FDE at `fde` points to. Only CIEs are parsed, an FDE pointing to
+    /// another FDE or to itself is rejected.
+    fn cie_at(&self, offset: usize, fde: usize) -> Result<Cie<'a>, DwarfError> {
+        let invalid = DwarfError::InvalidCiePointer { fde, cie: offset };
+        match self.entry_header(offset) {
+            Ok((Some(header), _)) if header.is_cie => self
+                .parse_cie(offset, header.reader, header.end)
+                .map_err(|_| invalid),
+            _ => Err(invalid),
+        }
+    }
+
+    fn bases(&self) -> PointerBases {
+        PointerBases {
+            section: self.address,
+            ..PointerBases::default()
+        }
+    }
+
+    fn parse_cie(
+        &self,
+        offset: usize,
+        mut r: Reader<'a>,
+        end: usize,
+    ) -> Result<Cie<'a>, DwarfError> {
+        let version = r.u8()?;
+        if !matches!(version, 1 | 3 | 4) {
+            return Err(DwarfError::UnsupportedVersion {
+                what: "CIE",
+                version: version as u16,
+            });
+        }
+        let augmentation = r.cstr()?.to_string();
+        let mut address_size = self.address_size;
+        if version == 4 {
+            address_size = r.u8()?;
+            let _segment_size = r.u8()?;
+        }
+        // old GCC: the address of the exception table follows
+        if augmentation.contains("eh") {
+            r.word(address_size == 8)?;
+        }
+        let code_alignment = r.uleb128()?;
+        let data_alignment = r.sleb128()?;
+        let return_address_register = if version == 1 {
+            r.u8()? as u16
+        } else {
+            r.uleb128()? as u16
+        };
+
+        let mut cie = Cie {
+            offset,
+            version,
+            augmentation: augmentation.clone(),
+            address_size,
+            code_alignment,
+            data_alignment,
+            return_address_register,
+            fde_encoding: 0,
+            lsda_encoding: super::DW_EH_PE_OMIT,
+            personality: None,
+            signal_frame: false,
+            instructions: &[],
+        };
+        if let Some(letters) = augmentation.strip_prefix('z') {
+            let data_end = augmentation_end(&mut r)?;
+            for letter in letters.chars() {
+                match letter {
+                    'L' => cie.lsda_encoding = r.u8()?,
+                    'P' => {
+                        let encoding = r.u8()?;
+                        cie.personality =
+                            read_encoded_pointer(&mut r, encoding, &self.bases(), address_size)?;
+                    }
+                    'R' => cie.fde_encoding = r.u8()?,
+                    'S' => cie.signal_frame = true,
+                    // branch target identification and memory tagging carry no data
+                    _ => {}
+                }
+            }
+            r = Reader::at(&self.data[..end], data_end, self.endian, "call frame entry");
+        }
+        cie.instructions = r.take(end - r.offset())?;
+        Ok(cie)
+    }
+
+    fn parse_fde(
+        &self,
+        offset: usize,
+        mut r: Reader<'a>,
+        end: usize,
+        cie: Cie<'a>,
+    ) -> Result<Fde<'a>, DwarfError> {
+        let bases = self.bases();
+        let (initial_location, address_range) = match self.kind {
+            CfiKind::EhFrame => {
+                let location =
+                    read_encoded_pointer(&mut r, cie.fde_encoding, &bases, cie.address_size)?;
+                // the range is a length, only the format of the encoding applies to it
+                let range = read_encoded_pointer(
+                    &mut r,
+                    cie.fde_encoding & 0x0f,
+                    &bases,
+                    cie.address_size,
+                )?;
+                (location.unwrap_or(0), range.unwrap_or(0))
+            }
+            CfiKind::DebugFrame => {
+                let is_64 = cie.address_size == 8;
+                (r.word(is_64)?, r.word(is_64)?)
+            }
+        };
+        if initial_location.checked_add(address_range).is_none() {
+            return Err(DwarfError::Overflow("FDE address range"));
+        }
+        let mut lsda = None;
+        if cie.augmentation.starts_with('z') {
+            let data_end = augmentation_end(&mut r)?;
+            if cie.augmentation.contains('L') {
+                let bases = PointerBases {
+                    function: Some(initial_location),
+                    ..bases
+                };
+                lsda = read_encoded_pointer(&mut r, cie.lsda_encoding, &bases, cie.address_size)?;
+            }
+            r = Reader::at(&self.data[..end], data_end, self.endian, "call frame entry");
+        }
+        let instructions = r.take(end - r.offset())?;
+        Ok(Fde {
+            offset,
+            cie,
+            initial_location,
+            address_range,
+            lsda,
+            instructions,
+        })
+    }
+
+    /// The FDE at `offset`, as referenced by the compact unwind encodings.
+    pub fn fde_at(&self, offset: usize) -> Result<Fde<'a>, DwarfError> {
+        match self.entry_at(offset)? {
+            (CfiEntry::Fde(fde), _) => Ok(fde),
+            _ => Err(DwarfError::NotAnFde { offset }),
+        }
+    }
+
+    /// Every FDE of the section, in section order.
+    pub fn fdes(&self) -> Result<Vec<Fde<'a>>, DwarfError> {
+        let mut fdes = Vec::new();
+        let mut offset = 0;
+        while offset < self.data.len() {
+            match self.entry_at(offset)? {
+                (CfiEntry::Fde(fde), next) => {
+                    fdes.push(fde);
+                    offset = next;
+                }
+                (CfiEntry::Cie(_), next) => offset = next,
+                (CfiEntry::Terminator, _) if self.kind == CfiKind::EhFrame => break,
+                (CfiEntry::Terminator, next) => offset = next,
+            }
+        }
+        Ok(fdes)
+    }
+
+    /// Sorted address ranges of the FDEs, for repeated lookups.
+    pub fn index(&self) -> Result<CfiIndex, DwarfError> {
+        let mut ranges: Vec<FdeRange> = self
+            .fdes()?
+            .iter()
+            // FDEs of functions removed by the linker are left with a zero location
+            .filter(|fde| fde.initial_location != 0 && fde.address_range != 0)
+            .filter_map(|fde| {
+                Some(FdeRange {
+                    start: fde.initial_location,
+                    end: fde.initial_location.checked_add(fde.address_range)?,
+                    offset: fde.offset,
+                })
+            })
+            .collect();
+        ranges.sort_by_key(|r| r.start);
+        Ok(CfiIndex { ranges })
+    }
+
+    /// The FDE covering `address`, searched linearly.
+    pub fn find_fde(&self, address: u64) -> Result<Option<Fde<'a>>, DwarfError> {
+        Ok(self.fdes()?.into_iter().find(|fde| fde.contains(address)))
+    }
+}
+
+impl<'a> Fde<'a> {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.initial_location && address - self.initial_location < self.address_range
+    }
+
+    pub fn end(&self) -> u64 {
+        self.initial_location.saturating_add(self.address_range)
+    }
+
+    /// Runs the CIE then the FDE instructions up to `address` and returns the rules in effect.
+    pub fn unwind_row(
+        &self,
+        section: &CfiSection<'a>,
+        address: u64,
+    ) -> Result<UnwindRow<'a>, DwarfError> {
+        let mut row = UnwindRow {
+            start: self.initial_location,
+            end: self.end(),
+            cfa: CfaRule::RegisterOffset {
+                register: 0,
+                offset: 0,
+            },
+            registers: Vec::new(),
+        };
+        let mut interpreter = Interpreter {
+            section,
+            fde: self,
+            target: address,
+            stack: Vec::new(),
+        };
+        if !interpreter.run(self.cie.instructions, &mut row, None)? {
+            let initial = row.clone();
+            interpreter.run(self.instructions, &mut row, Some(&initial))?;
+        }
+        Ok(row)
+    }
+}
+
+struct Interpreter<'s, 'a> {
+    section: &'s CfiSection<'a>,
+    fde: &'s Fde<'a>,
+    target: u64,
+    // DW_CFA_remember_state pushes the rules, DW_CFA_restore_state pops them
+    stack: Vec<(CfaRule<'a>, Vec<(u16, RegisterRule<'a>)>)>,
+}
+
+impl<'s, 'a> Interpreter<'s, 'a> {
+    /// Executes `instructions`, stops as soon as the location moves past the target address and
+    /// returns whether it did. `initial` holds the rules set by the CIE, for the restore
+    /// instructions.
+    fn run(
+        &mut self,
+        instructions: &'a [u8],
+        row: &mut UnwindRow<'a>,
+        initial: Option<&UnwindRow<'a>>,
+    ) -> Result<bool, DwarfError> {
+        let cie = &self.fde.cie;
+        let mut r = Reader::new(instructions, self.section.endian, "call frame instructions");
+        let data_alignment = cie.data_alignment;
+        let restore = |row: &mut UnwindRow<'a>, register: u16| {
+            let rule = initial
+                .and_then(|initial| initial.rule(register))
+                .unwrap_or(RegisterRule::SameValue);
+            row.set_rule(register, rule);
+        };
+
+        while !r.is_empty() {
+            let opcode = r.u8()?;
+            let operand = opcode & 0x3f;
+            let mut advance = None;
+            match opcode & 0xc0 {
+                DW_CFA_ADVANCE_LOC => advance = Some(operand as u64),
+                DW_CFA_OFFSET => {
+                    let offset = factored(r.uleb128()?, data_alignment)?;
+                    row.set_rule(operand as u16, RegisterRule::Offset(offset));
+                }
+                DW_CFA_RESTORE => restore(row, operand as u16),
+                _ => match opcode {
+                    DW_CFA_NOP => {}
+                    DW_CFA_SET_LOC => {
+                        let bases = self.section.bases();
+                        let location = read_encoded_pointer(
+                            &mut r,
+                            cie.fde_encoding,
+                            &bases,
+                            cie.address_size,
+                        )?
+                        .unwrap_or(0);
+                        if location > self.target {
+                            row.end = location;
+                            return Ok(true);
+                        }
+                        row.start = location;
+                    }
+                    DW_CFA_ADVANCE_LOC1 => advance = Some(r.u8()? as u64),
+                    DW_CFA_ADVANCE_LOC2 => advance = Some(r.u16()? as u64),
+                    DW_CFA_ADVANCE_LOC4 => advance = Some(r.u32()? as u64),
+                    DW_CFA_OFFSET_EXTENDED => {
+                        let register = r.uleb128()? as u16;
+                        let offset = factored(r.uleb128()?, data_alignment)?;
+                        row.set_rule(register, RegisterRule::Offset(offset));
+                    }
+                    DW_CFA_RESTORE_EXTENDED => restore(row, r.uleb128()? as u16),
+                    DW_CFA_UNDEFINED => row.set_rule(r.uleb128()? as u16, RegisterRule::Undefined),
+                    DW_CFA_SAME_VALUE => row.set_rule(r.uleb128()? as u16, RegisterRule::SameValue),
+                    DW_CFA_REGISTER => {
+                        let register = r.uleb128()? as u16;
+                        let source = r.uleb128()? as u16;
+                        row.set_rule(register, RegisterRule::Register(source));
+                    }
+                    DW_CFA_REMEMBER_STATE => self.stack.push((row.cfa, row.registers.clone())),
+                    DW_CFA_RESTORE_STATE => {
+                        // the location is not part of the remembered state
+                        let (cfa, registers) =
+                            self.stack.pop().ok_or(DwarfError::RuleStackUnderflow)?;
+                        row.cfa = cfa;
+                        row.registers = registers;
+                    }
+                    DW_CFA_DEF_CFA => {
+                        let register = r.uleb128()? as u16;
+                        let offset = r.uleb128()? as i64;
+                        row.cfa = CfaRule::RegisterOffset { register, offset };
+                    }
+                    DW_CFA_DEF_CFA_SF => {
+                        let register = r.uleb128()? as u16;
+                        let offset = signed_factored(r.sleb128()?, data_alignment)?;
+                        row.cfa = CfaRule::RegisterOffset { register, offset };
+                    }
+                    DW_CFA_DEF_CFA_REGISTER => {
+                        let register = r.uleb128()? as u16;
+                        if let CfaRule::RegisterOffset { offset, .. } = row.cfa {
+                            row.cfa = CfaRule::RegisterOffset { register, offset };
+                        }
+                    }
+                    DW_CFA_DEF_CFA_OFFSET | DW_CFA_DEF_CFA_OFFSET_SF => {
+                        let offset = if opcode == DW_CFA_DEF_CFA_OFFSET {
+                            r.uleb128()? as i64
+                        } else {
+                            signed_factored(r.sleb128()?, data_alignment)?
+                        };
+                        if let CfaRule::RegisterOffset { register, .. } = row.cfa {
+                            row.cfa = CfaRule::RegisterOffset { register, offset };
+                        }
+                    }
+                    DW_CFA_DEF_CFA_EXPRESSION => {
+                        let length = r.uleb128()? as usize;
+                        row.cfa = CfaRule::Expression(r.take(length)?);
+                    }
+                    DW_CFA_EXPRESSION | DW_CFA_VAL_EXPRESSION => {
+                        let register = r.uleb128()? as u16;
+                        let length = r.uleb128()? as usize;
+                        let expression = r.take(length)?;
+                        let rule = if opcode == DW_CFA_EXPRESSION {
+                            RegisterRule::Expression(expression)
+                        } else {
+                            RegisterRule::ValExpression(expression)
+                        };
+                        row.set_rule(register, rule);
+                    }
+                    DW_CFA_OFFSET_EXTENDED_SF => {
+                        let register = r.uleb128()? as u16;
+                        let offset = signed_factored(r.sleb128()?, data_alignment)?;
+                        row.set_rule(register, RegisterRule::Offset(offset));
+                    }
+                    DW_CFA_VAL_OFFSET => {
+                        let register = r.uleb128()? as u16;
+                        let offset = factored(r.uleb128()?, data_alignment)?;
+                        row.set_rule(register, RegisterRule::ValOffset(offset));
+                    }
+                    DW_CFA_VAL_OFFSET_SF => {
+                        let register = r.uleb128()? as u16;
+                        let offset = signed_factored(r.sleb128()?, data_alignment)?;
+                        row.set_rule(register, RegisterRule::ValOffset(offset));
+                    }
+                    // return addresses are stripped when they are read, the state is not needed
+                    DW_CFA_AARCH64_NEGATE_RA_STATE => {}
+                    DW_CFA_GNU_ARGS_SIZE => {
+                        r.uleb128()?;
+                    }
+                    DW_CFA_GNU_NEGATIVE_OFFSET_EXTENDED => {
+                        let register = r.uleb128()? as u16;
+                        let offset = factored(r.uleb128()?, data_alignment)?
+                            .checked_neg()
+                            .ok_or(DwarfError::Overflow("call frame offset"))?;
+                        row.set_rule(register, RegisterRule::Offset(offset));
+                    }
+                    _ => return Err(DwarfError::UnknownCfaOpcode(opcode)),
+                },
+            }
+            if let Some(delta) = advance {
+                let location = delta
+                    .checked_mul(cie.code_alignment)
+                    .and_then(|delta| row.start.checked_add(delta))
+                    .ok_or(DwarfError::Overflow("call frame location"))?;
+                if location > self.target {
+                    row.end = location;
+                    return Ok(true);
+                }
+                row.start = location;
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// End of the augmentation data starting with its length at the position of `r`.
+fn augmentation_end(r: &mut Reader) -> Result<usize, DwarfError> {
+    let length = r.uleb128()?;
+    usize::try_from(length)
+        .ok()
+        .and_then(|length| r.offset().checked_add(length))
+        .ok_or(DwarfError::Overflow("augmentation data"))
+}
+
+/// `value * data_alignment`, the factored offsets of the register rules.
+fn factored(value: u64, data_alignment: i64) -> Result<i64, DwarfError> {
+    i64::try_from(value)
+        .ok()
+        .and_then(|value| value.checked_mul(data_alignment))
+        .ok_or(DwarfError::Overflow("call frame offset"))
+}
+
+fn signed_factored(value: i64, data_alignment: i64) -> Result<i64, DwarfError> {
+    value
+        .checked_mul(data_alignment)
+        .ok_or(DwarfError::Overflow("call frame offset"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::{DW_EH_PE_PCREL, DW_EH_PE_SDATA4};
+
+    // x86_64 DWARF register numbers
+    const RSP: u16 = 7;
+    const RIP: u16 = 16;
+
+    fn entry(body: &[u8]) -> Vec<u8> {
+        let mut entry = (body.len() as u32).to_le_bytes().to_vec();
+        entry.extend_from_slice(body);
+        entry
+    }
+
+    /// A .debug_frame CIE: cfa = rsp+8, rip at cfa-8.
+    fn cie_entry(code_alignment: &[u8]) -> Vec<u8> {
+        let mut body = vec![0xff, 0xff, 0xff, 0xff, 1, 0];
+        body.extend_from_slice(code_alignment);
+        body.extend_from_slice(&[0x78, RIP as u8, DW_CFA_DEF_CFA, RSP as u8, 8]);
+        body.extend_from_slice(&[DW_CFA_OFFSET | RIP as u8, 1]);
+        entry(&body)
+    }
+
+    fn fde_entry(cie: u32, location: u64, range: u64, instructions: &[u8]) -> Vec<u8> {
+        let mut body = cie.to_le_bytes().to_vec();
+        body.extend_from_slice(&location.to_le_bytes());
+        body.extend_from_slice(&range.to_le_bytes());
+        body.extend_from_slice(instructions);
+        entry(&body)
+    }
+
+    fn debug_frame(data: &[u8]) -> CfiSection<'_> {
+        CfiSection::new(CfiKind::DebugFrame, data, 0, Endian::Little, 8)
+    }
+
+    #[test]
+    fn fde_pointing_to_itself_is_rejected() {
+        let data = fde_entry(0, 0x1000, 0x10, &[]);
+        assert_eq!(
+            debug_frame(&data).entry_at(0),
+            Err(DwarfError::InvalidCiePointer { fde: 0, cie: 0 })
+        );
+    }
+
+    #[test]
+    fn fdes_pointing_to_each_other_are_rejected() {
+        let mut data = fde_entry(24, 0x1000, 0x10, &[]);
+        assert_eq!(data.len(), 24);
+        data.extend(fde_entry(0, 0x2000, 0x10, &[]));
+        let section = debug_frame(&data);
+        assert_eq!(
+            section.entry_at(0),
+            Err(DwarfError::InvalidCiePointer { fde: 0, cie: 24 })
+        );
+        assert!(section.fdes().is_err());
+    }
+
+    #[test]
+    fn rows_follow_the_advances() {
+        let mut data = cie_entry(&[1]);
+        let cie_len = data.len() as u32;
+        // advance 4, cfa = rsp+16
+        data.extend(fde_entry(
+            0,
+            0x1000,
+            0x100,
+            &[DW_CFA_ADVANCE_LOC | 4, DW_CFA_DEF_CFA_OFFSET, 16],
+        ));
+        let section = debug_frame(&data);
+        let fde = section.fde_at(cie_len as usize).unwrap();
+        assert_eq!(fde.cie.data_alignment, -8);
+
+        let row = fde.unwind_row(&section, 0x1002).unwrap();
+        assert_eq!((row.start, row.end), (0x1000, 0x1004));
+        assert_eq!(
+            row.cfa,
+            CfaRule::RegisterOffset {
+                register: RSP,
+                offset: 8
+            }
+        );
+        assert_eq!(row.rule(RIP), Some(RegisterRule::Offset(-8)));
+
+        let row = fde.unwind_row(&section, 0x1080).unwrap();
+        assert_eq!((row.start, row.end), (0x1004, 0x1100));
+        assert_eq!(
+            row.cfa,
+            CfaRule::RegisterOffset {
+                register: RSP,
+                offset: 16
+            }
+        );
+        assert_eq!(
+            section.index().unwrap().lookup(0x10ff).unwrap().start,
+            0x1000
+        );
+    }
+
+    #[test]
+    fn hostile_values_overflow_into_errors() {
+        let mut data = cie_entry(&[1]);
+        let cie_len = data.len();
+        data.extend(fde_entry(0, u64::MAX - 1, 0x10, &[]));
+        assert_eq!(
+            debug_frame(&data).entry_at(cie_len).map(|_| ()),
+            Err(DwarfError::Overflow("FDE address range"))
+        );
+
+        // code alignment of 2^62 then an advance of 2^32 - 1
+        let mut data = cie_entry(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x40]);
+        let cie_len = data.len();
+        data.extend(fde_entry(
+            0,
+            0x1000,
+            0x10,
+            &[DW_CFA_ADVANCE_LOC4, 0xff, 0xff, 0xff, 0xff],
+        ));
+        let section = debug_frame(&data);
+        let fde = section.fde_at(cie_len).unwrap();
+        assert_eq!(
+            fde.unwind_row(&section, 0x1008),
+            Err(DwarfError::Overflow("call frame location"))
+        );
+
+        // a factored offset of 2^62 times -8
+        let mut data = cie_entry(&[1]);
+        let cie_len = data.len();
+        let offset = [
+            DW_CFA_OFFSET | 3,
+            0x80,
+            0x80,
+            0x80,
+            0x80,
+            0x80,
+            0x80,
+            0x80,
+            0x80,
+            0x40,
+        ];
+        data.extend(fde_entry(0, 0x1000, 0x10, &offset));
+        let section = debug_frame(&data);
+        let fde = section.fde_at(cie_len).unwrap();
+        assert_eq!(
+            fde.unwind_row(&section, 0x1008),
+            Err(DwarfError::Overflow("call frame offset"))
+        );
+    }
+
+    /// An .eh_frame CIE with pc relative FDE pointers: cfa = rsp+8, rip at cfa-8.
+    fn eh_frame_cie() -> Vec<u8> {
+        let mut body = vec![0, 0, 0, 0, 1];
+        body.extend_from_slice(b"zR\0");
+        body.extend_from_slice(&[1, 0x78, RIP as u8, 1, DW_EH_PE_PCREL | DW_EH_PE_SDATA4]);
+        body.extend_from_slice(&[DW_CFA_DEF_CFA, RSP as u8, 8, DW_CFA_OFFSET | RIP as u8, 1]);
+        entry(&body)
+    }
+
+    /// Appends an .eh_frame FDE of the CIE at 0 to `data`, mapped at `address`.
+    fn eh_frame_fde(
+        data: &mut Vec<u8>,
+        address: u64,
+        location: u64,
+        range: u32,
+        instructions: &[u8],
+    ) {
+        let offset = data.len() as u64;
+        let mut body = (offset as u32 + 4).to_le_bytes().to_vec();
+        let pc = location.wrapping_sub(address + offset + 8) as u32;
+        body.extend_from_slice(&pc.to_le_bytes());
+        body.extend_from_slice(&range.to_le_bytes());
+        body.push(0);
+        body.extend_from_slice(instructions);
+        data.extend(entry(&body));
+    }
+
+    #[test]
+    fn eh_frame_rows() {
+        const RBP: u16 = 6;
+        const ADDRESS: u64 = 0x3000;
+        let mut data = eh_frame_cie();
+        let first = data.len();
+        // push rbp; mov rbp, rsp
+        let prologue = [
+            DW_CFA_ADVANCE_LOC | 1,
+            DW_CFA_DEF_CFA_OFFSET,
+            16,
+            DW_CFA_OFFSET | RBP as u8,
+            2,
+            DW_CFA_ADVANCE_LOC | 3,
+            DW_CFA_DEF_CFA_REGISTER,
+            RBP as u8,
+        ];
+        eh_frame_fde(&mut data, ADDRESS, 0x1000, 0x40, &prologue);
+        let second = data.len();
+        eh_frame_fde(&mut data, ADDRESS, 0x1040, 0x20, &[]);
+        // nothing is read past the terminator
+        data.extend_from_slice(&[0, 0, 0, 0, 0xff, 0xff]);
+
+        let cfi = CfiSection::new(CfiKind::EhFrame, &data, ADDRESS, Endian::Little, 8);
+        let fdes = cfi.fdes().unwrap();
+        let ranges: Vec<_> = fdes
+            .iter()
+            .map(|fde| (fde.offset, fde.initial_location, fde.end()))
+            .collect();
+        assert_eq!(ranges, [(first, 0x1000, 0x1040), (second, 0x1040, 0x1060)]);
+        assert!(fdes.iter().all(|fde| fde.cie.augmentation == "zR"));
+        let index = cfi.index().unwrap();
+        assert_eq!(index.lookup(0x103f).map(|range| range.offset), Some(first));
+        assert_eq!(index.lookup(0x1050).map(|range| range.offset), Some(second));
+        assert_eq!(index.lookup(0x1060), None);
+
+        let rsp = |offset| CfaRule::RegisterOffset {
+            register: RSP,
+            offset,
+        };
+        let fde = &fdes[0];
+        let row = fde.unwind_row(&cfi, 0x1000).unwrap();
+        assert_eq!((row.start, row.end, row.cfa), (0x1000, 0x1001, rsp(8)));
+        assert_eq!(row.rule(RIP), Some(RegisterRule::Offset(-8)));
+        let row = fde.unwind_row(&cfi, 0x1002).unwrap();
+        assert_eq!((row.start, row.end, row.cfa), (0x1001, 0x1004, rsp(16)));
+        assert_eq!(row.rule(RBP), Some(RegisterRule::Offset(-16)));
+        let row = fde.unwind_row(&cfi, 0x103f).unwrap();
+        assert_eq!(
+            (row.start, row.end, row.cfa),
+            (
+                0x1004,
+                0x1040,
+                CfaRule::RegisterOffset {
+                    register: RBP,
+                    offset: 16
+                }
+            )
+        );
+        let row = fdes[1].unwind_row(&cfi, 0x1040).unwrap();
+        assert_eq!(row.cfa, rsp(8));
+    }
+}
@@ -0,0 +1,295 @@
+// DWARF expressions
+// a stack machine used by the call frame rules to compute the CFA or where a register is saved;
+// only the operations that make sense without debug information are supported
+
+use super::DwarfError;
+use crate::macho::reader::{Endian, Reader};
+use crate::unwind::{Memory, Registers};
+
+pub const DW_OP_ADDR: u8 = 0x03;
+pub const DW_OP_DEREF: u8 = 0x06;
+pub const DW_OP_CONST1U: u8 = 0x08;
+pub const DW_OP_CONST1S: u8 = 0x09;
+pub const DW_OP_CONST2U: u8 = 0x0a;
+pub const DW_OP_CONST2S: u8 = 0x0b;
+pub const DW_OP_CONST4U: u8 = 0x0c;
+pub const DW_OP_CONST4S: u8 = 0x0d;
+pub const DW_OP_CONST8U: u8 = 0x0e;
+pub const DW_OP_CONST8S: u8 = 0x0f;
+pub const DW_OP_CONSTU: u8 = 0x10;
+pub const DW_OP_CONSTS: u8 = 0x11;
+pub const DW_OP_DUP: u8 = 0x12;
+pub const DW_OP_DROP: u8 = 0x13;
+pub const DW_OP_OVER: u8 = 0x14;
+pub const DW_OP_PICK: u8 = 0x15;
+pub const DW_OP_SWAP: u8 = 0x16;
+pub const DW_OP_ROT: u8 = 0x17;
+pub const DW_OP_ABS: u8 = 0x19;
+pub const DW_OP_AND: u8 = 0x1a;
+pub const DW_OP_DIV: u8 = 0x1b;
+pub const DW_OP_MINUS: u8 = 0x1c;
+pub const DW_OP_MOD: u8 = 0x1d;
+pub const DW_OP_MUL: u8 = 0x1e;
+pub const DW_OP_NEG: u8 = 0x1f;
+pub const DW_OP_NOT: u8 = 0x20;
+pub const DW_OP_OR: u8 = 0x21;
+pub const DW_OP_PLUS: u8 = 0x22;
+pub const DW_OP_PLUS_UCONST: u8 = 0x23;
+pub const DW_OP_SHL: u8 = 0x24;
+pub const DW_OP_SHR: u8 = 0x25;
+pub const DW_OP_SHRA: u8 = 0x26;
+pub const DW_OP_XOR: u8 = 0x27;
+pub const DW_OP_BRA: u8 = 0x28;
+pub const DW_OP_EQ: u8 = 0x29;
+pub const DW_OP_GE: u8 = 0x2a;
+pub const DW_OP_GT: u8 = 0x2b;
+pub const DW_OP_LE: u8 = 0x2c;
+pub const DW_OP_LT: u8 = 0x2d;
+pub const DW_OP_NE: u8 = 0x2e;
+pub const DW_OP_SKIP: u8 = 0x2f;
+pub const DW_OP_LIT0: u8 = 0x30;
+pub const DW_OP_LIT31: u8 = 0x4f;
+pub const DW_OP_REG0: u8 = 0x50;
+pub const DW_OP_REG31: u8 = 0x6f;
+pub const DW_OP_BREG0: u8 = 0x70;
+pub const DW_OP_BREG31: u8 = 0x8f;
+pub const DW_OP_REGX: u8 = 0x90;
+pub const DW_OP_BREGX: u8 = 0x92;
+pub const DW_OP_DEREF_SIZE: u8 = 0x94;
+pub const DW_OP_NOP: u8 = 0x96;
+
+// the expressions of the call frame rules are a few operations long, the branches can loop
+const MAX_OPERATIONS: usize = 10_000;
+const MAX_STACK_DEPTH: usize = 64;
+
+/// Evaluates `expression` with `initial` pushed on the stack and returns the value on top.
+pub fn evaluate(
+    expression: &[u8],
+    endian: Endian,
+    address_size: u8,
+    registers: &Registers,
+    memory: &dyn Memory,
+    initial: &[u64],
+) -> Result<u64, DwarfError> {
+    let mut stack: Vec<u64> = initial.to_vec();
+    let mut r = Reader::new(expression, endian, "DWARF expression");
+    let register = |reg: u64| -> Result<u64, DwarfError> {
+        let reg = u16::try_from(reg).map_err(|_| DwarfError::UnavailableValue)?;
+        registers.get(reg).ok_or(DwarfError::UnavailableValue)
+    };
+
+    let mut operations = 0;
+    while !r.is_empty() {
+        operations += 1;
+        if operations > MAX_OPERATIONS {
+            return Err(DwarfError::ExpressionTooLong);
+        }
+        if stack.len() > MAX_STACK_DEPTH {
+            return Err(DwarfError::ExpressionStackOverflow);
+        }
+        let op = r.u8()?;
+        match op {
+            DW_OP_LIT0..=DW_OP_LIT31 => stack.push((op - DW_OP_LIT0) as u64),
+            DW_OP_ADDR => stack.push(r.word(address_size == 8)?),
+            DW_OP_CONST1U => stack.push(r.u8()? as u64),
+            DW_OP_CONST1S => stack.push(r.u8()? as i8 as u64),
+            DW_OP_CONST2U => stack.push(r.u16()? as u64),
+            DW_OP_CONST2S => stack.push(r.u16()? as i16 as u64),
+            DW_OP_CONST4U => stack.push(r.u32()? as u64),
+            DW_OP_CONST4S => stack.push(r.u32()? as i32 as u64),
+            DW_OP_CONST8U | DW_OP_CONST8S => stack.push(r.u64()?),
+            DW_OP_CONSTU => stack.push(r.uleb128()?),
+            DW_OP_CONSTS => stack.push(r.sleb128()? as u64),
+            DW_OP_BREG0..=DW_OP_BREG31 => {
+                let value = register((op - DW_OP_BREG0) as u64)?;
+                stack.push(value.wrapping_add(r.sleb128()? as u64));
+            }
+            DW_OP_BREGX => {
+                let value = register(r.uleb128()?)?;
+                stack.push(value.wrapping_add(r.sleb128()? as u64));
+            }
+            // a register location, only meaningful as the whole expression
+            DW_OP_REG0..=DW_OP_REG31 => stack.push(register((op - DW_OP_REG0) as u64)?),
+            DW_OP_REGX => stack.push(register(r.uleb128()?)?),
+            DW_OP_DUP => stack.push(peek(&stack, 0)?),
+            DW_OP_DROP => {
+                pop(&mut stack)?;
+            }
+            DW_OP_OVER => stack.push(peek(&stack, 1)?),
+            DW_OP_PICK => {
+                let index = r.u8()? as usize;
+                stack.push(peek(&stack, index)?);
+            }
+            DW_OP_SWAP => {
+                let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                stack.push(a);
+                stack.push(b);
+            }
+            DW_OP_ROT => {
+                let (a, b, c) = (pop(&mut stack)?, pop(&mut stack)?, pop(&mut stack)?);
+                stack.push(a);
+                stack.push(c);
+                stack.push(b);
+            }
+            DW_OP_DEREF => {
+                let address = pop(&mut stack)?;
+                let value = match address_size {
+                    8 => memory.read_u64(address),
+                    _ => memory.read_u32(address).map(|v| v as u64),
+                };
+                stack.push(value.ok_or(DwarfError::UnavailableValue)?);
+            }
+            DW_OP_DEREF_SIZE => {
+                let size = r.u8()? as usize;
+                let address = pop(&mut stack)?;
+                let mut bytes = [0u8; 8];
+                if size > 8 || !memory.read(address, &mut bytes[..size]) {
+                    return Err(DwarfError::UnavailableValue);
+                }
+                stack.push(u64::from_le_bytes(bytes));
+            }
+            DW_OP_ABS => {
+                let value = pop(&mut stack)? as i64;
+                stack.push(value.unsigned_abs());
+            }
+            DW_OP_NEG => {
+                let value = pop(&mut stack)? as i64;
+                stack.push(value.wrapping_neg() as u64);
+            }
+            DW_OP_NOT => {
+                let value = pop(&mut stack)?;
+                stack.push(!value);
+            }
+            DW_OP_PLUS_UCONST => {
+                let value = pop(&mut stack)?;
+                stack.push(value.wrapping_add(r.uleb128()?));
+            }
+            DW_OP_AND | DW_OP_DIV | DW_OP_MINUS | DW_OP_MOD | DW_OP_MUL | DW_OP_OR | DW_OP_PLUS
+            | DW_OP_SHL | DW_OP_SHR | DW_OP_SHRA | DW_OP_XOR | DW_OP_EQ | DW_OP_GE | DW_OP_GT
+            | DW_OP_LE | DW_OP_LT | DW_OP_NE => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(binary_operation(op, a, b)?);
+            }
+            DW_OP_SKIP | DW_OP_BRA => {
+                let offset = r.u16()? as i16 as isize;
+                let taken = op == DW_OP_SKIP || pop(&mut stack)? != 0;
+                if taken {
+                    let target = r.offset() as isize + offset;
+                    let target = usize::try_from(target)
+                        .ok()
+                        .filter(|target| *target <= expression.len())
+                        .ok_or(DwarfError::UnsupportedOperation(op))?;
+                    r = Reader::at(expression, target, endian, "DWARF expression");
+                }
+            }
+            DW_OP_NOP => {}
+            _ => return Err(DwarfError::UnsupportedOperation(op)),
+        }
+    }
+    pop(&mut stack)
+}
+
+fn binary_operation(op: u8, a: u64, b: u64) -> Result<u64, DwarfError> {
+    let (sa, sb) = (a as i64, b as i64);
+    Ok(match op {
+        DW_OP_AND => a & b,
+        DW_OP_OR => a | b,
+        DW_OP_XOR => a ^ b,
+        DW_OP_PLUS => a.wrapping_add(b),
+        DW_OP_MINUS => a.wrapping_sub(b),
+        DW_OP_MUL => a.wrapping_mul(b),
+        DW_OP_DIV => sa.checked_div(sb).ok_or(DwarfError::UnavailableValue)? as u64,
+        DW_OP_MOD => a.checked_rem(b).ok_or(DwarfError::UnavailableValue)?,
+        DW_OP_SHL => a.checked_shl(b as u32).unwrap_or(0),
+        DW_OP_SHR => a.checked_shr(b as u32).unwrap_or(0),
+        DW_OP_SHRA => sa.checked_shr(b as u32).unwrap_or(sa >> 63) as u64,
+        DW_OP_EQ => (sa == sb) as u64,
+        DW_OP_GE => (sa >= sb) as u64,
+        DW_OP_GT => (sa > sb) as u64,
+        DW_OP_LE => (sa <= sb) as u64,
+        DW_OP_LT => (sa < sb) as u64,
+        DW_OP_NE => (sa != sb) as u64,
+        _ => return Err(DwarfError::UnsupportedOperation(op)),
+    })
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, DwarfError> {
+    stack.pop().ok_or(DwarfError::ExpressionStackUnderflow)
+}
+
+fn peek(stack: &[u64], depth: usize) -> Result<u64, DwarfError> {
+    stack
+        .len()
+        .checked_sub(depth + 1)
+        .map(|i| stack[i])
+        .ok_or(DwarfError::ExpressionStackUnderflow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unwind::CpuFamily;
+
+    struct NoMemory;
+
+    impl Memory for NoMemory {
+        fn read(&self, _address: u64, _buf: &mut [u8]) -> bool {
+            false
+        }
+    }
+
+    fn run(expression: &[u8], initial: &[u64]) -> Result<u64, DwarfError> {
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        registers.set(7, 0x7000);
+        evaluate(
+            expression,
+            Endian::Little,
+            8,
+            &registers,
+            &NoMemory,
+            initial,
+        )
+    }
+
+    #[test]
+    fn arithmetic_and_branches() {
+        // rsp + 16, then the initial value times 3
+        assert_eq!(run(&[DW_OP_BREG0 + 7, 16], &[]), Ok(0x7010));
+        assert_eq!(run(&[DW_OP_LIT0 + 3, DW_OP_MUL], &[5]), Ok(15));
+        // 4 - 1 until zero: the branch back is taken three times
+        let countdown = [
+            DW_OP_LIT0 + 1,
+            DW_OP_MINUS,
+            DW_OP_DUP,
+            DW_OP_BRA,
+            0xfa,
+            0xff,
+        ];
+        assert_eq!(run(&countdown, &[4]), Ok(0));
+        assert_eq!(
+            run(&[DW_OP_SKIP, 0x10, 0], &[]),
+            Err(DwarfError::UnsupportedOperation(DW_OP_SKIP))
+        );
+        assert_eq!(
+            run(&[DW_OP_DROP], &[]),
+            Err(DwarfError::ExpressionStackUnderflow)
+        );
+        assert_eq!(
+            run(&[DW_OP_DEREF], &[0x1000]),
+            Err(DwarfError::UnavailableValue)
+        );
+    }
+
+    #[test]
+    fn loops_are_cut() {
+        assert_eq!(
+            run(&[DW_OP_SKIP, 0xfd, 0xff], &[]),
+            Err(DwarfError::ExpressionTooLong)
+        );
+        assert_eq!(
+            run(&[DW_OP_DUP, DW_OP_SKIP, 0xfc, 0xff], &[1]),
+            Err(DwarfError::ExpressionStackOverflow)
+        );
+    }
+}
@@ -0,0 +1,389 @@
+// functions and inlined calls of .debug_info
+// an address of an optimized build stands for the function containing it and for every call
+// inlined at this place, the DW_TAG_inlined_subroutine entries nested in the function tell which
+// functions were inlined and where they were called from
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::interval::IntervalIndex;
+use super::line::LineTable;
+use super::unit::{
+    AttributeValue, DW_AT_ABSTRACT_ORIGIN, DW_AT_CALL_COLUMN, DW_AT_CALL_FILE, DW_AT_CALL_LINE,
+    DW_AT_LINKAGE_NAME, DW_AT_MIPS_LINKAGE_NAME, DW_AT_NAME, DW_AT_SPECIFICATION,
+    DW_TAG_INLINED_SUBROUTINE, DW_TAG_SUBPROGRAM, Entry, Unit, UnitHeader,
+};
+use super::{DwarfError, DwarfSections};
+
+// abstract origins and specifications can refer to each other, stop following them after a few
+const MAX_NAME_INDIRECTIONS: usize = 16;
+
+/// A call inlined into a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Inlinee {
+    /// Number of inlined calls this one is nested in, 0 when inlined directly into the function.
+    pub depth: usize,
+    pub ranges: Vec<Range<u64>>,
+    /// Name of the inlined function, mangled when a linkage name is known.
+    pub name: Option<String>,
+    /// Source position of the call, in the caller.
+    pub call_file: Option<String>,
+    pub call_line: u32,
+    pub call_column: u32,
+}
+
+/// A function with code and the calls inlined into it, in the order of the entries.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub ranges: Vec<Range<u64>>,
+    pub name: Option<String>,
+    pub inlinees: Vec<Inlinee>,
+}
+
+impl Function {
+    /// The inlined calls covering `address`, outermost first.
+    pub fn inlined_at(&self, address: u64) -> Vec<&Inlinee> {
+        let mut chain: Vec<&Inlinee> = Vec::new();
+        for inlinee in &self.inlinees {
+            if inlinee.depth == chain.len() && contains(&inlinee.ranges, address) {
+                chain.push(inlinee);
+            }
+        }
+        chain
+    }
+}
+
+/// Functions of an image by address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionTable {
+    functions: Vec<Function>,
+    // function index by range
+    ranges: IntervalIndex,
+}
+
+// what an entry opens for the entries nested in it
+enum Scope {
+    Function(usize),
+    Inlined,
+    Other,
+}
+
+impl FunctionTable {
+    /// Collects the functions of every unit, the call files are resolved with the line programs
+    /// of the units.
+    pub fn parse(sections: &DwarfSections, lines: &LineTable) -> Result<FunctionTable, DwarfError> {
+        let headers = UnitHeader::all(sections)?;
+        let mut functions = Vec::new();
+        for header in &headers {
+            let unit = Unit::parse(sections, *header)?;
+            functions.extend(Self::parse_unit(&headers, &unit, lines)?.functions);
+        }
+        Ok(FunctionTable::from_functions(functions))
+    }
+
+    /// Collects the functions of one unit, `headers` lists every unit of the image to follow the
+    /// references to other units.
+    pub fn parse_unit(
+        headers: &[UnitHeader],
+        unit: &Unit,
+        lines: &LineTable,
+    ) -> Result<FunctionTable, DwarfError> {
+        let mut names = NameResolver {
+            headers,
+            units: HashMap::new(),
+            cache: HashMap::new(),
+        };
+        let mut functions: Vec<Function> = Vec::new();
+        let mut scopes: Vec<(usize, Scope)> = Vec::new();
+        for entry in unit.entries()? {
+            while scopes
+                .last()
+                .is_some_and(|(depth, _)| *depth >= entry.depth)
+            {
+                scopes.pop();
+            }
+            let scope = match entry.tag {
+                DW_TAG_SUBPROGRAM => {
+                    let ranges = unit.ranges(&entry)?;
+                    if ranges.is_empty() {
+                        Scope::Other
+                    } else {
+                        functions.push(Function {
+                            ranges,
+                            name: names.name(unit, &entry),
+                            inlinees: Vec::new(),
+                        });
+                        Scope::Function(functions.len() - 1)
+                    }
+                }
+                DW_TAG_INLINED_SUBROUTINE => {
+                    // depth counts the inlined calls since the closest function
+                    let mut depth = 0;
+                    let mut function = None;
+                    for (_, scope) in scopes.iter().rev() {
+                        match scope {
+                            Scope::Function(index) => {
+                                function = Some(*index);
+                                break;
+                            }
+                            Scope::Inlined => depth += 1,
+                            Scope::Other => {}
+                        }
+                    }
+                    let ranges = unit.ranges(&entry)?;
+                    if let Some(function) = function
+                        && !ranges.is_empty()
+                    {
+                        let call_file = entry
+                            .attribute(DW_AT_CALL_FILE)
+                            .and_then(|v| v.unsigned())
+                            .zip(unit.stmt_list)
+                            .and_then(|(file, program)| lines.program_file(program as usize, file));
+                        let number = |name| {
+                            entry
+                                .attribute(name)
+                                .and_then(|v| v.unsigned())
+                                .unwrap_or(0) as u32
+                        };
+                        functions[function].inlinees.push(Inlinee {
+                            depth,
+                            ranges,
+                            name: names.name(unit, &entry),
+                            call_file: call_file.map(str::to_string),
+                            call_line: number(DW_AT_CALL_LINE),
+                            call_column: number(DW_AT_CALL_COLUMN),
+                        });
+                    }
+                    Scope::Inlined
+                }
+                _ => Scope::Other,
+            };
+            if entry.has_children {
+                scopes.push((entry.depth, scope));
+            }
+        }
+        Ok(FunctionTable::from_functions(functions))
+    }
+
+    /// Indexes the ranges of `functions`.
+    pub fn from_functions(functions: Vec<Function>) -> FunctionTable {
+        let ranges =
+            IntervalIndex::new(functions.iter().enumerate().flat_map(|(index, function)| {
+                function
+                    .ranges
+                    .iter()
+                    .map(move |range| (range.clone(), index))
+            }));
+        FunctionTable { functions, ranges }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    /// The function containing `address`, an address of the file.
+    pub fn lookup(&self, address: u64) -> Option<&Function> {
+        Some(&self.functions[self.ranges.lookup(address)?])
+    }
+}
+
+fn contains(ranges: &[Range<u64>], address: u64) -> bool {
+    ranges.iter().any(|range| range.contains(&address))
+}
+
+// names of entries, following DW_AT_abstract_origin and DW_AT_specification to the declaration
+// that holds them, possibly in another unit
+// a linkage name anywhere along the references wins over the plain names
+struct NameResolver<'u, 'a> {
+    headers: &'u [UnitHeader],
+    // the other units referenced so far, by offset
+    units: HashMap<usize, Rc<Unit<'a>>>,
+    // (name, whether it is a linkage name) by entry offset
+    cache: HashMap<usize, Option<(String, bool)>>,
+}
+
+impl<'a> NameResolver<'_, 'a> {
+    fn name(&mut self, unit: &Unit<'a>, entry: &Entry<'a>) -> Option<String> {
+        self.resolve(unit, entry, 0).map(|(name, _)| name)
+    }
+
+    fn resolve(
+        &mut self,
+        unit: &Unit<'a>,
+        entry: &Entry<'a>,
+        indirections: usize,
+    ) -> Option<(String, bool)> {
+        let linkage_name = entry
+            .attribute(DW_AT_LINKAGE_NAME)
+            .or_else(|| entry.attribute(DW_AT_MIPS_LINKAGE_NAME))
+            .and_then(|v| unit.string(&v));
+        if let Some(name) = linkage_name {
+            return Some((name.to_string(), true));
+        }
+        let name = entry
+            .attribute(DW_AT_NAME)
+            .and_then(|v| unit.string(&v))
+            .map(|name| (name.to_string(), false));
+        let referenced = self.resolve_reference(unit, entry, indirections);
+        match referenced {
+            Some((_, true)) => referenced,
+            _ => name.or(referenced),
+        }
+    }
+
+    fn resolve_reference(
+        &mut self,
+        unit: &Unit<'a>,
+        entry: &Entry<'a>,
+        indirections: usize,
+    ) -> Option<(String, bool)> {
+        if indirections >= MAX_NAME_INDIRECTIONS {
+            return None;
+        }
+        let reference = entry
+            .attribute(DW_AT_ABSTRACT_ORIGIN)
+            .or_else(|| entry.attribute(DW_AT_SPECIFICATION))?;
+        let offset = match reference {
+            AttributeValue::UnitReference(offset) => unit.header.offset + offset as usize,
+            AttributeValue::InfoReference(offset) => offset as usize,
+            _ => return None,
+        };
+        if let Some(name) = self.cache.get(&offset) {
+            return name.clone();
+        }
+        let name = if unit.contains(offset) {
+            match unit.entry_at(offset) {
+                Ok(Some(origin)) => self.resolve(unit, &origin, indirections + 1),
+                _ => None,
+            }
+        } else {
+            let target = self.unit(unit, offset)?;
+            match target.entry_at(offset) {
+                Ok(Some(origin)) => self.resolve(&target, &origin, indirections + 1),
+                _ => None,
+            }
+        };
+        self.cache.insert(offset, name.clone());
+        name
+    }
+
+    // the unit containing `offset` of .debug_info
+    fn unit(&mut self, from: &Unit<'a>, offset: usize) -> Option<Rc<Unit<'a>>> {
+        let i = self.headers.partition_point(|h| h.offset <= offset);
+        let header = *self.headers.get(i.checked_sub(1)?)?;
+        if let Some(unit) = self.units.get(&header.offset) {
+            return Some(unit.clone());
+        }
+        let unit = Rc::new(Unit::parse(&from.sections, header).ok()?);
+        self.units.insert(header.offset, unit.clone());
+        Some(unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::line::{DW_LNS_ADVANCE_PC, DW_LNS_COPY};
+    use crate::dwarf::tests::{END_SEQUENCE, line_program, set_address, unit_4};
+    use crate::macho::reader::Endian;
+
+    const ABBREVIATIONS: &[u8] = &[
+        1, 0x11, 1, // compile unit with children
+        0x10, 0x17, // DW_AT_stmt_list, DW_FORM_sec_offset
+        0, 0, //
+        2, 0x2e, 1, // subprogram with children
+        0x03, 0x08, // DW_AT_name, DW_FORM_string
+        0x11, 0x01, // DW_AT_low_pc, DW_FORM_addr
+        0x12, 0x06, // DW_AT_high_pc, DW_FORM_data4
+        0, 0, //
+        3, 0x1d, 1, // inlined subroutine with children
+        0x03, 0x08, // DW_AT_name, DW_FORM_string
+        0x11, 0x01, // DW_AT_low_pc, DW_FORM_addr
+        0x12, 0x06, // DW_AT_high_pc, DW_FORM_data4
+        0x58, 0x0b, // DW_AT_call_file, DW_FORM_data1
+        0x59, 0x0b, // DW_AT_call_line, DW_FORM_data1
+        0x57, 0x0b, // DW_AT_call_column, DW_FORM_data1
+        0, 0, 0,
+    ];
+
+    fn entry(abbreviation: u8, name: &str, range: Range<u64>) -> Vec<u8> {
+        let mut entry = vec![abbreviation];
+        entry.extend(name.bytes().chain([0]));
+        entry.extend(range.start.to_le_bytes());
+        entry.extend(((range.end - range.start) as u32).to_le_bytes());
+        entry
+    }
+
+    fn inlined(name: &str, range: Range<u64>, call_line: u8, call_column: u8) -> Vec<u8> {
+        let mut entry = entry(3, name, range);
+        entry.extend([1, call_line, call_column]);
+        entry
+    }
+
+    fn call(inlinee: &Inlinee) -> (&str, Option<&str>, u32, u32) {
+        (
+            inlinee.name.as_deref().unwrap(),
+            inlinee.call_file.as_deref(),
+            inlinee.call_line,
+            inlinee.call_column,
+        )
+    }
+
+    #[test]
+    fn nested_inlined_calls() {
+        // outer calls middle calling inner, then calls last
+        let mut entries = vec![1];
+        entries.extend(0u32.to_le_bytes());
+        entries.extend(entry(2, "outer", 0x1000..0x1100));
+        entries.extend(inlined("middle", 0x1010..0x1080, 10, 3));
+        entries.extend(inlined("inner", 0x1020..0x1040, 20, 5));
+        entries.extend([0, 0]);
+        entries.extend(inlined("last", 0x1090..0x10a0, 30, 7));
+        entries.extend([0, 0, 0]);
+        let debug_info = unit_4(&entries);
+
+        let mut opcodes = set_address(0x1000);
+        opcodes.extend_from_slice(&[DW_LNS_COPY, DW_LNS_ADVANCE_PC, 0x80, 0x02]);
+        opcodes.extend_from_slice(&END_SEQUENCE);
+        let debug_line = line_program(1, &opcodes);
+
+        let sections = DwarfSections {
+            endian: Endian::Little,
+            debug_info: &debug_info,
+            debug_abbrev: ABBREVIATIONS,
+            debug_line: &debug_line,
+            debug_line_str: &[],
+            debug_str: &[],
+            debug_str_offsets: &[],
+            debug_addr: &[],
+            debug_ranges: &[],
+            debug_rnglists: &[],
+        };
+        let lines = LineTable::parse(&sections).unwrap();
+        let table = FunctionTable::parse(&sections, &lines).unwrap();
+        let function = table.lookup(0x1030).unwrap();
+        assert_eq!(function.name.as_deref(), Some("outer"));
+        let depths: Vec<usize> = function.inlinees.iter().map(|i| i.depth).collect();
+        assert_eq!(depths, [0, 1, 0]);
+
+        let chain: Vec<_> = function.inlined_at(0x1030).into_iter().map(call).collect();
+        assert_eq!(
+            chain,
+            [
+                ("middle", Some("src/main.c"), 10, 3),
+                ("inner", Some("src/main.c"), 20, 5)
+            ]
+        );
+        let chain: Vec<_> = function.inlined_at(0x1050).into_iter().map(call).collect();
+        assert_eq!(chain, [("middle", Some("src/main.c"), 10, 3)]);
+        let chain: Vec<_> = function.inlined_at(0x1098).into_iter().map(call).collect();
+        assert_eq!(chain, [("last", Some("src/main.c"), 30, 7)]);
+        assert!(function.inlined_at(0x10f0).is_empty());
+        assert!(table.lookup(0x1100).is_none());
+    }
+}
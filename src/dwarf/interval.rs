@@ -0,0 +1,105 @@
+// lookup by address in the ranges of line sequences, functions and units
+// the ranges overlap: inlined and split functions nest, and the DWARF of the functions removed
+// by the linker stays behind with addresses from 0, so the range containing an address is not
+// always the last one starting before it
+
+use std::ops::Range;
+
+/// Address ranges with a value each, to find the range containing an address.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntervalIndex {
+    // (range, value) sorted by start address
+    ranges: Vec<(Range<u64>, usize)>,
+    // highest end of ranges[..=i], none of them contains an address past it
+    max_ends: Vec<u64>,
+}
+
+impl IntervalIndex {
+    /// Indexes `ranges`, leaving out the empty ones and the ones starting at 0: no image has
+    /// code at address 0, they belong to functions removed by the linker.
+    pub fn new(ranges: impl IntoIterator<Item = (Range<u64>, usize)>) -> IntervalIndex {
+        let mut ranges: Vec<_> = ranges
+            .into_iter()
+            .filter(|(range, _)| range.start != 0 && range.start < range.end)
+            .collect();
+        // stable, the last of the ranges starting at the same address wins like a later one
+        ranges.sort_by_key(|(range, _)| range.start);
+        let max_ends = ranges
+            .iter()
+            .scan(0, |max_end, (range, _)| {
+                *max_end = range.end.max(*max_end);
+                Some(*max_end)
+            })
+            .collect();
+        IntervalIndex { ranges, max_ends }
+    }
+
+    /// Value of the range containing `address` that starts last.
+    pub fn lookup(&self, address: u64) -> Option<usize> {
+        let mut i = self
+            .ranges
+            .partition_point(|(range, _)| range.start <= address);
+        while i > 0 && address < self.max_ends[i - 1] {
+            i -= 1;
+            let (range, value) = &self.ranges[i];
+            if address < range.end {
+                return Some(*value);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_ranges_find_the_innermost() {
+        let index = IntervalIndex::new([
+            (0x1000..0x2000, 0),
+            (0x1100..0x1200, 1),
+            (0x2000..0x2100, 2),
+        ]);
+        assert_eq!(index.lookup(0xfff), None);
+        assert_eq!(index.lookup(0x1000), Some(0));
+        assert_eq!(index.lookup(0x1150), Some(1));
+        assert_eq!(index.lookup(0x1200), Some(0));
+        assert_eq!(index.lookup(0x2000), Some(2));
+        assert_eq!(index.lookup(0x2100), None);
+    }
+
+    #[test]
+    fn ranges_are_found_past_many_shorter_ones() {
+        // a long function followed by more small ones than any fixed window
+        let index = IntervalIndex::new(
+            std::iter::once((0x1000..0x9000, 0))
+                .chain((1..100).map(|i| (0x1000 + i * 0x10..0x1008 + i * 0x10, i as usize))),
+        );
+        assert_eq!(index.lookup(0x1000 + 50 * 0x10 + 4), Some(50));
+        assert_eq!(index.lookup(0x1000 + 50 * 0x10 + 8), Some(0));
+        assert_eq!(index.lookup(0x8fff), Some(0));
+        assert_eq!(index.lookup(0x9000), None);
+    }
+
+    #[test]
+    fn dead_and_empty_ranges_are_dropped() {
+        let index = IntervalIndex::new([
+            (0..0x4000, 0),
+            (0..0x20, 1),
+            (0x1000..0x1000, 2),
+            (0x3000..0x3100, 3),
+        ]);
+        assert_eq!(index.lookup(0x10), None);
+        assert_eq!(index.lookup(0x1000), None);
+        assert_eq!(index.lookup(0x3000), Some(3));
+        assert_eq!(IntervalIndex::new([(0..0x10, 0)]), IntervalIndex::default());
+    }
+
+    #[test]
+    fn later_ranges_win_at_the_same_start() {
+        let index = IntervalIndex::new([(0x1000..0x1100, 0), (0x1000..0x1080, 1)]);
+        assert_eq!(index.lookup(0x1000), Some(1));
+        assert_eq!(index.lookup(0x1090), Some(0));
+    }
+}
@@ -0,0 +1,596 @@
+// line number programs (.debug_line, __DWARF,__debug_line)
+// each unit holds a small state machine program producing rows that map addresses to a file,
+// a line and a column; the rows of all units are kept sorted for lookups
+
+use std::collections::HashMap;
+
+use super::interval::IntervalIndex;
+use super::unit::{
+    DW_FORM_BLOCK, DW_FORM_DATA1, DW_FORM_DATA2, DW_FORM_DATA4, DW_FORM_DATA8, DW_FORM_DATA16,
+    DW_FORM_LINE_STRP, DW_FORM_STRING, DW_FORM_STRP, DW_FORM_UDATA, Unit,
+};
+use super::{DwarfError, DwarfSections, read_initial_length};
+use crate::macho::reader::{self, Reader};
+
+// standard opcodes
+pub const DW_LNS_COPY: u8 = 1;
+pub const DW_LNS_ADVANCE_PC: u8 = 2;
+pub const DW_LNS_ADVANCE_LINE: u8 = 3;
+pub const DW_LNS_SET_FILE: u8 = 4;
+pub const DW_LNS_SET_COLUMN: u8 = 5;
+pub const DW_LNS_NEGATE_STMT: u8 = 6;
+pub const DW_LNS_SET_BASIC_BLOCK: u8 = 7;
+pub const DW_LNS_CONST_ADD_PC: u8 = 8;
+pub const DW_LNS_FIXED_ADVANCE_PC: u8 = 9;
+pub const DW_LNS_SET_PROLOGUE_END: u8 = 10;
+pub const DW_LNS_SET_EPILOGUE_BEGIN: u8 = 11;
+pub const DW_LNS_SET_ISA: u8 = 12;
+
+// extended opcodes
+pub const DW_LNE_END_SEQUENCE: u8 = 1;
+pub const DW_LNE_SET_ADDRESS: u8 = 2;
+
+// content types of the DWARF 5 directory and file entries
+pub const DW_LNCT_PATH: u64 = 1;
+pub const DW_LNCT_DIRECTORY_INDEX: u64 = 2;
+
+/// One row of a line table, `file` indexes `LineTable::files`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRow {
+    pub address: u64,
+    pub file: usize,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Rows of contiguous addresses `start..end`, sorted by address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineSequence {
+    pub start: u64,
+    pub end: u64,
+    pub rows: Vec<LineRow>,
+}
+
+/// Source position of an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineLocation<'t> {
+    pub file: &'t str,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Rows of every line program of an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    pub files: Vec<String>,
+    sequences: Vec<LineSequence>,
+    index: IntervalIndex,
+    file_indices: HashMap<String, usize>,
+    // files of each program by offset, indexed by file number
+    program_files: HashMap<usize, Vec<Option<usize>>>,
+}
+
+/// Fields of a line program header needed to run it.
+struct LineProgramHeader {
+    version: u16,
+    minimum_instruction_length: u8,
+    line_base: i8,
+    line_range: u8,
+    opcode_base: u8,
+    standard_opcode_lengths: Vec<u8>,
+    directories: Vec<String>,
+    // (path, directory index)
+    files: Vec<(String, u64)>,
+}
+
+impl LineTable {
+    /// Runs the line program of every compilation unit, or every program of `.debug_line`
+    /// when there is no `.debug_info` to find them.
+    pub fn parse(sections: &DwarfSections) -> Result<LineTable, DwarfError> {
+        let mut table = LineTable::default();
+        if sections.debug_info.is_empty() {
+            let mut offset = 0;
+            while offset < sections.debug_line.len() {
+                offset = table.parse_unit(sections, offset, None)?;
+            }
+        } else {
+            for unit in Unit::all(sections)? {
+                if let Some(offset) = unit.stmt_list {
+                    table.parse_unit(sections, offset as usize, unit.comp_dir)?;
+                }
+            }
+        }
+        table.index_sequences();
+        Ok(table)
+    }
+
+    /// Table of the line program at `offset` alone, the program of one unit.
+    pub fn parse_program(
+        sections: &DwarfSections,
+        offset: usize,
+        comp_dir: Option<&str>,
+    ) -> Result<LineTable, DwarfError> {
+        let mut table = LineTable::default();
+        table.parse_unit(sections, offset, comp_dir)?;
+        table.index_sequences();
+        Ok(table)
+    }
+
+    /// Table of already sorted sequences, as stored in the symbol store. The files of the line
+    /// programs are not known, `program_file` finds nothing.
+    pub fn from_sequences(files: Vec<String>, sequences: Vec<LineSequence>) -> LineTable {
+        let file_indices = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| (file.clone(), index))
+            .collect();
+        let mut table = LineTable {
+            files,
+            sequences,
+            index: IntervalIndex::default(),
+            file_indices,
+            program_files: HashMap::new(),
+        };
+        table.index_sequences();
+        table
+    }
+
+    fn index_sequences(&mut self) {
+        self.sequences.sort_by_key(|s| s.start);
+        self.index = IntervalIndex::new(
+            self.sequences
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (s.start..s.end, i)),
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    pub fn sequences(&self) -> &[LineSequence] {
+        &self.sequences
+    }
+
+    /// Source position of `address`, an address of the file.
+    pub fn lookup(&self, address: u64) -> Option<LineLocation<'_>> {
+        let sequence = &self.sequences[self.index.lookup(address)?];
+        let j = sequence.rows.partition_point(|row| row.address <= address);
+        let row = sequence.rows.get(j.checked_sub(1)?)?;
+        Some(LineLocation {
+            file: &self.files[row.file],
+            line: row.line,
+            column: row.column,
+        })
+    }
+
+    /// Runs the line program at `offset` and returns the offset of the next one. `comp_dir`
+    /// is the compilation directory, for relative paths of DWARF 4 and older programs.
+    fn parse_unit(
+        &mut self,
+        sections: &DwarfSections,
+        offset: usize,
+        comp_dir: Option<&str>,
+    ) -> Result<usize, DwarfError> {
+        let data = sections.debug_line;
+        let mut r = Reader::at(data, offset, sections.endian, "line program header");
+        let (length, is_64) = read_initial_length(&mut r)?;
+        let end = r.offset().saturating_add(length as usize);
+        let unit = reader::slice(data, 0, end, "line program")?;
+        let mut r = Reader::at(unit, r.offset(), sections.endian, "line program header");
+
+        let header = parse_header(&mut r, is_64, sections, comp_dir)?;
+        // file numbers start at 1 before DWARF 5
+        let mut files = if header.version >= 5 {
+            Vec::new()
+        } else {
+            vec![None]
+        };
+        for (path, directory) in &header.files {
+            let directory = header.directories.get(*directory as usize);
+            files.push(Some(
+                self.intern(join_path(directory.map(String::as_str), path)),
+            ));
+        }
+        self.run_program(&mut r, &header, &files)?;
+        self.program_files.insert(offset, files);
+        Ok(end)
+    }
+
+    /// Path of file number `file` of the line program at `offset`, for the DW_AT_decl_file and
+    /// DW_AT_call_file attributes of its unit.
+    pub fn program_file(&self, offset: usize, file: u64) -> Option<&str> {
+        let index = (*self.program_files.get(&offset)?.get(file as usize)?)?;
+        Some(&self.files[index])
+    }
+
+    fn intern(&mut self, path: String) -> usize {
+        if let Some(index) = self.file_indices.get(&path) {
+            return *index;
+        }
+        self.files.push(path.clone());
+        self.file_indices.insert(path, self.files.len() - 1);
+        self.files.len() - 1
+    }
+
+    fn run_program(
+        &mut self,
+        r: &mut Reader,
+        header: &LineProgramHeader,
+        files: &[Option<usize>],
+    ) -> Result<(), DwarfError> {
+        let file_index = |file: u64| files.get(file as usize).copied().flatten();
+        let min_length = header.minimum_instruction_length as u64;
+        let line_range = header.line_range.max(1);
+
+        // the address wraps, a sequence ending before it starts is left out by the index
+        let mut address = 0u64;
+        let mut file = 1u64;
+        let mut line = 1i64;
+        let mut column = 0u64;
+        let mut rows: Vec<LineRow> = Vec::new();
+
+        while !r.is_empty() {
+            let opcode = r.u8()?;
+            let mut emit = false;
+            if opcode >= header.opcode_base {
+                let adjusted = opcode - header.opcode_base;
+                address = address.wrapping_add((adjusted / line_range) as u64 * min_length);
+                line = line
+                    .checked_add(header.line_base as i64 + (adjusted % line_range) as i64)
+                    .ok_or(DwarfError::Overflow("line number"))?;
+                emit = true;
+            } else {
+                match opcode {
+                    0 => {
+                        let length = r.uleb128()?;
+                        if length == 0 {
+                            continue;
+                        }
+                        let start = r.offset();
+                        let end = usize::try_from(length)
+                            .ok()
+                            .and_then(|length| start.checked_add(length))
+                            .ok_or(DwarfError::Overflow("extended line opcode"))?;
+                        match r.u8()? {
+                            DW_LNE_END_SEQUENCE => {
+                                // the end address is the first byte after the sequence
+                                if let Some(first) = rows.first() {
+                                    self.sequences.push(LineSequence {
+                                        start: first.address,
+                                        end: address,
+                                        rows: std::mem::take(&mut rows),
+                                    });
+                                }
+                                address = 0;
+                                file = 1;
+                                line = 1;
+                                column = 0;
+                            }
+                            DW_LNE_SET_ADDRESS => {
+                                address = match length - 1 {
+                                    8 => r.u64()?,
+                                    4 => r.u32()? as u64,
+                                    2 => r.u16()? as u64,
+                                    _ => return Err(DwarfError::UnsupportedForm(length)),
+                                };
+                            }
+                            // DW_LNE_define_file and vendor opcodes are skipped
+                            _ => {}
+                        }
+                        r.seek(end);
+                    }
+                    DW_LNS_COPY => emit = true,
+                    DW_LNS_ADVANCE_PC => {
+                        let advance = r
+                            .uleb128()?
+                            .checked_mul(min_length)
+                            .ok_or(DwarfError::Overflow("line program address advance"))?;
+                        address = address.wrapping_add(advance);
+                    }
+                    DW_LNS_ADVANCE_LINE => {
+                        line = line
+                            .checked_add(r.sleb128()?)
+                            .ok_or(DwarfError::Overflow("line number"))?;
+                    }
+                    DW_LNS_SET_FILE => file = r.uleb128()?,
+                    DW_LNS_SET_COLUMN => column = r.uleb128()?,
+                    // every row is kept, statement or not
+                    DW_LNS_NEGATE_STMT
+                    | DW_LNS_SET_BASIC_BLOCK
+                    | DW_LNS_SET_PROLOGUE_END
+                    | DW_LNS_SET_EPILOGUE_BEGIN => {}
+                    DW_LNS_CONST_ADD_PC => {
+                        let adjusted = 255 - header.opcode_base;
+                        address = address.wrapping_add((adjusted / line_range) as u64 * min_length);
+                    }
+                    DW_LNS_FIXED_ADVANCE_PC => address = address.wrapping_add(r.u16()? as u64),
+                    DW_LNS_SET_ISA => {
+                        r.uleb128()?;
+                    }
+                    // unknown standard opcodes declare how many operands to skip
+                    _ => {
+                        let operands = header
+                            .standard_opcode_lengths
+                            .get(opcode as usize - 1)
+                            .copied()
+                            .unwrap_or(0);
+                        for _ in 0..operands {
+                            r.uleb128()?;
+                        }
+                    }
+                }
+            }
+            if emit && let Some(file) = file_index(file) {
+                rows.push(LineRow {
+                    address,
+                    file,
+                    line: line.max(0) as u32,
+                    column: column as u32,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_header(
+    r: &mut Reader,
+    is_64: bool,
+    sections: &DwarfSections,
+    comp_dir: Option<&str>,
+) -> Result<LineProgramHeader, DwarfError> {
+    let version = r.u16()?;
+    if !(2..=5).contains(&version) {
+        return Err(DwarfError::UnsupportedVersion {
+            what: "line program",
+            version,
+        });
+    }
+    if version >= 5 {
+        let _address_size = r.u8()?;
+        let _segment_selector_size = r.u8()?;
+    }
+    let header_length = if is_64 { r.u64()? } else { r.u32()? as u64 };
+    let program_start = usize::try_from(header_length)
+        .ok()
+        .and_then(|length| r.offset().checked_add(length))
+        .ok_or(DwarfError::Overflow("line program header"))?;
+    let minimum_instruction_length = r.u8()?;
+    if version >= 4 {
+        let _maximum_operations_per_instruction = r.u8()?;
+    }
+    let _default_is_stmt = r.u8()?;
+    let line_base = r.u8()? as i8;
+    let line_range = r.u8()?;
+    let opcode_base = r.u8()?;
+    let standard_opcode_lengths = (1..opcode_base)
+        .map(|_| r.u8())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut directories = Vec::new();
+    let mut files = Vec::new();
+    if version >= 5 {
+        for (path, _) in parse_entries(r, is_64, sections)? {
+            directories.push(path);
+        }
+        files = parse_entries(r, is_64, sections)?;
+        // the first directory is the compilation directory
+        if let (Some(first), Some(comp_dir)) = (directories.first_mut(), comp_dir) {
+            *first = join_path(Some(comp_dir), first);
+        }
+    } else {
+        // directory 0 is the compilation directory, the list starts at 1
+        directories.push(comp_dir.unwrap_or_default().to_string());
+        loop {
+            let directory = r.cstr()?;
+            if directory.is_empty() {
+                break;
+            }
+            directories.push(directory.to_string());
+        }
+        loop {
+            let path = r.cstr()?;
+            if path.is_empty() {
+                break;
+            }
+            let directory = r.uleb128()?;
+            let _mtime = r.uleb128()?;
+            let _length = r.uleb128()?;
+            files.push((path.to_string(), directory));
+        }
+    }
+    // the other directories are relative to the compilation directory
+    if let Some((first, others)) = directories.split_first_mut() {
+        for directory in others {
+            *directory = join_path(Some(first), directory);
+        }
+    }
+    r.seek(program_start);
+
+    Ok(LineProgramHeader {
+        version,
+        minimum_instruction_length,
+        line_base,
+        line_range,
+        opcode_base,
+        standard_opcode_lengths,
+        directories,
+        files,
+    })
+}
+
+/// Reads a DWARF 5 directory or file name table as `(path, directory index)` pairs.
+fn parse_entries(
+    r: &mut Reader,
+    is_64: bool,
+    sections: &DwarfSections,
+) -> Result<Vec<(String, u64)>, DwarfError> {
+    let format_count = r.u8()?;
+    let formats = (0..format_count)
+        .map(|_| Ok((r.uleb128()?, r.uleb128()?)))
+        .collect::<Result<Vec<_>, DwarfError>>()?;
+    let count = r.uleb128()?;
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut path = String::new();
+        let mut directory = 0;
+        for (content, form) in &formats {
+            // strings and numbers, the MD5 and other blocks are skipped
+            let (string, number) = match *form {
+                DW_FORM_STRING => (Some(r.cstr()?), None),
+                DW_FORM_LINE_STRP | DW_FORM_STRP => {
+                    let offset = if is_64 { r.u64()? } else { r.u32()? as u64 };
+                    let strings = if *form == DW_FORM_LINE_STRP {
+                        sections.debug_line_str
+                    } else {
+                        sections.debug_str
+                    };
+                    (reader::cstr(strings, offset as usize), None)
+                }
+                DW_FORM_UDATA => (None, Some(r.uleb128()?)),
+                DW_FORM_DATA1 => (None, Some(r.u8()? as u64)),
+                DW_FORM_DATA2 => (None, Some(r.u16()? as u64)),
+                DW_FORM_DATA4 => (None, Some(r.u32()? as u64)),
+                DW_FORM_DATA8 => (None, Some(r.u64()?)),
+                DW_FORM_DATA16 => {
+                    r.skip(16)?;
+                    (None, None)
+                }
+                DW_FORM_BLOCK => {
+                    let length = r.uleb128()? as usize;
+                    r.skip(length)?;
+                    (None, None)
+                }
+                form => return Err(DwarfError::UnsupportedForm(form)),
+            };
+            match *content {
+                DW_LNCT_PATH => path = string.unwrap_or_default().to_string(),
+                DW_LNCT_DIRECTORY_INDEX => directory = number.unwrap_or(0),
+                _ => {}
+            }
+        }
+        entries.push((path, directory));
+    }
+    Ok(entries)
+}
+
+/// `directory/path` unless the path is absolute.
+fn join_path(directory: Option<&str>, path: &str) -> String {
+    match directory {
+        Some(directory) if !directory.is_empty() && !path.starts_with('/') => {
+            format!("{}/{}", directory.trim_end_matches('/'), path)
+        }
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dwarf::tests::{END_SEQUENCE, line_program as program, set_address};
+    use crate::macho::reader::Endian;
+
+    fn sections(debug_line: &[u8]) -> DwarfSections<'_> {
+        DwarfSections {
+            endian: Endian::Little,
+            debug_info: &[],
+            debug_abbrev: &[],
+            debug_line,
+            debug_line_str: &[],
+            debug_str: &[],
+            debug_str_offsets: &[],
+            debug_addr: &[],
+            debug_ranges: &[],
+            debug_rnglists: &[],
+        }
+    }
+
+    fn location(table: &LineTable, address: u64) -> Option<(&str, u32, u32)> {
+        table
+            .lookup(address)
+            .map(|location| (location.file, location.line, location.column))
+    }
+
+    #[test]
+    fn rows_of_a_line_program() {
+        let mut opcodes = set_address(0x1000);
+        opcodes.push(DW_LNS_COPY);
+        // special opcode: address + 4, line + 2
+        opcodes.push(13 + (2 + 5) + 14 * 4);
+        opcodes.extend_from_slice(&[DW_LNS_ADVANCE_LINE, 10, DW_LNS_ADVANCE_PC, 8]);
+        opcodes.extend_from_slice(&[DW_LNS_SET_COLUMN, 7, DW_LNS_COPY]);
+        opcodes.extend_from_slice(&[DW_LNS_ADVANCE_PC, 4]);
+        opcodes.extend_from_slice(&END_SEQUENCE);
+        // a sequence of a function removed by the linker
+        opcodes.extend(set_address(0));
+        opcodes.extend_from_slice(&[DW_LNS_COPY, DW_LNS_ADVANCE_PC, 0x7f]);
+        opcodes.extend_from_slice(&END_SEQUENCE);
+
+        let data = program(1, &opcodes);
+        let table = LineTable::parse(&sections(&data)).unwrap();
+        assert_eq!(table.sequences().len(), 2);
+        assert_eq!(location(&table, 0xfff), None);
+        assert_eq!(location(&table, 0x1000), Some(("src/main.c", 1, 0)));
+        assert_eq!(location(&table, 0x1005), Some(("src/main.c", 3, 0)));
+        assert_eq!(location(&table, 0x100f), Some(("src/main.c", 13, 7)));
+        assert_eq!(location(&table, 0x1010), None);
+        assert_eq!(location(&table, 0x10), None);
+        assert_eq!(table.program_file(0, 1), Some("src/main.c"));
+    }
+
+    #[test]
+    fn hostile_values_overflow_into_errors() {
+        let advance = [
+            DW_LNS_ADVANCE_PC,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0xff,
+            0x01,
+        ];
+        assert_eq!(
+            LineTable::parse(&sections(&program(4, &advance))),
+            Err(DwarfError::Overflow("line program address advance"))
+        );
+
+        let extended = [
+            0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0,
+        ];
+        assert_eq!(
+            LineTable::parse(&sections(&program(1, &extended))),
+            Err(DwarfError::Overflow("extended line opcode"))
+        );
+
+        let mut lines = Vec::new();
+        for _ in 0..2 {
+            lines.extend_from_slice(&[DW_LNS_ADVANCE_LINE, 0xff, 0xff, 0xff, 0xff]);
+            lines.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0x00]);
+        }
+        assert_eq!(
+            LineTable::parse(&sections(&program(1, &lines))),
+            Err(DwarfError::Overflow("line number"))
+        );
+    }
+
+    #[test]
+    fn truncated_programs_are_errors() {
+        let mut opcodes = set_address(0x1000);
+        opcodes.push(DW_LNS_COPY);
+        opcodes.extend_from_slice(&END_SEQUENCE);
+        let data = program(1, &opcodes);
+        assert!(LineTable::parse(&sections(&data)).is_ok());
+        for length in 1..data.len() {
+            assert!(
+                LineTable::parse(&sections(&data[..length])).is_err(),
+                "{length}"
+            );
+        }
+    }
+}
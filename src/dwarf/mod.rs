@@ -0,0 +1,274 @@
+// DWARF decoding
+// shared by the Mach-O and ELF images, the sections are found by the format specific parsers
+// and handed over as byte slices with the address they are mapped at
+// https://dwarfstd.org/doc/DWARF5.pdf
+
+pub mod cfi;
+pub mod expression;
+pub mod inline;
+pub mod interval;
+pub mod line;
+pub mod unit;
+
+use std::fmt;
+
+use crate::elf::Elf;
+use crate::macho::reader::{Endian, Reader};
+use crate::macho::{MachO, MachOError};
+
+// pointer encodings of .eh_frame, the low nibble is the format, the high one how to apply it
+pub const DW_EH_PE_ABSPTR: u8 = 0x00;
+pub const DW_EH_PE_ULEB128: u8 = 0x01;
+pub const DW_EH_PE_UDATA2: u8 = 0x02;
+pub const DW_EH_PE_UDATA4: u8 = 0x03;
+pub const DW_EH_PE_UDATA8: u8 = 0x04;
+pub const DW_EH_PE_SLEB128: u8 = 0x09;
+pub const DW_EH_PE_SDATA2: u8 = 0x0a;
+pub const DW_EH_PE_SDATA4: u8 = 0x0b;
+pub const DW_EH_PE_SDATA8: u8 = 0x0c;
+pub const DW_EH_PE_PCREL: u8 = 0x10;
+pub const DW_EH_PE_TEXTREL: u8 = 0x20;
+pub const DW_EH_PE_DATAREL: u8 = 0x30;
+pub const DW_EH_PE_FUNCREL: u8 = 0x40;
+pub const DW_EH_PE_OMIT: u8 = 0xff;
+
+/// The debug sections of an image, empty when missing.
+#[derive(Debug, Clone, Copy)]
+pub struct DwarfSections<'a> {
+    pub endian: Endian,
+    pub debug_info: &'a [u8],
+    pub debug_abbrev: &'a [u8],
+    pub debug_line: &'a [u8],
+    pub debug_line_str: &'a [u8],
+    pub debug_str: &'a [u8],
+    pub debug_str_offsets: &'a [u8],
+    pub debug_addr: &'a [u8],
+    pub debug_ranges: &'a [u8],
+    pub debug_rnglists: &'a [u8],
+}
+
+impl<'a> DwarfSections<'a> {
+    /// Sections of the __DWARF segment, found in dSYM bundles and object files.
+    pub fn from_macho(binary: &MachO<'a>) -> DwarfSections<'a> {
+        // section names are truncated to 16 characters
+        Self::from_sections(binary.endian, |name| {
+            let section = binary.find_section("__DWARF", &format!("__{}", name))?;
+            binary.section_data(section).ok()
+        })
+    }
+
+    pub fn from_elf(binary: &Elf<'a>) -> DwarfSections<'a> {
+        Self::from_sections(binary.endian, |name| {
+            let section = binary.section(&format!(".{}", name))?;
+            binary.section_data(section).ok()
+        })
+    }
+
+    fn from_sections(endian: Endian, find: impl Fn(&str) -> Option<&'a [u8]>) -> DwarfSections<'a> {
+        let section = |name: &str| find(name).unwrap_or(&[]);
+        DwarfSections {
+            endian,
+            debug_info: section("debug_info"),
+            debug_abbrev: section("debug_abbrev"),
+            debug_line: section("debug_line"),
+            debug_line_str: section("debug_line_str"),
+            debug_str: section("debug_str"),
+            debug_str_offsets: find("debug_str_offsets")
+                .or_else(|| find("debug_str_offs"))
+                .unwrap_or(&[]),
+            debug_addr: section("debug_addr"),
+            debug_ranges: section("debug_ranges"),
+            debug_rnglists: section("debug_rnglists"),
+        }
+    }
+}
+
+/// Reads the initial length of a unit, returns the length and whether the unit uses the 64-bit
+/// format.
+pub fn read_initial_length(r: &mut Reader) -> Result<(u64, bool), DwarfError> {
+    let length = r.u32()?;
+    if length == 0xffff_ffff {
+        Ok((r.u64()?, true))
+    } else {
+        Ok((length as u64, false))
+    }
+}
+
+/// Every way DWARF data can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DwarfError {
+    /// A structure runs past the end of its section.
+    Read(MachOError),
+    /// A unit or entry has a version this decoder does not know.
+    UnsupportedVersion { what: &'static str, version: u16 },
+    /// A pointer encoding (DW_EH_PE_*) is unknown or cannot be applied.
+    UnsupportedPointerEncoding(u8),
+    /// The CIE pointer of an FDE does not point to a CIE.
+    InvalidCiePointer { fde: usize, cie: usize },
+    /// The entry at this offset is not an FDE.
+    NotAnFde { offset: usize },
+    /// A call frame instruction is unknown.
+    UnknownCfaOpcode(u8),
+    /// DW_CFA_restore_state without a matching DW_CFA_remember_state.
+    RuleStackUnderflow,
+    /// A DWARF expression uses an operation the evaluator does not support.
+    UnsupportedOperation(u8),
+    /// A DWARF expression pops more values than it pushed.
+    ExpressionStackUnderflow,
+    /// A DWARF expression pushes more values than the evaluator keeps.
+    ExpressionStackOverflow,
+    /// A DWARF expression runs more operations than the evaluator allows, a branch loops.
+    ExpressionTooLong,
+    /// A DWARF expression reads a register or memory that is not available.
+    UnavailableValue,
+    /// An attribute form is unknown or cannot be decoded here.
+    UnsupportedForm(u64),
+    /// An entry uses an abbreviation code missing from the table of its unit.
+    UnknownAbbreviation { code: u64, offset: usize },
+    /// A range list entry (DW_RLE_*) is unknown.
+    UnknownRangeListEntry(u8),
+    /// An offset, length or address computed from the section does not fit in 64 bits.
+    Overflow(&'static str),
+}
+
+impl fmt::Display for DwarfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DwarfError::Read(e) => write!(f, "{e}"),
+            DwarfError::UnsupportedVersion { what, version } => {
+                write!(f, "unsupported {what} version {version}")
+            }
+            DwarfError::UnsupportedPointerEncoding(encoding) => {
+                write!(f, "unsupported pointer encoding {encoding:#x}")
+            }
+            DwarfError::InvalidCiePointer { fde, cie } => {
+                write!(f, "FDE at {fde:#x} points to {cie:#x} which is not a CIE")
+            }
+            DwarfError::NotAnFde { offset } => write!(f, "no FDE at {offset:#x}"),
+            DwarfError::UnknownCfaOpcode(opcode) => {
+                write!(f, "unknown call frame instruction {opcode:#x}")
+            }
+            DwarfError::RuleStackUnderflow => {
+                write!(f, "DW_CFA_restore_state without a remembered state")
+            }
+            DwarfError::UnsupportedOperation(op) => {
+                write!(f, "unsupported DWARF expression operation {op:#x}")
+            }
+            DwarfError::ExpressionStackUnderflow => write!(f, "DWARF expression stack underflow"),
+            DwarfError::ExpressionStackOverflow => write!(f, "DWARF expression stack overflow"),
+            DwarfError::ExpressionTooLong => {
+                write!(f, "DWARF expression runs too many operations")
+            }
+            DwarfError::UnavailableValue => {
+                write!(
+                    f,
+                    "DWARF expression reads an unavailable register or memory"
+                )
+            }
+            DwarfError::UnsupportedForm(form) => write!(f, "unsupported attribute form {form:#x}"),
+            DwarfError::UnknownAbbreviation { code, offset } => {
+                write!(
+                    f,
+                    "unknown abbreviation {code} for the entry at {offset:#x}"
+                )
+            }
+            DwarfError::UnknownRangeListEntry(kind) => {
+                write!(f, "unknown range list entry {kind:#x}")
+            }
+            DwarfError::Overflow(what) => write!(f, "{what} overflows"),
+        }
+    }
+}
+
+impl std::error::Error for DwarfError {}
+
+impl From<MachOError> for DwarfError {
+    fn from(e: MachOError) -> DwarfError {
+        DwarfError::Read(e)
+    }
+}
+
+/// Addresses the relative pointer encodings are computed from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PointerBases {
+    /// Address the section being read is mapped at, for DW_EH_PE_pcrel.
+    pub section: u64,
+    pub text: Option<u64>,
+    pub data: Option<u64>,
+    pub function: Option<u64>,
+}
+
+/// Reads a pointer encoded with `encoding`, `None` for DW_EH_PE_omit.
+///
+/// Indirect pointers are returned as the address of the pointer, they can only be followed in the
+/// memory of the process.
+pub fn read_encoded_pointer(
+    r: &mut Reader,
+    encoding: u8,
+    bases: &PointerBases,
+    address_size: u8,
+) -> Result<Option<u64>, DwarfError> {
+    if encoding == DW_EH_PE_OMIT {
+        return Ok(None);
+    }
+    let position = bases.section.wrapping_add(r.offset() as u64);
+    let value = match encoding & 0x0f {
+        DW_EH_PE_ABSPTR => r.word(address_size == 8)?,
+        DW_EH_PE_ULEB128 => r.uleb128()?,
+        DW_EH_PE_UDATA2 => r.u16()? as u64,
+        DW_EH_PE_UDATA4 => r.u32()? as u64,
+        DW_EH_PE_UDATA8 => r.u64()?,
+        DW_EH_PE_SLEB128 => r.sleb128()? as u64,
+        DW_EH_PE_SDATA2 => r.u16()? as i16 as u64,
+        DW_EH_PE_SDATA4 => r.u32()? as i32 as u64,
+        DW_EH_PE_SDATA8 => r.u64()?,
+        _ => return Err(DwarfError::UnsupportedPointerEncoding(encoding)),
+    };
+    let base = match encoding & 0x70 {
+        DW_EH_PE_ABSPTR => Some(0),
+        DW_EH_PE_PCREL => Some(position),
+        DW_EH_PE_TEXTREL => bases.text,
+        DW_EH_PE_DATAREL => bases.data,
+        DW_EH_PE_FUNCREL => bases.function,
+        _ => None,
+    };
+    match base {
+        Some(base) => Ok(Some(base.wrapping_add(value))),
+        None => Err(DwarfError::UnsupportedPointerEncoding(encoding)),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::line::{DW_LNE_END_SEQUENCE, DW_LNE_SET_ADDRESS};
+
+    /// A DWARF 4 unit with 8 bytes addresses and its abbreviations at offset 0.
+    pub(crate) fn unit_4(entries: &[u8]) -> Vec<u8> {
+        let mut data = ((entries.len() + 7) as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&[4, 0, 0, 0, 0, 0, 8]);
+        data.extend_from_slice(entries);
+        data
+    }
+
+    /// A DWARF 4 program of src/main.c, line_base -5, line_range 14, opcode_base 13.
+    pub(crate) fn line_program(minimum_instruction_length: u8, opcodes: &[u8]) -> Vec<u8> {
+        let mut header = vec![minimum_instruction_length, 1, 1, (-5i8) as u8, 14, 13];
+        header.extend_from_slice(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        header.extend_from_slice(b"src\0\0main.c\0\x01\0\0\0");
+        let mut unit = 4u16.to_le_bytes().to_vec();
+        unit.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        unit.extend_from_slice(&header);
+        unit.extend_from_slice(opcodes);
+        let mut data = (unit.len() as u32).to_le_bytes().to_vec();
+        data.extend_from_slice(&unit);
+        data
+    }
+
+    pub(crate) fn set_address(address: u64) -> Vec<u8> {
+        let mut opcode = vec![0, 9, DW_LNE_SET_ADDRESS];
+        opcode.extend_from_slice(&address.to_le_bytes());
+        opcode
+    }
+
+    pub(crate) const END_SEQUENCE: [u8; 3] = [0, 1, DW_LNE_END_SEQUENCE];
+}
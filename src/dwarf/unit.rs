@@ -0,0 +1,841 @@
+// compilation units of .debug_info
+// a unit is a tree of entries (DIEs), each entry is a tag and attributes whose encoding is
+// described by the abbreviation table of the unit
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::{DwarfError, DwarfSections, read_initial_length};
+use crate::macho::reader::{self, Reader};
+
+pub const DW_UT_COMPILE: u8 = 0x01;
+pub const DW_UT_TYPE: u8 = 0x02;
+pub const DW_UT_SKELETON: u8 = 0x04;
+pub const DW_UT_SPLIT_COMPILE: u8 = 0x05;
+pub const DW_UT_SPLIT_TYPE: u8 = 0x06;
+
+pub const DW_TAG_INLINED_SUBROUTINE: u64 = 0x1d;
+pub const DW_TAG_SUBPROGRAM: u64 = 0x2e;
+
+pub const DW_AT_NAME: u64 = 0x03;
+pub const DW_AT_STMT_LIST: u64 = 0x10;
+pub const DW_AT_LOW_PC: u64 = 0x11;
+pub const DW_AT_HIGH_PC: u64 = 0x12;
+pub const DW_AT_COMP_DIR: u64 = 0x1b;
+pub const DW_AT_ABSTRACT_ORIGIN: u64 = 0x31;
+pub const DW_AT_SPECIFICATION: u64 = 0x47;
+pub const DW_AT_RANGES: u64 = 0x55;
+pub const DW_AT_CALL_COLUMN: u64 = 0x57;
+pub const DW_AT_CALL_FILE: u64 = 0x58;
+pub const DW_AT_CALL_LINE: u64 = 0x59;
+pub const DW_AT_LINKAGE_NAME: u64 = 0x6e;
+pub const DW_AT_STR_OFFSETS_BASE: u64 = 0x72;
+pub const DW_AT_ADDR_BASE: u64 = 0x73;
+pub const DW_AT_RNGLISTS_BASE: u64 = 0x74;
+pub const DW_AT_MIPS_LINKAGE_NAME: u64 = 0x2007;
+pub const DW_AT_GNU_ADDR_BASE: u64 = 0x2133;
+
+pub const DW_FORM_ADDR: u64 = 0x01;
+pub const DW_FORM_BLOCK2: u64 = 0x03;
+pub const DW_FORM_BLOCK4: u64 = 0x04;
+pub const DW_FORM_DATA2: u64 = 0x05;
+pub const DW_FORM_DATA4: u64 = 0x06;
+pub const DW_FORM_DATA8: u64 = 0x07;
+pub const DW_FORM_STRING: u64 = 0x08;
+pub const DW_FORM_BLOCK: u64 = 0x09;
+pub const DW_FORM_BLOCK1: u64 = 0x0a;
+pub const DW_FORM_DATA1: u64 = 0x0b;
+pub const DW_FORM_FLAG: u64 = 0x0c;
+pub const DW_FORM_SDATA: u64 = 0x0d;
+pub const DW_FORM_STRP: u64 = 0x0e;
+pub const DW_FORM_UDATA: u64 = 0x0f;
+pub const DW_FORM_REF_ADDR: u64 = 0x10;
+pub const DW_FORM_REF1: u64 = 0x11;
+pub const DW_FORM_REF2: u64 = 0x12;
+pub const DW_FORM_REF4: u64 = 0x13;
+pub const DW_FORM_REF8: u64 = 0x14;
+pub const DW_FORM_REF_UDATA: u64 = 0x15;
+pub const DW_FORM_INDIRECT: u64 = 0x16;
+pub const DW_FORM_SEC_OFFSET: u64 = 0x17;
+pub const DW_FORM_EXPRLOC: u64 = 0x18;
+pub const DW_FORM_FLAG_PRESENT: u64 = 0x19;
+pub const DW_FORM_STRX: u64 = 0x1a;
+pub const DW_FORM_ADDRX: u64 = 0x1b;
+pub const DW_FORM_REF_SUP4: u64 = 0x1c;
+pub const DW_FORM_STRP_SUP: u64 = 0x1d;
+pub const DW_FORM_DATA16: u64 = 0x1e;
+pub const DW_FORM_LINE_STRP: u64 = 0x1f;
+pub const DW_FORM_REF_SIG8: u64 = 0x20;
+pub const DW_FORM_IMPLICIT_CONST: u64 = 0x21;
+pub const DW_FORM_LOCLISTX: u64 = 0x22;
+pub const DW_FORM_RNGLISTX: u64 = 0x23;
+pub const DW_FORM_REF_SUP8: u64 = 0x24;
+pub const DW_FORM_STRX1: u64 = 0x25;
+pub const DW_FORM_STRX2: u64 = 0x26;
+pub const DW_FORM_STRX3: u64 = 0x27;
+pub const DW_FORM_STRX4: u64 = 0x28;
+pub const DW_FORM_ADDRX1: u64 = 0x29;
+pub const DW_FORM_ADDRX2: u64 = 0x2a;
+pub const DW_FORM_ADDRX3: u64 = 0x2b;
+pub const DW_FORM_ADDRX4: u64 = 0x2c;
+pub const DW_FORM_GNU_ADDR_INDEX: u64 = 0x1f01;
+pub const DW_FORM_GNU_STR_INDEX: u64 = 0x1f02;
+pub const DW_FORM_GNU_REF_ALT: u64 = 0x1f20;
+pub const DW_FORM_GNU_STRP_ALT: u64 = 0x1f21;
+
+// entries of the DWARF 5 range lists of .debug_rnglists
+pub const DW_RLE_END_OF_LIST: u8 = 0x00;
+pub const DW_RLE_BASE_ADDRESSX: u8 = 0x01;
+pub const DW_RLE_STARTX_ENDX: u8 = 0x02;
+pub const DW_RLE_STARTX_LENGTH: u8 = 0x03;
+pub const DW_RLE_OFFSET_PAIR: u8 = 0x04;
+pub const DW_RLE_BASE_ADDRESS: u8 = 0x05;
+pub const DW_RLE_START_END: u8 = 0x06;
+pub const DW_RLE_START_LENGTH: u8 = 0x07;
+
+/// How one attribute of an abbreviation is encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeSpec {
+    pub name: u64,
+    pub form: u64,
+    /// Value of DW_FORM_implicit_const attributes, stored in the abbreviation.
+    pub implicit_const: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Abbreviation {
+    pub tag: u64,
+    pub has_children: bool,
+    pub attributes: Vec<AttributeSpec>,
+}
+
+/// Abbreviations of a unit by code.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Abbreviations {
+    map: HashMap<u64, Abbreviation>,
+}
+
+impl Abbreviations {
+    pub fn parse(sections: &DwarfSections, offset: usize) -> Result<Abbreviations, DwarfError> {
+        let mut r = Reader::at(
+            sections.debug_abbrev,
+            offset,
+            sections.endian,
+            "abbreviations",
+        );
+        let mut map = HashMap::new();
+        loop {
+            let code = r.uleb128()?;
+            if code == 0 {
+                break;
+            }
+            let tag = r.uleb128()?;
+            let has_children = r.u8()? != 0;
+            let mut attributes = Vec::new();
+            loop {
+                let name = r.uleb128()?;
+                let form = r.uleb128()?;
+                if name == 0 && form == 0 {
+                    break;
+                }
+                let implicit_const = if form == DW_FORM_IMPLICIT_CONST {
+                    r.sleb128()?
+                } else {
+                    0
+                };
+                attributes.push(AttributeSpec {
+                    name,
+                    form,
+                    implicit_const,
+                });
+            }
+            map.insert(
+                code,
+                Abbreviation {
+                    tag,
+                    has_children,
+                    attributes,
+                },
+            );
+        }
+        Ok(Abbreviations { map })
+    }
+
+    pub fn get(&self, code: u64) -> Option<&Abbreviation> {
+        self.map.get(&code)
+    }
+}
+
+/// Header of a unit, offsets are relative to the start of .debug_info.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitHeader {
+    pub offset: usize,
+    pub version: u16,
+    pub unit_type: u8,
+    pub is_64: bool,
+    pub address_size: u8,
+    pub abbrev_offset: usize,
+    /// Offset of the first entry.
+    pub entries_offset: usize,
+    /// Offset of the first byte after the unit.
+    pub end: usize,
+}
+
+impl UnitHeader {
+    pub fn parse(sections: &DwarfSections, offset: usize) -> Result<UnitHeader, DwarfError> {
+        let mut r = Reader::at(sections.debug_info, offset, sections.endian, "unit header");
+        let (length, is_64) = read_initial_length(&mut r)?;
+        let end = r.offset().saturating_add(length as usize);
+        let version = r.u16()?;
+        if !(2..=5).contains(&version) {
+            return Err(DwarfError::UnsupportedVersion {
+                what: "compilation unit",
+                version,
+            });
+        }
+        let read_offset = |r: &mut Reader| -> Result<usize, DwarfError> {
+            Ok(if is_64 { r.u64()? } else { r.u32()? as u64 } as usize)
+        };
+        let (unit_type, address_size, abbrev_offset) = if version >= 5 {
+            let unit_type = r.u8()?;
+            let address_size = r.u8()?;
+            let abbrev_offset = read_offset(&mut r)?;
+            match unit_type {
+                DW_UT_SKELETON | DW_UT_SPLIT_COMPILE => r.skip(8)?,
+                DW_UT_TYPE | DW_UT_SPLIT_TYPE => {
+                    r.skip(8)?;
+                    read_offset(&mut r)?;
+                }
+                _ => {}
+            }
+            (unit_type, address_size, abbrev_offset)
+        } else {
+            let abbrev_offset = read_offset(&mut r)?;
+            (DW_UT_COMPILE, r.u8()?, abbrev_offset)
+        };
+        Ok(UnitHeader {
+            offset,
+            version,
+            unit_type,
+            is_64,
+            address_size,
+            abbrev_offset,
+            entries_offset: r.offset(),
+            end,
+        })
+    }
+
+    /// Every unit header of .debug_info.
+    pub fn all(sections: &DwarfSections) -> Result<Vec<UnitHeader>, DwarfError> {
+        let mut headers = Vec::new();
+        let mut offset = 0;
+        while offset < sections.debug_info.len() {
+            let header = UnitHeader::parse(sections, offset)?;
+            offset = header.end;
+            headers.push(header);
+        }
+        Ok(headers)
+    }
+}
+
+/// Value of an attribute, indices and offsets into other sections are left unresolved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeValue<'a> {
+    Address(u64),
+    AddressIndex(u64),
+    Unsigned(u64),
+    Signed(i64),
+    Flag(bool),
+    String(&'a str),
+    StringIndex(u64),
+    /// Offset of an entry from the start of the unit.
+    UnitReference(u64),
+    /// Offset of an entry from the start of .debug_info.
+    InfoReference(u64),
+    SectionOffset(u64),
+    RangeListIndex(u64),
+    LocationListIndex(u64),
+    Block(&'a [u8]),
+    /// References to other files (supplementary objects, type units).
+    External,
+}
+
+impl AttributeValue<'_> {
+    pub fn unsigned(&self) -> Option<u64> {
+        match *self {
+            AttributeValue::Unsigned(value) | AttributeValue::SectionOffset(value) => Some(value),
+            AttributeValue::Signed(value) => u64::try_from(value).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// A debugging information entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry<'a> {
+    pub offset: usize,
+    /// Nesting level, the unit entry is at depth 0.
+    pub depth: usize,
+    pub tag: u64,
+    pub has_children: bool,
+    pub attributes: Vec<(u64, AttributeValue<'a>)>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn attribute(&self, name: u64) -> Option<AttributeValue<'a>> {
+        self.attributes
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| *value)
+    }
+}
+
+/// A unit with what is needed to decode its entries.
+#[derive(Debug, Clone)]
+pub struct Unit<'a> {
+    pub header: UnitHeader,
+    pub sections: DwarfSections<'a>,
+    abbreviations: Abbreviations,
+    pub name: Option<&'a str>,
+    pub comp_dir: Option<&'a str>,
+    pub stmt_list: Option<u64>,
+    pub low_pc: u64,
+    pub str_offsets_base: u64,
+    pub addr_base: u64,
+    pub rnglists_base: u64,
+}
+
+impl<'a> Unit<'a> {
+    /// Reads the abbreviations and the unit entry.
+    pub fn parse(sections: &DwarfSections<'a>, header: UnitHeader) -> Result<Unit<'a>, DwarfError> {
+        let mut unit = Unit {
+            header,
+            sections: *sections,
+            abbreviations: Abbreviations::parse(sections, header.abbrev_offset)?,
+            name: None,
+            comp_dir: None,
+            stmt_list: None,
+            low_pc: 0,
+            str_offsets_base: 0,
+            addr_base: 0,
+            rnglists_base: 0,
+        };
+        let Some(root) = unit.entry_at(header.entries_offset)? else {
+            return Ok(unit);
+        };
+        // the bases are needed to resolve the other attributes of the entry
+        let base = |name| root.attribute(name).and_then(|v| v.unsigned());
+        unit.str_offsets_base = base(DW_AT_STR_OFFSETS_BASE).unwrap_or(0);
+        unit.addr_base = base(DW_AT_ADDR_BASE)
+            .or_else(|| base(DW_AT_GNU_ADDR_BASE))
+            .unwrap_or(0);
+        unit.rnglists_base = base(DW_AT_RNGLISTS_BASE).unwrap_or(0);
+        unit.name = root.attribute(DW_AT_NAME).and_then(|v| unit.string(&v));
+        unit.comp_dir = root.attribute(DW_AT_COMP_DIR).and_then(|v| unit.string(&v));
+        unit.stmt_list = base(DW_AT_STMT_LIST);
+        unit.low_pc = root
+            .attribute(DW_AT_LOW_PC)
+            .and_then(|v| unit.address(&v))
+            .unwrap_or(0);
+        Ok(unit)
+    }
+
+    /// Every unit of the sections.
+    pub fn all(sections: &DwarfSections<'a>) -> Result<Vec<Unit<'a>>, DwarfError> {
+        UnitHeader::all(sections)?
+            .into_iter()
+            .map(|header| Unit::parse(sections, header))
+            .collect()
+    }
+
+    /// The entry at `offset` of .debug_info, `None` for a null entry.
+    pub fn entry_at(&self, offset: usize) -> Result<Option<Entry<'a>>, DwarfError> {
+        let mut r = self.reader(offset);
+        self.read_entry(&mut r, 0)
+    }
+
+    /// Every entry of the unit in depth-first order.
+    pub fn entries(&self) -> Result<Vec<Entry<'a>>, DwarfError> {
+        let mut r = self.reader(self.header.entries_offset);
+        let mut entries = Vec::new();
+        let mut depth = 0;
+        while !r.is_empty() {
+            match self.read_entry(&mut r, depth)? {
+                Some(entry) => {
+                    if entry.has_children {
+                        depth += 1;
+                    }
+                    entries.push(entry);
+                }
+                // a null entry ends the children of the parent
+                None => match depth.checked_sub(1) {
+                    Some(parent) => depth = parent,
+                    None => break,
+                },
+            }
+        }
+        Ok(entries)
+    }
+
+    fn reader(&self, offset: usize) -> Reader<'a> {
+        let unit = &self.sections.debug_info[..self.header.end.min(self.sections.debug_info.len())];
+        Reader::at(
+            unit,
+            offset,
+            self.sections.endian,
+            "debugging information entry",
+        )
+    }
+
+    fn read_entry(
+        &self,
+        r: &mut Reader<'a>,
+        depth: usize,
+    ) -> Result<Option<Entry<'a>>, DwarfError> {
+        let offset = r.offset();
+        let code = r.uleb128()?;
+        if code == 0 {
+            return Ok(None);
+        }
+        let abbreviation = self
+            .abbreviations
+            .get(code)
+            .ok_or(DwarfError::UnknownAbbreviation { code, offset })?;
+        let mut attributes = Vec::with_capacity(abbreviation.attributes.len());
+        for spec in &abbreviation.attributes {
+            let value = self.read_value(r, spec.form, spec.implicit_const)?;
+            attributes.push((spec.name, value));
+        }
+        Ok(Some(Entry {
+            offset,
+            depth,
+            tag: abbreviation.tag,
+            has_children: abbreviation.has_children,
+            attributes,
+        }))
+    }
+
+    fn read_value(
+        &self,
+        r: &mut Reader<'a>,
+        form: u64,
+        implicit_const: i64,
+    ) -> Result<AttributeValue<'a>, DwarfError> {
+        let header = &self.header;
+        let offset = |r: &mut Reader| -> Result<u64, DwarfError> {
+            Ok(if header.is_64 {
+                r.u64()?
+            } else {
+                r.u32()? as u64
+            })
+        };
+        let u24 = |r: &mut Reader| -> Result<u64, DwarfError> {
+            let bytes = r.take(3)?;
+            Ok(match r.endian() {
+                reader::Endian::Little => {
+                    bytes[0] as u64 | (bytes[1] as u64) << 8 | (bytes[2] as u64) << 16
+                }
+                reader::Endian::Big => {
+                    bytes[2] as u64 | (bytes[1] as u64) << 8 | (bytes[0] as u64) << 16
+                }
+            })
+        };
+        Ok(match form {
+            DW_FORM_ADDR => AttributeValue::Address(r.word(header.address_size == 8)?),
+            DW_FORM_BLOCK1 => {
+                let length = r.u8()? as usize;
+                AttributeValue::Block(r.take(length)?)
+            }
+            DW_FORM_BLOCK2 => {
+                let length = r.u16()? as usize;
+                AttributeValue::Block(r.take(length)?)
+            }
+            DW_FORM_BLOCK4 => {
+                let length = r.u32()? as usize;
+                AttributeValue::Block(r.take(length)?)
+            }
+            DW_FORM_BLOCK | DW_FORM_EXPRLOC => {
+                let length = r.uleb128()? as usize;
+                AttributeValue::Block(r.take(length)?)
+            }
+            DW_FORM_DATA1 => AttributeValue::Unsigned(r.u8()? as u64),
+            DW_FORM_DATA2 => AttributeValue::Unsigned(r.u16()? as u64),
+            DW_FORM_DATA4 => AttributeValue::Unsigned(r.u32()? as u64),
+            DW_FORM_DATA8 => AttributeValue::Unsigned(r.u64()?),
+            DW_FORM_DATA16 => AttributeValue::Block(r.take(16)?),
+            DW_FORM_SDATA => AttributeValue::Signed(r.sleb128()?),
+            DW_FORM_UDATA => AttributeValue::Unsigned(r.uleb128()?),
+            DW_FORM_IMPLICIT_CONST => AttributeValue::Signed(implicit_const),
+            DW_FORM_FLAG => AttributeValue::Flag(r.u8()? != 0),
+            DW_FORM_FLAG_PRESENT => AttributeValue::Flag(true),
+            DW_FORM_STRING => AttributeValue::String(r.cstr()?),
+            DW_FORM_STRP | DW_FORM_LINE_STRP => {
+                let strings = if form == DW_FORM_STRP {
+                    self.sections.debug_str
+                } else {
+                    self.sections.debug_line_str
+                };
+                let offset = offset(r)? as usize;
+                AttributeValue::String(reader::cstr(strings, offset).unwrap_or_default())
+            }
+            DW_FORM_STRX | DW_FORM_GNU_STR_INDEX => AttributeValue::StringIndex(r.uleb128()?),
+            DW_FORM_STRX1 => AttributeValue::StringIndex(r.u8()? as u64),
+            DW_FORM_STRX2 => AttributeValue::StringIndex(r.u16()? as u64),
+            DW_FORM_STRX3 => AttributeValue::StringIndex(u24(r)?),
+            DW_FORM_STRX4 => AttributeValue::StringIndex(r.u32()? as u64),
+            DW_FORM_ADDRX | DW_FORM_GNU_ADDR_INDEX => AttributeValue::AddressIndex(r.uleb128()?),
+            DW_FORM_ADDRX1 => AttributeValue::AddressIndex(r.u8()? as u64),
+            DW_FORM_ADDRX2 => AttributeValue::AddressIndex(r.u16()? as u64),
+            DW_FORM_ADDRX3 => AttributeValue::AddressIndex(u24(r)?),
+            DW_FORM_ADDRX4 => AttributeValue::AddressIndex(r.u32()? as u64),
+            DW_FORM_REF1 => AttributeValue::UnitReference(r.u8()? as u64),
+            DW_FORM_REF2 => AttributeValue::UnitReference(r.u16()? as u64),
+            DW_FORM_REF4 => AttributeValue::UnitReference(r.u32()? as u64),
+            DW_FORM_REF8 => AttributeValue::UnitReference(r.u64()?),
+            DW_FORM_REF_UDATA => AttributeValue::UnitReference(r.uleb128()?),
+            DW_FORM_REF_ADDR => {
+                // an address sized offset in DWARF 2
+                if header.version == 2 {
+                    AttributeValue::InfoReference(r.word(header.address_size == 8)?)
+                } else {
+                    AttributeValue::InfoReference(offset(r)?)
+                }
+            }
+            DW_FORM_SEC_OFFSET => AttributeValue::SectionOffset(offset(r)?),
+            DW_FORM_LOCLISTX => AttributeValue::LocationListIndex(r.uleb128()?),
+            DW_FORM_RNGLISTX => AttributeValue::RangeListIndex(r.uleb128()?),
+            DW_FORM_REF_SIG8 => {
+                r.skip(8)?;
+                AttributeValue::External
+            }
+            DW_FORM_REF_SUP4 => {
+                r.skip(4)?;
+                AttributeValue::External
+            }
+            DW_FORM_REF_SUP8 => {
+                r.skip(8)?;
+                AttributeValue::External
+            }
+            DW_FORM_STRP_SUP | DW_FORM_GNU_REF_ALT | DW_FORM_GNU_STRP_ALT => {
+                offset(r)?;
+                AttributeValue::External
+            }
+            DW_FORM_INDIRECT => {
+                // the form comes first, nothing needs it to be indirect again
+                let form = r.uleb128()?;
+                if form == DW_FORM_INDIRECT {
+                    return Err(DwarfError::UnsupportedForm(form));
+                }
+                return self.read_value(r, form, implicit_const);
+            }
+            _ => return Err(DwarfError::UnsupportedForm(form)),
+        })
+    }
+
+    /// Resolves a string attribute, following the string offsets table for indices.
+    pub fn string(&self, value: &AttributeValue<'a>) -> Option<&'a str> {
+        match *value {
+            AttributeValue::String(string) => Some(string),
+            AttributeValue::StringIndex(index) => {
+                let size = if self.header.is_64 { 8 } else { 4 };
+                let position = table_position(self.str_offsets_base, index, size)?;
+                let mut r = Reader::at(
+                    self.sections.debug_str_offsets,
+                    position,
+                    self.sections.endian,
+                    "string offsets",
+                );
+                let offset = if self.header.is_64 {
+                    r.u64().ok()?
+                } else {
+                    r.u32().ok()? as u64
+                };
+                reader::cstr(self.sections.debug_str, offset as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves an address attribute, following the address table for indices.
+    pub fn address(&self, value: &AttributeValue<'a>) -> Option<u64> {
+        match *value {
+            AttributeValue::Address(address) => Some(address),
+            AttributeValue::AddressIndex(index) => {
+                let size = self.header.address_size as u64;
+                let position = table_position(self.addr_base, index, size)?;
+                let mut r = Reader::at(
+                    self.sections.debug_addr,
+                    position,
+                    self.sections.endian,
+                    "address table",
+                );
+                r.word(size == 8).ok()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the entry at `offset` of .debug_info belongs to the unit.
+    pub fn contains(&self, offset: usize) -> bool {
+        offset >= self.header.entries_offset && offset < self.header.end
+    }
+
+    /// Address ranges covered by an entry, from DW_AT_low_pc and DW_AT_high_pc or from the
+    /// range list of DW_AT_ranges. Empty ranges are dropped.
+    pub fn ranges(&self, entry: &Entry<'a>) -> Result<Vec<Range<u64>>, DwarfError> {
+        let mut ranges = Vec::new();
+        if let Some(value) = entry.attribute(DW_AT_RANGES) {
+            match value {
+                AttributeValue::RangeListIndex(index) => {
+                    let offset = self.range_list_offset(index)?;
+                    self.read_rnglist(offset, &mut ranges)?;
+                }
+                _ => {
+                    let Some(offset) = value.unsigned() else {
+                        return Ok(ranges);
+                    };
+                    if self.header.version >= 5 {
+                        self.read_rnglist(offset as usize, &mut ranges)?;
+                    } else {
+                        self.read_ranges(offset as usize, &mut ranges)?;
+                    }
+                }
+            }
+        } else if let Some(low) = entry.attribute(DW_AT_LOW_PC).and_then(|v| self.address(&v)) {
+            // DW_AT_high_pc is an address, or an offset from DW_AT_low_pc since DWARF 4
+            let high = match entry.attribute(DW_AT_HIGH_PC) {
+                Some(value @ (AttributeValue::Address(_) | AttributeValue::AddressIndex(_))) => {
+                    self.address(&value)
+                }
+                Some(value) => value.unsigned().map(|size| low.wrapping_add(size)),
+                None => None,
+            };
+            if let Some(high) = high {
+                ranges.push(low..high);
+            }
+        }
+        ranges.retain(|range| range.start < range.end);
+        Ok(ranges)
+    }
+
+    // a DW_FORM_rnglistx index goes through the offsets table after DW_AT_rnglists_base
+    fn range_list_offset(&self, index: u64) -> Result<usize, DwarfError> {
+        let size = if self.header.is_64 { 8 } else { 4 };
+        let position = table_position(self.rnglists_base, index, size)
+            .ok_or(DwarfError::Overflow("range list index"))?;
+        let mut r = Reader::at(
+            self.sections.debug_rnglists,
+            position,
+            self.sections.endian,
+            "range list offsets",
+        );
+        let offset = if self.header.is_64 {
+            r.u64()?
+        } else {
+            r.u32()? as u64
+        };
+        // the offsets are relative to the base as well
+        table_position(self.rnglists_base, offset, 1)
+            .ok_or(DwarfError::Overflow("range list offset"))
+    }
+
+    // DWARF 4 and older: pairs of addresses relative to the base address, (0, 0) ends the list
+    // and (-1, address) changes the base address
+    fn read_ranges(&self, offset: usize, ranges: &mut Vec<Range<u64>>) -> Result<(), DwarfError> {
+        let is_64 = self.header.address_size == 8;
+        let max = if is_64 { u64::MAX } else { u32::MAX as u64 };
+        let mut r = Reader::at(
+            self.sections.debug_ranges,
+            offset,
+            self.sections.endian,
+            "range list",
+        );
+        let mut base = self.low_pc;
+        loop {
+            let start = r.word(is_64)?;
+            let end = r.word(is_64)?;
+            if start == 0 && end == 0 {
+                return Ok(());
+            }
+            if start == max {
+                base = end;
+            } else {
+                ranges.push(base.wrapping_add(start)..base.wrapping_add(end));
+            }
+        }
+    }
+
+    fn read_rnglist(&self, offset: usize, ranges: &mut Vec<Range<u64>>) -> Result<(), DwarfError> {
+        let is_64 = self.header.address_size == 8;
+        let mut r = Reader::at(
+            self.sections.debug_rnglists,
+            offset,
+            self.sections.endian,
+            "range list",
+        );
+        let address = |index: u64| {
+            self.address(&AttributeValue::AddressIndex(index))
+                .ok_or(DwarfError::UnavailableValue)
+        };
+        let mut base = self.low_pc;
+        loop {
+            match r.u8()? {
+                DW_RLE_END_OF_LIST => return Ok(()),
+                DW_RLE_BASE_ADDRESSX => base = address(r.uleb128()?)?,
+                DW_RLE_STARTX_ENDX => {
+                    let start = address(r.uleb128()?)?;
+                    ranges.push(start..address(r.uleb128()?)?);
+                }
+                DW_RLE_STARTX_LENGTH => {
+                    let start = address(r.uleb128()?)?;
+                    ranges.push(start..start.wrapping_add(r.uleb128()?));
+                }
+                DW_RLE_OFFSET_PAIR => {
+                    let start = base.wrapping_add(r.uleb128()?);
+                    ranges.push(start..base.wrapping_add(r.uleb128()?));
+                }
+                DW_RLE_BASE_ADDRESS => base = r.word(is_64)?,
+                DW_RLE_START_END => {
+                    let start = r.word(is_64)?;
+                    ranges.push(start..r.word(is_64)?);
+                }
+                DW_RLE_START_LENGTH => {
+                    let start = r.word(is_64)?;
+                    ranges.push(start..start.wrapping_add(r.uleb128()?));
+                }
+                kind => return Err(DwarfError::UnknownRangeListEntry(kind)),
+            }
+        }
+    }
+}
+
+// position of entry `index` of a table of `size` byte entries starting at `base`, the bases and
+// indices come from the file
+fn table_position(base: u64, index: u64, size: u64) -> Option<usize> {
+    let position = index.checked_mul(size)?.checked_add(base)?;
+    usize::try_from(position).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::reader::Endian;
+
+    const ABBREVIATIONS: &[u8] = &[
+        1, 0x11, 0, // compile unit without children
+        0x72, 0x17, // DW_AT_str_offsets_base, DW_FORM_sec_offset
+        0x74, 0x17, // DW_AT_rnglists_base, DW_FORM_sec_offset
+        0x73, 0x17, // DW_AT_addr_base, DW_FORM_sec_offset
+        0x03, 0x25, // DW_AT_name, DW_FORM_strx1
+        0x55, 0x23, // DW_AT_ranges, DW_FORM_rnglistx
+        0, 0, 0,
+    ];
+
+    // a DWARF 5 unit reading its name and ranges through the index tables
+    struct Tables {
+        debug_info: Vec<u8>,
+        debug_str_offsets: Vec<u8>,
+        debug_addr: Vec<u8>,
+        debug_rnglists: Vec<u8>,
+    }
+
+    impl Tables {
+        fn new(range_list_index: &[u8]) -> Tables {
+            let mut entries = vec![1];
+            for base in [8u32, 12, 8] {
+                entries.extend_from_slice(&base.to_le_bytes());
+            }
+            entries.push(0);
+            entries.extend_from_slice(range_list_index);
+            let mut debug_info = ((entries.len() + 8) as u32).to_le_bytes().to_vec();
+            debug_info.extend_from_slice(&[5, 0, DW_UT_COMPILE, 8, 0, 0, 0, 0]);
+            debug_info.extend_from_slice(&entries);
+
+            let debug_str_offsets =
+                [&8u32.to_le_bytes()[..], &[5, 0, 0, 0], &1u32.to_le_bytes()].concat();
+            let debug_addr = [
+                &12u32.to_le_bytes()[..],
+                &[5, 0, 8, 0],
+                &0x2000u64.to_le_bytes(),
+            ]
+            .concat();
+
+            let mut lists = 4u32.to_le_bytes().to_vec();
+            lists.push(DW_RLE_START_LENGTH);
+            lists.extend_from_slice(&0x1000u64.to_le_bytes());
+            lists.extend_from_slice(&[0x20, DW_RLE_STARTX_LENGTH, 0, 0x10, DW_RLE_END_OF_LIST]);
+            let mut debug_rnglists = ((lists.len() + 8) as u32).to_le_bytes().to_vec();
+            debug_rnglists.extend_from_slice(&[5, 0, 8, 0]);
+            debug_rnglists.extend_from_slice(&1u32.to_le_bytes());
+            debug_rnglists.extend_from_slice(&lists);
+            Tables {
+                debug_info,
+                debug_str_offsets,
+                debug_addr,
+                debug_rnglists,
+            }
+        }
+
+        fn sections(&self) -> DwarfSections<'_> {
+            DwarfSections {
+                endian: Endian::Little,
+                debug_info: &self.debug_info,
+                debug_abbrev: ABBREVIATIONS,
+                debug_line: &[],
+                debug_line_str: &[],
+                debug_str: b"\0main.c\0",
+                debug_str_offsets: &self.debug_str_offsets,
+                debug_addr: &self.debug_addr,
+                debug_ranges: &[],
+                debug_rnglists: &self.debug_rnglists,
+            }
+        }
+    }
+
+    fn root_ranges(tables: &Tables) -> Result<(Option<String>, Vec<Range<u64>>), DwarfError> {
+        let sections = tables.sections();
+        let headers = UnitHeader::all(&sections)?;
+        assert_eq!(headers.len(), 1);
+        let unit = Unit::parse(&sections, headers[0])?;
+        let root = unit.entry_at(headers[0].entries_offset)?.unwrap();
+        Ok((unit.name.map(str::to_string), unit.ranges(&root)?))
+    }
+
+    #[test]
+    fn indexed_strings_and_ranges() {
+        let (name, ranges) = root_ranges(&Tables::new(&[0])).unwrap();
+        assert_eq!(name.as_deref(), Some("main.c"));
+        assert_eq!(ranges, vec![0x1000..0x1020, 0x2000..0x2010]);
+    }
+
+    #[test]
+    fn hostile_indices_overflow_into_errors() {
+        let index = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert_eq!(
+            root_ranges(&Tables::new(&index)),
+            Err(DwarfError::Overflow("range list index"))
+        );
+        assert_eq!(table_position(u64::MAX, 1, 1), None);
+        assert_eq!(table_position(8, 2, 4), Some(16));
+    }
+
+    #[test]
+    fn indirect_forms_are_read_once() {
+        let tables = Tables::new(&[0]);
+        let sections = tables.sections();
+        let header = UnitHeader::all(&sections).unwrap()[0];
+        let unit = Unit::parse(&sections, header).unwrap();
+        let read = |bytes: &'static [u8]| {
+            let mut r = Reader::new(bytes, Endian::Little, "test");
+            unit.read_value(&mut r, DW_FORM_INDIRECT, 0)
+        };
+        assert_eq!(
+            read(&[DW_FORM_DATA1 as u8, 7]),
+            Ok(AttributeValue::Unsigned(7))
+        );
+        assert_eq!(
+            read(&[DW_FORM_INDIRECT as u8; 100_000]),
+            Err(DwarfError::UnsupportedForm(DW_FORM_INDIRECT))
+        );
+    }
+}
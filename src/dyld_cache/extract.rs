@@ -0,0 +1,402 @@
+// extraction of the images of the cache
+// the builder merges the __LINKEDIT of every image in shared tables and moves the segments of an
+// image apart, in different mappings and often in different subcaches, so the image in the cache
+// cannot be parsed as it is
+// an image is rebuilt by laying out its segments one after the other in a new file and by copying
+// the parts of the shared __LINKEDIT it uses to a __LINKEDIT of its own, its symbol table gets a
+// string table of its own and the local symbols stripped by the builder
+
+use crate::macho::header::{MACH_HEADER_64_SIZE, MH_DYLIB_IN_CACHE, MachHeader};
+use crate::macho::load_command::{
+    self, LC_CODE_SIGNATURE, LC_DATA_IN_CODE, LC_DYLD_CHAINED_FIXUPS, LC_DYLD_EXPORTS_TRIE,
+    LC_DYLD_INFO, LC_DYLD_INFO_ONLY, LC_DYSYMTAB, LC_FUNCTION_STARTS, LC_SEGMENT, LC_SEGMENT_64,
+    LC_SEGMENT_SPLIT_INFO, LC_SYMTAB, RawLoadCommand,
+};
+use crate::macho::reader::{self, Endian, Reader};
+use crate::macho::segment::{S_GB_ZEROFILL, S_ZEROFILL, SECTION_64_SIZE, SECTION_SIZE};
+use crate::macho::symtab::{NLIST_64_SIZE, NLIST_SIZE, SymtabCommand};
+use crate::macho::{MachO, MachOError};
+
+use super::{CacheError, CacheImage, DyldCache};
+
+// the segments are aligned on the largest page size of the supported architectures
+const PAGE_SIZE: u64 = 0x4000;
+const LINKEDIT: &str = "__LINKEDIT";
+const TEXT: &str = "__TEXT";
+
+// sizes of the entries of the LC_DYSYMTAB tables
+const TOC_ENTRY_SIZE: usize = 8;
+const MODULE_SIZE: usize = 52;
+const MODULE_64_SIZE: usize = 56;
+const RELOCATION_SIZE: usize = 8;
+
+// offsets of the fields patched in the commands, from the start of the command
+const SEGMENT_FIELDS_OFFSET: usize = 24;
+const SECTION_OFFSET_64: usize = 48;
+const SECTION_OFFSET: usize = 40;
+const HEADER_FLAGS_OFFSET: usize = 24;
+
+/// The file range of a segment in the cache and in the rebuilt image.
+#[derive(Debug, Clone)]
+struct SegmentLayout<'a> {
+    command: RawLoadCommand<'a>,
+    name: String,
+    vmaddr: u64,
+    vmsize: u64,
+    fileoff: u64,
+    filesize: u64,
+    /// Offset of the segment in the rebuilt image.
+    new_fileoff: u64,
+}
+
+/// The new __LINKEDIT, blobs are appended at 8 byte aligned offsets from its start.
+struct Linkedit<'a> {
+    cache: &'a DyldCache,
+    /// The __LINKEDIT segment of the image in the cache.
+    segment: SegmentLayout<'a>,
+    bytes: Vec<u8>,
+}
+
+impl<'a> Linkedit<'a> {
+    /// The bytes at the file offset `offset` of the shared __LINKEDIT of the cache.
+    fn read(&self, offset: u32, size: usize) -> Result<&'a [u8], CacheError> {
+        let address = (offset as u64)
+            .checked_sub(self.segment.fileoff)
+            .map(|delta| self.segment.vmaddr + delta)
+            .ok_or(CacheError::AddressNotMapped {
+                address: offset as u64,
+                size: size as u64,
+            })?;
+        self.cache.read(address, size as u64)
+    }
+
+    /// Appends `bytes`, returns their offset in the rebuilt image.
+    fn push(&mut self, bytes: &[u8]) -> u32 {
+        self.bytes.resize(self.bytes.len().next_multiple_of(8), 0);
+        let offset = self.segment.new_fileoff as usize + self.bytes.len();
+        self.bytes.extend_from_slice(bytes);
+        offset as u32
+    }
+
+    /// Copies the blob at `offset` in the cache, returns its new offset, 0 for empty blobs.
+    fn copy(&mut self, offset: u32, size: u32) -> Result<u32, CacheError> {
+        if size == 0 {
+            return Ok(0);
+        }
+        let bytes = self.read(offset, size as usize)?;
+        Ok(self.push(bytes))
+    }
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_word(bytes: &mut [u8], offset: usize, value: u64, is_64: bool) {
+    if is_64 {
+        bytes[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    } else {
+        put_u32(bytes, offset, value as u32);
+    }
+}
+
+impl DyldCache {
+    /// Rebuilds `image` as a standalone Mach-O file.
+    ///
+    /// The code and the symbols are usable as they are. The data segments stay as the builder
+    /// left them: their pointers are still encoded for the slide info of the cache, and the
+    /// references to other images of the cache are already bound.
+    pub fn extract_image(&self, image: &CacheImage) -> Result<Vec<u8>, CacheError> {
+        let cannot_extract = |reason| CacheError::CannotExtract {
+            path: image.path.clone(),
+            reason,
+        };
+        let (header, endian) =
+            MachHeader::parse(self.read(image.address, MACH_HEADER_64_SIZE as u64)?)?;
+        // the caches of the supported architectures are little endian
+        if endian != Endian::Little {
+            return Err(cannot_extract("its header is big endian"));
+        }
+        let is_64 = header.is_64();
+        let commands_end = header.size() + header.sizeofcmds as usize;
+        let head = self.read(image.address, commands_end as u64)?;
+        let commands = load_command::parse_load_commands(
+            head,
+            header.size(),
+            header.ncmds,
+            header.sizeofcmds,
+            endian,
+        )?;
+
+        let mut segments = Vec::new();
+        for command in commands
+            .iter()
+            .filter(|lc| lc.cmd == LC_SEGMENT || lc.cmd == LC_SEGMENT_64)
+        {
+            let mut r = command.body(endian);
+            segments.push(SegmentLayout {
+                command: *command,
+                name: r.name16()?,
+                vmaddr: r.word(is_64)?,
+                vmsize: r.word(is_64)?,
+                fileoff: r.word(is_64)?,
+                filesize: r.word(is_64)?,
+                new_fileoff: 0,
+            });
+        }
+        // the header is at the start of __TEXT, it stays at the start of the file
+        segments.sort_by_key(|segment| segment.name != TEXT);
+        if segments.first().is_none_or(|segment| segment.name != TEXT) {
+            return Err(cannot_extract("it has no __TEXT segment"));
+        }
+        let linkedit_index = segments
+            .iter()
+            .position(|segment| segment.name == LINKEDIT)
+            .ok_or(cannot_extract("it has no __LINKEDIT segment"))?;
+        let linkedit_segment = segments.remove(linkedit_index);
+
+        let mut end = 0;
+        for segment in segments.iter_mut().filter(|segment| segment.filesize != 0) {
+            segment.new_fileoff = end;
+            end = (end + segment.filesize).next_multiple_of(PAGE_SIZE);
+        }
+        let mut linkedit = Linkedit {
+            cache: self,
+            segment: SegmentLayout {
+                new_fileoff: end,
+                ..linkedit_segment
+            },
+            bytes: Vec::new(),
+        };
+
+        let mut new_commands = head.to_vec();
+        for segment in &segments {
+            patch_segment(&mut new_commands, segment, is_64, endian)?;
+        }
+        for command in &commands {
+            let mut r = command.body(endian);
+            let at = command.offset;
+            match command.cmd {
+                LC_SYMTAB => {
+                    command.expect_size(24)?;
+                    let symtab = SymtabCommand::parse(&mut r)?;
+                    let (symtab, locals) = copy_symbols(&mut linkedit, image, symtab, is_64)?;
+                    put_u32(&mut new_commands, at + 8, symtab.symoff);
+                    put_u32(&mut new_commands, at + 12, symtab.nsyms);
+                    put_u32(&mut new_commands, at + 16, symtab.stroff);
+                    put_u32(&mut new_commands, at + 20, symtab.strsize);
+                    // the locals are appended after the symbols of the image
+                    if let Some(dysymtab) = commands.iter().find(|lc| lc.cmd == LC_DYSYMTAB) {
+                        let dysymtab = dysymtab.offset;
+                        let nlocalsym =
+                            Reader::at(&new_commands, dysymtab + 12, endian, "dysymtab").u32()?;
+                        if nlocalsym == 0 && locals != 0 {
+                            put_u32(&mut new_commands, dysymtab + 8, symtab.nsyms - locals);
+                            put_u32(&mut new_commands, dysymtab + 12, locals);
+                        }
+                    }
+                }
+                LC_DYSYMTAB => {
+                    command.expect_size(80)?;
+                    let module_size = if is_64 { MODULE_64_SIZE } else { MODULE_SIZE };
+                    // (offset field, entry size) of the tables, the count follows the offset
+                    let tables = [
+                        (32, TOC_ENTRY_SIZE),
+                        (40, module_size),
+                        (48, 4),
+                        (56, 4),
+                        (64, RELOCATION_SIZE),
+                        (72, RELOCATION_SIZE),
+                    ];
+                    for (field, entry_size) in tables {
+                        r.seek(field);
+                        let offset = r.u32()?;
+                        let count = r.u32()?;
+                        let size = (count as usize)
+                            .checked_mul(entry_size)
+                            .and_then(|size| u32::try_from(size).ok())
+                            .ok_or(cannot_extract("a table of LC_DYSYMTAB is too large"))?;
+                        let offset = linkedit.copy(offset, size)?;
+                        put_u32(&mut new_commands, at + field, offset);
+                    }
+                }
+                LC_DYLD_INFO | LC_DYLD_INFO_ONLY => {
+                    command.expect_size(48)?;
+                    for field in (8..48).step_by(8) {
+                        r.seek(field);
+                        let offset = r.u32()?;
+                        let size = r.u32()?;
+                        let offset = linkedit.copy(offset, size)?;
+                        put_u32(&mut new_commands, at + field, offset);
+                    }
+                }
+                LC_FUNCTION_STARTS
+                | LC_DATA_IN_CODE
+                | LC_CODE_SIGNATURE
+                | LC_SEGMENT_SPLIT_INFO
+                | LC_DYLD_CHAINED_FIXUPS
+                | LC_DYLD_EXPORTS_TRIE => {
+                    command.expect_size(16)?;
+                    let offset = r.u32()?;
+                    let size = r.u32()?;
+                    let offset = linkedit.copy(offset, size)?;
+                    put_u32(&mut new_commands, at + 8, offset);
+                }
+                _ => {}
+            }
+        }
+
+        let linkedit_size = linkedit.bytes.len() as u64;
+        let segment = SegmentLayout {
+            filesize: linkedit_size,
+            vmsize: linkedit_size.next_multiple_of(PAGE_SIZE),
+            ..linkedit.segment.clone()
+        };
+        let fields = segment.command.offset + SEGMENT_FIELDS_OFFSET;
+        let word = if is_64 { 8 } else { 4 };
+        put_word(&mut new_commands, fields + word, segment.vmsize, is_64);
+        put_word(
+            &mut new_commands,
+            fields + 2 * word,
+            segment.new_fileoff,
+            is_64,
+        );
+        put_word(
+            &mut new_commands,
+            fields + 3 * word,
+            segment.filesize,
+            is_64,
+        );
+        put_u32(
+            &mut new_commands,
+            HEADER_FLAGS_OFFSET,
+            header.flags & !MH_DYLIB_IN_CACHE,
+        );
+
+        let mut file = vec![0; (segment.new_fileoff + linkedit_size) as usize];
+        for segment in segments.iter().filter(|segment| segment.filesize != 0) {
+            let bytes = self.read(segment.vmaddr, segment.filesize)?;
+            let start = segment.new_fileoff as usize;
+            file[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        file[..new_commands.len()].copy_from_slice(&new_commands);
+        let start = segment.new_fileoff as usize;
+        file[start..].copy_from_slice(&linkedit.bytes);
+
+        MachO::parse(&file)?;
+        Ok(file)
+    }
+}
+
+/// Moves a segment and its sections to their offsets in the rebuilt image.
+fn patch_segment(
+    commands: &mut [u8],
+    segment: &SegmentLayout,
+    is_64: bool,
+    endian: Endian,
+) -> Result<(), MachOError> {
+    let word = if is_64 { 8 } else { 4 };
+    let fields = segment.command.offset + SEGMENT_FIELDS_OFFSET;
+    put_word(commands, fields + 2 * word, segment.new_fileoff, is_64);
+
+    let (section_size, offset_field) = if is_64 {
+        (SECTION_64_SIZE, SECTION_OFFSET_64)
+    } else {
+        (SECTION_SIZE, SECTION_OFFSET)
+    };
+    // maxprot, initprot, nsects and flags follow the four words, then the sections
+    let sections_start = fields + 4 * word + 16;
+    let nsects = Reader::at(commands, fields + 4 * word + 8, endian, "segment").u32()?;
+    for index in 0..nsects as usize {
+        let section = sections_start + index * section_size;
+        let mut r = Reader::at(commands, section + 32, endian, "section");
+        let addr = r.word(is_64)?;
+        let _size = r.word(is_64)?;
+        let offset = r.u32()?;
+        r.skip(12)?;
+        let section_type = r.u32()? & 0xff;
+        if offset == 0 || section_type == S_ZEROFILL || section_type == S_GB_ZEROFILL {
+            continue;
+        }
+        let new_offset = segment.new_fileoff + addr.wrapping_sub(segment.vmaddr);
+        put_u32(commands, section + offset_field, new_offset as u32);
+    }
+    Ok(())
+}
+
+/// Copies the symbols of the image with a string table of their own and appends its local
+/// symbols, returns the new LC_SYMTAB and the number of locals.
+fn copy_symbols(
+    linkedit: &mut Linkedit,
+    image: &CacheImage,
+    symtab: SymtabCommand,
+    is_64: bool,
+) -> Result<(SymtabCommand, u32), CacheError> {
+    let SymtabCommand {
+        symoff,
+        nsyms,
+        stroff,
+        strsize,
+    } = symtab;
+    let entry_size = if is_64 { NLIST_64_SIZE } else { NLIST_SIZE };
+    let symbols = linkedit.read(
+        symoff,
+        (nsyms as usize)
+            .checked_mul(entry_size)
+            .ok_or(MachOError::SymbolTableOutOfRange { symoff, nsyms })?,
+    )?;
+    let strings = linkedit
+        .read(stroff, strsize as usize)
+        .map_err(|_| MachOError::StringTableOutOfRange { stroff, strsize })?;
+
+    // index 0 is the empty name
+    let mut new_strings = vec![0u8];
+    let mut new_symbols = Vec::with_capacity(symbols.len());
+    let mut add = |entry: &[u8], strings: &[u8]| -> Result<(), MachOError> {
+        let strx = Reader::new(entry, Endian::Little, "symbol").u32()?;
+        let name =
+            reader::cstr(strings, strx as usize).ok_or(MachOError::StringIndexOutOfRange {
+                strx,
+                strsize: strings.len() as u32,
+            })?;
+        let new_strx = if name.is_empty() {
+            0
+        } else {
+            let new_strx = new_strings.len() as u32;
+            new_strings.extend_from_slice(name.as_bytes());
+            new_strings.push(0);
+            new_strx
+        };
+        new_symbols.extend_from_slice(&new_strx.to_le_bytes());
+        new_symbols.extend_from_slice(&entry[4..]);
+        Ok(())
+    };
+    for entry in symbols.chunks_exact(entry_size) {
+        add(entry, strings)?;
+    }
+
+    // a missing symbols file leaves the image with its exported symbols only
+    let mut locals = 0;
+    if is_64 && let Ok(local_symbols) = linkedit.cache.local_symbols(image) {
+        for nlist in &local_symbols.entries {
+            let mut entry = [0u8; NLIST_64_SIZE];
+            entry[0..4].copy_from_slice(&nlist.n_strx.to_le_bytes());
+            entry[4] = nlist.n_type;
+            entry[5] = nlist.n_sect;
+            entry[6..8].copy_from_slice(&nlist.n_desc.to_le_bytes());
+            entry[8..16].copy_from_slice(&nlist.n_value.to_le_bytes());
+            add(&entry, local_symbols.strings())?;
+        }
+        locals = local_symbols.entries.len() as u32;
+    }
+
+    let new_symoff = linkedit.push(&new_symbols);
+    let new_stroff = linkedit.push(&new_strings);
+    let symtab = SymtabCommand {
+        symoff: new_symoff,
+        nsyms: nsyms + locals,
+        stroff: new_stroff,
+        strsize: new_strings.len() as u32,
+    };
+    Ok((symtab, locals))
+}
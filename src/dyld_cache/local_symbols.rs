@@ -0,0 +1,142 @@
+// local symbols of the cache
+// the builder strips the non-exported symbols of every image and keeps them in one table: an nlist
+// array and a string table shared by all images, and one entry per image giving its range of the
+// array. The table is at the end of the main file of old caches, in the `.symbols` file of split
+// caches
+// the entries identify their image by the file offset of its header in old caches, by its VM
+// offset from the start of the cache since the 64-bit entries
+
+use crate::macho::MachOError;
+use crate::macho::reader::{self, Endian, Reader};
+use crate::macho::symtab::{NLIST_64_SIZE, Nlist};
+
+use super::{CacheError, CacheFile, CacheImage, DyldCache};
+
+/// Size of `dyld_cache_local_symbols_entry`.
+pub const LOCAL_SYMBOLS_ENTRY_SIZE: usize = 12;
+/// Size of `dyld_cache_local_symbols_entry_64`.
+pub const LOCAL_SYMBOLS_ENTRY_64_SIZE: usize = 16;
+
+/// The local symbols of one image, with the string table they index.
+#[derive(Debug, Clone)]
+pub struct LocalSymbols<'a> {
+    pub entries: Vec<Nlist>,
+    strings: &'a [u8],
+}
+
+impl<'a> LocalSymbols<'a> {
+    /// String table shared by the local symbols of every image.
+    pub fn strings(&self) -> &'a [u8] {
+        self.strings
+    }
+}
+
+impl DyldCache {
+    /// The file holding the local symbols, `None` when the cache has none or its symbols file
+    /// is missing.
+    fn local_symbols_file(&self) -> Option<&CacheFile> {
+        let file = if self.header().symbol_file_uuid != [0; 16] {
+            self.symbols_file.as_ref()?
+        } else {
+            &self.files[0]
+        };
+        (file.header.local_symbols_offset != 0).then_some(file)
+    }
+
+    /// The symbols the cache builder stripped from `image`.
+    pub fn local_symbols(&self, image: &CacheImage) -> Result<LocalSymbols<'_>, CacheError> {
+        let file = self
+            .local_symbols_file()
+            .ok_or(CacheError::NoLocalSymbols)?;
+        let data = file.data();
+        let start = usize::try_from(file.header.local_symbols_offset).unwrap_or(usize::MAX);
+        let mut r = Reader::at(data, start, Endian::Little, "local symbols info");
+        let nlist_offset = r.u32()? as usize;
+        let nlist_count = r.u32()? as usize;
+        let strings_offset = r.u32()? as usize;
+        let strings_size = r.u32()? as usize;
+        let entries_offset = r.u32()? as usize;
+        let entries_count = r.u32()? as usize;
+        let strings = reader::slice(
+            data,
+            start.saturating_add(strings_offset),
+            strings_size,
+            "local symbols strings",
+        )?;
+
+        let (image_offset, entry_size) = if self.header().has_64_bit_local_symbols() {
+            (
+                image.address.wrapping_sub(self.base_address()),
+                LOCAL_SYMBOLS_ENTRY_64_SIZE,
+            )
+        } else {
+            let main = &self.files[0];
+            let offset = main
+                .mappings
+                .iter()
+                .find(|m| m.contains(image.address))
+                .map(|m| m.file_offset + (image.address - m.address))
+                .ok_or(CacheError::AddressNotMapped {
+                    address: image.address,
+                    size: 1,
+                })?;
+            (offset, LOCAL_SYMBOLS_ENTRY_SIZE)
+        };
+
+        let mut range = None;
+        let mut r = Reader::at(
+            data,
+            start.saturating_add(entries_offset),
+            Endian::Little,
+            "local symbols entries",
+        );
+        for _ in 0..entries_count {
+            let dylib_offset = if entry_size == LOCAL_SYMBOLS_ENTRY_64_SIZE {
+                r.u64()?
+            } else {
+                r.u32()? as u64
+            };
+            let nlist_start = r.u32()? as usize;
+            let count = r.u32()? as usize;
+            if dylib_offset == image_offset {
+                range = Some((nlist_start, count));
+                break;
+            }
+        }
+        // images without local symbols have no entry
+        let Some((nlist_start, count)) = range else {
+            return Ok(LocalSymbols {
+                entries: Vec::new(),
+                strings,
+            });
+        };
+        if nlist_start.saturating_add(count) > nlist_count {
+            return Err(MachOError::SymbolIndexOutOfRange {
+                index: nlist_start.saturating_add(count) as u32,
+                nsyms: nlist_count as u32,
+            }
+            .into());
+        }
+
+        // the caches of the supported architectures are 64-bit
+        let mut r = Reader::at(
+            data,
+            start
+                .saturating_add(nlist_offset)
+                .saturating_add(nlist_start * NLIST_64_SIZE),
+            Endian::Little,
+            "local symbols",
+        );
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            entries.push(Nlist {
+                n_strx: r.u32()?,
+                n_type: r.u8()?,
+                n_sect: r.u8()?,
+                n_desc: r.u16()?,
+                n_value: r.u64()?,
+            });
+        }
+        Ok(LocalSymbols { entries, strings })
+    }
+}
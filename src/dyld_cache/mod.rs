@@ -0,0 +1,679 @@
+// dyld shared cache
+// the system libraries of macOS are not on disk, dyld maps them from one prelinked cache shared
+// by every process: /System/Volumes/Preboot/Cryptexes/OS/System/Library/dyld/dyld_shared_cache_*
+// since macOS 12 the cache is split in a main file and subcaches next to it (`.01`, `.02`... or
+// `.1`, `.2`... before macOS 13), and the symbols of the libraries stripped from the cache are in
+// a separate `.symbols` file
+// https://github.com/apple-oss-distributions/dyld/blob/main/cache-builder/dyld_cache_format.h
+//
+// every file starts with a dyld_cache_header, newer fields are appended at the end and exist only
+// if the header, which ends where the mappings start, is long enough
+// the caches of the supported architectures are little endian
+
+pub mod extract;
+pub mod local_symbols;
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::macho::MachOError;
+use crate::macho::reader::{self, Endian, Reader};
+use crate::mapped::MappedFile;
+
+pub const CACHE_MAGIC_PREFIX: &str = "dyld_v1";
+
+// offsets of the header fields whose presence changes how the rest of the cache is read
+const SUB_CACHE_ARRAY_OFFSET: u32 = 392;
+const SYMBOL_FILE_UUID_OFFSET: u32 = 400;
+const IMAGES_OFFSET: u32 = 448;
+const CACHE_SUB_TYPE_OFFSET: u32 = 456;
+// every field known here, shorter headers are padded with zeroes
+const HEADER_SIZE: usize = 512;
+
+/// Size of `dyld_cache_image_info`.
+pub const IMAGE_INFO_SIZE: usize = 32;
+/// Size of `dyld_cache_image_text_info`.
+pub const IMAGE_TEXT_INFO_SIZE: usize = 32;
+
+/// Suffix of the file holding the local symbols of a split cache.
+pub const SYMBOLS_FILE_SUFFIX: &str = ".symbols";
+
+// protections of the mappings, from <mach/vm_prot.h>
+pub const VM_PROT_READ: u32 = 0x1;
+pub const VM_PROT_WRITE: u32 = 0x2;
+pub const VM_PROT_EXECUTE: u32 = 0x4;
+
+/// Every way a shared cache can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheError {
+    /// The file does not start with `dyld_v1`.
+    NotACache,
+    /// A file of the cache cannot be opened.
+    Io { path: PathBuf, error: String },
+    /// A subcache or symbols file is not the one the main cache was built with.
+    UuidMismatch { path: PathBuf },
+    /// No mapping of the cache covers the address range.
+    AddressNotMapped { address: u64, size: u64 },
+    /// The cache has no image with this install name.
+    ImageNotFound(String),
+    /// The cache has no local symbols, or its symbols file is missing.
+    NoLocalSymbols,
+    /// An image of the cache cannot be turned back into a standalone file.
+    CannotExtract { path: String, reason: &'static str },
+    /// A structure of the cache or of an image points outside of its file.
+    Read(MachOError),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::NotACache => write!(f, "not a dyld shared cache"),
+            CacheError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            CacheError::UuidMismatch { path } => {
+                write!(f, "{} does not belong to this cache", path.display())
+            }
+            CacheError::AddressNotMapped { address, size } => {
+                write!(
+                    f,
+                    "{size:#x} bytes at {address:#x} are not mapped by the cache"
+                )
+            }
+            CacheError::ImageNotFound(name) => write!(f, "no image {name} in the cache"),
+            CacheError::NoLocalSymbols => {
+                write!(
+                    f,
+                    "the cache has no local symbols or its symbols file is missing"
+                )
+            }
+            CacheError::CannotExtract { path, reason } => {
+                write!(f, "cannot extract {path}: {reason}")
+            }
+            CacheError::Read(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+impl From<MachOError> for CacheError {
+    fn from(e: MachOError) -> CacheError {
+        CacheError::Read(e)
+    }
+}
+
+/// Decoded `dyld_cache_header`, fields missing from older caches are 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheHeader {
+    pub magic: String,
+    pub mapping_offset: u32,
+    pub mapping_count: u32,
+    /// Image list, from `imagesOffset` or `imagesOffsetOld` for caches older than macOS 12.
+    pub images_offset: u32,
+    pub images_count: u32,
+    pub dyld_base_address: u64,
+    pub code_signature_offset: u64,
+    pub code_signature_size: u64,
+    pub local_symbols_offset: u64,
+    pub local_symbols_size: u64,
+    pub uuid: [u8; 16],
+    pub cache_type: u64,
+    pub images_text_offset: u64,
+    pub images_text_count: u64,
+    pub platform: u32,
+    pub format_version: u32,
+    pub shared_region_start: u64,
+    pub shared_region_size: u64,
+    pub max_slide: u64,
+    pub mapping_with_slide_offset: u32,
+    pub mapping_with_slide_count: u32,
+    pub os_version: u32,
+    pub sub_cache_array_offset: u32,
+    pub sub_cache_array_count: u32,
+    pub symbol_file_uuid: [u8; 16],
+    pub cache_sub_type: u32,
+}
+
+impl CacheHeader {
+    pub fn parse(data: &[u8]) -> Result<CacheHeader, CacheError> {
+        let magic = reader::slice(data, 0, 16, "cache magic").map_err(|_| CacheError::NotACache)?;
+        if !magic.starts_with(CACHE_MAGIC_PREFIX.as_bytes()) {
+            return Err(CacheError::NotACache);
+        }
+        let mapping_offset = Reader::at(data, 16, Endian::Little, "cache header").u32()?;
+        // the fields past the end of the header of older caches read as zeroes
+        let mut bytes = [0u8; HEADER_SIZE];
+        let size = (mapping_offset as usize).min(HEADER_SIZE);
+        bytes[..size].copy_from_slice(reader::slice(data, 0, size, "cache header")?);
+
+        let mut r = Reader::at(&bytes, 20, Endian::Little, "cache header");
+        let mapping_count = r.u32()?;
+        let images_offset_old = r.u32()?;
+        let images_count_old = r.u32()?;
+        let dyld_base_address = r.u64()?;
+        let code_signature_offset = r.u64()?;
+        let code_signature_size = r.u64()?;
+        let _slide_info_offset = r.u64()?;
+        let _slide_info_size = r.u64()?;
+        let local_symbols_offset = r.u64()?;
+        let local_symbols_size = r.u64()?;
+        let uuid = r.take(16)?.try_into().unwrap();
+        let cache_type = r.u64()?;
+        let _branch_pools_offset = r.u32()?;
+        let _branch_pools_count = r.u32()?;
+        let _dyld_in_cache_mh = r.u64()?;
+        let _dyld_in_cache_entry = r.u64()?;
+        let images_text_offset = r.u64()?;
+        let images_text_count = r.u64()?;
+        // patch info, other image group, program closures and their trie
+        r.skip(8 * 8)?;
+        let platform = r.u32()?;
+        // the low byte is the version, the other bits are flags
+        let format_version = r.u32()? & 0xff;
+        let shared_region_start = r.u64()?;
+        let shared_region_size = r.u64()?;
+        let max_slide = r.u64()?;
+        // dylib and other image arrays and tries
+        r.skip(8 * 8)?;
+        let mapping_with_slide_offset = r.u32()?;
+        let mapping_with_slide_count = r.u32()?;
+        // prebuilt loader sets and the program trie
+        r.skip(5 * 8 + 4)?;
+        let os_version = r.u32()?;
+        let _alt_platform = r.u32()?;
+        let _alt_os_version = r.u32()?;
+        let _swift_opts_offset = r.u64()?;
+        let _swift_opts_size = r.u64()?;
+        let sub_cache_array_offset = r.u32()?;
+        let sub_cache_array_count = r.u32()?;
+        let symbol_file_uuid = r.take(16)?.try_into().unwrap();
+        // rosetta read-only and read-write ranges
+        r.skip(4 * 8)?;
+        let images_offset = r.u32()?;
+        let images_count = r.u32()?;
+        let cache_sub_type = r.u32()?;
+
+        // the image list moved when the header grew the subcache fields
+        let (images_offset, images_count) = if mapping_offset > IMAGES_OFFSET {
+            (images_offset, images_count)
+        } else {
+            (images_offset_old, images_count_old)
+        };
+        Ok(CacheHeader {
+            magic: reader::fixed_name(magic),
+            mapping_offset,
+            mapping_count,
+            images_offset,
+            images_count,
+            dyld_base_address,
+            code_signature_offset,
+            code_signature_size,
+            local_symbols_offset,
+            local_symbols_size,
+            uuid,
+            cache_type,
+            images_text_offset,
+            images_text_count,
+            platform,
+            format_version,
+            shared_region_start,
+            shared_region_size,
+            max_slide,
+            mapping_with_slide_offset,
+            mapping_with_slide_count,
+            os_version,
+            sub_cache_array_offset,
+            sub_cache_array_count,
+            symbol_file_uuid,
+            cache_sub_type,
+        })
+    }
+
+    /// Architecture of the cache, from the end of the magic: `dyld_v1   arm64e`.
+    pub fn arch(&self) -> &str {
+        self.magic[CACHE_MAGIC_PREFIX.len()..].trim()
+    }
+
+    /// The subcache entries have an explicit file suffix since the header has `cacheSubType`.
+    fn has_sub_cache_suffixes(&self) -> bool {
+        self.mapping_offset > CACHE_SUB_TYPE_OFFSET
+    }
+
+    /// The local symbols entries use 64-bit VM offsets since the header has `symbolFileUUID`.
+    fn has_64_bit_local_symbols(&self) -> bool {
+        self.mapping_offset >= SYMBOL_FILE_UUID_OFFSET
+    }
+}
+
+/// Decoded `dyld_cache_mapping_info`: a range of a cache file mapped at `address`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheMapping {
+    pub address: u64,
+    pub size: u64,
+    pub file_offset: u64,
+    pub max_prot: u32,
+    pub init_prot: u32,
+}
+
+impl CacheMapping {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.address && address - self.address < self.size
+    }
+
+    /// `r-x` style initial protection.
+    pub fn protection(&self) -> String {
+        [
+            (VM_PROT_READ, 'r'),
+            (VM_PROT_WRITE, 'w'),
+            (VM_PROT_EXECUTE, 'x'),
+        ]
+        .iter()
+        .map(|(bit, c)| if self.init_prot & bit != 0 { *c } else { '-' })
+        .collect()
+    }
+}
+
+/// One file of the cache, the main file, a subcache or the symbols file.
+#[derive(Debug)]
+pub struct CacheFile {
+    pub path: PathBuf,
+    pub header: CacheHeader,
+    pub mappings: Vec<CacheMapping>,
+    file: MappedFile,
+}
+
+impl CacheFile {
+    pub fn open(path: &Path) -> Result<CacheFile, CacheError> {
+        let file = MappedFile::open(path).map_err(|e| CacheError::Io {
+            path: path.to_path_buf(),
+            error: e.to_string(),
+        })?;
+        let header = CacheHeader::parse(&file)?;
+        let mut r = Reader::at(
+            &file,
+            header.mapping_offset as usize,
+            Endian::Little,
+            "cache mappings",
+        );
+        let mut mappings = Vec::new();
+        for _ in 0..header.mapping_count {
+            mappings.push(CacheMapping {
+                address: r.u64()?,
+                size: r.u64()?,
+                file_offset: r.u64()?,
+                max_prot: r.u32()?,
+                init_prot: r.u32()?,
+            });
+        }
+        Ok(CacheFile {
+            path: path.to_path_buf(),
+            header,
+            mappings,
+            file,
+        })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.file.data()
+    }
+
+    /// The bytes mapped at `address` by this file, `None` when no mapping covers all of them.
+    pub fn read(&self, address: u64, size: u64) -> Option<&[u8]> {
+        let mapping = self.mappings.iter().find(|m| m.contains(address))?;
+        let offset = address - mapping.address;
+        if mapping.size - offset < size {
+            return None;
+        }
+        reader::slice64(
+            self.data(),
+            mapping.file_offset + offset,
+            size,
+            "cache mapping",
+        )
+        .ok()
+    }
+}
+
+/// A subcache listed by the main cache file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubCacheEntry {
+    pub uuid: [u8; 16],
+    /// Offset of the subcache from the start of the main cache in memory.
+    pub vm_offset: u64,
+    /// Appended to the path of the main cache to get the path of the subcache.
+    pub suffix: String,
+}
+
+/// An image of the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheImage {
+    /// Install name of the image.
+    pub path: String,
+    /// Address of its mach header, the cache is not slid.
+    pub address: u64,
+    pub uuid: Option<[u8; 16]>,
+}
+
+impl CacheImage {
+    /// File name of the install name.
+    pub fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// A shared cache with all its files.
+#[derive(Debug)]
+pub struct DyldCache {
+    /// The main file first, then the subcaches.
+    pub files: Vec<CacheFile>,
+    pub sub_caches: Vec<SubCacheEntry>,
+    /// The file with the local symbols, when they are not in the main file.
+    pub symbols_file: Option<CacheFile>,
+    pub images: Vec<CacheImage>,
+}
+
+impl DyldCache {
+    /// Opens the main cache file at `path` and the subcaches next to it.
+    ///
+    /// A missing subcache is an error, a missing symbols file only leaves the cache without its
+    /// local symbols.
+    pub fn open(path: &Path) -> Result<DyldCache, CacheError> {
+        let main = CacheFile::open(path)?;
+        let sub_caches = sub_cache_entries(&main)?;
+        let mut files = vec![main];
+        for entry in &sub_caches {
+            let path = with_suffix(path, &entry.suffix);
+            let file = CacheFile::open(&path)?;
+            if file.header.uuid != entry.uuid {
+                return Err(CacheError::UuidMismatch { path });
+            }
+            files.push(file);
+        }
+
+        let symbol_file_uuid = files[0].header.symbol_file_uuid;
+        let symbols_file = if symbol_file_uuid != [0; 16] {
+            let path = with_suffix(path, SYMBOLS_FILE_SUFFIX);
+            match CacheFile::open(&path) {
+                Ok(file) if file.header.uuid != symbol_file_uuid => {
+                    return Err(CacheError::UuidMismatch { path });
+                }
+                Ok(file) => Some(file),
+                Err(_) => None,
+            }
+        } else {
+            None
+        };
+
+        let images = images(&files[0])?;
+        Ok(DyldCache {
+            files,
+            sub_caches,
+            symbols_file,
+            images,
+        })
+    }
+
+    pub fn header(&self) -> &CacheHeader {
+        &self.files[0].header
+    }
+
+    /// Address of the start of the cache, the VM offsets of the cache are relative to it.
+    pub fn base_address(&self) -> u64 {
+        self.files[0]
+            .mappings
+            .first()
+            .map_or(self.header().shared_region_start, |m| m.address)
+    }
+
+    /// The bytes at `address`, from whichever file maps them.
+    pub fn read(&self, address: u64, size: u64) -> Result<&[u8], CacheError> {
+        self.files
+            .iter()
+            .find_map(|file| file.read(address, size))
+            .ok_or(CacheError::AddressNotMapped { address, size })
+    }
+
+    /// The image with this install name, or else the only image with this file name.
+    pub fn image(&self, name: &str) -> Result<&CacheImage, CacheError> {
+        if let Some(image) = self.images.iter().find(|image| image.path == name) {
+            return Ok(image);
+        }
+        let mut named = self.images.iter().filter(|image| image.name() == name);
+        match (named.next(), named.next()) {
+            (Some(image), None) => Ok(image),
+            _ => Err(CacheError::ImageNotFound(name.to_string())),
+        }
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// dyld_subcache_entry_v1 has no suffix, the subcaches are numbered from 1
+fn sub_cache_entries(main: &CacheFile) -> Result<Vec<SubCacheEntry>, CacheError> {
+    let header = &main.header;
+    if header.mapping_offset <= SUB_CACHE_ARRAY_OFFSET {
+        return Ok(Vec::new());
+    }
+    let mut r = Reader::at(
+        main.data(),
+        header.sub_cache_array_offset as usize,
+        Endian::Little,
+        "subcache entries",
+    );
+    let mut entries = Vec::new();
+    for i in 0..header.sub_cache_array_count {
+        let uuid = r.take(16)?.try_into().unwrap();
+        let vm_offset = r.u64()?;
+        let suffix = if header.has_sub_cache_suffixes() {
+            reader::fixed_name(r.take(32)?)
+        } else {
+            format!(".{}", i + 1)
+        };
+        entries.push(SubCacheEntry {
+            uuid,
+            vm_offset,
+            suffix,
+        });
+    }
+    Ok(entries)
+}
+
+// the image infos name the images, the text infos in the same order have their UUID
+fn images(main: &CacheFile) -> Result<Vec<CacheImage>, CacheError> {
+    let header = &main.header;
+    let data = main.data();
+    let mut text = Reader::at(
+        data,
+        header.images_text_offset as usize,
+        Endian::Little,
+        "image text infos",
+    );
+    let has_text = header.images_text_count == header.images_count as u64;
+    let mut images = Vec::with_capacity(header.images_count as usize);
+    for i in 0..header.images_count as usize {
+        let mut r = Reader::at(
+            data,
+            header.images_offset as usize + i * IMAGE_INFO_SIZE,
+            Endian::Little,
+            "image info",
+        );
+        let address = r.u64()?;
+        let _mod_time = r.u64()?;
+        let _inode = r.u64()?;
+        let path_offset = r.u32()?;
+        let path = reader::cstr(data, path_offset as usize).ok_or(MachOError::OutOfBounds {
+            what: "image path",
+            offset: path_offset as usize,
+            size: 1,
+        })?;
+        let uuid = if has_text {
+            let uuid: [u8; 16] = text.take(16)?.try_into().unwrap();
+            text.skip(IMAGE_TEXT_INFO_SIZE - 16)?;
+            Some(uuid)
+        } else {
+            None
+        };
+        images.push(CacheImage {
+            path: path.to_string(),
+            address,
+            uuid,
+        });
+    }
+    Ok(images)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const BASE: u64 = 0x1_8000_0000;
+    const MAIN_UUID: [u8; 16] = [1; 16];
+    const SUB_UUID: [u8; 16] = [2; 16];
+    const IMAGE_UUID: [u8; 16] = [3; 16];
+    const PATH: &str = "/usr/lib/libtest.dylib";
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A cache file of 0x1000 bytes with one mapping of the whole file at `address`.
+    fn cache_file(mapping_offset: u32, uuid: [u8; 16], address: u64, init_prot: u32) -> Vec<u8> {
+        let mut data = vec![0; 0x1000];
+        put(&mut data, 0, b"dyld_v1   arm64\0");
+        put(&mut data, 16, &mapping_offset.to_le_bytes());
+        put(&mut data, 20, &1u32.to_le_bytes());
+        put(&mut data, 88, &uuid);
+        put(&mut data, 224, &address.to_le_bytes());
+        let mut mapping = Vec::new();
+        for value in [address, 0x1000, 0] {
+            mapping.extend(u64::to_le_bytes(value));
+        }
+        mapping.extend(VM_PROT_READ.to_le_bytes());
+        mapping.extend(init_prot.to_le_bytes());
+        put(&mut data, mapping_offset as usize, &mapping);
+        data
+    }
+
+    /// The main file and the subcache of a cache, with the headers of macOS 13 or of macOS 12
+    /// when `legacy`: the image list at its old place and unnamed subcache entries.
+    fn write_cache(name: &str, legacy: bool) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustprof-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("dyld_shared_cache_arm64");
+
+        let mapping_offset = if legacy { 0x1c0 } else { 0x200 };
+        let mut main = cache_file(
+            mapping_offset,
+            MAIN_UUID,
+            BASE,
+            VM_PROT_READ | VM_PROT_EXECUTE,
+        );
+        let images_at = if legacy { 24 } else { 448 };
+        put(&mut main, images_at, &0x260u32.to_le_bytes());
+        put(&mut main, images_at + 4, &1u32.to_le_bytes());
+        put(&mut main, 136, &0x280u64.to_le_bytes());
+        put(&mut main, 144, &1u64.to_le_bytes());
+        put(&mut main, 392, &0x220u32.to_le_bytes());
+        put(&mut main, 396, &1u32.to_le_bytes());
+        // the subcache entry, the image info and text info, the path
+        let mut entry = SUB_UUID.to_vec();
+        entry.extend(0x1000u64.to_le_bytes());
+        if !legacy {
+            entry.extend(b".01\0");
+            entry.resize(56, 0);
+        }
+        put(&mut main, 0x220, &entry);
+        put(&mut main, 0x260, &(BASE + 0x800).to_le_bytes());
+        put(&mut main, 0x278, &0x300u32.to_le_bytes());
+        put(&mut main, 0x280, &IMAGE_UUID);
+        put(&mut main, 0x300, PATH.as_bytes());
+        put(&mut main, 0x800, b"main");
+        fs::write(&path, main).unwrap();
+
+        let mut sub = cache_file(0x200, SUB_UUID, BASE + 0x1000, VM_PROT_READ | VM_PROT_WRITE);
+        put(&mut sub, 0x810, b"sub!");
+        let suffix = if legacy { ".1" } else { ".01" };
+        fs::write(with_suffix(&path, suffix), sub).unwrap();
+        path
+    }
+
+    #[test]
+    fn opens_a_split_cache() {
+        for legacy in [false, true] {
+            let path = write_cache(if legacy { "legacy" } else { "split" }, legacy);
+            let cache = DyldCache::open(&path).unwrap();
+            assert_eq!(cache.header().arch(), "arm64");
+            assert_eq!(cache.header().uuid, MAIN_UUID);
+            assert_eq!(cache.base_address(), BASE);
+            assert_eq!(cache.files.len(), 2);
+            assert_eq!(
+                cache.sub_caches[0].suffix,
+                if legacy { ".1" } else { ".01" }
+            );
+            assert!(cache.symbols_file.is_none());
+            let protections: Vec<_> = cache
+                .files
+                .iter()
+                .map(|file| file.mappings[0].protection())
+                .collect();
+            assert_eq!(protections, ["r-x", "rw-"]);
+
+            assert_eq!(
+                cache.images,
+                [CacheImage {
+                    path: PATH.to_string(),
+                    address: BASE + 0x800,
+                    uuid: Some(IMAGE_UUID),
+                }]
+            );
+            assert_eq!(cache.image("libtest.dylib").unwrap().path, PATH);
+            assert_eq!(cache.image(PATH).unwrap().name(), "libtest.dylib");
+            assert_eq!(
+                cache.image("libother.dylib").unwrap_err(),
+                CacheError::ImageNotFound("libother.dylib".to_string())
+            );
+
+            // read from whichever file maps the address, never across two
+            assert_eq!(cache.read(BASE + 0x800, 4).unwrap(), b"main");
+            assert_eq!(cache.read(BASE + 0x1810, 4).unwrap(), b"sub!");
+            assert_eq!(
+                cache.read(BASE + 0xffe, 4).unwrap_err(),
+                CacheError::AddressNotMapped {
+                    address: BASE + 0xffe,
+                    size: 4
+                }
+            );
+            let _ = fs::remove_dir_all(path.parent().unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_foreign_files() {
+        let path = write_cache("mismatch", false);
+        let sub = with_suffix(&path, ".01");
+        let mut data = fs::read(&sub).unwrap();
+        put(&mut data, 88, &[9; 16]);
+        fs::write(&sub, data).unwrap();
+        assert!(matches!(
+            DyldCache::open(&path).unwrap_err(),
+            CacheError::UuidMismatch { path } if path == sub
+        ));
+
+        fs::remove_file(&sub).unwrap();
+        assert!(matches!(
+            DyldCache::open(&path).unwrap_err(),
+            CacheError::Io { path, .. } if path == sub
+        ));
+        assert_eq!(
+            CacheHeader::parse(b"\xcf\xfa\xed\xfe").unwrap_err(),
+            CacheError::NotACache
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -0,0 +1,309 @@
+// ELF parsing
+// only the headers, sections and segments, enough to find the unwind and debug information of
+// Linux binaries; the byte reading is shared with the Mach-O parser
+
+pub mod note;
+pub mod symtab;
+
+use std::fmt;
+
+use crate::macho::MachOError;
+use crate::macho::reader::{self, Endian, Reader};
+
+pub const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+pub const ELFCLASS32: u8 = 1;
+pub const ELFCLASS64: u8 = 2;
+pub const ELFDATA2LSB: u8 = 1;
+pub const ELFDATA2MSB: u8 = 2;
+
+// e_type
+pub const ET_CORE: u16 = 4;
+
+// e_machine
+pub const EM_386: u16 = 3;
+pub const EM_X86_64: u16 = 62;
+pub const EM_AARCH64: u16 = 183;
+
+// sh_type
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_NOTE: u32 = 7;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_DYNSYM: u32 = 11;
+// sh_flags
+pub const SHF_COMPRESSED: u64 = 0x800;
+
+// p_type
+pub const PT_LOAD: u32 = 1;
+pub const PT_NOTE: u32 = 4;
+
+/// Every way an ELF buffer can be rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ElfError {
+    /// The buffer does not start with `\x7fELF`.
+    NotElf,
+    /// `EI_CLASS` is neither 32 nor 64-bit.
+    UnknownClass(u8),
+    /// `EI_DATA` is neither little nor big endian.
+    UnknownDataEncoding(u8),
+    /// A section's contents are compressed, which is not supported.
+    CompressedSection(String),
+    /// A header or a section points outside of the buffer.
+    Read(MachOError),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "not an ELF file"),
+            ElfError::UnknownClass(class) => write!(f, "unknown ELF class {class}"),
+            ElfError::UnknownDataEncoding(data) => write!(f, "unknown ELF data encoding {data}"),
+            ElfError::CompressedSection(name) => {
+                write!(f, "section {name} is compressed, which is not supported")
+            }
+            ElfError::Read(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+impl From<MachOError> for ElfError {
+    fn from(e: MachOError) -> ElfError {
+        ElfError::Read(e)
+    }
+}
+
+/// A section header with its resolved name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSection {
+    pub name: String,
+    /// Offset of the name in the section name string table.
+    pub name_offset: u32,
+    pub sh_type: u32,
+    pub flags: u64,
+    pub addr: u64,
+    pub offset: u64,
+    pub size: u64,
+    pub link: u32,
+    pub info: u32,
+    pub addralign: u64,
+    pub entsize: u64,
+}
+
+/// A program header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+/// A parsed ELF file borrowing its buffer.
+#[derive(Debug, Clone)]
+pub struct Elf<'a> {
+    pub is_64: bool,
+    pub endian: Endian,
+    pub e_type: u16,
+    pub machine: u16,
+    pub program_headers: Vec<ProgramHeader>,
+    pub sections: Vec<ElfSection>,
+    data: &'a [u8],
+}
+
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(ELF_MAGIC)
+}
+
+/// Architecture name of `e_machine`, spelled like the Mach-O ones.
+pub fn machine_name(machine: u16) -> String {
+    match machine {
+        EM_386 => "i386".to_string(),
+        EM_X86_64 => "x86_64".to_string(),
+        EM_AARCH64 => "arm64".to_string(),
+        _ => format!("machine {machine}"),
+    }
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        Self::parse_headers(data, true)
+    }
+
+    /// An image as mapped in memory, from its program headers only: the section headers are
+    /// at the end of the file and not part of any segment.
+    pub fn parse_mapped(data: &'a [u8]) -> Result<Elf<'a>, ElfError> {
+        Self::parse_headers(data, false)
+    }
+
+    fn parse_headers(data: &'a [u8], with_sections: bool) -> Result<Elf<'a>, ElfError> {
+        if !is_elf(data) {
+            return Err(ElfError::NotElf);
+        }
+        let ident = reader::slice(data, 0, 16, "ELF identification")?;
+        let is_64 = match ident[4] {
+            ELFCLASS32 => false,
+            ELFCLASS64 => true,
+            class => return Err(ElfError::UnknownClass(class)),
+        };
+        let endian = match ident[5] {
+            ELFDATA2LSB => Endian::Little,
+            ELFDATA2MSB => Endian::Big,
+            encoding => return Err(ElfError::UnknownDataEncoding(encoding)),
+        };
+
+        let mut r = Reader::at(data, 16, endian, "ELF header");
+        let e_type = r.u16()?;
+        let machine = r.u16()?;
+        let _version = r.u32()?;
+        let _entry = r.word(is_64)?;
+        let phoff = r.word(is_64)? as usize;
+        let shoff = r.word(is_64)? as usize;
+        let _flags = r.u32()?;
+        let _ehsize = r.u16()?;
+        let phentsize = r.u16()? as usize;
+        let phnum = r.u16()? as usize;
+        let shentsize = r.u16()? as usize;
+        let shnum = r.u16()? as usize;
+        let shstrndx = r.u16()? as usize;
+
+        let program_headers = (0..phnum)
+            .map(|i| {
+                let mut r = Reader::at(data, phoff + i * phentsize, endian, "program header");
+                ProgramHeader::parse(&mut r, is_64)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let shnum = if with_sections { shnum } else { 0 };
+        let mut sections = (0..shnum)
+            .map(|i| {
+                let mut r = Reader::at(data, shoff + i * shentsize, endian, "section header");
+                ElfSection::parse(&mut r, is_64)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // names are indices in the section name string table
+        if let Some(shstrtab) = sections.get(shstrndx).cloned() {
+            let strings = reader::slice64(data, shstrtab.offset, shstrtab.size, "section names")?;
+            for section in &mut sections {
+                section.name = reader::cstr(strings, section.name_offset as usize)
+                    .unwrap_or_default()
+                    .to_string();
+            }
+        }
+
+        Ok(Elf {
+            is_64,
+            endian,
+            e_type,
+            machine,
+            program_headers,
+            sections,
+            data,
+        })
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn section(&self, name: &str) -> Option<&ElfSection> {
+        self.sections.iter().find(|s| s.name == name)
+    }
+
+    /// Contents of a section, empty for sections without data in the file.
+    pub fn section_data(&self, section: &ElfSection) -> Result<&'a [u8], ElfError> {
+        if section.sh_type == SHT_NOBITS {
+            return Ok(&[]);
+        }
+        if section.flags & SHF_COMPRESSED != 0 {
+            return Err(ElfError::CompressedSection(section.name.clone()));
+        }
+        Ok(reader::slice64(
+            self.data,
+            section.offset,
+            section.size,
+            "section data",
+        )?)
+    }
+
+    /// Lowest address of the loadable segments, what the load bias is computed against.
+    pub fn base_address(&self) -> u64 {
+        self.program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| ph.vaddr - ph.offset)
+            .min()
+            .unwrap_or(0)
+    }
+}
+
+impl ElfSection {
+    fn parse(r: &mut Reader, is_64: bool) -> Result<ElfSection, MachOError> {
+        let name_offset = r.u32()?;
+        let sh_type = r.u32()?;
+        let flags = r.word(is_64)?;
+        let addr = r.word(is_64)?;
+        let offset = r.word(is_64)?;
+        let size = r.word(is_64)?;
+        let link = r.u32()?;
+        let info = r.u32()?;
+        let addralign = r.word(is_64)?;
+        let entsize = r.word(is_64)?;
+        Ok(ElfSection {
+            // resolved once the string table is known
+            name: String::new(),
+            name_offset,
+            sh_type,
+            flags,
+            addr,
+            offset,
+            size,
+            link,
+            info,
+            addralign,
+            entsize,
+        })
+    }
+}
+
+impl ProgramHeader {
+    fn parse(r: &mut Reader, is_64: bool) -> Result<ProgramHeader, MachOError> {
+        // the flags moved after the type in the 64-bit layout
+        if is_64 {
+            let p_type = r.u32()?;
+            let flags = r.u32()?;
+            let offset = r.u64()?;
+            let vaddr = r.u64()?;
+            let _paddr = r.u64()?;
+            Ok(ProgramHeader {
+                p_type,
+                flags,
+                offset,
+                vaddr,
+                filesz: r.u64()?,
+                memsz: r.u64()?,
+                align: r.u64()?,
+            })
+        } else {
+            let p_type = r.u32()?;
+            let offset = r.u32()? as u64;
+            let vaddr = r.u32()? as u64;
+            let _paddr = r.u32()?;
+            let filesz = r.u32()? as u64;
+            let memsz = r.u32()? as u64;
+            let flags = r.u32()?;
+            Ok(ProgramHeader {
+                p_type,
+                flags,
+                offset,
+                vaddr,
+                filesz,
+                memsz,
+                align: r.u32()? as u64,
+            })
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// ELF notes
+// PT_NOTE segments and SHT_NOTE sections hold a list of (name, type, descriptor) records, each
+// field padded to 4 bytes
+
+use super::{Elf, ElfError, PT_NOTE, SHT_NOTE};
+use crate::macho::reader::{self, Endian, Reader};
+
+// note types of the "GNU" owner
+pub const NT_GNU_BUILD_ID: u32 = 3;
+// note types of the "CORE" owner, written in core files by the Linux kernel
+pub const NT_PRSTATUS: u32 = 1;
+pub const NT_AUXV: u32 = 6;
+pub const NT_FILE: u32 = 0x4649_4c45;
+
+/// A note record borrowing its descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note<'a> {
+    pub name: &'a str,
+    pub n_type: u32,
+    pub desc: &'a [u8],
+}
+
+/// Parses the notes of a PT_NOTE segment or SHT_NOTE section.
+pub fn parse_notes(data: &[u8], endian: Endian) -> Result<Vec<Note<'_>>, ElfError> {
+    let mut r = Reader::new(data, endian, "note");
+    let mut notes = Vec::new();
+    // a trailing partial header is padding
+    while r.remaining() >= 12 {
+        let namesz = r.u32()? as usize;
+        let descsz = r.u32()? as usize;
+        let n_type = r.u32()?;
+        let name = r.take(namesz)?;
+        r.skip(padding(namesz))?;
+        let desc = r.take(descsz)?;
+        r.skip(padding(descsz).min(r.remaining()))?;
+        notes.push(Note {
+            // the name is NUL terminated
+            name: reader::cstr(name, 0).unwrap_or_default(),
+            n_type,
+            desc,
+        });
+    }
+    Ok(notes)
+}
+
+fn padding(size: usize) -> usize {
+    (4 - size % 4) % 4
+}
+
+impl<'a> Elf<'a> {
+    /// Every note of the file, from the PT_NOTE segments or from the SHT_NOTE sections of files
+    /// without program headers.
+    pub fn notes(&self) -> Result<Vec<Note<'a>>, ElfError> {
+        let mut notes = Vec::new();
+        if self.program_headers.iter().any(|ph| ph.p_type == PT_NOTE) {
+            for ph in self
+                .program_headers
+                .iter()
+                .filter(|ph| ph.p_type == PT_NOTE)
+            {
+                let data = reader::slice64(self.data(), ph.offset, ph.filesz, "note segment")?;
+                notes.extend(parse_notes(data, self.endian)?);
+            }
+        } else {
+            for section in self.sections.iter().filter(|s| s.sh_type == SHT_NOTE) {
+                notes.extend(parse_notes(self.section_data(section)?, self.endian)?);
+            }
+        }
+        Ok(notes)
+    }
+
+    /// The GNU build-id identifying the build, `None` when the linker did not emit one.
+    pub fn build_id(&self) -> Option<&'a [u8]> {
+        self.notes()
+            .ok()?
+            .into_iter()
+            .find(|note| note.name == "GNU" && note.n_type == NT_GNU_BUILD_ID)
+            .map(|note| note.desc)
+    }
+}
+
+/// Formats a build-id the way `file` and `readelf -n` print it.
+pub fn format_build_id(build_id: &[u8]) -> String {
+    build_id.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(endian: Endian, name: &str, n_type: u32, desc: &[u8]) -> Vec<u8> {
+        let u32 = |value: u32| match endian {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        };
+        let mut data = Vec::new();
+        for value in [name.len() as u32 + 1, desc.len() as u32, n_type] {
+            data.extend(u32(value));
+        }
+        data.extend(name.as_bytes());
+        data.push(0);
+        data.resize(data.len() + padding(name.len() + 1), 0);
+        data.extend(desc);
+        data.resize(data.len() + padding(desc.len()), 0);
+        data
+    }
+
+    #[test]
+    fn notes_are_padded_to_four_bytes() {
+        for endian in [Endian::Little, Endian::Big] {
+            let build_id: Vec<u8> = (0..20).collect();
+            let mut data = note(endian, "GNU", NT_GNU_BUILD_ID, &build_id);
+            data.extend(note(endian, "CORE", NT_PRSTATUS, &[1, 2, 3, 4, 5]));
+            data.extend(note(endian, "LINUX", 0x200, &[]));
+            // a trailing partial header is padding
+            data.extend([0; 8]);
+            let notes = parse_notes(&data, endian).unwrap();
+            assert_eq!(
+                notes,
+                [
+                    Note {
+                        name: "GNU",
+                        n_type: NT_GNU_BUILD_ID,
+                        desc: &build_id
+                    },
+                    Note {
+                        name: "CORE",
+                        n_type: NT_PRSTATUS,
+                        desc: &[1, 2, 3, 4, 5]
+                    },
+                    Note {
+                        name: "LINUX",
+                        n_type: 0x200,
+                        desc: &[]
+                    }
+                ]
+            );
+        }
+        // the padding of the last descriptor may be missing
+        let data = note(Endian::Little, "CORE", NT_AUXV, &[1, 2]);
+        assert_eq!(
+            parse_notes(&data[..data.len() - 2], Endian::Little).unwrap()[0].desc,
+            [1, 2]
+        );
+        assert_eq!(format_build_id(&[0x00, 0x1f, 0xab]), "001fab".to_string());
+    }
+
+    #[test]
+    fn truncated_notes() {
+        let data = note(Endian::Little, "GNU", NT_GNU_BUILD_ID, &[0xaa; 20]);
+        for len in 12..data.len() {
+            assert!(
+                parse_notes(&data[..len], Endian::Little).is_err(),
+                "{len} bytes"
+            );
+        }
+        // sizes past the end of the segment
+        let mut huge = data.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(parse_notes(&huge, Endian::Little).is_err());
+    }
+
+    #[test]
+    fn build_id_of_a_linked_file() {
+        let bytes = std::fs::read("/bin/ls").expect("/bin/ls");
+        let binary = Elf::parse(&bytes).unwrap();
+        // the notes of the PT_NOTE segments are those of the SHT_NOTE sections
+        let Some(section) = binary.section(".note.gnu.build-id") else {
+            assert_eq!(binary.build_id(), None);
+            return;
+        };
+        let data = binary.section_data(section).unwrap();
+        let notes = parse_notes(data, binary.endian).unwrap();
+        assert_eq!(binary.build_id(), Some(notes[0].desc));
+        // sha1 by default, md5 or a uuid with --build-id=...
+        assert!(matches!(notes[0].desc.len(), 16 | 20));
+    }
+}
@@ -0,0 +1,86 @@
+// ELF symbol tables
+// .symtab is stripped from release builds, .dynsym always stays for the dynamic linker; both use
+// the same Elf32_Sym / Elf64_Sym entries with names in the string table of sh_link
+
+use super::{Elf, ElfError, SHT_DYNSYM, SHT_SYMTAB};
+use crate::macho::reader::{self, Reader};
+
+// st_info: binding in the high nibble, type in the low one
+pub const STB_LOCAL: u8 = 0;
+pub const STT_SECTION: u8 = 3;
+pub const STT_FILE: u8 = 4;
+pub const STT_TLS: u8 = 6;
+
+pub const SHN_UNDEF: u16 = 0;
+
+/// A symbol table entry with its resolved name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElfSymbol<'a> {
+    pub name: &'a str,
+    pub value: u64,
+    pub size: u64,
+    pub info: u8,
+    pub other: u8,
+    pub shndx: u16,
+}
+
+impl ElfSymbol<'_> {
+    pub fn binding(&self) -> u8 {
+        self.info >> 4
+    }
+
+    pub fn symbol_type(&self) -> u8 {
+        self.info & 0xf
+    }
+
+    /// Defined in a section of the file, as opposed to imported or absolute.
+    pub fn is_defined(&self) -> bool {
+        self.shndx != SHN_UNDEF && self.shndx < 0xff00
+    }
+}
+
+impl<'a> Elf<'a> {
+    /// Entries of .symtab, or of .dynsym when the file is stripped.
+    pub fn symbols(&self) -> Result<Vec<ElfSymbol<'a>>, ElfError> {
+        let table = self
+            .sections
+            .iter()
+            .find(|s| s.sh_type == SHT_SYMTAB)
+            .or_else(|| self.sections.iter().find(|s| s.sh_type == SHT_DYNSYM));
+        let Some(table) = table else {
+            return Ok(Vec::new());
+        };
+        let strings = match self.sections.get(table.link as usize) {
+            Some(strtab) => self.section_data(strtab)?,
+            None => &[],
+        };
+        let data = self.section_data(table)?;
+        let entry_size = if self.is_64 { 24 } else { 16 };
+        let mut symbols = Vec::with_capacity(data.len() / entry_size);
+        // the first entry is reserved
+        for i in 1..data.len() / entry_size {
+            let mut r = Reader::at(data, i * entry_size, self.endian, "symbol");
+            let name = r.u32()?;
+            // the value moved after the info in the 64-bit layout
+            let (value, size, info, other, shndx) = if self.is_64 {
+                let info = r.u8()?;
+                let other = r.u8()?;
+                let shndx = r.u16()?;
+                (r.u64()?, r.u64()?, info, other, shndx)
+            } else {
+                let value = r.u32()? as u64;
+                let size = r.u32()? as u64;
+                (value, size, r.u8()?, r.u8()?, r.u16()?)
+            };
+            symbols.push(ElfSymbol {
+                name: reader::cstr(strings, name as usize).unwrap_or_default(),
+                value,
+                size,
+                info,
+                other,
+                shndx,
+            });
+        }
+        Ok(symbols)
+    }
+}
@@ -0,0 +1,1319 @@
+// offline inspection of a binary file
+// does not need a running process so it works on any host
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+use std::process::exit;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use crate::coredump::{CoreError, CoreImage, CoreMemory, CoreThread, ElfCore, MachCore};
+use crate::dwarf::cfi::{CfaRule, CfiKind, CfiSection, RegisterRule, UnwindRow};
+use crate::dyld_cache::DyldCache;
+use crate::elf::note::format_build_id;
+use crate::elf::{self, Elf};
+use crate::logs;
+use crate::macho::codesign::{self, CodeDirectory, CodeSignature, SlotCheck, Verification};
+use crate::macho::cpu::Arch;
+use crate::macho::fixups::{self, BindKind, Import};
+use crate::macho::load_command::{format_source_version, format_uuid, format_version};
+use crate::macho::unwind_info::UnwindInfo;
+use crate::macho::{
+    self, DebugMap, FatBinary, Fixups, LoadCommand, MachO, cpu, dysymtab, exports, function_starts,
+    objc, swift,
+};
+use crate::mapped::{self, MappedFile};
+use crate::symbolize::{
+    CachedSymbols, DebugSearch, DemangleMode, Frame, LoadedImage, SymbolStore, Symbolizer, dsym,
+    store,
+};
+use crate::unwind::registers::{CpuFamily, register_name};
+use crate::unwind::{self, UnwindImage, Unwinder};
+
+pub fn run_inspect(
+    path: &str,
+    arch: Option<&str>,
+    show_symbols: bool,
+    show_imports: bool,
+    show_codesign: bool,
+    verify: bool,
+) {
+    let bytes = read_file(path);
+    if macho::fat::is_fat(&bytes) {
+        match FatBinary::parse(&bytes) {
+            Ok(fat) => {
+                println!("universal binary with {} slices:", fat.arches.len());
+                for slice in &fat.arches {
+                    println!(
+                        "    {:<10} cpu {:#x}/{:#x} file {:#x}+{:#x} align 2^{}",
+                        slice.name(),
+                        slice.cputype,
+                        slice.cpusubtype,
+                        slice.offset,
+                        slice.size,
+                        slice.align
+                    );
+                }
+            }
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                exit(1);
+            }
+        }
+    }
+    let binary = parse_image(path, &bytes, arch);
+
+    let header = &binary.header;
+    println!(
+        "architecture: {}",
+        macho::cpu::arch_name(header.cputype, header.cpusubtype)
+    );
+    println!("magic: {:#x} ({:?} endian)", header.magic, binary.endian);
+    println!(
+        "cpu type: {:#x} subtype: {:#x}",
+        header.cputype, header.cpusubtype
+    );
+    println!("file type: {:#x}", header.filetype);
+    println!("flags: {:#x}", header.flags);
+    println!(
+        "load commands: {} ({} bytes)",
+        header.ncmds, header.sizeofcmds
+    );
+    for (raw, lc) in binary.raw_load_commands.iter().zip(&binary.load_commands) {
+        println!(
+            "    {:#010x} {:<24} size {:<6} {}",
+            raw.offset,
+            lc.name(),
+            raw.cmdsize,
+            describe_load_command(lc)
+        );
+    }
+    println!("segments:");
+    for segment in binary.segments() {
+        println!(
+            "    {:<16} vm {:#x}-{:#x} file {:#x}+{:#x} sections {}",
+            segment.segname,
+            segment.vmaddr,
+            segment.vmaddr + segment.vmsize,
+            segment.fileoff,
+            segment.filesize,
+            segment.nsects
+        );
+        for section in &segment.sections {
+            println!(
+                "        {:<16} {:<16} addr {:#x} size {:#x} offset {:#x} align 2^{} {}",
+                section.segname,
+                section.sectname,
+                section.addr,
+                section.size,
+                section.offset,
+                section.align,
+                section.type_name()
+            );
+        }
+    }
+    match &binary.symtab {
+        Some(symtab) => println!(
+            "symbols: {} ({} bytes of strings)",
+            symtab.entries.len(),
+            symtab.command.strsize
+        ),
+        None => println!("symbols: none"),
+    }
+    match function_starts::function_starts(&binary) {
+        Ok(starts) if !starts.is_empty() => println!("function starts: {}", starts.len()),
+        Ok(_) => {}
+        Err(e) => logs::error_log_with_code(
+            "Cannot read the function starts:".to_string(),
+            e.to_string(),
+        ),
+    }
+    let fixups = match macho::fixups::fixups(&binary) {
+        Ok(fixups) => fixups,
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the fixups:".to_string(), e.to_string());
+            Fixups::default()
+        }
+    };
+    if !fixups.imports.is_empty() || !fixups.rebases.is_empty() {
+        println!(
+            "fixups: {} imports bound to {} slots, {} rebases",
+            fixups.imports.len(),
+            fixups.binds.len(),
+            fixups.rebases.len()
+        );
+    }
+    match exports::exports(&binary) {
+        Ok(exports) if !exports.is_empty() => println!("exports: {}", exports.len()),
+        Ok(_) => {}
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the export trie:".to_string(), e.to_string())
+        }
+    }
+    match objc::methods(&binary) {
+        Ok(methods) if !methods.is_empty() => println!("objc methods: {}", methods.len()),
+        Ok(_) => {}
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the objc metadata:".to_string(), e.to_string())
+        }
+    }
+    match swift::types(&binary) {
+        Ok(types) if !types.is_empty() => println!("swift types: {}", types.len()),
+        Ok(_) => {}
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the swift types:".to_string(), e.to_string())
+        }
+    }
+    match swift::conformances(&binary) {
+        Ok(conformances) if !conformances.is_empty() => {
+            println!("swift conformances: {}", conformances.len())
+        }
+        Ok(_) => {}
+        Err(e) => logs::error_log_with_code(
+            "Cannot read the swift conformances:".to_string(),
+            e.to_string(),
+        ),
+    }
+    let signature = match CodeSignature::from_macho(&binary) {
+        Ok(signature) => signature,
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the code signature:".to_string(), e.to_string());
+            None
+        }
+    };
+    if let Some(signature) = &signature {
+        println!(
+            "code signature: {} blobs, {}",
+            signature.blobs.len(),
+            if signature.cms().is_some() {
+                "signed"
+            } else {
+                "no cms signature"
+            }
+        );
+    }
+    if let Some(section) = binary.find_section("__TEXT", "__unwind_info") {
+        match binary
+            .section_data(section)
+            .and_then(|data| UnwindInfo::parse(data, binary.endian))
+            .and_then(|info| info.entries())
+        {
+            Ok(entries) => println!("compact unwind entries: {}", entries.len()),
+            Err(e) => logs::error_log_with_code(
+                "Cannot read the compact unwind info:".to_string(),
+                e.to_string(),
+            ),
+        }
+    }
+    if show_symbols {
+        print_symbols(&binary);
+    }
+    if show_imports {
+        print_imports(&binary, &fixups);
+    }
+    if show_codesign || verify {
+        match &signature {
+            Some(signature) => print_codesign(&binary, signature, verify),
+            None => println!("code signature: none"),
+        }
+    }
+}
+
+/// Prints the code directories, entitlements and requirements of the signature, and checks the
+/// hashes of the directories against the image when `verify` is set.
+fn print_codesign(binary: &MachO, signature: &CodeSignature, verify: bool) {
+    println!("code signature at {:#x}:", signature.offset);
+    for blob in &signature.blobs {
+        println!(
+            "    {:<28} magic {:#x} {} bytes",
+            codesign::slot_name(blob.slot),
+            blob.magic,
+            blob.data.len()
+        );
+    }
+    let directories = match signature.code_directories() {
+        Ok(directories) => directories,
+        Err(e) => {
+            logs::error_log_with_code(
+                "Cannot read the code directories:".to_string(),
+                e.to_string(),
+            );
+            Vec::new()
+        }
+    };
+    for cd in &directories {
+        println!("code directory {:#x}:", cd.version);
+        println!("    identifier: {}", cd.identifier);
+        println!("    team id: {}", cd.team_id.unwrap_or("none"));
+        println!(
+            "    hash: {}, {} bytes, page size {:#x}",
+            cd.hash_name(),
+            cd.hash_size,
+            cd.page_bytes()
+        );
+        match cd.cdhash() {
+            Ok(cdhash) => println!("    cdhash: {}", format_hash(&cdhash)),
+            Err(e) => println!("    cdhash: {}", e),
+        }
+        println!("    flags: {:#x} ({})", cd.flags, cd.flag_names());
+        println!(
+            "    hardened runtime: {}",
+            if cd.has_hardened_runtime() {
+                "yes"
+            } else {
+                "no"
+            }
+        );
+        if let Some(runtime) = cd.runtime.filter(|runtime| *runtime != 0) {
+            println!("    runtime version: {}", format_version(runtime));
+        }
+        println!(
+            "    code limit: {:#x}, {} pages, {} special slots",
+            cd.code_limit, cd.n_code_slots, cd.n_special_slots
+        );
+        if cd.version >= codesign::CS_SUPPORTSEXECSEG {
+            println!(
+                "    executable segment: {:#x}+{:#x} flags {:#x} ({})",
+                cd.exec_seg_base,
+                cd.exec_seg_limit,
+                cd.exec_seg_flags,
+                cd.exec_seg_flag_names()
+            );
+        }
+        if verify {
+            match cd.verify(binary, signature) {
+                Ok(verification) => print_verification(&verification, cd),
+                Err(e) => logs::error_log_with_code(
+                    "Cannot verify the code directory:".to_string(),
+                    e.to_string(),
+                ),
+            }
+        }
+    }
+    println!(
+        "get-task-allow: {}",
+        if signature.get_task_allow() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    if let Some(entitlements) = signature.entitlements() {
+        println!("entitlements:");
+        for line in entitlements.lines() {
+            println!("    {}", line);
+        }
+    }
+    if let Some(der) = signature.der_entitlements() {
+        println!("der entitlements: {} bytes", der.len());
+    }
+    match signature.requirements() {
+        Ok(Some(requirements)) => {
+            println!("requirements:");
+            for requirement in &requirements.requirements {
+                match requirement.decompile() {
+                    Ok(text) => println!("    {} => {}", requirement.kind_name(), text),
+                    Err(e) => println!("    {} => {}", requirement.kind_name(), e),
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            logs::error_log_with_code("Cannot read the requirements:".to_string(), e.to_string())
+        }
+    }
+    match signature.cms() {
+        Some(cms) => println!("cms signature: {} bytes", cms.len()),
+        None => println!("cms signature: none"),
+    }
+}
+
+fn print_verification(verification: &Verification, cd: &CodeDirectory) {
+    if verification.mismatched_pages.is_empty() {
+        println!("    pages: {} hashed, all match", verification.pages);
+    } else {
+        println!(
+            "    pages: {} hashed, {} mismatch:",
+            verification.pages,
+            verification.mismatched_pages.len()
+        );
+        for page in &verification.mismatched_pages {
+            println!(
+                "        page {} at {:#x}",
+                page,
+                *page as u64 * cd.page_bytes()
+            );
+        }
+    }
+    for (slot, check) in &verification.special_slots {
+        let check = match check {
+            SlotCheck::Match => "match",
+            SlotCheck::Mismatch => "mismatch",
+            SlotCheck::NotChecked => "not in the image",
+        };
+        println!("    {}: {}", codesign::slot_name(*slot), check);
+    }
+    println!(
+        "    signature: {}",
+        if verification.is_valid() {
+            "valid"
+        } else {
+            "invalid"
+        }
+    );
+}
+
+fn format_hash(hash: &[u8]) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Lists the imports by the dylib they come from, then every bound slot.
+fn print_imports(binary: &MachO, fixups: &Fixups) {
+    let mut ordinals: Vec<i32> = fixups.imports.iter().map(|i| i.library_ordinal).collect();
+    ordinals.sort_by_key(|ordinal| (*ordinal <= 0, ordinal.abs()));
+    ordinals.dedup();
+    println!("imports:");
+    for ordinal in ordinals {
+        println!("    {}", fixups::library_name(binary, ordinal));
+        let mut imports: Vec<&Import> = fixups
+            .imports
+            .iter()
+            .filter(|import| import.library_ordinal == ordinal)
+            .collect();
+        imports.sort_by_key(|import| (import.name, import.addend));
+        imports.dedup_by_key(|import| (import.name, import.weak));
+        for import in imports {
+            println!(
+                "        {}{}",
+                import.name,
+                if import.weak { " (weak import)" } else { "" }
+            );
+        }
+    }
+    println!("bindings:");
+    for bind in &fixups.binds {
+        let kind = match bind.kind {
+            BindKind::Regular => "",
+            BindKind::Weak => " (weak)",
+            BindKind::Lazy => " (lazy)",
+        };
+        println!("    {:#018x} {}{}", bind.address, fixups.label(bind), kind);
+    }
+}
+
+/// Dumps the symbol table with decoded types, the export trie, then the debug map built from
+/// its stabs.
+fn print_symbols(binary: &MachO) {
+    let Some(symtab) = &binary.symtab else {
+        return;
+    };
+    for nlist in &symtab.entries {
+        println!(
+            "    {:#018x} {:<40} {}",
+            nlist.n_value,
+            symtab.name(nlist).unwrap_or("<invalid name>"),
+            nlist.describe()
+        );
+    }
+    match dysymtab::indirect_entries(binary) {
+        Ok(entries) if !entries.is_empty() => {
+            println!("indirect symbols:");
+            for entry in entries {
+                println!("    {:#018x} {}", entry.address, entry.label());
+            }
+        }
+        Ok(_) => {}
+        Err(e) => logs::error_log_with_code(
+            "Cannot read the indirect symbols:".to_string(),
+            e.to_string(),
+        ),
+    }
+    if let Ok(exports) = exports::exports(binary)
+        && !exports.is_empty()
+    {
+        println!("exports:");
+        for export in exports {
+            let offset = match export.offset() {
+                Some(offset) => format!("{:#018x}", offset),
+                None => format!("{:>18}", "-"),
+            };
+            println!("    {} {:<40} {}", offset, export.name, export.describe());
+        }
+    }
+    if let Ok(methods) = objc::methods(binary)
+        && !methods.is_empty()
+    {
+        println!("objc methods:");
+        for method in methods {
+            println!("    {:#018x} {}", method.imp, method.name());
+        }
+    }
+    if let Ok(types) = swift::types(binary)
+        && !types.is_empty()
+    {
+        println!("swift types:");
+        for swift_type in types {
+            let accessor = match swift_type.accessor {
+                Some(accessor) => format!("{:#018x}", accessor),
+                None => format!("{:>18}", "-"),
+            };
+            println!(
+                "    {:#018x} {} {:<6} {}",
+                swift_type.descriptor,
+                accessor,
+                swift_type.kind.name(),
+                swift_type.name
+            );
+        }
+    }
+    if let Ok(conformances) = swift::conformances(binary)
+        && !conformances.is_empty()
+    {
+        println!("swift conformances:");
+        for conformance in conformances {
+            println!(
+                "    {:#018x} {} : {}",
+                conformance.descriptor, conformance.type_name, conformance.protocol
+            );
+        }
+    }
+    let debug_map = DebugMap::parse(symtab);
+    if debug_map.is_empty() {
+        return;
+    }
+    println!("debug map:");
+    for object in &debug_map.objects {
+        println!(
+            "    {} ({})",
+            object.object_path,
+            object.source.as_deref().unwrap_or("unknown source")
+        );
+        for function in &object.functions {
+            println!(
+                "        {:#018x} {:#8x} {}",
+                function.address, function.size, function.name
+            );
+        }
+    }
+}
+
+/// Resolves addresses of a binary to `image+offset symbol+offset`.
+///
+/// Without a load address the image is considered loaded at its preferred address, so the
+/// addresses are the ones found in the file.
+pub fn run_symbolize(
+    path: &str,
+    arch: Option<&str>,
+    load_address: Option<u64>,
+    search: &DebugSearch,
+    demangle: DemangleMode,
+    addresses: &[u64],
+) {
+    let bytes = read_file(path);
+    let binary = parse_image(path, &bytes, arch);
+    let preferred = binary
+        .segment("__TEXT")
+        .map(|text| text.vmaddr)
+        .unwrap_or(0);
+    let image = LoadedImage::new(path, load_address.unwrap_or(preferred), &binary);
+    let mut symbolizer = Symbolizer::load(image, &bytes, &binary, search);
+    symbolizer.set_demangle_mode(demangle);
+    for address in addresses {
+        for frame in symbolizer.symbolize(*address) {
+            println!("{}", frame);
+        }
+    }
+}
+
+/// Unwinds and symbolizes every thread of a Mach-O or ELF core file.
+///
+/// An image of the process is read from the first of `binaries` with its UUID or build-id, else
+/// from the path the core names, else rebuilt from the memory saved in the core.
+pub fn run_core(path: &str, binaries: &[String], search: &DebugSearch, demangle: DemangleMode) {
+    let bytes = read_file(path);
+    if elf::is_elf(&bytes) {
+        run_elf_core(path, &bytes, binaries, search, demangle);
+        return;
+    }
+    let core = match MachCore::parse(&bytes) {
+        Ok(core) => core,
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+            exit(1);
+        }
+    };
+    let header = &core.binary.header;
+    println!(
+        "core of {}: {} threads, {} memory regions, {} images",
+        cpu::arch_name(header.cputype, header.cpusubtype),
+        core.threads.len(),
+        core.memory.regions().len(),
+        core.images.len()
+    );
+
+    let candidates = candidate_images(binaries);
+    // the files first, the parsed images borrow from them
+    let mut files = Vec::new();
+    println!("images:");
+    for image in &core.images {
+        let uuid = image.uuid.as_ref().map_or("-".to_string(), format_uuid);
+        let name = image
+            .path
+            .clone()
+            .unwrap_or_else(|| format!("image@{:#x}", image.load_address));
+        match core_image_file(&core, image, &candidates) {
+            Ok((ImageFile { path, file, range }, source)) => {
+                let path = path.unwrap_or(name);
+                println!(
+                    "    {:#018x} {} {} ({})",
+                    image.load_address, uuid, path, source
+                );
+                files.push((image.load_address, path, file, range));
+            }
+            Err(e) => println!(
+                "    {:#018x} {} {} (not symbolized: {})",
+                image.load_address, uuid, name, e
+            ),
+        }
+    }
+    let mut unwind_images = Vec::new();
+    let mut symbolizers = Vec::new();
+    for (load_address, path, file, range) in &files {
+        let binary = match MachO::parse(&file[range.clone()]) {
+            Ok(binary) => binary,
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                continue;
+            }
+        };
+        let image = LoadedImage::new(path, *load_address, &binary);
+        unwind_images.push(UnwindImage::new(image.clone(), &binary));
+        let mut symbolizer = Symbolizer::load(image, file, &binary, search);
+        symbolizer.set_demangle_mode(demangle);
+        symbolizers.push(symbolizer);
+    }
+    let unwinder = Unwinder::new(unwind_images);
+    print_core_threads(&core.threads, &core.memory, &unwinder, &symbolizers);
+}
+
+/// `run_core` for the ELF core files of Linux.
+fn run_elf_core(
+    path: &str,
+    bytes: &[u8],
+    binaries: &[String],
+    search: &DebugSearch,
+    demangle: DemangleMode,
+) {
+    let core = match ElfCore::parse(bytes) {
+        Ok(core) => core,
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+            exit(1);
+        }
+    };
+    println!(
+        "core of {}: {} threads, {} memory regions, {} images",
+        elf::machine_name(core.binary.machine),
+        core.threads.len(),
+        core.memory.regions().len(),
+        core.images.len()
+    );
+
+    let candidates = elf_candidate_images(binaries);
+    // the files first, the parsed images borrow from them
+    let mut files = Vec::new();
+    println!("images:");
+    for image in &core.images {
+        let build_id = image
+            .build_id
+            .as_deref()
+            .map_or("-".to_string(), format_build_id);
+        let name = image
+            .path
+            .clone()
+            .unwrap_or_else(|| format!("image@{:#x}", image.load_address));
+        match elf_core_image_file(&core, image, &candidates) {
+            Ok((ImageFile { path, file, .. }, source)) => {
+                let path = path.unwrap_or(name);
+                println!(
+                    "    {:#018x} {} {} ({})",
+                    image.load_address, build_id, path, source
+                );
+                files.push((image.load_address, path, file));
+            }
+            Err(e) => println!(
+                "    {:#018x} {} {} (not symbolized: {})",
+                image.load_address, build_id, name, e
+            ),
+        }
+    }
+    let mut unwind_images = Vec::new();
+    let mut symbolizers = Vec::new();
+    for (load_address, path, file) in &files {
+        let binary = match Elf::parse(file) {
+            Ok(binary) => binary,
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                continue;
+            }
+        };
+        let image = LoadedImage::from_elf(path, *load_address, &binary);
+        unwind_images.push(UnwindImage::from_elf(image.clone(), &binary));
+        let mut symbolizer = Symbolizer::load_elf(image, file, &binary, search);
+        symbolizer.set_demangle_mode(demangle);
+        symbolizers.push(symbolizer);
+    }
+    let unwinder = Unwinder::new(unwind_images);
+    print_core_threads(&core.threads, &core.memory, &unwinder, &symbolizers);
+}
+
+/// Prints the symbolized stack of every thread of a core.
+fn print_core_threads(
+    threads: &[CoreThread],
+    memory: &CoreMemory,
+    unwinder: &Unwinder,
+    symbolizers: &[Symbolizer],
+) {
+    for thread in threads {
+        println!("thread #{}:", thread.id);
+        let addresses = unwinder.unwind(thread.registers.clone(), memory, unwind::MAX_FRAMES);
+        // the first address is the pc of the thread, the others return addresses
+        for (i, address) in addresses.into_iter().enumerate() {
+            let lookup = if i == 0 { address } else { address - 1 };
+            let frames = match symbolizers.iter().find(|s| s.image.contains(lookup)) {
+                Some(symbolizer) if i == 0 => symbolizer.symbolize(address),
+                Some(symbolizer) => symbolizer.symbolize_return_address(address),
+                None => vec![Frame::new(address)],
+            };
+            for frame in frames {
+                println!("    {}", frame);
+            }
+        }
+    }
+}
+
+/// A binary an image of a core is read from: its path, contents and the range of its slice.
+#[derive(Clone)]
+struct ImageFile {
+    path: Option<String>,
+    file: Arc<MappedFile>,
+    range: Range<usize>,
+}
+
+/// Binaries given to match the images of a core with, every slice of the universal ones, with
+/// their UUID.
+fn candidate_images(paths: &[String]) -> Vec<([u8; 16], ImageFile)> {
+    let mut candidates = Vec::new();
+    for path in paths {
+        let file = read_file(path);
+        let slices = if macho::fat::is_fat(&file) {
+            match FatBinary::parse(&file) {
+                Ok(fat) => fat.arches.iter().map(|a| fat.slice(a)).collect(),
+                Err(e) => {
+                    logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                    continue;
+                }
+            }
+        } else {
+            vec![file.data()]
+        };
+        for slice in slices {
+            if let Some(uuid) = MachO::parse(slice).ok().and_then(|binary| binary.uuid()) {
+                let range = mapped::range_in(file.data(), slice);
+                let image = ImageFile {
+                    path: Some(path.clone()),
+                    file: file.clone(),
+                    range,
+                };
+                candidates.push((uuid, image));
+            }
+        }
+    }
+    candidates
+}
+
+/// The file of an image of a core and where it comes from.
+fn core_image_file(
+    core: &MachCore,
+    image: &CoreImage,
+    candidates: &[([u8; 16], ImageFile)],
+) -> Result<(ImageFile, &'static str), CoreError> {
+    if let Some((_, candidate)) = candidates
+        .iter()
+        .find(|(uuid, _)| Some(*uuid) == image.uuid)
+    {
+        return Ok((candidate.clone(), "given"));
+    }
+    // the path of the image on this host, when it is the same build
+    if let Some(path) = &image.path
+        && let Ok(file) = MappedFile::open(path)
+    {
+        // the slices of the cpu type of the core, arm64 and arm64e builds have their own uuid
+        let target = Arch {
+            cputype: core.binary.header.cputype,
+            cpusubtype: None,
+        };
+        let slices = if macho::fat::is_fat(&file) {
+            FatBinary::parse(&file)
+                .map(|fat| {
+                    fat.arches
+                        .iter()
+                        .filter(|a| target.matches(a.cputype, a.cpusubtype))
+                        .map(|a| fat.slice(a))
+                        .collect()
+                })
+                .unwrap_or_default()
+        } else {
+            vec![file.data()]
+        };
+        let found = slices.into_iter().find(|slice| {
+            MachO::parse(slice)
+                .is_ok_and(|binary| image.uuid.is_none_or(|uuid| binary.uuid() == Some(uuid)))
+        });
+        if let Some(slice) = found {
+            let range = mapped::range_in(file.data(), slice);
+            let file = ImageFile {
+                path: Some(path.clone()),
+                file: Arc::new(file),
+                range,
+            };
+            return Ok((file, "on disk"));
+        }
+    }
+    let rebuilt = core.rebuild_image(image.load_address)?;
+    let file = ImageFile {
+        path: None,
+        range: 0..rebuilt.len(),
+        file: Arc::new(MappedFile::from_bytes(rebuilt)),
+    };
+    Ok((file, "rebuilt from the core"))
+}
+
+/// ELF files given to match the images of a core with, with their build-id.
+fn elf_candidate_images(paths: &[String]) -> Vec<(Vec<u8>, ImageFile)> {
+    let mut candidates = Vec::new();
+    for path in paths {
+        let file = read_file(path);
+        let build_id = match Elf::parse(&file) {
+            Ok(binary) => binary.build_id().map(<[u8]>::to_vec),
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                continue;
+            }
+        };
+        let Some(build_id) = build_id else {
+            logs::error_log(format!("{} has no build-id to be matched with", path));
+            continue;
+        };
+        let image = ImageFile {
+            path: Some(path.clone()),
+            range: 0..file.len(),
+            file,
+        };
+        candidates.push((build_id, image));
+    }
+    candidates
+}
+
+/// The file of an image of an ELF core and where it comes from.
+fn elf_core_image_file(
+    core: &ElfCore,
+    image: &CoreImage,
+    candidates: &[(Vec<u8>, ImageFile)],
+) -> Result<(ImageFile, &'static str), CoreError> {
+    if let Some((_, candidate)) = candidates
+        .iter()
+        .find(|(build_id, _)| Some(build_id) == image.build_id.as_ref())
+    {
+        return Ok((candidate.clone(), "given"));
+    }
+    // the mapped file on this host, when it is the same build
+    if let Some(path) = &image.path
+        && let Ok(file) = MappedFile::open(path)
+        && Elf::parse(&file).is_ok_and(|binary| {
+            image
+                .build_id
+                .as_deref()
+                .is_none_or(|build_id| binary.build_id() == Some(build_id))
+        })
+    {
+        let file = ImageFile {
+            path: Some(path.clone()),
+            range: 0..file.len(),
+            file: Arc::new(file),
+        };
+        return Ok((file, "on disk"));
+    }
+    let rebuilt = core.rebuild_image(image.load_address)?;
+    let file = ImageFile {
+        path: None,
+        range: 0..rebuilt.len(),
+        file: Arc::new(MappedFile::from_bytes(rebuilt)),
+    };
+    Ok((file, "rebuilt from the core"))
+}
+
+/// Adds the symbols of Mach-O or ELF files to the symbol store, every slice of a universal
+/// binary unless `arch` selects one.
+pub fn run_symbols_add(paths: &[String], arch: Option<&str>, search: &DebugSearch) {
+    let store = open_store(search);
+    for path in paths {
+        let bytes = read_file(path);
+        let name = path.rsplit('/').next().unwrap_or(path);
+        let mut added = Vec::new();
+        if elf::is_elf(&bytes) {
+            let binary = match Elf::parse(&bytes) {
+                Ok(binary) => binary,
+                Err(e) => {
+                    logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                    continue;
+                }
+            };
+            let Some(build_id) = binary.build_id() else {
+                logs::error_log(format!("{} has no build-id to be stored under", path));
+                continue;
+            };
+            let machine = elf::machine_name(binary.machine);
+            let symbols = CachedSymbols::from_elf(name, &machine, &binary);
+            added.push((store::elf_key(build_id), symbols));
+        } else {
+            let slices = match (macho::fat::is_fat(&bytes), arch) {
+                (true, None) => match FatBinary::parse(&bytes) {
+                    Ok(fat) => fat.arches.iter().map(|a| fat.slice(a)).collect(),
+                    Err(e) => {
+                        logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                        continue;
+                    }
+                },
+                _ => vec![parse_image(path, &bytes, arch).data()],
+            };
+            for slice in slices {
+                let binary = match MachO::parse(slice) {
+                    Ok(binary) => binary,
+                    Err(e) => {
+                        logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                        continue;
+                    }
+                };
+                let Some(uuid) = binary.uuid() else {
+                    logs::error_log(format!("{} has no LC_UUID to be stored under", path));
+                    continue;
+                };
+                let arch = cpu::arch_name(binary.header.cputype, binary.header.cpusubtype);
+                let image = LoadedImage::new(path, 0, &binary);
+                let symbols = Symbolizer::build(image, &bytes, &binary, search).cached(&arch);
+                added.push((store::macho_key(&uuid), symbols));
+            }
+        }
+        for (key, symbols) in added {
+            match store.insert(&key, &symbols) {
+                Ok(_) => println!(
+                    "{} {} {}: {} symbols, {} line sequences, {} functions",
+                    key,
+                    symbols.arch,
+                    symbols.name,
+                    symbols.index.len(),
+                    symbols.lines.sequences().len(),
+                    symbols.functions.functions().len()
+                ),
+                Err(e) => logs::error_log_with_code(
+                    format!("Cannot store the symbols of {}:", path),
+                    e.to_string(),
+                ),
+            }
+        }
+    }
+}
+
+/// Lists the indexes of the symbol store.
+pub fn run_symbols_list(search: &DebugSearch) {
+    let store = open_store(search);
+    let entries = match store.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            logs::error_log_with_code("Cannot list the symbol store:".to_string(), e.to_string());
+            exit(1);
+        }
+    };
+    println!("{}: {} entries", store.root().display(), entries.len());
+    let now = SystemTime::now();
+    for (key, entry) in entries {
+        match entry {
+            Ok(entry) => {
+                let days = now
+                    .duration_since(entry.last_used)
+                    .map(|age| age.as_secs() / SECONDS_PER_DAY)
+                    .unwrap_or(0);
+                println!(
+                    "    {:<40} {:<8} {:<24} {:>8} symbols {:>8} sequences {:>8} functions {:>10} bytes, used {} days ago",
+                    key,
+                    entry.arch,
+                    entry.name,
+                    entry.symbols,
+                    entry.sequences,
+                    entry.functions,
+                    entry.size,
+                    days
+                );
+            }
+            Err(e) => println!("    {:<40} unreadable: {}", key, e),
+        }
+    }
+}
+
+/// Removes the indexes of the symbol store unused for `days` days and the unreadable ones.
+pub fn run_symbols_prune(search: &DebugSearch, days: u64) {
+    let store = open_store(search);
+    match store.prune(Duration::from_secs(days * SECONDS_PER_DAY)) {
+        Ok(removed) => {
+            for key in &removed {
+                println!("removed {}", key);
+            }
+            println!("{} entries removed", removed.len());
+        }
+        Err(e) => {
+            logs::error_log_with_code("Cannot prune the symbol store:".to_string(), e.to_string());
+            exit(1);
+        }
+    }
+}
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+fn open_store(search: &DebugSearch) -> SymbolStore {
+    match &search.store {
+        Some(root) => SymbolStore::new(root),
+        None => {
+            logs::error_log(format!(
+                "No symbol store: set {} to its directory",
+                dsym::SYMBOL_STORE_ENV
+            ));
+            exit(1);
+        }
+    }
+}
+
+/// Prints the DWARF call frame rules in effect at file addresses of a Mach-O or ELF binary.
+pub fn run_cfi(path: &str, arch: Option<&str>, addresses: &[u64]) {
+    let bytes = read_file(path);
+    let (family, sections) = if elf::is_elf(&bytes) {
+        let binary = match Elf::parse(&bytes) {
+            Ok(binary) => binary,
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+                exit(1);
+            }
+        };
+        let address_size = if binary.is_64 { 8 } else { 4 };
+        let sections = [
+            (".eh_frame", CfiKind::EhFrame),
+            (".debug_frame", CfiKind::DebugFrame),
+        ]
+        .into_iter()
+        .filter_map(|(name, kind)| {
+            let section = binary.section(name)?;
+            let data = binary.section_data(section).ok()?;
+            Some(CfiSection::new(
+                kind,
+                data,
+                section.addr,
+                binary.endian,
+                address_size,
+            ))
+        })
+        .collect::<Vec<_>>();
+        (CpuFamily::from_elf_machine(binary.machine), sections)
+    } else {
+        let binary = parse_image(path, &bytes, arch);
+        let address_size = if binary.header.is_64() { 8 } else { 4 };
+        let sections = binary
+            .find_section("__TEXT", "__eh_frame")
+            .and_then(|section| {
+                let data = binary.section_data(section).ok()?;
+                Some(CfiSection::new(
+                    CfiKind::EhFrame,
+                    data,
+                    section.addr,
+                    binary.endian,
+                    address_size,
+                ))
+            })
+            .into_iter()
+            .collect::<Vec<_>>();
+        (CpuFamily::from_cputype(binary.header.cputype), sections)
+    };
+    if sections.is_empty() {
+        logs::error_log(format!("{} has no call frame information", path));
+        exit(1);
+    }
+
+    for address in addresses {
+        let found = sections
+            .iter()
+            .find_map(|section| match section.find_fde(*address) {
+                Ok(fde) => fde.map(|fde| (section, fde)),
+                Err(e) => {
+                    logs::error_log_with_code(
+                        "Cannot read the call frame information:".to_string(),
+                        e.to_string(),
+                    );
+                    None
+                }
+            });
+        let Some((section, fde)) = found else {
+            println!("{:#x}: no FDE", address);
+            continue;
+        };
+        println!(
+            "{:#x}: FDE at {:#x} covers {:#x}-{:#x} (CIE at {:#x}, augmentation \"{}\")",
+            address,
+            fde.offset,
+            fde.initial_location,
+            fde.end(),
+            fde.cie.offset,
+            fde.cie.augmentation
+        );
+        match fde.unwind_row(section, *address) {
+            Ok(row) => println!(
+                "    {:#x}-{:#x} {}",
+                row.start,
+                row.end,
+                describe_row(&row, family)
+            ),
+            Err(e) => logs::error_log_with_code(
+                "Cannot run the call frame instructions:".to_string(),
+                e.to_string(),
+            ),
+        }
+    }
+}
+
+/// `cfa=rsp+16 rbp=[cfa-16] rip=[cfa-8]`, the registers sorted by number.
+fn describe_row(row: &UnwindRow, family: Option<CpuFamily>) -> String {
+    let mut out = match row.cfa {
+        CfaRule::RegisterOffset { register, offset } => {
+            format!("cfa={}{:+}", register_name(family, register), offset)
+        }
+        CfaRule::Expression(expression) => format!("cfa=expr({})", expression.len()),
+    };
+    let mut registers = row.registers.clone();
+    registers.sort_by_key(|(register, _)| *register);
+    for (register, rule) in registers {
+        let rule = match rule {
+            RegisterRule::Undefined => "undefined".to_string(),
+            RegisterRule::SameValue => "same".to_string(),
+            RegisterRule::Offset(offset) => format!("[cfa{:+}]", offset),
+            RegisterRule::ValOffset(offset) => format!("cfa{:+}", offset),
+            RegisterRule::Register(source) => register_name(family, source),
+            RegisterRule::Expression(expression) => format!("[expr({})]", expression.len()),
+            RegisterRule::ValExpression(expression) => format!("expr({})", expression.len()),
+        };
+        out.push_str(&format!(" {}={}", register_name(family, register), rule));
+    }
+    out
+}
+
+pub fn run_cache(path: &str, names: &[String], show_symbols: bool, output: Option<&str>) {
+    let cache = match DyldCache::open(Path::new(path)) {
+        Ok(cache) => cache,
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+            exit(1);
+        }
+    };
+    if names.is_empty() {
+        print_cache(&cache);
+        return;
+    }
+    for name in names {
+        let image = match cache.image(name) {
+            Ok(image) => image,
+            Err(e) => {
+                logs::error_log(e.to_string());
+                continue;
+            }
+        };
+        let bytes = match cache.extract_image(image) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                logs::error_log_with_code(format!("Cannot extract {}:", image.path), e.to_string());
+                continue;
+            }
+        };
+        let binary = match MachO::parse(&bytes) {
+            Ok(binary) => binary,
+            Err(e) => {
+                logs::error_log_with_code(
+                    format!("Cannot parse the extracted {}:", image.path),
+                    e.to_string(),
+                );
+                continue;
+            }
+        };
+        let locals = binary.dysymtab().map_or(0, |dysymtab| dysymtab.nlocalsym);
+        println!(
+            "{} at {:#x}: {} bytes, {} symbols, {} local",
+            image.path,
+            image.address,
+            bytes.len(),
+            binary
+                .symtab
+                .as_ref()
+                .map_or(0, |symtab| symtab.entries.len()),
+            locals
+        );
+        if show_symbols {
+            print_symbols(&binary);
+        }
+        if let Some(directory) = output {
+            let file = Path::new(directory).join(image.name());
+            match fs::create_dir_all(directory).and_then(|_| fs::write(&file, &bytes)) {
+                Ok(()) => println!("    written to {}", file.display()),
+                Err(e) => logs::error_log_with_code(
+                    format!("Cannot write {}:", file.display()),
+                    e.to_string(),
+                ),
+            }
+        }
+    }
+}
+
+fn print_cache(cache: &DyldCache) {
+    let header = cache.header();
+    println!("arch: {}", header.arch());
+    println!("uuid: {}", format_uuid(&header.uuid));
+    println!("format version: {}", header.format_version);
+    println!(
+        "shared region: {:#x} size {:#x}",
+        header.shared_region_start, header.shared_region_size
+    );
+    for file in &cache.files {
+        println!("{}:", file.path.display());
+        for mapping in &file.mappings {
+            println!(
+                "    {:#018x}-{:#018x} {} file offset {:#x}",
+                mapping.address,
+                mapping.address + mapping.size,
+                mapping.protection(),
+                mapping.file_offset
+            );
+        }
+    }
+    for entry in &cache.sub_caches {
+        println!(
+            "subcache {}: {} at offset {:#x}",
+            entry.suffix,
+            format_uuid(&entry.uuid),
+            entry.vm_offset
+        );
+    }
+    match &cache.symbols_file {
+        Some(file) => println!("local symbols in {}", file.path.display()),
+        None if header.symbol_file_uuid != [0; 16] => {
+            println!("local symbols file missing")
+        }
+        None => {}
+    }
+    println!("images ({}):", cache.images.len());
+    for image in &cache.images {
+        println!("    {:#018x} {}", image.address, image.path);
+    }
+}
+
+fn read_file(path: &str) -> Arc<MappedFile> {
+    match MappedFile::open(path) {
+        Ok(file) => Arc::new(file),
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot read {}:", path), e.to_string());
+            exit(1);
+        }
+    }
+}
+
+/// Selects the slice to use in `bytes` and parses it, exits on error.
+fn parse_image<'a>(path: &str, bytes: &'a [u8], arch: Option<&str>) -> MachO<'a> {
+    let image = match macho::select_slice(bytes, arch, None) {
+        Ok(image) => image,
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot select a slice of {}:", path), e.to_string());
+            exit(1);
+        }
+    };
+    match MachO::parse(image) {
+        Ok(binary) => binary,
+        Err(e) => {
+            logs::error_log_with_code(format!("Cannot parse {}:", path), e.to_string());
+            exit(1);
+        }
+    }
+}
+
+/// One line summary of the interesting fields of a load command.
+fn describe_load_command(lc: &LoadCommand) -> String {
+    match lc {
+        LoadCommand::Segment(segment) | LoadCommand::Segment64(segment) => segment.segname.clone(),
+        LoadCommand::Symtab(symtab) => format!(
+            "{} symbols at {:#x}, strings at {:#x}",
+            symtab.nsyms, symtab.symoff, symtab.stroff
+        ),
+        LoadCommand::Dysymtab(dysymtab) => format!(
+            "{} local, {} defined, {} undefined, {} indirect",
+            dysymtab.nlocalsym, dysymtab.nextdefsym, dysymtab.nundefsym, dysymtab.nindirectsyms
+        ),
+        LoadCommand::Uuid(uuid) => format_uuid(uuid),
+        LoadCommand::BuildVersion(build) => format!(
+            "platform {} minos {} sdk {}",
+            build.platform,
+            format_version(build.minos),
+            format_version(build.sdk)
+        ),
+        LoadCommand::VersionMinMacosx(version)
+        | LoadCommand::VersionMinIphoneos(version)
+        | LoadCommand::VersionMinTvos(version)
+        | LoadCommand::VersionMinWatchos(version) => format!(
+            "version {} sdk {}",
+            format_version(version.version),
+            format_version(version.sdk)
+        ),
+        LoadCommand::Main { entryoff, .. } => format!("entry {:#x}", entryoff),
+        LoadCommand::LoadDylib(dylib)
+        | LoadCommand::LoadWeakDylib(dylib)
+        | LoadCommand::ReexportDylib(dylib)
+        | LoadCommand::LazyLoadDylib(dylib)
+        | LoadCommand::LoadUpwardDylib(dylib)
+        | LoadCommand::IdDylib(dylib) => {
+            format!("{} ({})", dylib.name, format_version(dylib.current_version))
+        }
+        LoadCommand::LoadDylinker(name)
+        | LoadCommand::IdDylinker(name)
+        | LoadCommand::Rpath(name) => name.to_string(),
+        LoadCommand::FunctionStarts(data)
+        | LoadCommand::DataInCode(data)
+        | LoadCommand::CodeSignature(data)
+        | LoadCommand::SegmentSplitInfo(data)
+        | LoadCommand::DyldChainedFixups(data)
+        | LoadCommand::DyldExportsTrie(data) => {
+            format!("{} bytes at {:#x}", data.datasize, data.dataoff)
+        }
+        LoadCommand::DyldInfo(info) | LoadCommand::DyldInfoOnly(info) => format!(
+            "rebase {} bind {} lazy {} export {} bytes",
+            info.rebase_size, info.bind_size, info.lazy_bind_size, info.export_size
+        ),
+        LoadCommand::SourceVersion(version) => format_source_version(*version),
+        LoadCommand::EncryptionInfo(info) | LoadCommand::EncryptionInfo64(info) => format!(
+            "{} bytes at {:#x}, cryptid {}",
+            info.cryptsize, info.cryptoff, info.cryptid
+        ),
+        LoadCommand::Thread(states) | LoadCommand::UnixThread(states) => states
+            .iter()
+            .map(|state| format!("flavor {} ({} words)", state.flavor, state.state.len() / 4))
+            .collect::<Vec<_>>()
+            .join(", "),
+        LoadCommand::Note(note) => format!(
+            "{} {} bytes at {:#x}",
+            note.data_owner, note.size, note.offset
+        ),
+        LoadCommand::Unknown { data, .. } => format!("{} bytes", data.len()),
+    }
+}
@@ -5,6 +5,11 @@ pub fn info_log(msg: String) {
     println!("{} {}", info, msg);
 }
 
+pub fn warning_log(msg: String) {
+    let info = "[WARNING]".truecolor(255, 200, 0);
+    println!("{} {}", info, msg);
+}
+
 pub fn error_log(msg: String) {
     let info = "[ERROR]".truecolor(255, 0, 0);
     println!("{} {}", info, msg);
@@ -0,0 +1,420 @@
+// LC_DYLD_CHAINED_FIXUPS
+// the blob lists the imports and, for each page of each segment, the offset of the first pointer
+// to fix up. Every such pointer encodes whether it is a bind or a rebase, its target and the
+// distance to the next one, so the chain of a page is walked through the segment data itself
+// layouts from <mach-o/fixup-chains.h>
+
+use super::fixups::{BIND_SPECIAL_DYLIB_WEAK_LOOKUP, BindKind, Fixups, Import, Rebase};
+use super::load_command::LinkeditData;
+use super::reader::{self, Reader};
+use super::{MachO, MachOError, Segment};
+
+pub const DYLD_CHAINED_IMPORT: u32 = 1;
+pub const DYLD_CHAINED_IMPORT_ADDEND: u32 = 2;
+pub const DYLD_CHAINED_IMPORT_ADDEND64: u32 = 3;
+
+pub const DYLD_CHAINED_PTR_ARM64E: u16 = 1;
+pub const DYLD_CHAINED_PTR_64: u16 = 2;
+pub const DYLD_CHAINED_PTR_32: u16 = 3;
+pub const DYLD_CHAINED_PTR_32_CACHE: u16 = 4;
+pub const DYLD_CHAINED_PTR_32_FIRMWARE: u16 = 5;
+pub const DYLD_CHAINED_PTR_64_OFFSET: u16 = 6;
+pub const DYLD_CHAINED_PTR_ARM64E_USERLAND: u16 = 9;
+pub const DYLD_CHAINED_PTR_ARM64E_USERLAND24: u16 = 12;
+
+// values of the page starts
+pub const DYLD_CHAINED_PTR_START_NONE: u16 = 0xffff;
+pub const DYLD_CHAINED_PTR_START_MULTI: u16 = 0x8000;
+pub const DYLD_CHAINED_PTR_START_LAST: u16 = 0x8000;
+
+// size of dyld_chained_starts_in_segment up to the page starts
+const STARTS_IN_SEGMENT_SIZE: usize = 22;
+
+/// Header of the LC_DYLD_CHAINED_FIXUPS blob, offsets are from the start of the blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainedFixupsHeader {
+    pub fixups_version: u32,
+    pub starts_offset: u32,
+    pub imports_offset: u32,
+    pub symbols_offset: u32,
+    pub imports_count: u32,
+    pub imports_format: u32,
+    pub symbols_format: u32,
+}
+
+/// Where the chains of a segment start, from `dyld_chained_starts_in_segment`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentStarts {
+    pub page_size: u16,
+    pub pointer_format: u16,
+    /// Offset of the segment from the mach header, in memory.
+    pub segment_offset: u64,
+    /// 32-bit formats only: larger targets are not pointers but plain values.
+    pub max_valid_pointer: u32,
+    pub page_count: u16,
+    /// Offset of the first fixup of each page, followed by the extra starts of the 32-bit
+    /// formats.
+    pub page_starts: Vec<u16>,
+}
+
+/// Decodes the imports and walks every chain of the blob of `linkedit`.
+pub fn parse<'a>(binary: &MachO<'a>, linkedit: &LinkeditData) -> Result<Fixups<'a>, MachOError> {
+    let data = reader::slice(
+        binary.data(),
+        linkedit.dataoff as usize,
+        linkedit.datasize as usize,
+        "chained fixups",
+    )?;
+    let mut r = Reader::new(data, binary.endian, "chained fixups");
+    let header = ChainedFixupsHeader {
+        fixups_version: r.u32()?,
+        starts_offset: r.u32()?,
+        imports_offset: r.u32()?,
+        symbols_offset: r.u32()?,
+        imports_count: r.u32()?,
+        imports_format: r.u32()?,
+        symbols_format: r.u32()?,
+    };
+    if header.fixups_version != 0 {
+        return Err(MachOError::UnsupportedChainedFixups {
+            what: "version",
+            value: header.fixups_version,
+        });
+    }
+    // 1 is zlib, never produced by the linker
+    if header.symbols_format != 0 {
+        return Err(MachOError::UnsupportedChainedFixups {
+            what: "symbols format",
+            value: header.symbols_format,
+        });
+    }
+    let imports = parse_imports(binary, data, &header)?;
+    let base = binary.segment("__TEXT").map_or(0, |text| text.vmaddr);
+    let segments: Vec<&Segment> = binary.segments().collect();
+
+    let mut fixups = Fixups::default();
+    for (index, starts) in segment_starts(binary, data, &header)? {
+        let segment = segments
+            .get(index)
+            .ok_or(MachOError::MalformedFixups("segment index out of range"))?;
+        let walker = ChainWalker {
+            binary,
+            segment,
+            starts: &starts,
+            imports: &imports,
+            base,
+        };
+        walker.walk(&mut fixups)?;
+    }
+    Ok(fixups)
+}
+
+/// The `dyld_chained_starts_in_segment` of every segment with fixups, with the segment index.
+pub fn segment_starts(
+    binary: &MachO,
+    data: &[u8],
+    header: &ChainedFixupsHeader,
+) -> Result<Vec<(usize, SegmentStarts)>, MachOError> {
+    let starts_offset = header.starts_offset as usize;
+    let mut r = Reader::at(data, starts_offset, binary.endian, "chained starts");
+    let seg_count = r.u32()?;
+    let mut segments = Vec::new();
+    for index in 0..seg_count as usize {
+        let info_offset = r.u32()? as usize;
+        if info_offset == 0 {
+            continue;
+        }
+        let start = starts_offset + info_offset;
+        let mut s = Reader::at(data, start, binary.endian, "chained starts in segment");
+        let size = s.u32()? as usize;
+        let page_size = s.u16()?;
+        let pointer_format = s.u16()?;
+        let segment_offset = s.u64()?;
+        let max_valid_pointer = s.u32()?;
+        let page_count = s.u16()?;
+        // the starts of the 32-bit formats go on after page_count
+        let count = (size.saturating_sub(STARTS_IN_SEGMENT_SIZE) / 2).max(page_count as usize);
+        let page_starts = (0..count).map(|_| s.u16()).collect::<Result<_, _>>()?;
+        segments.push((
+            index,
+            SegmentStarts {
+                page_size,
+                pointer_format,
+                segment_offset,
+                max_valid_pointer,
+                page_count,
+                page_starts,
+            },
+        ));
+    }
+    Ok(segments)
+}
+
+fn parse_imports<'a>(
+    binary: &MachO,
+    data: &'a [u8],
+    header: &ChainedFixupsHeader,
+) -> Result<Vec<Import<'a>>, MachOError> {
+    let mut r = Reader::at(
+        data,
+        header.imports_offset as usize,
+        binary.endian,
+        "chained imports",
+    );
+    let symbols = data
+        .get(header.symbols_offset as usize..)
+        .ok_or(MachOError::MalformedFixups(
+            "symbols past the end of the blob",
+        ))?;
+    let mut imports = Vec::new();
+    for _ in 0..header.imports_count {
+        let (ordinal, weak, name_offset, addend) = match header.imports_format {
+            DYLD_CHAINED_IMPORT | DYLD_CHAINED_IMPORT_ADDEND => {
+                let import = r.u32()?;
+                let addend = if header.imports_format == DYLD_CHAINED_IMPORT_ADDEND {
+                    r.u32()? as i32 as i64
+                } else {
+                    0
+                };
+                // the special ordinals are sign extended from the 8-bit field
+                let ordinal = (import & 0xff) as u8;
+                let ordinal = if ordinal >= 0xf0 {
+                    ordinal as i8 as i32
+                } else {
+                    ordinal as i32
+                };
+                (ordinal, import & 0x100 != 0, import >> 9, addend)
+            }
+            DYLD_CHAINED_IMPORT_ADDEND64 => {
+                let import = r.u64()?;
+                let addend = r.u64()? as i64;
+                let ordinal = (import & 0xffff) as u16;
+                let ordinal = if ordinal >= 0xfff0 {
+                    ordinal as i16 as i32
+                } else {
+                    ordinal as i32
+                };
+                (
+                    ordinal,
+                    import & 0x1_0000 != 0,
+                    (import >> 32) as u32,
+                    addend,
+                )
+            }
+            format => {
+                return Err(MachOError::UnsupportedChainedFixups {
+                    what: "imports format",
+                    value: format,
+                });
+            }
+        };
+        let name =
+            reader::cstr(symbols, name_offset as usize).ok_or(MachOError::InvalidString {
+                offset: header.symbols_offset as usize + name_offset as usize,
+            })?;
+        imports.push(Import {
+            name,
+            library_ordinal: ordinal,
+            addend,
+            weak,
+        });
+    }
+    Ok(imports)
+}
+
+// a pointer of a chain, decoded
+enum ChainedPointer {
+    Bind { ordinal: u32, addend: i64 },
+    Rebase { target: u64 },
+    // a 32-bit value that only looks like a rebase
+    Value,
+}
+
+// walks the chains of one segment
+struct ChainWalker<'w, 'a> {
+    binary: &'w MachO<'a>,
+    segment: &'w Segment,
+    starts: &'w SegmentStarts,
+    imports: &'w [Import<'a>],
+    // address of the mach header, the offsets of some formats are relative to it
+    base: u64,
+}
+
+impl<'a> ChainWalker<'_, 'a> {
+    fn walk(&self, fixups: &mut Fixups<'a>) -> Result<(), MachOError> {
+        let starts = &self.starts.page_starts;
+        for page in 0..self.starts.page_count as usize {
+            let start = starts[page];
+            if start == DYLD_CHAINED_PTR_START_NONE {
+                continue;
+            }
+            let page_offset = page as u64 * self.starts.page_size as u64;
+            if !self.is_32() || start & DYLD_CHAINED_PTR_START_MULTI == 0 {
+                self.walk_chain(page_offset + start as u64, fixups)?;
+                continue;
+            }
+            // several chains in the page, their starts follow the page starts
+            let mut index = (start & !DYLD_CHAINED_PTR_START_MULTI) as usize;
+            loop {
+                let start = *starts
+                    .get(index)
+                    .ok_or(MachOError::MalformedFixups("chain start out of range"))?;
+                self.walk_chain(
+                    page_offset + (start & !DYLD_CHAINED_PTR_START_LAST) as u64,
+                    fixups,
+                )?;
+                if start & DYLD_CHAINED_PTR_START_LAST != 0 {
+                    break;
+                }
+                index += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_32(&self) -> bool {
+        matches!(
+            self.starts.pointer_format,
+            DYLD_CHAINED_PTR_32 | DYLD_CHAINED_PTR_32_CACHE | DYLD_CHAINED_PTR_32_FIRMWARE
+        )
+    }
+
+    // bytes between two pointers counted by `next`
+    fn stride(&self) -> Result<u64, MachOError> {
+        match self.starts.pointer_format {
+            DYLD_CHAINED_PTR_ARM64E
+            | DYLD_CHAINED_PTR_ARM64E_USERLAND
+            | DYLD_CHAINED_PTR_ARM64E_USERLAND24 => Ok(8),
+            DYLD_CHAINED_PTR_64 | DYLD_CHAINED_PTR_64_OFFSET | DYLD_CHAINED_PTR_32 => Ok(4),
+            format => Err(MachOError::UnsupportedChainedFixups {
+                what: "pointer format",
+                value: format as u32,
+            }),
+        }
+    }
+
+    // `offset` is relative to the start of the segment
+    fn walk_chain(&self, mut offset: u64, fixups: &mut Fixups<'a>) -> Result<(), MachOError> {
+        let stride = self.stride()?;
+        loop {
+            if offset >= self.segment.filesize {
+                return Err(MachOError::MalformedFixups(
+                    "chain past the end of its segment",
+                ));
+            }
+            let size = if self.is_32() { 4 } else { 8 };
+            let bytes = reader::slice64(
+                self.binary.data(),
+                self.segment.fileoff + offset,
+                size,
+                "chained pointer",
+            )?;
+            let raw = Reader::new(bytes, self.binary.endian, "chained pointer").word(size == 8)?;
+            let (pointer, next) = self.decode(raw);
+            let address = self.segment.vmaddr + offset;
+            match pointer {
+                ChainedPointer::Bind { ordinal, addend } => {
+                    let mut import = *self
+                        .imports
+                        .get(ordinal as usize)
+                        .ok_or(MachOError::MalformedFixups("bind to an unknown import"))?;
+                    import.addend = import.addend.wrapping_add(addend);
+                    let kind = if import.library_ordinal == BIND_SPECIAL_DYLIB_WEAK_LOOKUP {
+                        BindKind::Weak
+                    } else {
+                        BindKind::Regular
+                    };
+                    fixups.bind(address, import, kind);
+                }
+                ChainedPointer::Rebase { target } => {
+                    fixups.rebases.push(Rebase { address, target });
+                }
+                ChainedPointer::Value => {}
+            }
+            if next == 0 {
+                return Ok(());
+            }
+            offset += next * stride;
+        }
+    }
+
+    // the pointer and the number of strides to the next one
+    fn decode(&self, raw: u64) -> (ChainedPointer, u64) {
+        let bits = |shift: u32, width: u32| (raw >> shift) & ((1 << width) - 1);
+        let sign_extend = |value: u64, width: u32| ((value << (64 - width)) as i64) >> (64 - width);
+        match self.starts.pointer_format {
+            DYLD_CHAINED_PTR_ARM64E
+            | DYLD_CHAINED_PTR_ARM64E_USERLAND
+            | DYLD_CHAINED_PTR_ARM64E_USERLAND24 => {
+                let auth = bits(63, 1) != 0;
+                let bind = bits(62, 1) != 0;
+                let next = bits(51, 11);
+                let ordinal_width =
+                    if self.starts.pointer_format == DYLD_CHAINED_PTR_ARM64E_USERLAND24 {
+                        24
+                    } else {
+                        16
+                    };
+                let pointer = match (bind, auth) {
+                    (true, false) => ChainedPointer::Bind {
+                        ordinal: bits(0, ordinal_width) as u32,
+                        addend: sign_extend(bits(32, 19), 19),
+                    },
+                    (true, true) => ChainedPointer::Bind {
+                        ordinal: bits(0, ordinal_width) as u32,
+                        addend: 0,
+                    },
+                    // authenticated targets are always offsets from the mach header
+                    (false, true) => ChainedPointer::Rebase {
+                        target: self.base + bits(0, 32),
+                    },
+                    (false, false) => {
+                        let target = bits(0, 43) | bits(43, 8) << 56;
+                        let target = if self.starts.pointer_format == DYLD_CHAINED_PTR_ARM64E {
+                            target
+                        } else {
+                            self.base + target
+                        };
+                        ChainedPointer::Rebase { target }
+                    }
+                };
+                (pointer, next)
+            }
+            DYLD_CHAINED_PTR_64 | DYLD_CHAINED_PTR_64_OFFSET => {
+                let next = bits(51, 12);
+                let pointer = if bits(63, 1) != 0 {
+                    ChainedPointer::Bind {
+                        ordinal: bits(0, 24) as u32,
+                        addend: bits(24, 8) as i64,
+                    }
+                } else {
+                    let target = bits(0, 36) | bits(36, 8) << 56;
+                    let target = if self.starts.pointer_format == DYLD_CHAINED_PTR_64 {
+                        target
+                    } else {
+                        self.base + target
+                    };
+                    ChainedPointer::Rebase { target }
+                };
+                (pointer, next)
+            }
+            // DYLD_CHAINED_PTR_32, the only 32-bit format `stride` lets through
+            _ => {
+                let next = bits(26, 5);
+                let pointer = if bits(31, 1) != 0 {
+                    ChainedPointer::Bind {
+                        ordinal: bits(0, 20) as u32,
+                        addend: bits(20, 6) as i64,
+                    }
+                } else if bits(0, 26) > self.starts.max_valid_pointer as u64 {
+                    ChainedPointer::Value
+                } else {
+                    ChainedPointer::Rebase {
+                        target: bits(0, 26),
+                    }
+                };
+                (pointer, next)
+            }
+        }
+    }
+}
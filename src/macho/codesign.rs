@@ -0,0 +1,747 @@
+// code signature of LC_CODE_SIGNATURE
+// a big endian SuperBlob indexing blobs by slot: the CodeDirectory with the hash of every page of
+// the image, the requirements, the entitlements and the CMS signature of the CodeDirectory
+// the pages are hashed up to the code limit, where the signature itself starts
+
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384};
+
+use super::reader::{self, Endian, Reader};
+use super::requirement::Requirements;
+use super::{LoadCommand, MachO, MachOError};
+
+pub const CSMAGIC_REQUIREMENT: u32 = 0xfade0c00;
+pub const CSMAGIC_REQUIREMENTS: u32 = 0xfade0c01;
+pub const CSMAGIC_CODEDIRECTORY: u32 = 0xfade0c02;
+pub const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
+pub const CSMAGIC_EMBEDDED_ENTITLEMENTS: u32 = 0xfade7171;
+pub const CSMAGIC_EMBEDDED_DER_ENTITLEMENTS: u32 = 0xfade7172;
+pub const CSMAGIC_BLOBWRAPPER: u32 = 0xfade0b01;
+
+pub const CSSLOT_CODEDIRECTORY: u32 = 0;
+pub const CSSLOT_INFOSLOT: u32 = 1;
+pub const CSSLOT_REQUIREMENTS: u32 = 2;
+pub const CSSLOT_RESOURCEDIR: u32 = 3;
+pub const CSSLOT_APPLICATION: u32 = 4;
+pub const CSSLOT_ENTITLEMENTS: u32 = 5;
+pub const CSSLOT_DER_ENTITLEMENTS: u32 = 7;
+pub const CSSLOT_ALTERNATE_CODEDIRECTORIES: u32 = 0x1000;
+pub const CSSLOT_ALTERNATE_CODEDIRECTORY_MAX: u32 = 5;
+pub const CSSLOT_SIGNATURESLOT: u32 = 0x10000;
+
+pub const CS_HASHTYPE_SHA1: u8 = 1;
+pub const CS_HASHTYPE_SHA256: u8 = 2;
+pub const CS_HASHTYPE_SHA256_TRUNCATED: u8 = 3;
+pub const CS_HASHTYPE_SHA384: u8 = 4;
+
+// the CodeDirectory grew fields over time, each version adds the ones after the previous ones
+pub const CS_SUPPORTSSCATTER: u32 = 0x20100;
+pub const CS_SUPPORTSTEAMID: u32 = 0x20200;
+pub const CS_SUPPORTSCODELIMIT64: u32 = 0x20300;
+pub const CS_SUPPORTSEXECSEG: u32 = 0x20400;
+pub const CS_SUPPORTSRUNTIME: u32 = 0x20500;
+
+pub const CS_VALID: u32 = 0x1;
+pub const CS_ADHOC: u32 = 0x2;
+pub const CS_GET_TASK_ALLOW: u32 = 0x4;
+pub const CS_INSTALLER: u32 = 0x8;
+pub const CS_FORCED_LV: u32 = 0x10;
+pub const CS_INVALID_ALLOWED: u32 = 0x20;
+pub const CS_HARD: u32 = 0x100;
+pub const CS_KILL: u32 = 0x200;
+pub const CS_CHECK_EXPIRATION: u32 = 0x400;
+pub const CS_RESTRICT: u32 = 0x800;
+pub const CS_ENFORCEMENT: u32 = 0x1000;
+pub const CS_REQUIRE_LV: u32 = 0x2000;
+pub const CS_ENTITLEMENTS_VALIDATED: u32 = 0x4000;
+pub const CS_NVRAM_UNRESTRICTED: u32 = 0x8000;
+pub const CS_RUNTIME: u32 = 0x10000;
+pub const CS_LINKER_SIGNED: u32 = 0x20000;
+
+pub const CS_EXECSEG_MAIN_BINARY: u64 = 0x1;
+pub const CS_EXECSEG_ALLOW_UNSIGNED: u64 = 0x10;
+pub const CS_EXECSEG_DEBUGGER: u64 = 0x20;
+pub const CS_EXECSEG_JIT: u64 = 0x40;
+pub const CS_EXECSEG_SKIP_LV: u64 = 0x80;
+pub const CS_EXECSEG_CAN_LOAD_CDHASH: u64 = 0x100;
+pub const CS_EXECSEG_CAN_EXEC_CDHASH: u64 = 0x200;
+
+/// The entitlement letting other processes get the task port, and so read the memory, of a
+/// process running the image.
+pub const GET_TASK_ALLOW_ENTITLEMENT: &str = "com.apple.security.get-task-allow";
+// name of the same entitlement on iOS
+const GET_TASK_ALLOW_ENTITLEMENT_IOS: &str = "get-task-allow";
+
+const FLAG_NAMES: &[(u32, &str)] = &[
+    (CS_VALID, "valid"),
+    (CS_ADHOC, "adhoc"),
+    (CS_GET_TASK_ALLOW, "get-task-allow"),
+    (CS_INSTALLER, "installer"),
+    (CS_FORCED_LV, "forced-library-validation"),
+    (CS_INVALID_ALLOWED, "invalid-allowed"),
+    (CS_HARD, "hard"),
+    (CS_KILL, "kill"),
+    (CS_CHECK_EXPIRATION, "expires"),
+    (CS_RESTRICT, "restrict"),
+    (CS_ENFORCEMENT, "enforcement"),
+    (CS_REQUIRE_LV, "library-validation"),
+    (CS_ENTITLEMENTS_VALIDATED, "entitlements-validated"),
+    (CS_NVRAM_UNRESTRICTED, "nvram-unrestricted"),
+    (CS_RUNTIME, "runtime"),
+    (CS_LINKER_SIGNED, "linker-signed"),
+];
+
+const EXECSEG_FLAG_NAMES: &[(u64, &str)] = &[
+    (CS_EXECSEG_MAIN_BINARY, "main-binary"),
+    (CS_EXECSEG_ALLOW_UNSIGNED, "allow-unsigned"),
+    (CS_EXECSEG_DEBUGGER, "debugger"),
+    (CS_EXECSEG_JIT, "jit"),
+    (CS_EXECSEG_SKIP_LV, "skip-library-validation"),
+    (CS_EXECSEG_CAN_LOAD_CDHASH, "can-load-cdhash"),
+    (CS_EXECSEG_CAN_EXEC_CDHASH, "can-exec-cdhash"),
+];
+
+/// A blob of the SuperBlob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blob<'a> {
+    /// Slot of the blob in the index, one of the `CSSLOT_*` constants.
+    pub slot: u32,
+    pub magic: u32,
+    /// The whole blob, magic and length included, which is what the special slots hash.
+    pub data: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    /// The blob without its magic and length.
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[8..]
+    }
+}
+
+/// The embedded signature of an image.
+#[derive(Debug, Clone)]
+pub struct CodeSignature<'a> {
+    /// File offset of the signature in the image.
+    pub offset: u32,
+    pub blobs: Vec<Blob<'a>>,
+}
+
+impl<'a> CodeSignature<'a> {
+    /// The signature of `binary`, `None` when it is not signed.
+    pub fn from_macho(binary: &MachO<'a>) -> Result<Option<CodeSignature<'a>>, MachOError> {
+        let command = binary.load_commands.iter().find_map(|lc| match lc {
+            LoadCommand::CodeSignature(linkedit) => Some(*linkedit),
+            _ => None,
+        });
+        let Some(command) = command else {
+            return Ok(None);
+        };
+        let data = reader::slice(
+            binary.data(),
+            command.dataoff as usize,
+            command.datasize as usize,
+            "code signature",
+        )?;
+        let mut signature = Self::parse(data)?;
+        signature.offset = command.dataoff;
+        Ok(Some(signature))
+    }
+
+    /// Splits the SuperBlob `data` into its blobs.
+    pub fn parse(data: &'a [u8]) -> Result<CodeSignature<'a>, MachOError> {
+        let mut r = Reader::new(data, Endian::Big, "code signature");
+        if r.u32()? != CSMAGIC_EMBEDDED_SIGNATURE {
+            return Err(MachOError::MalformedCodeSignature(
+                "not an embedded signature",
+            ));
+        }
+        let length = r.u32()? as usize;
+        let data = reader::slice(data, 0, length, "code signature")?;
+        let count = r.u32()?;
+        let mut blobs = Vec::new();
+        for _ in 0..count {
+            let slot = r.u32()?;
+            let offset = r.u32()? as usize;
+            let mut blob = Reader::at(data, offset, Endian::Big, "code signature blob");
+            let magic = blob.u32()?;
+            let length = blob.u32()? as usize;
+            if length < 8 {
+                return Err(MachOError::MalformedCodeSignature(
+                    "blob shorter than its header",
+                ));
+            }
+            blobs.push(Blob {
+                slot,
+                magic,
+                data: reader::slice(data, offset, length, "code signature blob")?,
+            });
+        }
+        Ok(CodeSignature { offset: 0, blobs })
+    }
+
+    pub fn blob(&self, slot: u32) -> Option<&Blob<'a>> {
+        self.blobs.iter().find(|blob| blob.slot == slot)
+    }
+
+    /// The CodeDirectory of slot 0 then the alternate ones, signatures made for older systems
+    /// keep a SHA-1 directory in slot 0 and a SHA-256 one in an alternate slot.
+    pub fn code_directories(&self) -> Result<Vec<CodeDirectory<'a>>, MachOError> {
+        let alternates = CSSLOT_ALTERNATE_CODEDIRECTORIES
+            ..CSSLOT_ALTERNATE_CODEDIRECTORIES + CSSLOT_ALTERNATE_CODEDIRECTORY_MAX;
+        self.blobs
+            .iter()
+            .filter(|blob| blob.slot == CSSLOT_CODEDIRECTORY || alternates.contains(&blob.slot))
+            .map(|blob| CodeDirectory::parse(blob.data))
+            .collect()
+    }
+
+    /// The entitlements property list.
+    pub fn entitlements(&self) -> Option<&'a str> {
+        let blob = self.blob(CSSLOT_ENTITLEMENTS)?;
+        if blob.magic != CSMAGIC_EMBEDDED_ENTITLEMENTS {
+            return None;
+        }
+        std::str::from_utf8(blob.payload()).ok()
+    }
+
+    /// The entitlements encoded in DER, which newer systems read instead of the property list.
+    pub fn der_entitlements(&self) -> Option<&'a [u8]> {
+        self.blob(CSSLOT_DER_ENTITLEMENTS)
+            .filter(|blob| blob.magic == CSMAGIC_EMBEDDED_DER_ENTITLEMENTS)
+            .map(|blob| blob.payload())
+    }
+
+    pub fn requirements(&self) -> Result<Option<Requirements<'a>>, MachOError> {
+        match self.blob(CSSLOT_REQUIREMENTS) {
+            Some(blob) if blob.magic == CSMAGIC_REQUIREMENTS => {
+                Requirements::parse(blob.data).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The CMS signature of the CodeDirectories, `None` for ad-hoc signatures where the blob is
+    /// missing or empty.
+    pub fn cms(&self) -> Option<&'a [u8]> {
+        self.blob(CSSLOT_SIGNATURESLOT)
+            .filter(|blob| blob.magic == CSMAGIC_BLOBWRAPPER)
+            .map(|blob| blob.payload())
+            .filter(|cms| !cms.is_empty())
+    }
+
+    /// Whether the entitlements let a debugger, or this profiler, attach to the process.
+    pub fn get_task_allow(&self) -> bool {
+        self.entitlements().is_some_and(|entitlements| {
+            has_true_entitlement(entitlements, GET_TASK_ALLOW_ENTITLEMENT)
+                || has_true_entitlement(entitlements, GET_TASK_ALLOW_ENTITLEMENT_IOS)
+        })
+    }
+}
+
+/// Whether the entitlements property list sets the boolean `key` to true.
+pub fn has_true_entitlement(entitlements: &str, key: &str) -> bool {
+    let tag = format!("<key>{key}</key>");
+    entitlements.match_indices(&tag).any(|(i, _)| {
+        entitlements[i + tag.len()..]
+            .trim_start()
+            .starts_with("<true/>")
+    })
+}
+
+/// The hashes of the pages and of the other blobs, with what they apply to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodeDirectory<'a> {
+    pub version: u32,
+    pub flags: u32,
+    pub hash_offset: u32,
+    pub identifier: &'a str,
+    pub team_id: Option<&'a str>,
+    /// Number of hashes of other blobs stored before the page hashes.
+    pub n_special_slots: u32,
+    pub n_code_slots: u32,
+    /// Size of the file covered by the page hashes.
+    pub code_limit: u64,
+    pub hash_size: u8,
+    pub hash_type: u8,
+    pub platform: u8,
+    /// log2 of the page size, 0 when the whole code limit is a single page.
+    pub page_size: u8,
+    pub exec_seg_base: u64,
+    pub exec_seg_limit: u64,
+    pub exec_seg_flags: u64,
+    /// Version of the SDK the hardened runtime applies the rules of.
+    pub runtime: Option<u32>,
+    /// The whole blob.
+    pub data: &'a [u8],
+}
+
+impl<'a> CodeDirectory<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<CodeDirectory<'a>, MachOError> {
+        let mut r = Reader::new(data, Endian::Big, "code directory");
+        if r.u32()? != CSMAGIC_CODEDIRECTORY {
+            return Err(MachOError::MalformedCodeSignature(
+                "code directory slot without a code directory",
+            ));
+        }
+        let length = r.u32()? as usize;
+        let data = reader::slice(data, 0, length, "code directory")?;
+        let version = r.u32()?;
+        let flags = r.u32()?;
+        let hash_offset = r.u32()?;
+        let ident_offset = r.u32()?;
+        let n_special_slots = r.u32()?;
+        let n_code_slots = r.u32()?;
+        let code_limit = r.u32()?;
+        let hash_size = r.u8()?;
+        let hash_type = r.u8()?;
+        let platform = r.u8()?;
+        let page_size = r.u8()?;
+        let _spare2 = r.u32()?;
+        let string = |offset: u32| {
+            reader::cstr(data, offset as usize).ok_or(MachOError::MalformedCodeSignature(
+                "code directory string out of range",
+            ))
+        };
+        let mut cd = CodeDirectory {
+            version,
+            flags,
+            hash_offset,
+            identifier: string(ident_offset)?,
+            team_id: None,
+            n_special_slots,
+            n_code_slots,
+            code_limit: code_limit as u64,
+            hash_size,
+            hash_type,
+            platform,
+            page_size,
+            exec_seg_base: 0,
+            exec_seg_limit: 0,
+            exec_seg_flags: 0,
+            runtime: None,
+            data,
+        };
+        if version >= CS_SUPPORTSSCATTER {
+            let _scatter_offset = r.u32()?;
+        }
+        if version >= CS_SUPPORTSTEAMID {
+            let team_offset = r.u32()?;
+            if team_offset != 0 {
+                cd.team_id = Some(string(team_offset)?);
+            }
+        }
+        if version >= CS_SUPPORTSCODELIMIT64 {
+            let _spare3 = r.u32()?;
+            let code_limit64 = r.u64()?;
+            if code_limit64 != 0 {
+                cd.code_limit = code_limit64;
+            }
+        }
+        if version >= CS_SUPPORTSEXECSEG {
+            cd.exec_seg_base = r.u64()?;
+            cd.exec_seg_limit = r.u64()?;
+            cd.exec_seg_flags = r.u64()?;
+        }
+        if version >= CS_SUPPORTSRUNTIME {
+            cd.runtime = Some(r.u32()?);
+        }
+        // the hashes must hold in the blob, special slots included
+        let special = n_special_slots as u64 * hash_size as u64;
+        let hashes_end = hash_offset as u64 + n_code_slots as u64 * hash_size as u64;
+        if special + 8 > hash_offset as u64 || hashes_end > data.len() as u64 {
+            return Err(MachOError::MalformedCodeSignature(
+                "hashes out of the code directory",
+            ));
+        }
+        Ok(cd)
+    }
+
+    pub fn hash_name(&self) -> String {
+        match self.hash_type {
+            CS_HASHTYPE_SHA1 => "sha1".to_string(),
+            CS_HASHTYPE_SHA256 => "sha256".to_string(),
+            CS_HASHTYPE_SHA256_TRUNCATED => "sha256 (truncated)".to_string(),
+            CS_HASHTYPE_SHA384 => "sha384".to_string(),
+            hash_type => format!("unknown hash type {hash_type}"),
+        }
+    }
+
+    /// Size of the pages hashed, the code limit when the code is hashed as a single page.
+    pub fn page_bytes(&self) -> u64 {
+        match self.page_size {
+            0 => self.code_limit,
+            shift => 1 << shift,
+        }
+    }
+
+    pub fn has_hardened_runtime(&self) -> bool {
+        self.flags & CS_RUNTIME != 0
+    }
+
+    /// Names of the flags set, e.g. `adhoc, runtime`.
+    pub fn flag_names(&self) -> String {
+        describe_flags(
+            self.flags as u64,
+            FLAG_NAMES.iter().map(|(f, n)| (*f as u64, *n)),
+        )
+    }
+
+    pub fn exec_seg_flag_names(&self) -> String {
+        describe_flags(self.exec_seg_flags, EXECSEG_FLAG_NAMES.iter().copied())
+    }
+
+    /// Hash of page `index`.
+    pub fn code_hash(&self, index: u32) -> &'a [u8] {
+        let start = self.hash_offset as usize + index as usize * self.hash_size as usize;
+        &self.data[start..start + self.hash_size as usize]
+    }
+
+    /// Hash of the blob of special slot `slot`, `None` past the special slots.
+    pub fn special_hash(&self, slot: u32) -> Option<&'a [u8]> {
+        if slot == 0 || slot > self.n_special_slots {
+            return None;
+        }
+        let start = self.hash_offset as usize - slot as usize * self.hash_size as usize;
+        Some(&self.data[start..start + self.hash_size as usize])
+    }
+
+    /// The hash identifying the signed code, the first 20 bytes of the hash of this blob.
+    pub fn cdhash(&self) -> Result<Vec<u8>, MachOError> {
+        let mut hash = digest(self.hash_type, self.data)?;
+        hash.truncate(20);
+        Ok(hash)
+    }
+
+    /// Hashes the pages of `binary`, the image the signature was read from, and the blobs of the
+    /// special slots, and compares them with the hashes of the directory.
+    pub fn verify(
+        &self,
+        binary: &MachO,
+        signature: &CodeSignature,
+    ) -> Result<Verification, MachOError> {
+        let code = reader::slice64(binary.data(), 0, self.code_limit, "signed code")?;
+        let page = self.page_bytes().max(1) as usize;
+        let mut mismatched_pages = Vec::new();
+        for index in 0..self.n_code_slots {
+            let start = (index as usize).saturating_mul(page).min(code.len());
+            let end = start.saturating_add(page).min(code.len());
+            if !self.matches(self.code_hash(index), &code[start..end])? {
+                mismatched_pages.push(index);
+            }
+        }
+        let mut special_slots = Vec::new();
+        for slot in 1..=self.n_special_slots {
+            let Some(expected) = self.special_hash(slot) else {
+                continue;
+            };
+            // unused slots below the last one are zero
+            if expected.iter().all(|b| *b == 0) {
+                continue;
+            }
+            let data = match slot {
+                CSSLOT_INFOSLOT => match binary.find_section("__TEXT", "__info_plist") {
+                    Some(section) => Some(binary.section_data(section)?),
+                    None => None,
+                },
+                // the resources are in the bundle around the image
+                CSSLOT_RESOURCEDIR | CSSLOT_APPLICATION => None,
+                slot => signature.blob(slot).map(|blob| blob.data),
+            };
+            let check = match data {
+                Some(data) if self.matches(expected, data)? => SlotCheck::Match,
+                Some(_) => SlotCheck::Mismatch,
+                None => SlotCheck::NotChecked,
+            };
+            special_slots.push((slot, check));
+        }
+        Ok(Verification {
+            pages: self.n_code_slots,
+            mismatched_pages,
+            special_slots,
+        })
+    }
+
+    fn matches(&self, expected: &[u8], data: &[u8]) -> Result<bool, MachOError> {
+        let hash = digest(self.hash_type, data)?;
+        Ok(hash.get(..expected.len()) == Some(expected))
+    }
+}
+
+/// Outcome of the check of a special slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotCheck {
+    Match,
+    Mismatch,
+    /// What the slot hashes is not in the image.
+    NotChecked,
+}
+
+/// What `CodeDirectory::verify` found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    /// Number of pages hashed.
+    pub pages: u32,
+    /// Indices of the pages whose hash differs.
+    pub mismatched_pages: Vec<u32>,
+    /// Every special slot with a hash, by slot number.
+    pub special_slots: Vec<(u32, SlotCheck)>,
+}
+
+impl Verification {
+    pub fn is_valid(&self) -> bool {
+        self.mismatched_pages.is_empty()
+            && self
+                .special_slots
+                .iter()
+                .all(|(_, check)| *check != SlotCheck::Mismatch)
+    }
+}
+
+/// Name of a slot of the SuperBlob or of the special slots of a CodeDirectory.
+pub fn slot_name(slot: u32) -> String {
+    match slot {
+        CSSLOT_CODEDIRECTORY => "code directory".to_string(),
+        CSSLOT_INFOSLOT => "info plist".to_string(),
+        CSSLOT_REQUIREMENTS => "requirements".to_string(),
+        CSSLOT_RESOURCEDIR => "resources".to_string(),
+        CSSLOT_APPLICATION => "application".to_string(),
+        CSSLOT_ENTITLEMENTS => "entitlements".to_string(),
+        CSSLOT_DER_ENTITLEMENTS => "der entitlements".to_string(),
+        CSSLOT_SIGNATURESLOT => "signature".to_string(),
+        slot if (CSSLOT_ALTERNATE_CODEDIRECTORIES
+            ..CSSLOT_ALTERNATE_CODEDIRECTORIES + CSSLOT_ALTERNATE_CODEDIRECTORY_MAX)
+            .contains(&slot) =>
+        {
+            format!(
+                "alternate code directory #{}",
+                slot - CSSLOT_ALTERNATE_CODEDIRECTORIES
+            )
+        }
+        slot => format!("slot {slot:#x}"),
+    }
+}
+
+/// Hash of `data` with the algorithm of a CodeDirectory.
+pub fn digest(hash_type: u8, data: &[u8]) -> Result<Vec<u8>, MachOError> {
+    Ok(match hash_type {
+        CS_HASHTYPE_SHA1 => Sha1::digest(data).to_vec(),
+        CS_HASHTYPE_SHA256 => Sha256::digest(data).to_vec(),
+        CS_HASHTYPE_SHA256_TRUNCATED => Sha256::digest(data)[..20].to_vec(),
+        CS_HASHTYPE_SHA384 => Sha384::digest(data).to_vec(),
+        hash_type => return Err(MachOError::UnsupportedHashType(hash_type)),
+    })
+}
+
+fn describe_flags(flags: u64, names: impl Iterator<Item = (u64, &'static str)>) -> String {
+    let mut known = 0;
+    let mut out: Vec<String> = names
+        .filter(|(flag, _)| flags & flag != 0)
+        .map(|(flag, name)| {
+            known |= flag;
+            name.to_string()
+        })
+        .collect();
+    if flags & !known != 0 {
+        out.push(format!("{:#x}", flags & !known));
+    }
+    if out.is_empty() {
+        return "none".to_string();
+    }
+    out.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::cpu::CPU_TYPE_ARM64;
+    use crate::macho::tests::image;
+
+    const ENTITLEMENTS: &str =
+        "<plist><dict><key>com.apple.security.get-task-allow</key>\n<true/></dict></plist>";
+
+    fn blob(magic: u32, payload: &[u8]) -> Vec<u8> {
+        let mut data = magic.to_be_bytes().to_vec();
+        data.extend((payload.len() as u32 + 8).to_be_bytes());
+        data.extend(payload);
+        data
+    }
+
+    /// A version 0x20400 CodeDirectory hashing `code` in 64-byte pages with SHA-256, and the
+    /// requirements and entitlements blobs in its special slots.
+    fn code_directory(code: &[u8], requirements: &[u8], entitlements: &[u8]) -> Vec<u8> {
+        let identifier = b"com.example.test\0";
+        let n_special_slots = CSSLOT_ENTITLEMENTS;
+        let n_code_slots = code.len().div_ceil(64) as u32;
+        let hash_offset = 88 + identifier.len() as u32 + n_special_slots * 32;
+        let length = hash_offset + n_code_slots * 32;
+        let mut data = Vec::new();
+        for value in [
+            CSMAGIC_CODEDIRECTORY,
+            length,
+            CS_SUPPORTSEXECSEG,
+            CS_ADHOC | CS_RUNTIME,
+            hash_offset,
+            88,
+            n_special_slots,
+            n_code_slots,
+            code.len() as u32,
+        ] {
+            data.extend(value.to_be_bytes());
+        }
+        data.extend([32, CS_HASHTYPE_SHA256, 0, 6]);
+        // spare2, scatter, team id, spare3
+        data.extend([0; 16]);
+        for value in [0, 0, 0x4000, CS_EXECSEG_MAIN_BINARY] {
+            data.extend(u64::to_be_bytes(value));
+        }
+        data.extend(identifier);
+        // the special slots are stored in reverse, the last one first
+        for slot in (1..=n_special_slots).rev() {
+            match slot {
+                CSSLOT_REQUIREMENTS => data.extend(Sha256::digest(requirements)),
+                CSSLOT_ENTITLEMENTS => data.extend(Sha256::digest(entitlements)),
+                _ => data.extend([0; 32]),
+            }
+        }
+        for page in code.chunks(64) {
+            data.extend(Sha256::digest(page));
+        }
+        data
+    }
+
+    fn super_blob(blobs: &[(u32, &[u8])]) -> Vec<u8> {
+        let mut index = Vec::new();
+        let mut offset = 12 + 8 * blobs.len();
+        for (slot, blob) in blobs {
+            index.extend(slot.to_be_bytes());
+            index.extend((offset as u32).to_be_bytes());
+            offset += blob.len();
+        }
+        let mut data = Vec::new();
+        for value in [
+            CSMAGIC_EMBEDDED_SIGNATURE,
+            offset as u32,
+            blobs.len() as u32,
+        ] {
+            data.extend(value.to_be_bytes());
+        }
+        data.extend(index);
+        blobs.iter().for_each(|(_, blob)| data.extend(*blob));
+        data
+    }
+
+    fn signature_of(code: &[u8]) -> Vec<u8> {
+        let requirements = blob(CSMAGIC_REQUIREMENTS, &0u32.to_be_bytes());
+        let entitlements = blob(CSMAGIC_EMBEDDED_ENTITLEMENTS, ENTITLEMENTS.as_bytes());
+        let directory = code_directory(code, &requirements, &entitlements);
+        super_blob(&[
+            (CSSLOT_CODEDIRECTORY, &directory),
+            (CSSLOT_REQUIREMENTS, &requirements),
+            (CSSLOT_ENTITLEMENTS, &entitlements),
+        ])
+    }
+
+    #[test]
+    fn parses_a_code_directory() {
+        let code = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let data = signature_of(&code);
+        let signature = CodeSignature::parse(&data).unwrap();
+        assert_eq!(signature.blobs.len(), 3);
+        assert_eq!(signature.entitlements(), Some(ENTITLEMENTS));
+        assert!(signature.get_task_allow());
+        assert_eq!(signature.cms(), None);
+
+        let directories = signature.code_directories().unwrap();
+        let [cd] = directories.as_slice() else {
+            panic!("{} code directories", directories.len());
+        };
+        assert_eq!(cd.identifier, "com.example.test");
+        assert_eq!(cd.team_id, None);
+        assert_eq!((cd.n_special_slots, cd.n_code_slots), (5, 2));
+        assert_eq!((cd.code_limit, cd.page_bytes()), (128, 64));
+        assert_eq!(cd.hash_name(), "sha256");
+        assert_eq!(cd.flag_names(), "adhoc, runtime");
+        assert!(cd.has_hardened_runtime());
+        assert_eq!(
+            (cd.exec_seg_limit, cd.exec_seg_flag_names()),
+            (0x4000, "main-binary".to_string())
+        );
+        assert_eq!(cd.runtime, None);
+        assert_eq!(cd.code_hash(1), Sha256::digest(&code[64..]).as_slice());
+        assert_eq!(cd.special_hash(1), Some(&[0; 32][..]));
+        assert_eq!(cd.special_hash(6), None);
+        assert_eq!(cd.cdhash().unwrap(), Sha256::digest(cd.data)[..20].to_vec());
+    }
+
+    #[test]
+    fn verifies_the_page_and_slot_hashes() {
+        let original = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let data = signature_of(&original);
+        let signature = CodeSignature::parse(&data).unwrap();
+        let cd = signature.code_directories().unwrap()[0];
+        let binary = MachO::parse(&original).unwrap();
+        let verification = cd.verify(&binary, &signature).unwrap();
+        assert_eq!(
+            verification,
+            Verification {
+                pages: 2,
+                mismatched_pages: Vec::new(),
+                special_slots: vec![
+                    (CSSLOT_REQUIREMENTS, SlotCheck::Match),
+                    (CSSLOT_ENTITLEMENTS, SlotCheck::Match)
+                ],
+            }
+        );
+        assert!(verification.is_valid());
+
+        // a byte of the segment name padding, in the second page
+        let mut code = original.clone();
+        code[74] = b'X';
+        let binary = MachO::parse(&code).unwrap();
+        let verification = cd.verify(&binary, &signature).unwrap();
+        assert_eq!(verification.mismatched_pages, [1]);
+        assert!(!verification.is_valid());
+
+        // entitlements changed after signing
+        let mut data = data.clone();
+        let at = data.len() - 8;
+        data[at] = b'X';
+        let signature = CodeSignature::parse(&data).unwrap();
+        let binary = MachO::parse(&original).unwrap();
+        let verification = cd.verify(&binary, &signature).unwrap();
+        assert_eq!(
+            verification.special_slots[1],
+            (CSSLOT_ENTITLEMENTS, SlotCheck::Mismatch)
+        );
+    }
+
+    #[test]
+    fn malformed_code_directories() {
+        let code = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let directory = code_directory(&code, &[], &[]);
+        // the special slots overlapping the fixed fields
+        let mut overlapping = directory.clone();
+        overlapping[16..20].copy_from_slice(&100u32.to_be_bytes());
+        assert_eq!(
+            CodeDirectory::parse(&overlapping).unwrap_err(),
+            MachOError::MalformedCodeSignature("hashes out of the code directory")
+        );
+        // one more page hash than the blob holds
+        let mut too_many = directory.clone();
+        too_many[28..32].copy_from_slice(&3u32.to_be_bytes());
+        assert_eq!(
+            CodeDirectory::parse(&too_many).unwrap_err(),
+            MachOError::MalformedCodeSignature("hashes out of the code directory")
+        );
+        for len in 0..directory.len() {
+            assert!(
+                CodeDirectory::parse(&directory[..len]).is_err(),
+                "{len} bytes"
+            );
+        }
+        assert_eq!(
+            digest(CS_HASHTYPE_SHA256 + 10, &code).unwrap_err(),
+            MachOError::UnsupportedHashType(12)
+        );
+    }
+}
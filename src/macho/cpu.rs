@@ -0,0 +1,97 @@
+// cpu types and subtypes from <mach/machine.h>
+// used to name the slices of a fat binary and to select one from the command line
+
+pub const CPU_ARCH_ABI64: u32 = 0x0100_0000;
+pub const CPU_ARCH_ABI64_32: u32 = 0x0200_0000;
+// the high byte of the subtype holds capability bits (pointer authentication...)
+pub const CPU_SUBTYPE_MASK: u32 = 0xff00_0000;
+
+pub const CPU_TYPE_X86: u32 = 7;
+pub const CPU_TYPE_X86_64: u32 = CPU_TYPE_X86 | CPU_ARCH_ABI64;
+pub const CPU_TYPE_ARM: u32 = 12;
+pub const CPU_TYPE_ARM64: u32 = CPU_TYPE_ARM | CPU_ARCH_ABI64;
+pub const CPU_TYPE_ARM64_32: u32 = CPU_TYPE_ARM | CPU_ARCH_ABI64_32;
+pub const CPU_TYPE_POWERPC: u32 = 18;
+pub const CPU_TYPE_POWERPC64: u32 = CPU_TYPE_POWERPC | CPU_ARCH_ABI64;
+
+// (name, cputype, cpusubtype), the first entry of a cputype is its generic name
+const ARCHS: &[(&str, u32, u32)] = &[
+    ("x86_64", CPU_TYPE_X86_64, 3),
+    ("x86_64h", CPU_TYPE_X86_64, 8),
+    ("i386", CPU_TYPE_X86, 3),
+    ("arm64", CPU_TYPE_ARM64, 0),
+    ("arm64v8", CPU_TYPE_ARM64, 1),
+    ("arm64e", CPU_TYPE_ARM64, 2),
+    ("arm64_32", CPU_TYPE_ARM64_32, 1),
+    ("arm", CPU_TYPE_ARM, 0),
+    ("armv6", CPU_TYPE_ARM, 6),
+    ("armv7", CPU_TYPE_ARM, 9),
+    ("armv7s", CPU_TYPE_ARM, 11),
+    ("armv7k", CPU_TYPE_ARM, 12),
+    ("ppc", CPU_TYPE_POWERPC, 0),
+    ("ppc64", CPU_TYPE_POWERPC64, 0),
+];
+
+/// A cpu type, with an optional subtype when a specific variant is required.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arch {
+    pub cputype: u32,
+    pub cpusubtype: Option<u32>,
+}
+
+impl Arch {
+    /// Parses an architecture name as used by `lipo` (`arm64`, `x86_64`, `armv7s`...).
+    pub fn from_name(name: &str) -> Option<Arch> {
+        let (_, cputype, cpusubtype) = ARCHS.iter().find(|(n, _, _)| *n == name)?;
+        // generic names match every subtype of their cpu type
+        let generic = ARCHS
+            .iter()
+            .find(|(_, t, _)| t == cputype)
+            .map(|(n, _, _)| *n)
+            == Some(name);
+        Some(Arch {
+            cputype: *cputype,
+            cpusubtype: if generic { None } else { Some(*cpusubtype) },
+        })
+    }
+
+    /// Architecture of an image header, matching its own subtype only.
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    pub fn exact(cputype: u32, cpusubtype: u32) -> Arch {
+        Arch {
+            cputype,
+            cpusubtype: Some(cpusubtype & !CPU_SUBTYPE_MASK),
+        }
+    }
+
+    /// Architecture of the machine the profiler runs on.
+    pub fn host() -> Option<Arch> {
+        match std::env::consts::ARCH {
+            "aarch64" => Arch::from_name("arm64"),
+            "x86_64" => Arch::from_name("x86_64"),
+            "x86" => Arch::from_name("i386"),
+            "arm" => Arch::from_name("arm"),
+            "powerpc" => Arch::from_name("ppc"),
+            "powerpc64" => Arch::from_name("ppc64"),
+            _ => None,
+        }
+    }
+
+    pub fn matches(&self, cputype: u32, cpusubtype: u32) -> bool {
+        self.cputype == cputype
+            && self
+                .cpusubtype
+                .is_none_or(|s| s == cpusubtype & !CPU_SUBTYPE_MASK)
+    }
+}
+
+/// Name of a cpu type/subtype pair, falls back to the raw numbers for unknown ones.
+pub fn arch_name(cputype: u32, cpusubtype: u32) -> String {
+    let subtype = cpusubtype & !CPU_SUBTYPE_MASK;
+    ARCHS
+        .iter()
+        .find(|(_, t, s)| *t == cputype && *s == subtype)
+        .or_else(|| ARCHS.iter().find(|(_, t, _)| *t == cputype))
+        .map(|(n, _, _)| n.to_string())
+        .unwrap_or_else(|| format!("cpu {cputype:#x}/{cpusubtype:#x}"))
+}
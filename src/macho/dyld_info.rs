@@ -0,0 +1,513 @@
+// rebase and bind opcode streams of LC_DYLD_INFO
+// each stream is a little bytecode driving a cursor (segment, offset, symbol, ordinal...) over
+// the image, the DO_* opcodes record the slot under the cursor and move it forward
+// the lazy bind stream is a list of small programs separated by DONE, one per lazy pointer
+
+use super::fixups::{BIND_SPECIAL_DYLIB_WEAK_LOOKUP, BindKind, Fixups, Import, Rebase};
+use super::load_command::DyldInfo;
+use super::reader::{self, Reader};
+use super::{MachO, MachOError, Segment};
+
+// opcodes are in the high nibble, the low one is an immediate operand
+pub const OPCODE_MASK: u8 = 0xf0;
+pub const IMMEDIATE_MASK: u8 = 0x0f;
+
+pub const REBASE_TYPE_POINTER: u8 = 1;
+
+pub const REBASE_OPCODE_DONE: u8 = 0x00;
+pub const REBASE_OPCODE_SET_TYPE_IMM: u8 = 0x10;
+pub const REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: u8 = 0x20;
+pub const REBASE_OPCODE_ADD_ADDR_ULEB: u8 = 0x30;
+pub const REBASE_OPCODE_ADD_ADDR_IMM_SCALED: u8 = 0x40;
+pub const REBASE_OPCODE_DO_REBASE_IMM_TIMES: u8 = 0x50;
+pub const REBASE_OPCODE_DO_REBASE_ULEB_TIMES: u8 = 0x60;
+pub const REBASE_OPCODE_DO_REBASE_ADD_ADDR_ULEB: u8 = 0x70;
+pub const REBASE_OPCODE_DO_REBASE_ULEB_TIMES_SKIPPING_ULEB: u8 = 0x80;
+
+pub const BIND_SYMBOL_FLAGS_WEAK_IMPORT: u8 = 0x1;
+
+pub const BIND_OPCODE_DONE: u8 = 0x00;
+pub const BIND_OPCODE_SET_DYLIB_ORDINAL_IMM: u8 = 0x10;
+pub const BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB: u8 = 0x20;
+pub const BIND_OPCODE_SET_DYLIB_SPECIAL_IMM: u8 = 0x30;
+pub const BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM: u8 = 0x40;
+pub const BIND_OPCODE_SET_TYPE_IMM: u8 = 0x50;
+pub const BIND_OPCODE_SET_ADDEND_SLEB: u8 = 0x60;
+pub const BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: u8 = 0x70;
+pub const BIND_OPCODE_ADD_ADDR_ULEB: u8 = 0x80;
+pub const BIND_OPCODE_DO_BIND: u8 = 0x90;
+pub const BIND_OPCODE_DO_BIND_ADD_ADDR_ULEB: u8 = 0xa0;
+pub const BIND_OPCODE_DO_BIND_ADD_ADDR_IMM_SCALED: u8 = 0xb0;
+pub const BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB: u8 = 0xc0;
+
+/// Runs the rebase, bind, weak bind and lazy bind streams of `info`.
+pub fn parse<'a>(binary: &MachO<'a>, info: &DyldInfo) -> Result<Fixups<'a>, MachOError> {
+    let segments: Vec<&Segment> = binary.segments().collect();
+    let mut fixups = Fixups::default();
+    let stream = |offset: u32, size: u32, what| {
+        reader::slice(binary.data(), offset as usize, size as usize, what)
+    };
+    let rebases = stream(info.rebase_off, info.rebase_size, "rebase opcodes")?;
+    fixups.rebases = parse_rebases(binary, &segments, rebases)?;
+    for (offset, size, what, kind) in [
+        (
+            info.bind_off,
+            info.bind_size,
+            "bind opcodes",
+            BindKind::Regular,
+        ),
+        (
+            info.weak_bind_off,
+            info.weak_bind_size,
+            "weak bind opcodes",
+            BindKind::Weak,
+        ),
+        (
+            info.lazy_bind_off,
+            info.lazy_bind_size,
+            "lazy bind opcodes",
+            BindKind::Lazy,
+        ),
+    ] {
+        let data = stream(offset, size, what)?;
+        parse_binds(binary, &segments, data, what, kind, &mut fixups)?;
+    }
+    Ok(fixups)
+}
+
+// position of the opcode cursor in the image
+struct Cursor<'s> {
+    segments: &'s [&'s Segment],
+    segment: Option<&'s Segment>,
+    offset: u64,
+    pointer_size: u64,
+}
+
+impl<'s> Cursor<'s> {
+    fn new(binary: &MachO, segments: &'s [&'s Segment]) -> Cursor<'s> {
+        Cursor {
+            segments,
+            segment: None,
+            offset: 0,
+            pointer_size: if binary.header.is_64() { 8 } else { 4 },
+        }
+    }
+
+    fn set(&mut self, index: u8, offset: u64) -> Result<(), MachOError> {
+        self.segment = Some(
+            self.segments
+                .get(index as usize)
+                .ok_or(MachOError::MalformedFixups("segment index out of range"))?,
+        );
+        self.offset = offset;
+        Ok(())
+    }
+
+    // offsets going backwards are encoded as huge ULEB128 values
+    fn advance(&mut self, delta: u64) {
+        self.offset = self.offset.wrapping_add(delta);
+    }
+
+    // the slot under the cursor, checked against its segment
+    fn slot(&self) -> Result<(&'s Segment, u64), MachOError> {
+        let segment = self.segment.ok_or(MachOError::MalformedFixups(
+            "fixup before any segment is set",
+        ))?;
+        if self.offset >= segment.vmsize {
+            return Err(MachOError::MalformedFixups(
+                "fixup past the end of its segment",
+            ));
+        }
+        let address =
+            segment
+                .vmaddr
+                .checked_add(self.offset)
+                .ok_or(MachOError::MalformedFixups(
+                    "fixup past the end of the address space",
+                ))?;
+        Ok((segment, address))
+    }
+
+    // a repeat count, at most one slot per pointer of the segment
+    fn count(&self, count: u64) -> Result<u64, MachOError> {
+        let limit = self.segment.map_or(0, |s| s.vmsize / self.pointer_size + 1);
+        if count > limit {
+            return Err(MachOError::MalformedFixups(
+                "repeat count past the end of the segment",
+            ));
+        }
+        Ok(count)
+    }
+}
+
+fn parse_rebases(
+    binary: &MachO,
+    segments: &[&Segment],
+    data: &[u8],
+) -> Result<Vec<Rebase>, MachOError> {
+    let mut r = Reader::new(data, binary.endian, "rebase opcodes");
+    let mut cursor = Cursor::new(binary, segments);
+    let mut rebase_type = REBASE_TYPE_POINTER;
+    let mut rebases = Vec::new();
+    let mut rebase = |cursor: &Cursor, rebase_type: u8| -> Result<(), MachOError> {
+        let (segment, address) = cursor.slot()?;
+        // the slot holds the preferred address of the target
+        let offset = segment.fileoff + (address - segment.vmaddr);
+        let size = if rebase_type == REBASE_TYPE_POINTER {
+            cursor.pointer_size
+        } else {
+            4
+        };
+        let bytes = reader::slice64(binary.data(), offset, size, "rebased pointer")?;
+        let target = Reader::new(bytes, binary.endian, "rebased pointer").word(size == 8)?;
+        rebases.push(Rebase { address, target });
+        Ok(())
+    };
+    while !r.is_empty() {
+        let start = r.offset();
+        let byte = r.u8()?;
+        let immediate = byte & IMMEDIATE_MASK;
+        match byte & OPCODE_MASK {
+            REBASE_OPCODE_DONE => break,
+            REBASE_OPCODE_SET_TYPE_IMM => rebase_type = immediate,
+            REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB => cursor.set(immediate, r.uleb128()?)?,
+            REBASE_OPCODE_ADD_ADDR_ULEB => cursor.advance(r.uleb128()?),
+            REBASE_OPCODE_ADD_ADDR_IMM_SCALED => {
+                cursor.advance(immediate as u64 * cursor.pointer_size)
+            }
+            REBASE_OPCODE_DO_REBASE_IMM_TIMES | REBASE_OPCODE_DO_REBASE_ULEB_TIMES => {
+                let count = if byte & OPCODE_MASK == REBASE_OPCODE_DO_REBASE_IMM_TIMES {
+                    immediate as u64
+                } else {
+                    cursor.count(r.uleb128()?)?
+                };
+                for _ in 0..count {
+                    rebase(&cursor, rebase_type)?;
+                    cursor.advance(cursor.pointer_size);
+                }
+            }
+            REBASE_OPCODE_DO_REBASE_ADD_ADDR_ULEB => {
+                rebase(&cursor, rebase_type)?;
+                let delta = r.uleb128()?;
+                cursor.advance(delta.wrapping_add(cursor.pointer_size));
+            }
+            REBASE_OPCODE_DO_REBASE_ULEB_TIMES_SKIPPING_ULEB => {
+                let count = cursor.count(r.uleb128()?)?;
+                let skip = r.uleb128()?;
+                for _ in 0..count {
+                    rebase(&cursor, rebase_type)?;
+                    cursor.advance(skip.wrapping_add(cursor.pointer_size));
+                }
+            }
+            opcode => {
+                return Err(MachOError::UnknownFixupOpcode {
+                    what: "rebase opcodes",
+                    opcode,
+                    offset: start,
+                });
+            }
+        }
+    }
+    Ok(rebases)
+}
+
+fn parse_binds<'a>(
+    binary: &MachO<'a>,
+    segments: &[&Segment],
+    data: &'a [u8],
+    what: &'static str,
+    kind: BindKind,
+    fixups: &mut Fixups<'a>,
+) -> Result<(), MachOError> {
+    let mut r = Reader::new(data, binary.endian, what);
+    let mut cursor = Cursor::new(binary, segments);
+    // weak binds go to whichever image defines the symbol first
+    let mut import = Import {
+        name: "",
+        library_ordinal: if kind == BindKind::Weak {
+            BIND_SPECIAL_DYLIB_WEAK_LOOKUP
+        } else {
+            0
+        },
+        addend: 0,
+        weak: false,
+    };
+    let mut bind = |cursor: &Cursor, import: Import<'a>| -> Result<(), MachOError> {
+        let (_, address) = cursor.slot()?;
+        fixups.bind(address, import, kind);
+        Ok(())
+    };
+    while !r.is_empty() {
+        let start = r.offset();
+        let byte = r.u8()?;
+        let immediate = byte & IMMEDIATE_MASK;
+        match byte & OPCODE_MASK {
+            // lazy pointers each have their own program, ended by DONE
+            BIND_OPCODE_DONE if kind == BindKind::Lazy => {}
+            BIND_OPCODE_DONE => break,
+            BIND_OPCODE_SET_DYLIB_ORDINAL_IMM => import.library_ordinal = immediate as i32,
+            BIND_OPCODE_SET_DYLIB_ORDINAL_ULEB => import.library_ordinal = r.uleb128()? as i32,
+            BIND_OPCODE_SET_DYLIB_SPECIAL_IMM => {
+                // the special ordinals are negative, sign extended from the immediate
+                import.library_ordinal = if immediate == 0 {
+                    0
+                } else {
+                    (OPCODE_MASK | immediate) as i8 as i32
+                };
+            }
+            BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM => {
+                import.weak = immediate & BIND_SYMBOL_FLAGS_WEAK_IMPORT != 0;
+                import.name = r.cstr()?;
+            }
+            // every bind of the streams stores a pointer
+            BIND_OPCODE_SET_TYPE_IMM => {}
+            BIND_OPCODE_SET_ADDEND_SLEB => import.addend = r.sleb128()?,
+            BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB => cursor.set(immediate, r.uleb128()?)?,
+            BIND_OPCODE_ADD_ADDR_ULEB => cursor.advance(r.uleb128()?),
+            BIND_OPCODE_DO_BIND => {
+                bind(&cursor, import)?;
+                cursor.advance(cursor.pointer_size);
+            }
+            BIND_OPCODE_DO_BIND_ADD_ADDR_ULEB => {
+                bind(&cursor, import)?;
+                let delta = r.uleb128()?;
+                cursor.advance(delta.wrapping_add(cursor.pointer_size));
+            }
+            BIND_OPCODE_DO_BIND_ADD_ADDR_IMM_SCALED => {
+                bind(&cursor, import)?;
+                cursor.advance((immediate as u64 + 1) * cursor.pointer_size);
+            }
+            BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB => {
+                let count = cursor.count(r.uleb128()?)?;
+                let skip = r.uleb128()?;
+                for _ in 0..count {
+                    bind(&cursor, import)?;
+                    cursor.advance(skip.wrapping_add(cursor.pointer_size));
+                }
+            }
+            // BIND_OPCODE_THREADED only appeared in a few arm64e betas before chained fixups
+            opcode => {
+                return Err(MachOError::UnknownFixupOpcode {
+                    what,
+                    opcode,
+                    offset: start,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::cpu::CPU_TYPE_ARM64;
+    use crate::macho::fixups::{BIND_SPECIAL_DYLIB_FLAT_LOOKUP, Bind};
+    use crate::macho::reader::Endian;
+    use crate::macho::tests::image;
+
+    const TEXT: u64 = 0x1_0000_0000;
+
+    fn binds<'a>(
+        binary: &MachO<'a>,
+        data: &'a [u8],
+        kind: BindKind,
+    ) -> Result<Fixups<'a>, MachOError> {
+        let segments: Vec<&Segment> = binary.segments().collect();
+        let mut fixups = Fixups::default();
+        parse_binds(binary, &segments, data, "bind opcodes", kind, &mut fixups)?;
+        Ok(fixups)
+    }
+
+    fn import(name: &str, library_ordinal: i32, addend: i64, weak: bool) -> Import<'_> {
+        Import {
+            name,
+            library_ordinal,
+            addend,
+            weak,
+        }
+    }
+
+    #[test]
+    fn bind_opcodes_move_the_cursor() {
+        let data = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let binary = MachO::parse(&data).unwrap();
+        let mut opcodes = vec![
+            BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | 1,
+            BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM,
+        ];
+        opcodes.extend(b"_malloc\0");
+        opcodes.extend([
+            BIND_OPCODE_SET_TYPE_IMM | 1,
+            BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+            0x10,
+            BIND_OPCODE_DO_BIND,
+            BIND_OPCODE_DO_BIND_ADD_ADDR_ULEB,
+            8,
+            BIND_OPCODE_DO_BIND_ADD_ADDR_IMM_SCALED | 1,
+            BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM | BIND_SYMBOL_FLAGS_WEAK_IMPORT,
+        ]);
+        opcodes.extend(b"_free\0");
+        opcodes.extend([
+            BIND_OPCODE_SET_DYLIB_SPECIAL_IMM | 0x0e,
+            BIND_OPCODE_SET_ADDEND_SLEB,
+            0x78,
+            BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB,
+            2,
+            8,
+            BIND_OPCODE_DONE,
+            // past DONE, not run
+            BIND_OPCODE_DO_BIND,
+        ]);
+
+        let fixups = binds(&binary, &opcodes, BindKind::Regular).unwrap();
+        assert_eq!(
+            fixups.imports,
+            [
+                import("_malloc", 1, 0, false),
+                import("_free", BIND_SPECIAL_DYLIB_FLAT_LOOKUP, -8, true)
+            ]
+        );
+        let bound: Vec<_> = fixups
+            .binds
+            .iter()
+            .map(|bind| (bind.address - TEXT, bind.import))
+            .collect();
+        assert_eq!(
+            bound,
+            [(0x10, 0), (0x18, 0), (0x28, 0), (0x38, 1), (0x48, 1)]
+        );
+        assert!(
+            fixups
+                .binds
+                .iter()
+                .all(|bind| bind.kind == BindKind::Regular)
+        );
+    }
+
+    #[test]
+    fn lazy_binds_run_every_program() {
+        let data = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let binary = MachO::parse(&data).unwrap();
+        let mut opcodes = Vec::new();
+        for (offset, name) in [(0x20, &b"_a\0"[..]), (0x28, b"_b\0")] {
+            opcodes.extend([
+                BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+                offset,
+                BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | 2,
+                BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM,
+            ]);
+            opcodes.extend(name);
+            opcodes.extend([BIND_OPCODE_DO_BIND, BIND_OPCODE_DONE]);
+        }
+        let fixups = binds(&binary, &opcodes, BindKind::Lazy).unwrap();
+        assert_eq!(
+            fixups.binds,
+            [
+                Bind {
+                    address: TEXT + 0x20,
+                    import: 0,
+                    kind: BindKind::Lazy
+                },
+                Bind {
+                    address: TEXT + 0x28,
+                    import: 1,
+                    kind: BindKind::Lazy
+                }
+            ]
+        );
+        assert_eq!(fixups.imports[1], import("_b", 2, 0, false));
+
+        // weak binds look the symbol up in every image unless told otherwise
+        let weak = [
+            BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM,
+            b'_',
+            b'w',
+            0,
+            BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+            0,
+            BIND_OPCODE_DO_BIND,
+        ];
+        let fixups = binds(&binary, &weak, BindKind::Weak).unwrap();
+        assert_eq!(
+            fixups.imports,
+            [import("_w", BIND_SPECIAL_DYLIB_WEAK_LOOKUP, 0, false)]
+        );
+    }
+
+    #[test]
+    fn malformed_bind_opcodes() {
+        let data = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let binary = MachO::parse(&data).unwrap();
+        let error = |opcodes: &[u8]| binds(&binary, opcodes, BindKind::Regular).unwrap_err();
+        assert_eq!(
+            error(&[BIND_OPCODE_DO_BIND]),
+            MachOError::MalformedFixups("fixup before any segment is set")
+        );
+        assert_eq!(
+            error(&[BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB | 1, 0]),
+            MachOError::MalformedFixups("segment index out of range")
+        );
+        // __TEXT is 0x4000 bytes long
+        assert_eq!(
+            error(&[
+                BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+                0x80,
+                0x80,
+                1,
+                BIND_OPCODE_DO_BIND
+            ]),
+            MachOError::MalformedFixups("fixup past the end of its segment")
+        );
+        assert_eq!(
+            error(&[
+                BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+                0,
+                BIND_OPCODE_DO_BIND_ULEB_TIMES_SKIPPING_ULEB,
+                0xff,
+                0xff,
+                0x03,
+                0
+            ]),
+            MachOError::MalformedFixups("repeat count past the end of the segment")
+        );
+        assert_eq!(
+            error(&[BIND_OPCODE_SET_TYPE_IMM | 1, 0xd0]),
+            MachOError::UnknownFixupOpcode {
+                what: "bind opcodes",
+                opcode: 0xd0,
+                offset: 1
+            }
+        );
+    }
+
+    #[test]
+    fn rebases_read_their_target() {
+        let data = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let binary = MachO::parse(&data).unwrap();
+        let segments: Vec<&Segment> = binary.segments().collect();
+        // the slots are the uuid of the image, at 40 in the file
+        let opcodes = [
+            REBASE_OPCODE_SET_TYPE_IMM | REBASE_TYPE_POINTER,
+            REBASE_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+            32,
+            REBASE_OPCODE_ADD_ADDR_IMM_SCALED | 1,
+            REBASE_OPCODE_DO_REBASE_IMM_TIMES | 2,
+            REBASE_OPCODE_DONE,
+        ];
+        let rebases = parse_rebases(&binary, &segments, &opcodes).unwrap();
+        assert_eq!(
+            rebases,
+            [
+                Rebase {
+                    address: TEXT + 40,
+                    target: u64::from_le_bytes(*b"01234567")
+                },
+                Rebase {
+                    address: TEXT + 48,
+                    target: u64::from_le_bytes(*b"89abcdef")
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use super::reader::{self, Endian, Reader};
+use super::segment::{
+    S_LAZY_DYLIB_SYMBOL_POINTERS, S_LAZY_SYMBOL_POINTERS, S_NON_LAZY_SYMBOL_POINTERS,
+    S_SYMBOL_STUBS, S_THREAD_LOCAL_VARIABLE_POINTERS,
+};
+use super::{MachO, MachOError};
+
+// special values of the indirect symbol table
+// the slot refers to a local or absolute symbol that was stripped from the symbol table
+pub const INDIRECT_SYMBOL_LOCAL: u32 = 0x8000_0000;
+pub const INDIRECT_SYMBOL_ABS: u32 = 0x4000_0000;
+
+/// Payload of LC_DYSYMTAB: how the symbol table is split and where the dynamic linker tables are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DysymtabCommand {
+    pub ilocalsym: u32,
+    pub nlocalsym: u32,
+    pub iextdefsym: u32,
+    pub nextdefsym: u32,
+    pub iundefsym: u32,
+    pub nundefsym: u32,
+    pub tocoff: u32,
+    pub ntoc: u32,
+    pub modtaboff: u32,
+    pub nmodtab: u32,
+    pub extrefsymoff: u32,
+    pub nextrefsyms: u32,
+    pub indirectsymoff: u32,
+    pub nindirectsyms: u32,
+    pub extreloff: u32,
+    pub nextrel: u32,
+    pub locreloff: u32,
+    pub nlocrel: u32,
+}
+
+impl DysymtabCommand {
+    pub fn parse(r: &mut Reader) -> Result<DysymtabCommand, MachOError> {
+        Ok(DysymtabCommand {
+            ilocalsym: r.u32()?,
+            nlocalsym: r.u32()?,
+            iextdefsym: r.u32()?,
+            nextdefsym: r.u32()?,
+            iundefsym: r.u32()?,
+            nundefsym: r.u32()?,
+            tocoff: r.u32()?,
+            ntoc: r.u32()?,
+            modtaboff: r.u32()?,
+            nmodtab: r.u32()?,
+            extrefsymoff: r.u32()?,
+            nextrefsyms: r.u32()?,
+            indirectsymoff: r.u32()?,
+            nindirectsyms: r.u32()?,
+            extreloff: r.u32()?,
+            nextrel: r.u32()?,
+            locreloff: r.u32()?,
+            nlocrel: r.u32()?,
+        })
+    }
+
+    /// Reads the indirect symbol table: one symbol table index per stub or pointer slot.
+    pub fn indirect_symbols(&self, data: &[u8], endian: Endian) -> Result<Vec<u32>, MachOError> {
+        let table = (self.nindirectsyms as usize)
+            .checked_mul(4)
+            .and_then(|size| {
+                reader::slice(data, self.indirectsymoff as usize, size, "indirect symbols").ok()
+            })
+            .ok_or(MachOError::IndirectSymbolTableOutOfRange {
+                indirectsymoff: self.indirectsymoff,
+                nindirectsyms: self.nindirectsyms,
+            })?;
+        let mut r = Reader::new(table, endian, "indirect symbols");
+        (0..self.nindirectsyms).map(|_| r.u32()).collect()
+    }
+}
+
+/// What an indirect slot is used for, from the type of its section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndirectKind {
+    /// Code jumping to the imported symbol (`__stubs`, `__auth_stubs`).
+    Stub,
+    /// Pointer bound lazily on first call (`__la_symbol_ptr`).
+    LazyPointer,
+    /// Pointer bound at load time (`__got`, `__nl_symbol_ptr`, `__auth_got`).
+    NonLazyPointer,
+    /// Thread local variable descriptor pointer (`__thread_ptrs`).
+    ThreadLocalPointer,
+}
+
+/// A stub or pointer slot and the symbol it stands for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndirectEntry<'a> {
+    /// Preferred (unslid) address of the slot.
+    pub address: u64,
+    pub size: u64,
+    pub kind: IndirectKind,
+    /// `None` when the slot targets a local or absolute symbol stripped from the symbol table.
+    pub symbol: Option<&'a str>,
+}
+
+impl IndirectEntry<'_> {
+    /// Label of the slot, e.g. `stub for _malloc`.
+    pub fn label(&self) -> String {
+        let kind = match self.kind {
+            IndirectKind::Stub => "stub",
+            IndirectKind::LazyPointer => "lazy pointer",
+            IndirectKind::NonLazyPointer => "pointer",
+            IndirectKind::ThreadLocalPointer => "thread local pointer",
+        };
+        format!("{} for {}", kind, self.symbol.unwrap_or("<local>"))
+    }
+}
+
+/// Maps every stub and lazy/non-lazy pointer slot of the image to the symbol it calls or holds.
+///
+/// Each of those sections stores in `reserved1` the index of its first slot in the indirect symbol
+/// table, stubs store their size in `reserved2`.
+pub fn indirect_entries<'a>(binary: &MachO<'a>) -> Result<Vec<IndirectEntry<'a>>, MachOError> {
+    let (Some(dysymtab), Some(symtab)) = (binary.dysymtab(), &binary.symtab) else {
+        return Ok(Vec::new());
+    };
+    let indirect = dysymtab.indirect_symbols(binary.data(), binary.endian)?;
+    let pointer_size = if binary.header.is_64() { 8 } else { 4 };
+
+    let mut entries = Vec::new();
+    for section in binary.sections() {
+        let (kind, slot_size) = match section.section_type() {
+            S_SYMBOL_STUBS => (IndirectKind::Stub, section.reserved2 as u64),
+            S_LAZY_SYMBOL_POINTERS | S_LAZY_DYLIB_SYMBOL_POINTERS => {
+                (IndirectKind::LazyPointer, pointer_size)
+            }
+            S_NON_LAZY_SYMBOL_POINTERS => (IndirectKind::NonLazyPointer, pointer_size),
+            S_THREAD_LOCAL_VARIABLE_POINTERS => (IndirectKind::ThreadLocalPointer, pointer_size),
+            _ => continue,
+        };
+        if slot_size == 0 {
+            continue;
+        }
+        for i in 0..section.size / slot_size {
+            let Some(&index) = indirect.get(section.reserved1 as usize + i as usize) else {
+                return Err(MachOError::IndirectSymbolTableOutOfRange {
+                    indirectsymoff: dysymtab.indirectsymoff,
+                    nindirectsyms: dysymtab.nindirectsyms,
+                });
+            };
+            let symbol = if index & (INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS) != 0 {
+                None
+            } else {
+                let nlist = symtab.entries.get(index as usize).ok_or(
+                    MachOError::SymbolIndexOutOfRange {
+                        index,
+                        nsyms: symtab.command.nsyms,
+                    },
+                )?;
+                Some(symtab.name(nlist)?)
+            };
+            // a section past the end of the address space ends there
+            let Some(address) = section.addr.checked_add(i * slot_size) else {
+                break;
+            };
+            entries.push(IndirectEntry {
+                address,
+                size: slot_size,
+                kind,
+                symbol,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::macho::Section;
+    use crate::macho::load_command::{LC_DYSYMTAB, LC_SEGMENT_64, LC_SYMTAB};
+    use crate::macho::symtab::{N_EXT, N_UNDF};
+    use crate::macho::tests::{image_with_commands, section, segment_64, symbol_table};
+
+    /// An image at `base` with two 12-byte stubs, three non-lazy pointers and a lazy pointer,
+    /// their indirect symbols starting at `reserved1` of each section.
+    pub(crate) fn indirect_image(base: u64, reserved1: [u32; 3], indirect: &[u32]) -> Vec<u8> {
+        let sections = [
+            (S_SYMBOL_STUBS, "__stubs", 0x400, 24),
+            (S_NON_LAZY_SYMBOL_POINTERS, "__got", 0x420, 24),
+            (S_LAZY_SYMBOL_POINTERS, "__la_symbol_ptr", 0x438, 8),
+        ];
+        let sections: Vec<Section> = sections
+            .iter()
+            .zip(reserved1)
+            .map(|((flags, name, offset, size), reserved1)| Section {
+                flags: *flags,
+                reserved1,
+                reserved2: if *flags == S_SYMBOL_STUBS { 12 } else { 0 },
+                ..section("__TEXT", name, base + offset, *size, *offset as u32)
+            })
+            .collect();
+        let undefined = N_UNDF | N_EXT;
+        let (symtab, symbols) = symbol_table(
+            0x600,
+            &[
+                ("_malloc", undefined, 0, 0x100, 0),
+                ("_free", undefined, 0, 0x100, 0),
+            ],
+        );
+        let mut dysymtab = [0u32; 18];
+        dysymtab[12] = 0x800;
+        dysymtab[13] = indirect.len() as u32;
+        let mut data = image_with_commands(
+            &[
+                (
+                    LC_SEGMENT_64,
+                    segment_64("__TEXT", base, 0, 0x1000, &sections),
+                ),
+                (LC_SYMTAB, symtab),
+                (
+                    LC_DYSYMTAB,
+                    dysymtab.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ),
+            ],
+            0x1000,
+        );
+        data[0x600..0x600 + symbols.len()].copy_from_slice(&symbols);
+        for (i, index) in indirect.iter().enumerate() {
+            data[0x800 + 4 * i..0x804 + 4 * i].copy_from_slice(&index.to_le_bytes());
+        }
+        data
+    }
+
+    const BASE: u64 = 0x1_0000_0000;
+    const INDIRECT: [u32; 6] = [
+        0,
+        1,
+        1,
+        INDIRECT_SYMBOL_LOCAL,
+        INDIRECT_SYMBOL_LOCAL | INDIRECT_SYMBOL_ABS,
+        0,
+    ];
+
+    #[test]
+    fn stubs_and_pointers_are_labelled() {
+        let data = indirect_image(BASE, [0, 2, 5], &INDIRECT);
+        let binary = MachO::parse(&data).unwrap();
+        let entries = indirect_entries(&binary).unwrap();
+        let labels: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.address - BASE, entry.size, entry.label()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                (0x400, 12, "stub for _malloc".to_string()),
+                (0x40c, 12, "stub for _free".to_string()),
+                (0x420, 8, "pointer for _free".to_string()),
+                (0x428, 8, "pointer for <local>".to_string()),
+                (0x430, 8, "pointer for <local>".to_string()),
+                (0x438, 8, "lazy pointer for _malloc".to_string()),
+            ]
+        );
+        assert_eq!(entries[3].kind, IndirectKind::NonLazyPointer);
+        assert_eq!(entries[4].symbol, None);
+    }
+
+    #[test]
+    fn out_of_range_indices() {
+        // the lazy pointer starts past the end of the indirect symbol table
+        let data = indirect_image(BASE, [0, 2, 6], &INDIRECT);
+        let binary = MachO::parse(&data).unwrap();
+        assert_eq!(
+            indirect_entries(&binary),
+            Err(MachOError::IndirectSymbolTableOutOfRange {
+                indirectsymoff: 0x800,
+                nindirectsyms: 6
+            })
+        );
+
+        // a symbol index past the end of the symbol table
+        let mut indirect = INDIRECT;
+        indirect[5] = 5;
+        let data = indirect_image(BASE, [0, 2, 5], &indirect);
+        let binary = MachO::parse(&data).unwrap();
+        assert_eq!(
+            indirect_entries(&binary),
+            Err(MachOError::SymbolIndexOutOfRange { index: 5, nsyms: 2 })
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use std::fmt;
+
+/// Every way a Mach-O buffer can be rejected by the parser.
+///
+/// Offsets are always relative to the start of the buffer given to the parser so they can be
+/// checked directly against the file with a hex editor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachOError {
+    /// The buffer is smaller than the mach header.
+    TruncatedHeader { len: usize },
+    /// The first four bytes are not a magic number we know about.
+    UnknownMagic(u32),
+    /// `sizeofcmds` points past the end of the buffer.
+    LoadCommandsOutOfBounds { sizeofcmds: u32, available: usize },
+    /// A load command declares a `cmdsize` that is too small or overruns `sizeofcmds`.
+    CommandSizeOverrun {
+        index: u32,
+        offset: usize,
+        cmdsize: u32,
+        remaining: usize,
+    },
+    /// A load command is shorter than the structure its `cmd` requires.
+    CommandTooSmall {
+        cmd: u32,
+        cmdsize: u32,
+        expected: usize,
+    },
+    /// `symoff`/`nsyms` of LC_SYMTAB describe a range outside of the buffer.
+    SymbolTableOutOfRange { symoff: u32, nsyms: u32 },
+    /// `stroff`/`strsize` of LC_SYMTAB describe a range outside of the buffer.
+    StringTableOutOfRange { stroff: u32, strsize: u32 },
+    /// A string index points outside of the string table.
+    StringIndexOutOfRange { strx: u32, strsize: u32 },
+    /// `indirectsymoff`/`nindirectsyms` of LC_DYSYMTAB describe a range outside of the buffer,
+    /// or a section refers to slots past the end of the table.
+    IndirectSymbolTableOutOfRange {
+        indirectsymoff: u32,
+        nindirectsyms: u32,
+    },
+    /// An index into the symbol table is past its end.
+    SymbolIndexOutOfRange { index: u32, nsyms: u32 },
+    /// A segment's file range is outside of the buffer.
+    SegmentOutOfRange {
+        segname: String,
+        fileoff: u64,
+        filesize: u64,
+    },
+    /// A section's file range is outside of the buffer.
+    SectionOutOfRange {
+        segname: String,
+        sectname: String,
+        offset: u32,
+        size: u64,
+    },
+    /// A slice of a fat binary is outside of the buffer.
+    FatSliceOutOfRange { index: u32, offset: u64, size: u64 },
+    /// The architecture name given by the user is not known.
+    UnknownArch(String),
+    /// The fat binary has no slice for the requested architecture.
+    NoMatchingSlice { arch: String, available: String },
+    /// The `__unwind_info` section has a version other than 1.
+    UnsupportedUnwindVersion(u32),
+    /// The `__unwind_info` section is inconsistent.
+    MalformedUnwindInfo(&'static str),
+    /// The rebase and bind information is inconsistent.
+    MalformedFixups(&'static str),
+    /// A rebase or bind opcode stream uses an opcode this decoder does not know.
+    UnknownFixupOpcode {
+        what: &'static str,
+        opcode: u8,
+        offset: usize,
+    },
+    /// LC_DYLD_CHAINED_FIXUPS uses a version or format this decoder does not know.
+    UnsupportedChainedFixups { what: &'static str, value: u32 },
+    /// The export trie is inconsistent.
+    MalformedExportTrie(&'static str),
+    /// The code signature or one of its blobs is inconsistent.
+    MalformedCodeSignature(&'static str),
+    /// The Objective-C class or method lists are inconsistent.
+    MalformedObjcMetadata(&'static str),
+    /// The Swift type or conformance records are inconsistent.
+    MalformedSwiftMetadata(&'static str),
+    /// A pointer of the runtime metadata points outside of the segments of the image.
+    AddressNotMapped(u64),
+    /// A CodeDirectory hashes with an algorithm this decoder does not know.
+    UnsupportedHashType(u8),
+    /// A LEB128 value does not fit in 64 bits.
+    MalformedLeb128 { offset: usize },
+    /// A string is not valid UTF-8.
+    InvalidString { offset: usize },
+    /// Generic bounds failure for a read of `size` bytes at `offset`.
+    OutOfBounds {
+        what: &'static str,
+        offset: usize,
+        size: usize,
+    },
+}
+
+impl fmt::Display for MachOError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MachOError::TruncatedHeader { len } => {
+                write!(f, "truncated mach header: only {len} bytes available")
+            }
+            MachOError::UnknownMagic(magic) => write!(f, "unknown magic number {magic:#x}"),
+            MachOError::LoadCommandsOutOfBounds {
+                sizeofcmds,
+                available,
+            } => write!(
+                f,
+                "load commands ({sizeofcmds} bytes) overrun the buffer ({available} bytes left)"
+            ),
+            MachOError::CommandSizeOverrun {
+                index,
+                offset,
+                cmdsize,
+                remaining,
+            } => write!(
+                f,
+                "load command #{index} at {offset:#x} has cmdsize {cmdsize} but only {remaining} bytes remain"
+            ),
+            MachOError::CommandTooSmall {
+                cmd,
+                cmdsize,
+                expected,
+            } => write!(
+                f,
+                "load command {cmd:#x} has cmdsize {cmdsize}, expected at least {expected}"
+            ),
+            MachOError::SymbolTableOutOfRange { symoff, nsyms } => write!(
+                f,
+                "symbol table ({nsyms} entries at {symoff:#x}) is out of range"
+            ),
+            MachOError::StringTableOutOfRange { stroff, strsize } => write!(
+                f,
+                "string table ({strsize} bytes at {stroff:#x}) is out of range"
+            ),
+            MachOError::StringIndexOutOfRange { strx, strsize } => write!(
+                f,
+                "string index {strx:#x} is outside of the string table ({strsize} bytes)"
+            ),
+            MachOError::IndirectSymbolTableOutOfRange {
+                indirectsymoff,
+                nindirectsyms,
+            } => write!(
+                f,
+                "indirect symbol table ({nindirectsyms} entries at {indirectsymoff:#x}) is out of range"
+            ),
+            MachOError::SymbolIndexOutOfRange { index, nsyms } => write!(
+                f,
+                "symbol index {index} is past the end of the symbol table ({nsyms} entries)"
+            ),
+            MachOError::SegmentOutOfRange {
+                segname,
+                fileoff,
+                filesize,
+            } => write!(
+                f,
+                "segment {segname} ({filesize} bytes at {fileoff:#x}) is out of range"
+            ),
+            MachOError::SectionOutOfRange {
+                segname,
+                sectname,
+                offset,
+                size,
+            } => write!(
+                f,
+                "section {segname},{sectname} ({size} bytes at {offset:#x}) is out of range"
+            ),
+            MachOError::FatSliceOutOfRange {
+                index,
+                offset,
+                size,
+            } => write!(
+                f,
+                "fat slice #{index} ({size} bytes at {offset:#x}) is out of range"
+            ),
+            MachOError::UnknownArch(name) => write!(f, "unknown architecture {name}"),
+            MachOError::NoMatchingSlice { arch, available } => write!(
+                f,
+                "no slice for {arch} in the fat binary (available: {available})"
+            ),
+            MachOError::UnsupportedUnwindVersion(version) => {
+                write!(f, "unsupported unwind info version {version}")
+            }
+            MachOError::MalformedUnwindInfo(reason) => write!(f, "malformed unwind info: {reason}"),
+            MachOError::MalformedFixups(reason) => write!(f, "malformed fixups: {reason}"),
+            MachOError::UnknownFixupOpcode {
+                what,
+                opcode,
+                offset,
+            } => write!(f, "{what}: unknown opcode {opcode:#x} at {offset:#x}"),
+            MachOError::UnsupportedChainedFixups { what, value } => {
+                write!(f, "unsupported chained fixups {what} {value}")
+            }
+            MachOError::MalformedExportTrie(reason) => write!(f, "malformed export trie: {reason}"),
+            MachOError::MalformedCodeSignature(reason) => {
+                write!(f, "malformed code signature: {reason}")
+            }
+            MachOError::MalformedObjcMetadata(reason) => {
+                write!(f, "malformed objc metadata: {reason}")
+            }
+            MachOError::MalformedSwiftMetadata(reason) => {
+                write!(f, "malformed swift metadata: {reason}")
+            }
+            MachOError::AddressNotMapped(address) => {
+                write!(f, "address {address:#x} is not mapped by the image")
+            }
+            MachOError::UnsupportedHashType(hash_type) => {
+                write!(f, "unsupported code directory hash type {hash_type}")
+            }
+            MachOError::MalformedLeb128 { offset } => {
+                write!(f, "malformed LEB128 value at {offset:#x}")
+            }
+            MachOError::InvalidString { offset } => {
+                write!(f, "string at {offset:#x} is not valid UTF-8")
+            }
+            MachOError::OutOfBounds { what, offset, size } => {
+                write!(f, "{what}: {size} bytes at {offset:#x} are out of bounds")
+            }
+        }
+    }
+}
+
+impl std::error::Error for MachOError {}
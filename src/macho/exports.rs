@@ -0,0 +1,248 @@
+// export trie of LC_DYLD_EXPORTS_TRIE or of the export area of LC_DYLD_INFO
+// a prefix tree of the exported names: each node may end a name (its terminal information says
+// where the symbol is) and has edges labelled with the next characters of the names below it
+// the only names left in a dylib whose symbol table is stripped to the imports
+
+use std::collections::HashSet;
+
+use super::reader::{self, Endian, Reader};
+use super::{LoadCommand, MachO, MachOError};
+
+pub const EXPORT_SYMBOL_FLAGS_KIND_MASK: u64 = 0x03;
+pub const EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL: u64 = 0x01;
+pub const EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE: u64 = 0x02;
+pub const EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION: u64 = 0x04;
+pub const EXPORT_SYMBOL_FLAGS_REEXPORT: u64 = 0x08;
+pub const EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER: u64 = 0x10;
+
+/// Where an exported symbol is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportTarget<'a> {
+    /// Offset of the symbol from the mach header, or its value for absolute symbols.
+    Offset(u64),
+    /// Offset of a stub calling the resolver on first use, and offset of the resolver.
+    StubAndResolver { stub: u64, resolver: u64 },
+    /// The symbol is defined by the dylib with this ordinal, under `import` when renamed.
+    Reexport {
+        library_ordinal: u64,
+        import: Option<&'a str>,
+    },
+}
+
+/// A name of the export trie.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Export<'a> {
+    pub name: String,
+    pub flags: u64,
+    pub target: ExportTarget<'a>,
+}
+
+impl Export<'_> {
+    pub fn kind(&self) -> u64 {
+        self.flags & EXPORT_SYMBOL_FLAGS_KIND_MASK
+    }
+
+    pub fn is_weak_definition(&self) -> bool {
+        self.flags & EXPORT_SYMBOL_FLAGS_WEAK_DEFINITION != 0
+    }
+
+    /// Offset from the mach header of the code or data of the symbol, `None` for re-exports and
+    /// absolute symbols which are not in the image.
+    pub fn offset(&self) -> Option<u64> {
+        match self.target {
+            ExportTarget::Offset(offset) if self.kind() != EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE => {
+                Some(offset)
+            }
+            ExportTarget::StubAndResolver { stub, .. } => Some(stub),
+            _ => None,
+        }
+    }
+
+    /// Short description of the kind and flags, e.g. `weak definition`.
+    pub fn describe(&self) -> String {
+        let mut flags = Vec::new();
+        match self.kind() {
+            EXPORT_SYMBOL_FLAGS_KIND_THREAD_LOCAL => flags.push("thread local".to_string()),
+            EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE => flags.push("absolute".to_string()),
+            _ => {}
+        }
+        if self.is_weak_definition() {
+            flags.push("weak definition".to_string());
+        }
+        match &self.target {
+            ExportTarget::Offset(_) => {}
+            ExportTarget::StubAndResolver { resolver, .. } => {
+                flags.push(format!("resolver at {resolver:#x}"))
+            }
+            ExportTarget::Reexport {
+                library_ordinal,
+                import,
+            } => flags.push(match import {
+                Some(import) => format!("re-export of {import} from dylib #{library_ordinal}"),
+                None => format!("re-export from dylib #{library_ordinal}"),
+            }),
+        }
+        flags.join(", ")
+    }
+}
+
+/// The export trie of `binary`, empty when the image exports nothing.
+pub fn export_trie<'a>(binary: &MachO<'a>) -> Result<&'a [u8], MachOError> {
+    let range = binary.load_commands.iter().find_map(|lc| match lc {
+        LoadCommand::DyldExportsTrie(linkedit) => Some((linkedit.dataoff, linkedit.datasize)),
+        LoadCommand::DyldInfo(info) | LoadCommand::DyldInfoOnly(info) => {
+            Some((info.export_off, info.export_size))
+        }
+        _ => None,
+    });
+    let Some((offset, size)) = range else {
+        return Ok(&[]);
+    };
+    reader::slice(binary.data(), offset as usize, size as usize, "export trie")
+}
+
+/// Every name exported by `binary`, in the order of the trie.
+pub fn exports<'a>(binary: &MachO<'a>) -> Result<Vec<Export<'a>>, MachOError> {
+    parse_trie(export_trie(binary)?)
+}
+
+/// Walks the trie `data` depth first, building the names from the edge labels.
+pub fn parse_trie(data: &[u8]) -> Result<Vec<Export<'_>>, MachOError> {
+    let mut exports = Vec::new();
+    if data.is_empty() {
+        return Ok(exports);
+    }
+    // a node reached twice means the trie loops
+    let mut visited = HashSet::new();
+    let mut stack = vec![(0usize, String::new())];
+    while let Some((offset, prefix)) = stack.pop() {
+        if !visited.insert(offset) {
+            return Err(MachOError::MalformedExportTrie("node reached twice"));
+        }
+        // the trie only holds bytes, strings and ULEB128 values
+        let mut r = Reader::at(data, offset, Endian::Little, "export trie");
+        let terminal_size = r.uleb128()? as usize;
+        let children = r.offset() + terminal_size;
+        if terminal_size != 0 {
+            let flags = r.uleb128()?;
+            let target = if flags & EXPORT_SYMBOL_FLAGS_REEXPORT != 0 {
+                let library_ordinal = r.uleb128()?;
+                let import = r.cstr()?;
+                ExportTarget::Reexport {
+                    library_ordinal,
+                    // an empty name keeps the exported one
+                    import: (!import.is_empty()).then_some(import),
+                }
+            } else if flags & EXPORT_SYMBOL_FLAGS_STUB_AND_RESOLVER != 0 {
+                ExportTarget::StubAndResolver {
+                    stub: r.uleb128()?,
+                    resolver: r.uleb128()?,
+                }
+            } else {
+                ExportTarget::Offset(r.uleb128()?)
+            };
+            exports.push(Export {
+                name: prefix.clone(),
+                flags,
+                target,
+            });
+        }
+        r.seek(children);
+        let count = r.u8()?;
+        let mut edges = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let label = r.cstr()?;
+            let child = r.uleb128()? as usize;
+            if child >= data.len() {
+                return Err(MachOError::MalformedExportTrie(
+                    "child past the end of the trie",
+                ));
+            }
+            edges.push((child, format!("{prefix}{label}")));
+        }
+        // popped in the order of the edges
+        stack.extend(edges.into_iter().rev());
+    }
+    Ok(exports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // _main, _f with _foo below it, and _re, each terminal with a different target
+    const TRIE: &[u8] = &[
+        // 0: root
+        0, 1, b'_', 0, 5, //
+        // 5: "_"
+        0, 3, b'm', b'a', b'i', b'n', 0, 20, b'f', 0, 25, b'r', b'e', 0, 34, //
+        // 20: "_main" at 0x1000
+        3, 0, 0x80, 0x20, 0, //
+        // 25: "_f", weak at 0x2000
+        3, 4, 0x80, 0x40, 1, b'o', b'o', 0, 44, //
+        // 34: "_re", re-export of _real from dylib #2
+        8, 8, 2, b'_', b'r', b'e', b'a', b'l', 0, 0, //
+        // 44: "_foo", stub at 0x10 and resolver at 0x20
+        3, 0x10, 0x10, 0x20, 0,
+    ];
+
+    #[test]
+    fn names_are_built_from_the_edges() {
+        let exports = parse_trie(TRIE).unwrap();
+        let names: Vec<_> = exports.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["_main", "_f", "_foo", "_re"]);
+        assert_eq!(exports[0].target, ExportTarget::Offset(0x1000));
+        assert_eq!(exports[1].offset(), Some(0x2000));
+        assert_eq!(exports[1].describe(), "weak definition");
+        assert_eq!(
+            exports[2].target,
+            ExportTarget::StubAndResolver {
+                stub: 0x10,
+                resolver: 0x20
+            }
+        );
+        assert_eq!(exports[2].offset(), Some(0x10));
+        assert_eq!(
+            exports[3].target,
+            ExportTarget::Reexport {
+                library_ordinal: 2,
+                import: Some("_real")
+            }
+        );
+        assert_eq!(exports[3].offset(), None);
+        assert_eq!(exports[3].describe(), "re-export of _real from dylib #2");
+        assert!(parse_trie(&[]).unwrap().is_empty());
+
+        let absolute = Export {
+            name: "_version".to_string(),
+            flags: EXPORT_SYMBOL_FLAGS_KIND_ABSOLUTE,
+            target: ExportTarget::Offset(7),
+        };
+        assert_eq!(absolute.offset(), None);
+        assert_eq!(absolute.describe(), "absolute");
+    }
+
+    #[test]
+    fn malformed_tries() {
+        // an edge back to the root
+        let mut looping = TRIE.to_vec();
+        looping[4] = 0;
+        assert_eq!(
+            parse_trie(&looping).unwrap_err(),
+            MachOError::MalformedExportTrie("node reached twice")
+        );
+        let mut past_end = TRIE.to_vec();
+        past_end[19] = TRIE.len() as u8;
+        assert_eq!(
+            parse_trie(&past_end).unwrap_err(),
+            MachOError::MalformedExportTrie("child past the end of the trie")
+        );
+        // a terminal size running past the trie
+        let mut terminal = TRIE.to_vec();
+        terminal[20] = 0x7f;
+        assert!(parse_trie(&terminal).is_err());
+        for len in 1..TRIE.len() {
+            assert!(parse_trie(&TRIE[..len]).is_err(), "{len} bytes");
+        }
+    }
+}
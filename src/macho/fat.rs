@@ -0,0 +1,240 @@
+// universal (fat) binaries
+// a fat header followed by one fat_arch per slice, always stored big endian
+// each slice is a complete Mach-O image at `offset` in the file
+
+use super::MachOError;
+use super::cpu::{self, Arch};
+use super::reader::{self, Endian, Reader};
+
+pub const FAT_MAGIC: u32 = 0xcafebabe;
+pub const FAT_MAGIC_64: u32 = 0xcafebabf;
+
+/// One slice of a fat binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatArch {
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub offset: u64,
+    pub size: u64,
+    pub align: u32,
+}
+
+impl FatArch {
+    pub fn name(&self) -> String {
+        cpu::arch_name(self.cputype, self.cpusubtype)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FatBinary<'a> {
+    pub arches: Vec<FatArch>,
+    data: &'a [u8],
+}
+
+/// Returns true if the buffer starts with a fat magic number.
+pub fn is_fat(data: &[u8]) -> bool {
+    matches!(
+        data.get(..4)
+            .map(|m| u32::from_be_bytes(m.try_into().unwrap())),
+        Some(FAT_MAGIC | FAT_MAGIC_64)
+    )
+}
+
+impl<'a> FatBinary<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<FatBinary<'a>, MachOError> {
+        if data.len() < 8 {
+            return Err(MachOError::TruncatedHeader { len: data.len() });
+        }
+        let mut r = Reader::new(data, Endian::Big, "fat header");
+        let magic = r.u32()?;
+        let is_64 = match magic {
+            FAT_MAGIC => false,
+            FAT_MAGIC_64 => true,
+            _ => return Err(MachOError::UnknownMagic(magic)),
+        };
+        let nfat_arch = r.u32()?;
+        let entry_size = if is_64 { 32 } else { 20 };
+        reader::slice(data, 8, nfat_arch as usize * entry_size, "fat arch table")?;
+
+        let mut arches = Vec::with_capacity(nfat_arch as usize);
+        for index in 0..nfat_arch {
+            let arch = if is_64 {
+                let arch = FatArch {
+                    cputype: r.u32()?,
+                    cpusubtype: r.u32()?,
+                    offset: r.u64()?,
+                    size: r.u64()?,
+                    align: r.u32()?,
+                };
+                r.skip(4)?;
+                arch
+            } else {
+                FatArch {
+                    cputype: r.u32()?,
+                    cpusubtype: r.u32()?,
+                    offset: r.u32()? as u64,
+                    size: r.u32()? as u64,
+                    align: r.u32()?,
+                }
+            };
+            if reader::slice64(data, arch.offset, arch.size, "fat slice").is_err() {
+                return Err(MachOError::FatSliceOutOfRange {
+                    index,
+                    offset: arch.offset,
+                    size: arch.size,
+                });
+            }
+            arches.push(arch);
+        }
+        Ok(FatBinary { arches, data })
+    }
+
+    /// Bytes of the given slice, already validated by `parse`.
+    pub fn slice(&self, arch: &FatArch) -> &'a [u8] {
+        &self.data[arch.offset as usize..(arch.offset + arch.size) as usize]
+    }
+
+    pub fn find(&self, arch: &Arch) -> Option<&FatArch> {
+        self.arches
+            .iter()
+            .find(|a| arch.matches(a.cputype, a.cpusubtype))
+    }
+}
+
+/// Returns the Mach-O image to parse from `data`.
+///
+/// A thin file is returned as is. For a fat file the slice matching `arch` is returned, or the one
+/// matching `target`, the architecture of the process or core the image belongs to. Only plain
+/// files, without a target, fall back to the host when no architecture is requested.
+pub fn select_slice<'a>(
+    data: &'a [u8],
+    arch: Option<&str>,
+    target: Option<Arch>,
+) -> Result<&'a [u8], MachOError> {
+    if !is_fat(data) {
+        return Ok(data);
+    }
+    let fat = FatBinary::parse(data)?;
+    let wanted = match (arch, target) {
+        (Some(name), _) => {
+            Arch::from_name(name).ok_or(MachOError::UnknownArch(name.to_string()))?
+        }
+        (None, Some(target)) => target,
+        (None, None) => {
+            Arch::host().ok_or(MachOError::UnknownArch(std::env::consts::ARCH.to_string()))?
+        }
+    };
+    let available = || {
+        fat.arches
+            .iter()
+            .map(FatArch::name)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let slice = fat
+        .find(&wanted)
+        .ok_or_else(|| MachOError::NoMatchingSlice {
+            arch: arch
+                .map(str::to_string)
+                .unwrap_or_else(|| cpu::arch_name(wanted.cputype, wanted.cpusubtype.unwrap_or(0))),
+            available: available(),
+        })?;
+    Ok(fat.slice(slice))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::tests::image;
+    use cpu::{CPU_TYPE_ARM64, CPU_TYPE_X86_64};
+
+    /// A fat file of `(cputype, cpusubtype)` slices, each a distinct image.
+    fn fat(slices: &[(u32, u32)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for value in [FAT_MAGIC, slices.len() as u32] {
+            data.extend(value.to_be_bytes());
+        }
+        let mut offset = 8 + 20 * slices.len();
+        let images: Vec<_> = slices
+            .iter()
+            .map(|(cputype, _)| image(*cputype, true, Endian::Little))
+            .collect();
+        for ((cputype, cpusubtype), image) in slices.iter().zip(&images) {
+            for value in [*cputype, *cpusubtype, offset as u32, image.len() as u32, 0] {
+                data.extend(value.to_be_bytes());
+            }
+            offset += image.len();
+        }
+        images.into_iter().for_each(|image| data.extend(image));
+        data
+    }
+
+    fn selected(data: &[u8], arch: Option<&str>, target: Option<Arch>) -> (usize, usize) {
+        let slice = select_slice(data, arch, target).unwrap();
+        let offset = slice.as_ptr() as usize - data.as_ptr() as usize;
+        (offset, slice.len())
+    }
+
+    #[test]
+    fn lists_the_slices() {
+        let data = fat(&[(CPU_TYPE_X86_64, 3), (CPU_TYPE_ARM64, 2)]);
+        let binary = FatBinary::parse(&data).unwrap();
+        let names: Vec<_> = binary.arches.iter().map(FatArch::name).collect();
+        assert_eq!(names, ["x86_64", "arm64e"]);
+        assert_eq!(binary.arches[1].offset, 48 + 128);
+        assert!(is_fat(&data));
+        assert!(!is_fat(&image(CPU_TYPE_ARM64, true, Endian::Little)));
+    }
+
+    #[test]
+    fn slices_are_selected_by_name_then_target_then_host() {
+        let data = fat(&[
+            (CPU_TYPE_X86_64, 3),
+            (CPU_TYPE_ARM64, 0),
+            (CPU_TYPE_ARM64, 2),
+        ]);
+        let arm64e = Arch::exact(CPU_TYPE_ARM64, 0x8000_0002);
+        assert_eq!(selected(&data, Some("x86_64"), None), (68, 128));
+        assert_eq!(selected(&data, Some("arm64"), None), (68 + 128, 128));
+        assert_eq!(selected(&data, Some("arm64e"), None), (68 + 256, 128));
+        // the process runs the arm64e slice, the host does not matter
+        assert_eq!(selected(&data, None, Some(arm64e)), (68 + 256, 128));
+        assert_eq!(selected(&data, Some("x86_64"), Some(arm64e)), (68, 128));
+        if let Some(host) = Arch::host() {
+            let wanted = select_slice(&data, None, Some(host)).ok();
+            assert_eq!(select_slice(&data, None, None).ok(), wanted);
+        }
+
+        // thin files are used whatever the architecture
+        let thin = image(CPU_TYPE_ARM64, true, Endian::Little);
+        assert_eq!(selected(&thin, Some("x86_64"), None), (0, thin.len()));
+    }
+
+    #[test]
+    fn missing_slices() {
+        let data = fat(&[(CPU_TYPE_X86_64, 3)]);
+        assert_eq!(
+            select_slice(&data, None, Some(Arch::exact(CPU_TYPE_ARM64, 2))).unwrap_err(),
+            MachOError::NoMatchingSlice {
+                arch: "arm64e".to_string(),
+                available: "x86_64".to_string()
+            }
+        );
+        assert_eq!(
+            select_slice(&data, Some("vax"), None).unwrap_err(),
+            MachOError::UnknownArch("vax".to_string())
+        );
+
+        // a slice past the end of the file
+        let mut data = data;
+        data[20..24].copy_from_slice(&129u32.to_be_bytes());
+        assert_eq!(
+            FatBinary::parse(&data).unwrap_err(),
+            MachOError::FatSliceOutOfRange {
+                index: 0,
+                offset: 28,
+                size: 129
+            }
+        );
+    }
+}
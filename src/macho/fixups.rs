@@ -0,0 +1,132 @@
+// pointers dyld fixes up when loading an image
+// binds point to symbols of other images, rebases to the image itself and move with its slide
+// older images describe them with the opcode streams of LC_DYLD_INFO, newer ones thread them
+// through the pointers themselves with LC_DYLD_CHAINED_FIXUPS, both decode to the same lists
+
+use std::collections::HashMap;
+
+use super::{LoadCommand, MachO, MachOError, chained_fixups, dyld_info};
+
+// special library ordinals of binds, the others are 1-based indices of the dylib commands
+pub const BIND_SPECIAL_DYLIB_SELF: i32 = 0;
+pub const BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE: i32 = -1;
+pub const BIND_SPECIAL_DYLIB_FLAT_LOOKUP: i32 = -2;
+pub const BIND_SPECIAL_DYLIB_WEAK_LOOKUP: i32 = -3;
+
+/// A symbol the image needs from another image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Import<'a> {
+    pub name: &'a str,
+    /// 1-based index of the dylib in load order, or one of the special ordinals.
+    pub library_ordinal: i32,
+    /// Added to the address of the symbol before storing it.
+    pub addend: i64,
+    /// The image still loads when the symbol is missing, the pointer is then null.
+    pub weak: bool,
+}
+
+/// How a pointer gets bound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindKind {
+    /// At load time.
+    Regular,
+    /// At load time, to the first definition of a weak symbol among every image.
+    Weak,
+    /// On first call, through `dyld_stub_binder`.
+    Lazy,
+}
+
+/// A pointer slot set to the address of an import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bind {
+    /// Preferred (unslid) address of the slot.
+    pub address: u64,
+    /// Index of the import in `Fixups::imports`.
+    pub import: usize,
+    pub kind: BindKind,
+}
+
+/// A pointer slot to the image itself, slid with the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rebase {
+    /// Preferred (unslid) address of the slot.
+    pub address: u64,
+    /// Preferred (unslid) address the slot points to.
+    pub target: u64,
+}
+
+/// Imports, binds and rebases of an image.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Fixups<'a> {
+    /// Distinct imports, in the order they are first bound.
+    pub imports: Vec<Import<'a>>,
+    /// Binds in the order of the file.
+    pub binds: Vec<Bind>,
+    pub rebases: Vec<Rebase>,
+    // index of every import, to share them between binds
+    indices: HashMap<Import<'a>, usize>,
+}
+
+impl<'a> Fixups<'a> {
+    /// Records a bind of `import` at `address`.
+    pub fn bind(&mut self, address: u64, import: Import<'a>, kind: BindKind) {
+        let next = self.imports.len();
+        let index = *self.indices.entry(import).or_insert(next);
+        if index == next {
+            self.imports.push(import);
+        }
+        self.binds.push(Bind {
+            address,
+            import: index,
+            kind,
+        });
+    }
+
+    /// Label of a bound slot, e.g. `pointer for _malloc`.
+    pub fn label(&self, bind: &Bind) -> String {
+        let import = &self.imports[bind.import];
+        let kind = match bind.kind {
+            BindKind::Lazy => "lazy pointer",
+            BindKind::Regular | BindKind::Weak => "pointer",
+        };
+        match import.addend {
+            0 => format!("{} for {}", kind, import.name),
+            addend if addend < 0 => {
+                format!("{} for {}-{:#x}", kind, import.name, addend.unsigned_abs())
+            }
+            addend => format!("{} for {}+{:#x}", kind, import.name, addend),
+        }
+    }
+}
+
+/// Decodes the fixups of `binary`, from LC_DYLD_CHAINED_FIXUPS or else LC_DYLD_INFO(_ONLY).
+/// Images with neither only have the relocations of the old dynamic symbol table, which are
+/// not decoded.
+pub fn fixups<'a>(binary: &MachO<'a>) -> Result<Fixups<'a>, MachOError> {
+    for lc in &binary.load_commands {
+        match lc {
+            LoadCommand::DyldChainedFixups(linkedit) => {
+                return chained_fixups::parse(binary, linkedit);
+            }
+            LoadCommand::DyldInfo(info) | LoadCommand::DyldInfoOnly(info) => {
+                return dyld_info::parse(binary, info);
+            }
+            _ => {}
+        }
+    }
+    Ok(Fixups::default())
+}
+
+/// Install name of the dylib a library ordinal refers to, or what the special ordinals stand for.
+pub fn library_name(binary: &MachO, ordinal: i32) -> String {
+    match ordinal {
+        BIND_SPECIAL_DYLIB_SELF => "this image".to_string(),
+        BIND_SPECIAL_DYLIB_MAIN_EXECUTABLE => "the main executable".to_string(),
+        BIND_SPECIAL_DYLIB_FLAT_LOOKUP => "flat lookup".to_string(),
+        BIND_SPECIAL_DYLIB_WEAK_LOOKUP => "weak lookup".to_string(),
+        ordinal => binary.dylibs().nth((ordinal - 1) as usize).map_or_else(
+            || format!("dylib #{}", ordinal),
+            |dylib| dylib.name.to_string(),
+        ),
+    }
+}
@@ -0,0 +1,89 @@
+// LC_FUNCTION_STARTS
+// a ULEB128 stream of deltas between consecutive function entry points, the first one relative to
+// the start of __TEXT, terminated by a zero delta
+// kept even in stripped binaries so it is the only way to know where their functions begin
+
+use super::reader::{self, Reader};
+use super::{LoadCommand, MachO, MachOError};
+
+/// Preferred (unslid) addresses of every function entry point, in increasing order.
+pub fn function_starts(binary: &MachO) -> Result<Vec<u64>, MachOError> {
+    let Some(linkedit) = binary.load_commands.iter().find_map(|lc| match lc {
+        LoadCommand::FunctionStarts(linkedit) => Some(*linkedit),
+        _ => None,
+    }) else {
+        return Ok(Vec::new());
+    };
+    let Some(text) = binary.segment("__TEXT") else {
+        return Ok(Vec::new());
+    };
+    let data = reader::slice(
+        binary.data(),
+        linkedit.dataoff as usize,
+        linkedit.datasize as usize,
+        "function starts",
+    )?;
+
+    let mut r = Reader::new(data, binary.endian, "function starts");
+    let mut starts = Vec::new();
+    let mut address = text.vmaddr;
+    while !r.is_empty() {
+        let delta = r.uleb128()?;
+        if delta == 0 {
+            break;
+        }
+        address = address.wrapping_add(delta);
+        starts.push(address);
+    }
+    Ok(starts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::load_command::{LC_FUNCTION_STARTS, LC_SEGMENT_64};
+    use crate::macho::tests::{image_with_commands, segment_64};
+
+    const BASE: u64 = 0x1_0000_0000;
+
+    fn starts(blob: &[u8]) -> Result<Vec<u64>, MachOError> {
+        let linkedit = [0x800u32, blob.len() as u32];
+        let mut data = image_with_commands(
+            &[
+                (LC_SEGMENT_64, segment_64("__TEXT", BASE, 0, 0x1000, &[])),
+                (
+                    LC_FUNCTION_STARTS,
+                    linkedit.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ),
+            ],
+            0x1000,
+        );
+        data[0x800..0x800 + blob.len()].copy_from_slice(blob);
+        function_starts(&MachO::parse(&data).unwrap())
+    }
+
+    #[test]
+    fn deltas_from_the_start_of_text() {
+        // 0x400 and 0x90 take two bytes, nothing is read past the zero
+        let blob = [0x80, 0x08, 0x10, 0x90, 0x01, 0, 0x55, 0x80];
+        assert_eq!(
+            starts(&blob),
+            Ok(vec![BASE + 0x400, BASE + 0x410, BASE + 0x4a0])
+        );
+        // the terminator is optional at the end of the blob
+        assert_eq!(starts(&[0x20, 0x20]), Ok(vec![BASE + 0x20, BASE + 0x40]));
+        assert_eq!(starts(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn truncated_deltas() {
+        assert!(starts(&[0x10, 0x80]).is_err());
+        assert!(starts(&[0x80, 0x80, 0x80]).is_err());
+        // more than 64 bits
+        let overlong = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f];
+        assert_eq!(
+            starts(&overlong),
+            Err(MachOError::MalformedLeb128 { offset: 0 })
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use super::MachOError;
+use super::reader::{Endian, Reader};
+
+// magic numbers read as a little endian u32
+// the CIGAM variants mean the file is stored in the opposite (big endian) byte order
+pub const MH_MAGIC: u32 = 0xfeedface;
+pub const MH_CIGAM: u32 = 0xcefaedfe;
+pub const MH_MAGIC_64: u32 = 0xfeedfacf;
+pub const MH_CIGAM_64: u32 = 0xcffaedfe;
+
+// file types
+pub const MH_EXECUTE: u32 = 0x2;
+pub const MH_CORE: u32 = 0x4;
+pub const MH_DYLIB: u32 = 0x6;
+pub const MH_DYLINKER: u32 = 0x7;
+pub const MH_BUNDLE: u32 = 0x8;
+
+/// Flag of the images of the dyld shared cache, their file offsets are offsets in the cache.
+pub const MH_DYLIB_IN_CACHE: u32 = 0x8000_0000;
+
+/// Size in bytes of `mach_header`.
+pub const MACH_HEADER_SIZE: usize = 28;
+/// Size in bytes of `mach_header_64`.
+pub const MACH_HEADER_64_SIZE: usize = 32;
+
+/// Decoded `mach_header` or `mach_header_64`, `reserved` is 0 for 32-bit images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachHeader {
+    pub magic: u32,
+    pub cputype: u32,
+    pub cpusubtype: u32,
+    pub filetype: u32,
+    pub ncmds: u32,
+    pub sizeofcmds: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+impl MachHeader {
+    /// Detects the byte order from the magic number and decodes the header with it.
+    pub fn parse(data: &[u8]) -> Result<(MachHeader, Endian), MachOError> {
+        let Some(magic_bytes) = data.get(..4) else {
+            return Err(MachOError::TruncatedHeader { len: data.len() });
+        };
+        let raw_magic = u32::from_le_bytes(magic_bytes.try_into().unwrap());
+        let (endian, size) = match raw_magic {
+            MH_MAGIC => (Endian::Little, MACH_HEADER_SIZE),
+            MH_CIGAM => (Endian::Big, MACH_HEADER_SIZE),
+            MH_MAGIC_64 => (Endian::Little, MACH_HEADER_64_SIZE),
+            MH_CIGAM_64 => (Endian::Big, MACH_HEADER_64_SIZE),
+            _ => return Err(MachOError::UnknownMagic(raw_magic)),
+        };
+        if data.len() < size {
+            return Err(MachOError::TruncatedHeader { len: data.len() });
+        }
+        let mut r = Reader::new(data, endian, "mach header");
+        let magic = r.u32()?;
+        let header = MachHeader {
+            magic,
+            cputype: r.u32()?,
+            cpusubtype: r.u32()?,
+            filetype: r.u32()?,
+            ncmds: r.u32()?,
+            sizeofcmds: r.u32()?,
+            flags: r.u32()?,
+            reserved: if magic == MH_MAGIC_64 { r.u32()? } else { 0 },
+        };
+        Ok((header, endian))
+    }
+
+    /// The magic is decoded with the file's byte order so only the native values are left.
+    pub fn is_64(&self) -> bool {
+        self.magic == MH_MAGIC_64
+    }
+
+    /// Size of the header, the load commands start right after it.
+    pub fn size(&self) -> usize {
+        if self.is_64() {
+            MACH_HEADER_64_SIZE
+        } else {
+            MACH_HEADER_SIZE
+        }
+    }
+}
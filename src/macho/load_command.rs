@@ -0,0 +1,680 @@
+use super::MachOError;
+use super::dysymtab::DysymtabCommand;
+use super::reader::{self, Endian, Reader};
+use super::segment::{self, Segment};
+use super::symtab::SymtabCommand;
+
+// load command types from <mach-o/loader.h>
+// commands with LC_REQ_DYLD set must be understood by dyld for the image to load
+pub const LC_REQ_DYLD: u32 = 0x8000_0000;
+pub const LC_SEGMENT: u32 = 0x1;
+pub const LC_SYMTAB: u32 = 0x2;
+pub const LC_THREAD: u32 = 0x4;
+pub const LC_UNIXTHREAD: u32 = 0x5;
+pub const LC_DYSYMTAB: u32 = 0xb;
+pub const LC_LOAD_DYLIB: u32 = 0xc;
+pub const LC_ID_DYLIB: u32 = 0xd;
+pub const LC_LOAD_DYLINKER: u32 = 0xe;
+pub const LC_ID_DYLINKER: u32 = 0xf;
+pub const LC_LOAD_WEAK_DYLIB: u32 = 0x18 | LC_REQ_DYLD;
+pub const LC_SEGMENT_64: u32 = 0x19;
+pub const LC_UUID: u32 = 0x1b;
+pub const LC_RPATH: u32 = 0x1c | LC_REQ_DYLD;
+pub const LC_CODE_SIGNATURE: u32 = 0x1d;
+pub const LC_SEGMENT_SPLIT_INFO: u32 = 0x1e;
+pub const LC_REEXPORT_DYLIB: u32 = 0x1f | LC_REQ_DYLD;
+pub const LC_LAZY_LOAD_DYLIB: u32 = 0x20;
+pub const LC_ENCRYPTION_INFO: u32 = 0x21;
+pub const LC_DYLD_INFO: u32 = 0x22;
+pub const LC_DYLD_INFO_ONLY: u32 = 0x22 | LC_REQ_DYLD;
+pub const LC_LOAD_UPWARD_DYLIB: u32 = 0x23 | LC_REQ_DYLD;
+pub const LC_VERSION_MIN_MACOSX: u32 = 0x24;
+pub const LC_VERSION_MIN_IPHONEOS: u32 = 0x25;
+pub const LC_FUNCTION_STARTS: u32 = 0x26;
+pub const LC_MAIN: u32 = 0x28 | LC_REQ_DYLD;
+pub const LC_DATA_IN_CODE: u32 = 0x29;
+pub const LC_SOURCE_VERSION: u32 = 0x2a;
+pub const LC_ENCRYPTION_INFO_64: u32 = 0x2c;
+pub const LC_VERSION_MIN_TVOS: u32 = 0x2f;
+pub const LC_VERSION_MIN_WATCHOS: u32 = 0x30;
+pub const LC_NOTE: u32 = 0x31;
+pub const LC_BUILD_VERSION: u32 = 0x32;
+pub const LC_DYLD_EXPORTS_TRIE: u32 = 0x33 | LC_REQ_DYLD;
+pub const LC_DYLD_CHAINED_FIXUPS: u32 = 0x34 | LC_REQ_DYLD;
+
+/// Size of the `cmd` + `cmdsize` pair every load command starts with.
+pub const LOAD_COMMAND_SIZE: usize = 8;
+
+/// A load command as found in the file, `data` covers the whole command including its header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawLoadCommand<'a> {
+    pub cmd: u32,
+    pub cmdsize: u32,
+    /// Offset of the command from the start of the image.
+    pub offset: usize,
+    pub data: &'a [u8],
+}
+
+impl<'a> RawLoadCommand<'a> {
+    /// Reader positioned right after `cmd` and `cmdsize`, bounded to this command.
+    pub fn body(&self, endian: Endian) -> Reader<'a> {
+        Reader::at(self.data, LOAD_COMMAND_SIZE, endian, "load command")
+    }
+
+    /// Fails with `CommandTooSmall` if the command cannot hold `expected` bytes.
+    pub fn expect_size(&self, expected: usize) -> Result<(), MachOError> {
+        if (self.cmdsize as usize) < expected {
+            return Err(MachOError::CommandTooSmall {
+                cmd: self.cmd,
+                cmdsize: self.cmdsize,
+                expected,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the `lc_str` at `offset` from the start of the command.
+    fn string(&self, offset: u32) -> Result<&'a str, MachOError> {
+        if offset as usize >= self.data.len() {
+            return Err(MachOError::OutOfBounds {
+                what: "load command string",
+                offset: self.offset + offset as usize,
+                size: 1,
+            });
+        }
+        reader::cstr(self.data, offset as usize).ok_or(MachOError::OutOfBounds {
+            what: "load command string",
+            offset: self.offset + offset as usize,
+            size: self.data.len() - offset as usize,
+        })
+    }
+}
+
+/// Splits the `sizeofcmds` bytes following the header into load commands, validating every
+/// `cmdsize` against what is left.
+pub fn parse_load_commands<'a>(
+    data: &'a [u8],
+    start: usize,
+    ncmds: u32,
+    sizeofcmds: u32,
+    endian: Endian,
+) -> Result<Vec<RawLoadCommand<'a>>, MachOError> {
+    let commands =
+        reader::slice(data, start, sizeofcmds as usize, "load commands").map_err(|_| {
+            MachOError::LoadCommandsOutOfBounds {
+                sizeofcmds,
+                available: data.len().saturating_sub(start),
+            }
+        })?;
+
+    let mut load_commands = Vec::with_capacity(ncmds as usize);
+    let mut offset = 0;
+    for index in 0..ncmds {
+        let remaining = commands.len() - offset;
+        let overrun = |cmdsize| MachOError::CommandSizeOverrun {
+            index,
+            offset: start + offset,
+            cmdsize,
+            remaining,
+        };
+        if remaining < LOAD_COMMAND_SIZE {
+            return Err(overrun(0));
+        }
+        let mut r = Reader::at(commands, offset, endian, "load command");
+        let cmd = r.u32()?;
+        let cmdsize = r.u32()?;
+        if (cmdsize as usize) < LOAD_COMMAND_SIZE || cmdsize as usize > remaining {
+            return Err(overrun(cmdsize));
+        }
+        load_commands.push(RawLoadCommand {
+            cmd,
+            cmdsize,
+            offset: start + offset,
+            data: &commands[offset..offset + cmdsize as usize],
+        });
+        offset += cmdsize as usize;
+    }
+    Ok(load_commands)
+}
+
+/// Payload of the dylib commands (LC_LOAD_DYLIB, LC_ID_DYLIB, LC_REEXPORT_DYLIB...).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dylib<'a> {
+    pub name: &'a str,
+    pub timestamp: u32,
+    pub current_version: u32,
+    pub compatibility_version: u32,
+}
+
+/// Payload of the commands pointing to a blob in __LINKEDIT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkeditData {
+    pub dataoff: u32,
+    pub datasize: u32,
+}
+
+/// Payload of LC_DYLD_INFO and LC_DYLD_INFO_ONLY.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DyldInfo {
+    pub rebase_off: u32,
+    pub rebase_size: u32,
+    pub bind_off: u32,
+    pub bind_size: u32,
+    pub weak_bind_off: u32,
+    pub weak_bind_size: u32,
+    pub lazy_bind_off: u32,
+    pub lazy_bind_size: u32,
+    pub export_off: u32,
+    pub export_size: u32,
+}
+
+/// Payload of the LC_VERSION_MIN_* commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionMin {
+    pub version: u32,
+    pub sdk: u32,
+}
+
+/// Payload of LC_BUILD_VERSION.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildVersion {
+    pub platform: u32,
+    pub minos: u32,
+    pub sdk: u32,
+    /// (tool, version) pairs.
+    pub tools: Vec<(u32, u32)>,
+}
+
+/// A register state of LC_THREAD or LC_UNIXTHREAD, in the layout of its flavor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadState<'a> {
+    pub flavor: u32,
+    /// `count` 32-bit words.
+    pub state: &'a [u8],
+}
+
+/// Payload of LC_NOTE: data of a format known to its owner, stored elsewhere in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NoteCommand {
+    pub data_owner: String,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// Payload of LC_ENCRYPTION_INFO and LC_ENCRYPTION_INFO_64.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionInfo {
+    pub cryptoff: u32,
+    pub cryptsize: u32,
+    pub cryptid: u32,
+}
+
+/// A decoded load command.
+///
+/// Commands the parser has no use for are kept as `Unknown` with their raw bytes so nothing in
+/// the file is lost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadCommand<'a> {
+    Segment(Segment),
+    Segment64(Segment),
+    Symtab(SymtabCommand),
+    Dysymtab(DysymtabCommand),
+    Uuid([u8; 16]),
+    BuildVersion(BuildVersion),
+    VersionMinMacosx(VersionMin),
+    VersionMinIphoneos(VersionMin),
+    VersionMinTvos(VersionMin),
+    VersionMinWatchos(VersionMin),
+    Main {
+        entryoff: u64,
+        stacksize: u64,
+    },
+    LoadDylib(Dylib<'a>),
+    LoadWeakDylib(Dylib<'a>),
+    ReexportDylib(Dylib<'a>),
+    LazyLoadDylib(Dylib<'a>),
+    LoadUpwardDylib(Dylib<'a>),
+    IdDylib(Dylib<'a>),
+    LoadDylinker(&'a str),
+    IdDylinker(&'a str),
+    Rpath(&'a str),
+    FunctionStarts(LinkeditData),
+    DataInCode(LinkeditData),
+    CodeSignature(LinkeditData),
+    SegmentSplitInfo(LinkeditData),
+    DyldInfo(DyldInfo),
+    DyldInfoOnly(DyldInfo),
+    DyldChainedFixups(LinkeditData),
+    DyldExportsTrie(LinkeditData),
+    SourceVersion(u64),
+    EncryptionInfo(EncryptionInfo),
+    EncryptionInfo64(EncryptionInfo),
+    /// Register states of the threads of a core file, one command per thread.
+    Thread(Vec<ThreadState<'a>>),
+    /// Initial register state of the main thread of old executables.
+    UnixThread(Vec<ThreadState<'a>>),
+    Note(NoteCommand),
+    Unknown {
+        cmd: u32,
+        data: &'a [u8],
+    },
+}
+
+impl<'a> LoadCommand<'a> {
+    /// Decodes a raw command, `data` is the whole image and is used to validate the file ranges
+    /// the command refers to.
+    pub fn parse(
+        lc: &RawLoadCommand<'a>,
+        data: &[u8],
+        endian: Endian,
+    ) -> Result<LoadCommand<'a>, MachOError> {
+        let mut r = lc.body(endian);
+        let command = match lc.cmd {
+            LC_SEGMENT => {
+                lc.expect_size(segment::SEGMENT_COMMAND_SIZE)?;
+                LoadCommand::Segment(Segment::parse(&mut r, data, lc.cmdsize, false)?)
+            }
+            LC_SEGMENT_64 => {
+                lc.expect_size(segment::SEGMENT_COMMAND_64_SIZE)?;
+                LoadCommand::Segment64(Segment::parse(&mut r, data, lc.cmdsize, true)?)
+            }
+            LC_SYMTAB => {
+                lc.expect_size(24)?;
+                LoadCommand::Symtab(SymtabCommand::parse(&mut r)?)
+            }
+            LC_DYSYMTAB => {
+                lc.expect_size(80)?;
+                LoadCommand::Dysymtab(DysymtabCommand::parse(&mut r)?)
+            }
+            LC_UUID => {
+                lc.expect_size(24)?;
+                let mut uuid = [0u8; 16];
+                uuid.copy_from_slice(r.take(16)?);
+                LoadCommand::Uuid(uuid)
+            }
+            LC_BUILD_VERSION => {
+                lc.expect_size(24)?;
+                let platform = r.u32()?;
+                let minos = r.u32()?;
+                let sdk = r.u32()?;
+                let ntools = r.u32()?;
+                lc.expect_size(24 + ntools as usize * 8)?;
+                let mut tools = Vec::with_capacity(ntools as usize);
+                for _ in 0..ntools {
+                    tools.push((r.u32()?, r.u32()?));
+                }
+                LoadCommand::BuildVersion(BuildVersion {
+                    platform,
+                    minos,
+                    sdk,
+                    tools,
+                })
+            }
+            LC_VERSION_MIN_MACOSX
+            | LC_VERSION_MIN_IPHONEOS
+            | LC_VERSION_MIN_TVOS
+            | LC_VERSION_MIN_WATCHOS => {
+                lc.expect_size(16)?;
+                let version = VersionMin {
+                    version: r.u32()?,
+                    sdk: r.u32()?,
+                };
+                match lc.cmd {
+                    LC_VERSION_MIN_MACOSX => LoadCommand::VersionMinMacosx(version),
+                    LC_VERSION_MIN_IPHONEOS => LoadCommand::VersionMinIphoneos(version),
+                    LC_VERSION_MIN_TVOS => LoadCommand::VersionMinTvos(version),
+                    _ => LoadCommand::VersionMinWatchos(version),
+                }
+            }
+            LC_MAIN => {
+                lc.expect_size(24)?;
+                LoadCommand::Main {
+                    entryoff: r.u64()?,
+                    stacksize: r.u64()?,
+                }
+            }
+            LC_LOAD_DYLIB | LC_LOAD_WEAK_DYLIB | LC_REEXPORT_DYLIB | LC_LAZY_LOAD_DYLIB
+            | LC_LOAD_UPWARD_DYLIB | LC_ID_DYLIB => {
+                lc.expect_size(24)?;
+                let name_offset = r.u32()?;
+                let dylib = Dylib {
+                    name: lc.string(name_offset)?,
+                    timestamp: r.u32()?,
+                    current_version: r.u32()?,
+                    compatibility_version: r.u32()?,
+                };
+                match lc.cmd {
+                    LC_LOAD_DYLIB => LoadCommand::LoadDylib(dylib),
+                    LC_LOAD_WEAK_DYLIB => LoadCommand::LoadWeakDylib(dylib),
+                    LC_REEXPORT_DYLIB => LoadCommand::ReexportDylib(dylib),
+                    LC_LAZY_LOAD_DYLIB => LoadCommand::LazyLoadDylib(dylib),
+                    LC_LOAD_UPWARD_DYLIB => LoadCommand::LoadUpwardDylib(dylib),
+                    _ => LoadCommand::IdDylib(dylib),
+                }
+            }
+            LC_LOAD_DYLINKER | LC_ID_DYLINKER | LC_RPATH => {
+                lc.expect_size(12)?;
+                let name = lc.string(r.u32()?)?;
+                match lc.cmd {
+                    LC_LOAD_DYLINKER => LoadCommand::LoadDylinker(name),
+                    LC_ID_DYLINKER => LoadCommand::IdDylinker(name),
+                    _ => LoadCommand::Rpath(name),
+                }
+            }
+            LC_FUNCTION_STARTS
+            | LC_DATA_IN_CODE
+            | LC_CODE_SIGNATURE
+            | LC_SEGMENT_SPLIT_INFO
+            | LC_DYLD_CHAINED_FIXUPS
+            | LC_DYLD_EXPORTS_TRIE => {
+                lc.expect_size(16)?;
+                let linkedit = LinkeditData {
+                    dataoff: r.u32()?,
+                    datasize: r.u32()?,
+                };
+                reader::slice(
+                    data,
+                    linkedit.dataoff as usize,
+                    linkedit.datasize as usize,
+                    "linkedit data",
+                )?;
+                match lc.cmd {
+                    LC_FUNCTION_STARTS => LoadCommand::FunctionStarts(linkedit),
+                    LC_DATA_IN_CODE => LoadCommand::DataInCode(linkedit),
+                    LC_CODE_SIGNATURE => LoadCommand::CodeSignature(linkedit),
+                    LC_SEGMENT_SPLIT_INFO => LoadCommand::SegmentSplitInfo(linkedit),
+                    LC_DYLD_CHAINED_FIXUPS => LoadCommand::DyldChainedFixups(linkedit),
+                    _ => LoadCommand::DyldExportsTrie(linkedit),
+                }
+            }
+            LC_DYLD_INFO | LC_DYLD_INFO_ONLY => {
+                lc.expect_size(48)?;
+                let info = DyldInfo {
+                    rebase_off: r.u32()?,
+                    rebase_size: r.u32()?,
+                    bind_off: r.u32()?,
+                    bind_size: r.u32()?,
+                    weak_bind_off: r.u32()?,
+                    weak_bind_size: r.u32()?,
+                    lazy_bind_off: r.u32()?,
+                    lazy_bind_size: r.u32()?,
+                    export_off: r.u32()?,
+                    export_size: r.u32()?,
+                };
+                for (offset, size) in [
+                    (info.rebase_off, info.rebase_size),
+                    (info.bind_off, info.bind_size),
+                    (info.weak_bind_off, info.weak_bind_size),
+                    (info.lazy_bind_off, info.lazy_bind_size),
+                    (info.export_off, info.export_size),
+                ] {
+                    reader::slice(data, offset as usize, size as usize, "dyld info")?;
+                }
+                if lc.cmd == LC_DYLD_INFO {
+                    LoadCommand::DyldInfo(info)
+                } else {
+                    LoadCommand::DyldInfoOnly(info)
+                }
+            }
+            LC_SOURCE_VERSION => {
+                lc.expect_size(16)?;
+                LoadCommand::SourceVersion(r.u64()?)
+            }
+            LC_ENCRYPTION_INFO | LC_ENCRYPTION_INFO_64 => {
+                lc.expect_size(if lc.cmd == LC_ENCRYPTION_INFO { 20 } else { 24 })?;
+                let info = EncryptionInfo {
+                    cryptoff: r.u32()?,
+                    cryptsize: r.u32()?,
+                    cryptid: r.u32()?,
+                };
+                if lc.cmd == LC_ENCRYPTION_INFO {
+                    LoadCommand::EncryptionInfo(info)
+                } else {
+                    LoadCommand::EncryptionInfo64(info)
+                }
+            }
+            LC_THREAD | LC_UNIXTHREAD => {
+                // a list of (flavor, count, state) up to the end of the command
+                let mut states = Vec::new();
+                while r.remaining() >= 8 {
+                    let flavor = r.u32()?;
+                    let count = r.u32()? as usize;
+                    states.push(ThreadState {
+                        flavor,
+                        state: r.take(count * 4)?,
+                    });
+                }
+                if lc.cmd == LC_THREAD {
+                    LoadCommand::Thread(states)
+                } else {
+                    LoadCommand::UnixThread(states)
+                }
+            }
+            LC_NOTE => {
+                lc.expect_size(40)?;
+                let note = NoteCommand {
+                    data_owner: reader::fixed_name(r.take(16)?),
+                    offset: r.u64()?,
+                    size: r.u64()?,
+                };
+                reader::slice64(data, note.offset, note.size, "note")?;
+                LoadCommand::Note(note)
+            }
+            cmd => LoadCommand::Unknown { cmd, data: lc.data },
+        };
+        Ok(command)
+    }
+
+    /// Name of the command as spelled in <mach-o/loader.h>.
+    pub fn name(&self) -> String {
+        let name = match self {
+            LoadCommand::Segment(_) => "LC_SEGMENT",
+            LoadCommand::Segment64(_) => "LC_SEGMENT_64",
+            LoadCommand::Symtab(_) => "LC_SYMTAB",
+            LoadCommand::Dysymtab(_) => "LC_DYSYMTAB",
+            LoadCommand::Uuid(_) => "LC_UUID",
+            LoadCommand::BuildVersion(_) => "LC_BUILD_VERSION",
+            LoadCommand::VersionMinMacosx(_) => "LC_VERSION_MIN_MACOSX",
+            LoadCommand::VersionMinIphoneos(_) => "LC_VERSION_MIN_IPHONEOS",
+            LoadCommand::VersionMinTvos(_) => "LC_VERSION_MIN_TVOS",
+            LoadCommand::VersionMinWatchos(_) => "LC_VERSION_MIN_WATCHOS",
+            LoadCommand::Main { .. } => "LC_MAIN",
+            LoadCommand::LoadDylib(_) => "LC_LOAD_DYLIB",
+            LoadCommand::LoadWeakDylib(_) => "LC_LOAD_WEAK_DYLIB",
+            LoadCommand::ReexportDylib(_) => "LC_REEXPORT_DYLIB",
+            LoadCommand::LazyLoadDylib(_) => "LC_LAZY_LOAD_DYLIB",
+            LoadCommand::LoadUpwardDylib(_) => "LC_LOAD_UPWARD_DYLIB",
+            LoadCommand::IdDylib(_) => "LC_ID_DYLIB",
+            LoadCommand::LoadDylinker(_) => "LC_LOAD_DYLINKER",
+            LoadCommand::IdDylinker(_) => "LC_ID_DYLINKER",
+            LoadCommand::Rpath(_) => "LC_RPATH",
+            LoadCommand::FunctionStarts(_) => "LC_FUNCTION_STARTS",
+            LoadCommand::DataInCode(_) => "LC_DATA_IN_CODE",
+            LoadCommand::CodeSignature(_) => "LC_CODE_SIGNATURE",
+            LoadCommand::SegmentSplitInfo(_) => "LC_SEGMENT_SPLIT_INFO",
+            LoadCommand::DyldInfo(_) => "LC_DYLD_INFO",
+            LoadCommand::DyldInfoOnly(_) => "LC_DYLD_INFO_ONLY",
+            LoadCommand::DyldChainedFixups(_) => "LC_DYLD_CHAINED_FIXUPS",
+            LoadCommand::DyldExportsTrie(_) => "LC_DYLD_EXPORTS_TRIE",
+            LoadCommand::SourceVersion(_) => "LC_SOURCE_VERSION",
+            LoadCommand::EncryptionInfo(_) => "LC_ENCRYPTION_INFO",
+            LoadCommand::EncryptionInfo64(_) => "LC_ENCRYPTION_INFO_64",
+            LoadCommand::Thread(_) => "LC_THREAD",
+            LoadCommand::UnixThread(_) => "LC_UNIXTHREAD",
+            LoadCommand::Note(_) => "LC_NOTE",
+            LoadCommand::Unknown { cmd, .. } => return format!("cmd {cmd:#x}"),
+        };
+        name.to_string()
+    }
+}
+
+/// Formats a version packed as xxxx.yy.zz nibbles (dylib and minimum OS versions).
+pub fn format_version(version: u32) -> String {
+    format!(
+        "{}.{}.{}",
+        version >> 16,
+        (version >> 8) & 0xff,
+        version & 0xff
+    )
+}
+
+/// Formats a source version packed as a24.b10.c10.d10.e10.
+pub fn format_source_version(version: u64) -> String {
+    format!(
+        "{}.{}.{}.{}.{}",
+        version >> 40,
+        (version >> 30) & 0x3ff,
+        (version >> 20) & 0x3ff,
+        (version >> 10) & 0x3ff,
+        version & 0x3ff
+    )
+}
+
+/// Formats a UUID the way `dwarfdump --uuid` prints it.
+pub fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: Vec<String> = uuid.iter().map(|b| format!("{b:02X}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..].concat()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::MachO;
+    use crate::macho::tests::image_with_commands;
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    fn decode(data: &[u8]) -> Result<Vec<LoadCommand<'_>>, MachOError> {
+        MachO::parse(data).map(|binary| binary.load_commands)
+    }
+
+    fn image(commands: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        image_with_commands(commands, 0x400)
+    }
+
+    #[test]
+    fn dylib_names_follow_their_offset() {
+        // the name starts 24 bytes into the command, right after the versions
+        let mut payload = words(&[24, 2, 0x0500_0100, 0x0001_0000]);
+        payload.extend(b"/usr/lib/libSystem.B.dylib\0\0\0\0\0\0");
+        let mut weak = words(&[28, 0, 0, 0, 0]);
+        weak.extend(b"libweak\0");
+        let data = image(&[(LC_LOAD_DYLIB, payload), (LC_LOAD_WEAK_DYLIB, weak)]);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            decoded[0],
+            LoadCommand::LoadDylib(Dylib {
+                name: "/usr/lib/libSystem.B.dylib",
+                timestamp: 2,
+                current_version: 0x0500_0100,
+                compatibility_version: 0x0001_0000,
+            })
+        );
+        assert_eq!(format_version(0x0500_0100), "1280.1.0");
+        assert!(matches!(
+            decoded[1],
+            LoadCommand::LoadWeakDylib(Dylib {
+                name: "libweak",
+                ..
+            })
+        ));
+        assert_eq!(decoded[1].name(), "LC_LOAD_WEAK_DYLIB");
+
+        // a name offset past the end of the command
+        let err = decode(&image(&[(LC_LOAD_DYLIB, words(&[40, 0, 0, 0]))])).unwrap_err();
+        assert_eq!(
+            err,
+            MachOError::OutOfBounds {
+                what: "load command string",
+                offset: 32 + 40,
+                size: 1
+            }
+        );
+    }
+
+    #[test]
+    fn build_versions_list_their_tools() {
+        let payload = words(&[
+            1,
+            0x000e_0000,
+            0x000f_0200,
+            2,
+            3,
+            0x03e8_0100,
+            1,
+            0x0010_0000,
+        ]);
+        let data = image(&[(LC_BUILD_VERSION, payload)]);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            decoded[0],
+            LoadCommand::BuildVersion(BuildVersion {
+                platform: 1,
+                minos: 0x000e_0000,
+                sdk: 0x000f_0200,
+                tools: vec![(3, 0x03e8_0100), (1, 0x0010_0000)],
+            })
+        );
+
+        // three tools announced, two stored
+        let payload = words(&[1, 0, 0, 3, 3, 0, 1, 0]);
+        assert_eq!(
+            decode(&image(&[(LC_BUILD_VERSION, payload)])).unwrap_err(),
+            MachOError::CommandTooSmall {
+                cmd: LC_BUILD_VERSION,
+                cmdsize: 40,
+                expected: 48
+            }
+        );
+    }
+
+    #[test]
+    fn linkedit_data_inside_the_file() {
+        let data = image(&[(LC_FUNCTION_STARTS, words(&[0x300, 0x10]))]);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            decoded[0],
+            LoadCommand::FunctionStarts(LinkeditData {
+                dataoff: 0x300,
+                datasize: 0x10
+            })
+        );
+        for (cmd, dataoff, datasize) in [
+            (LC_FUNCTION_STARTS, 0x3f8, 0x10),
+            (LC_CODE_SIGNATURE, 0x500, 0),
+            (LC_DYLD_EXPORTS_TRIE, 0x10, u32::MAX),
+        ] {
+            assert_eq!(
+                decode(&image(&[(cmd, words(&[dataoff, datasize]))])).unwrap_err(),
+                MachOError::OutOfBounds {
+                    what: "linkedit data",
+                    offset: dataoff as usize,
+                    size: datasize as usize
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_commands_keep_their_bytes() {
+        let data = image(&[(0x99, vec![1, 2, 3, 4])]);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(
+            decoded[0],
+            LoadCommand::Unknown {
+                cmd: 0x99,
+                data: &[0x99, 0, 0, 0, 12, 0, 0, 0, 1, 2, 3, 4]
+            }
+        );
+        assert_eq!(decoded[0].name(), "cmd 0x99");
+    }
+}
@@ -0,0 +1,126 @@
+// contents of an image by preferred address
+// the runtime metadata of Objective-C and Swift is made of pointers fixed up by dyld: they are
+// read through the rebases and binds of the image, pointers bound to another image only give
+// the name of their symbol
+
+use std::collections::HashMap;
+
+use super::reader::{self, Reader};
+use super::{MachO, MachOError, fixups};
+
+/// Reads the file data of an image at preferred addresses, through its fixups.
+pub struct ImageMemory<'b, 'a> {
+    binary: &'b MachO<'a>,
+    is_64: bool,
+    /// Targets of the rebased pointers by slot address.
+    rebases: HashMap<u64, u64>,
+    /// Symbols of the bound pointers by slot address.
+    binds: HashMap<u64, &'a str>,
+}
+
+impl<'b, 'a> ImageMemory<'b, 'a> {
+    pub fn new(binary: &'b MachO<'a>) -> Result<ImageMemory<'b, 'a>, MachOError> {
+        let fixups = fixups::fixups(binary)?;
+        Ok(ImageMemory {
+            binary,
+            is_64: binary.header.is_64(),
+            rebases: fixups
+                .rebases
+                .iter()
+                .map(|rebase| (rebase.address, rebase.target))
+                .collect(),
+            binds: fixups
+                .binds
+                .iter()
+                .map(|bind| (bind.address, fixups.imports[bind.import].name))
+                .collect(),
+        })
+    }
+
+    pub fn is_64(&self) -> bool {
+        self.is_64
+    }
+
+    pub fn pointer_size(&self) -> u64 {
+        if self.is_64 { 8 } else { 4 }
+    }
+
+    /// `(file offset, size)` of the file data from `address` to the end of its segment.
+    fn file_range(&self, address: u64) -> Result<(u64, u64), MachOError> {
+        let segment = self
+            .binary
+            .segments()
+            .find(|segment| {
+                address >= segment.vmaddr && address - segment.vmaddr < segment.filesize
+            })
+            .ok_or(MachOError::AddressNotMapped(address))?;
+        let offset = address - segment.vmaddr;
+        Ok((segment.fileoff + offset, segment.filesize - offset))
+    }
+
+    /// File contents from `address` to the end of the file data of its segment.
+    pub fn bytes(&self, address: u64) -> Result<&'a [u8], MachOError> {
+        let (offset, size) = self.file_range(address)?;
+        reader::slice64(self.binary.data(), offset, size, "image memory")
+    }
+
+    pub fn reader(&self, address: u64) -> Result<Reader<'a>, MachOError> {
+        Ok(Reader::new(
+            self.bytes(address)?,
+            self.binary.endian,
+            "image memory",
+        ))
+    }
+
+    /// The pointer stored at `address`, `None` when it is null or bound to another image.
+    pub fn pointer(&self, address: u64) -> Result<Option<u64>, MachOError> {
+        if let Some(target) = self.rebases.get(&address) {
+            return Ok(Some(*target));
+        }
+        if self.binds.contains_key(&address) {
+            return Ok(None);
+        }
+        // images without fixups store the preferred address itself
+        let value = self.reader(address)?.word(self.is_64)?;
+        Ok((value != 0).then_some(value))
+    }
+
+    /// Symbol the pointer at `address` is bound to.
+    pub fn bound_symbol(&self, address: u64) -> Option<&'a str> {
+        self.binds.get(&address).copied()
+    }
+
+    /// Target of the 32-bit offset stored at `address`, relative to `address` itself, `None`
+    /// when the offset is 0.
+    pub fn relative(&self, address: u64) -> Result<Option<u64>, MachOError> {
+        let offset = self.reader(address)?.u32()? as i32 as i64;
+        Ok((offset != 0).then(|| address.wrapping_add_signed(offset)))
+    }
+
+    pub fn string(&self, address: u64) -> Result<&'a str, MachOError> {
+        let (offset, _) = self.file_range(address)?;
+        reader::cstr(self.bytes(address)?, 0).ok_or(MachOError::InvalidString {
+            offset: offset as usize,
+        })
+    }
+
+    /// The pointers of a list section, empty when the image has no such section.
+    pub fn pointer_list(&self, sectname: &str) -> Result<Vec<u64>, MachOError> {
+        let Some(section) = self
+            .binary
+            .sections()
+            .find(|section| section.sectname == sectname)
+        else {
+            return Ok(Vec::new());
+        };
+        let mut pointers = Vec::new();
+        let mut slot = section.addr;
+        while slot + self.pointer_size() <= section.addr + section.size {
+            if let Some(pointer) = self.pointer(slot)? {
+                pointers.push(pointer);
+            }
+            slot += self.pointer_size();
+        }
+        Ok(pointers)
+    }
+}
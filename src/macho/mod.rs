@@ -0,0 +1,505 @@
+// standalone Mach-O parser
+// works on any byte slice (a file read from disk, a fixture, a dump of a remote image)
+// every offset and size found in the file is validated against the buffer before being used
+// https://github.com/aidansteele/osx-abi-macho-file-format-reference
+
+pub mod chained_fixups;
+pub mod codesign;
+pub mod cpu;
+pub mod dyld_info;
+pub mod dysymtab;
+pub mod error;
+pub mod exports;
+pub mod fat;
+pub mod fixups;
+pub mod function_starts;
+pub mod header;
+pub mod load_command;
+pub mod memory;
+pub mod objc;
+pub mod reader;
+pub mod requirement;
+pub mod segment;
+pub mod stabs;
+pub mod swift;
+pub mod symtab;
+pub mod unwind_info;
+
+pub use error::MachOError;
+pub use fat::{FatBinary, select_slice};
+pub use fixups::Fixups;
+pub use header::MachHeader;
+pub use load_command::{Dylib, LoadCommand, RawLoadCommand};
+pub use reader::Endian;
+pub use segment::{Section, Segment};
+pub use stabs::DebugMap;
+pub use symtab::SymbolTable;
+
+/// A parsed Mach-O image borrowing from the buffer it was parsed from.
+///
+/// 32-bit and 64-bit images of either byte order are decoded into the same structures.
+#[derive(Debug, Clone)]
+pub struct MachO<'a> {
+    pub header: MachHeader,
+    pub endian: Endian,
+    /// Commands as found in the file, in the same order as `load_commands`.
+    pub raw_load_commands: Vec<RawLoadCommand<'a>>,
+    pub load_commands: Vec<LoadCommand<'a>>,
+    pub symtab: Option<SymbolTable<'a>>,
+    data: &'a [u8],
+}
+
+impl<'a> MachO<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<MachO<'a>, MachOError> {
+        let (header, endian) = MachHeader::parse(data)?;
+        let raw_load_commands = load_command::parse_load_commands(
+            data,
+            header.size(),
+            header.ncmds,
+            header.sizeofcmds,
+            endian,
+        )?;
+
+        let load_commands = raw_load_commands
+            .iter()
+            .map(|lc| LoadCommand::parse(lc, data, endian))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let symtab = load_commands
+            .iter()
+            .find_map(|lc| match lc {
+                LoadCommand::Symtab(command) => Some(*command),
+                _ => None,
+            })
+            .map(|command| SymbolTable::parse(data, command, endian, header.is_64()))
+            .transpose()?;
+
+        Ok(MachO {
+            header,
+            endian,
+            raw_load_commands,
+            load_commands,
+            symtab,
+            data,
+        })
+    }
+
+    /// The whole buffer the image was parsed from.
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Every LC_SEGMENT and LC_SEGMENT_64 in load order.
+    pub fn segments(&self) -> impl Iterator<Item = &Segment> {
+        self.load_commands.iter().filter_map(|lc| match lc {
+            LoadCommand::Segment(segment) | LoadCommand::Segment64(segment) => Some(segment),
+            _ => None,
+        })
+    }
+
+    pub fn segment(&self, name: &str) -> Option<&Segment> {
+        self.segments().find(|s| s.segname == name)
+    }
+
+    /// Every section of every segment in load order.
+    pub fn sections(&self) -> impl Iterator<Item = &Section> {
+        self.segments().flat_map(|segment| segment.sections.iter())
+    }
+
+    /// Looks a section up by name, e.g. `find_section("__TEXT", "__text")`.
+    ///
+    /// The segment name is the one stored in the section itself so this also works for object
+    /// files where every section lives in a single unnamed segment.
+    pub fn find_section(&self, segname: &str, sectname: &str) -> Option<&Section> {
+        self.sections()
+            .find(|s| s.segname == segname && s.sectname == sectname)
+    }
+
+    /// Section containing the given unslid address.
+    pub fn section_for_address(&self, addr: u64) -> Option<&Section> {
+        self.sections().find(|s| s.contains(addr))
+    }
+
+    /// File contents of a section, empty for zero fill sections.
+    pub fn section_data(&self, section: &Section) -> Result<&'a [u8], MachOError> {
+        if section.is_zerofill() {
+            return Ok(&[]);
+        }
+        reader::slice64(self.data, section.offset as u64, section.size, "section").map_err(|_| {
+            MachOError::SectionOutOfRange {
+                segname: section.segname.clone(),
+                sectname: section.sectname.clone(),
+                offset: section.offset,
+                size: section.size,
+            }
+        })
+    }
+
+    pub fn dysymtab(&self) -> Option<&dysymtab::DysymtabCommand> {
+        self.load_commands.iter().find_map(|lc| match lc {
+            LoadCommand::Dysymtab(dysymtab) => Some(dysymtab),
+            _ => None,
+        })
+    }
+
+    /// The dylibs the image links against, in load order: library ordinal `n` is the `n`th one.
+    pub fn dylibs(&self) -> impl Iterator<Item = &Dylib<'a>> {
+        self.load_commands.iter().filter_map(|lc| match lc {
+            LoadCommand::LoadDylib(dylib)
+            | LoadCommand::LoadWeakDylib(dylib)
+            | LoadCommand::ReexportDylib(dylib)
+            | LoadCommand::LazyLoadDylib(dylib)
+            | LoadCommand::LoadUpwardDylib(dylib) => Some(dylib),
+            _ => None,
+        })
+    }
+
+    pub fn uuid(&self) -> Option<[u8; 16]> {
+        self.load_commands.iter().find_map(|lc| match lc {
+            LoadCommand::Uuid(uuid) => Some(*uuid),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use header::{MH_EXECUTE, MH_MAGIC, MH_MAGIC_64};
+    use load_command::{LC_SEGMENT, LC_SEGMENT_64, LC_UUID};
+
+    pub(crate) const UUID: [u8; 16] = *b"0123456789abcdef";
+
+    /// An executable with an LC_UUID and an empty __TEXT segment.
+    pub(crate) fn image(cputype: u32, is_64: bool, endian: Endian) -> Vec<u8> {
+        let u32 = |value: u32| match endian {
+            Endian::Little => value.to_le_bytes(),
+            Endian::Big => value.to_be_bytes(),
+        };
+        let word = |value: u64| match (is_64, endian) {
+            (true, Endian::Little) => value.to_le_bytes().to_vec(),
+            (true, Endian::Big) => value.to_be_bytes().to_vec(),
+            (false, _) => u32(value as u32).to_vec(),
+        };
+        // the segment maps the whole file: the header and the two commands
+        let file_size = if is_64 { 32 + 24 + 72 } else { 28 + 24 + 56 };
+        let mut segment = b"__TEXT\0\0\0\0\0\0\0\0\0\0".to_vec();
+        let vmaddr = if is_64 { 0x1_0000_0000 } else { 0x1000 };
+        for value in [vmaddr, 0x4000, 0, file_size] {
+            segment.extend(word(value));
+        }
+        for value in [5, 5, 0, 0] {
+            segment.extend(u32(value));
+        }
+        let segment_cmd = if is_64 { LC_SEGMENT_64 } else { LC_SEGMENT };
+        let mut commands = Vec::new();
+        for (cmd, payload) in [(LC_UUID, UUID.to_vec()), (segment_cmd, segment)] {
+            commands.extend(u32(cmd));
+            commands.extend(u32(payload.len() as u32 + 8));
+            commands.extend(payload);
+        }
+        let magic = if is_64 { MH_MAGIC_64 } else { MH_MAGIC };
+        let mut data = Vec::new();
+        for value in [magic, cputype, 0, MH_EXECUTE, 2, commands.len() as u32, 0] {
+            data.extend(u32(value));
+        }
+        if is_64 {
+            data.extend(u32(0));
+        }
+        data.extend(commands);
+        data
+    }
+
+    /// Name padded to the 16 bytes of the segment and section names.
+    fn name16(name: &str) -> Vec<u8> {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(16, 0);
+        bytes
+    }
+
+    /// A regular section of `segname` at `addr`, with its contents at `offset` in the file.
+    pub(crate) fn section(
+        segname: &str,
+        sectname: &str,
+        addr: u64,
+        size: u64,
+        offset: u32,
+    ) -> Section {
+        Section {
+            sectname: sectname.to_string(),
+            segname: segname.to_string(),
+            addr,
+            size,
+            offset,
+            align: 0,
+            reloff: 0,
+            nreloc: 0,
+            flags: 0,
+            reserved1: 0,
+            reserved2: 0,
+            reserved3: 0,
+        }
+    }
+
+    /// Payload of an LC_SEGMENT_64 mapping `filesize` bytes from `fileoff` at `vmaddr`.
+    pub(crate) fn segment_64(
+        segname: &str,
+        vmaddr: u64,
+        fileoff: u64,
+        filesize: u64,
+        sections: &[Section],
+    ) -> Vec<u8> {
+        let mut segment = name16(segname);
+        for value in [vmaddr, filesize, fileoff, filesize] {
+            segment.extend(value.to_le_bytes());
+        }
+        for value in [3, 3, sections.len() as u32, 0] {
+            segment.extend(value.to_le_bytes());
+        }
+        for section in sections {
+            segment.extend(name16(&section.sectname));
+            segment.extend(name16(&section.segname));
+            segment.extend(section.addr.to_le_bytes());
+            segment.extend(section.size.to_le_bytes());
+            for value in [
+                section.offset,
+                section.align,
+                section.reloff,
+                section.nreloc,
+                section.flags,
+                section.reserved1,
+                section.reserved2,
+                section.reserved3,
+            ] {
+                segment.extend(value.to_le_bytes());
+            }
+        }
+        segment
+    }
+
+    /// A 64-bit little endian executable with the `(cmd, payload)` load commands, zero filled
+    /// up to `size` bytes.
+    pub(crate) fn image_with_commands(commands: &[(u32, Vec<u8>)], size: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (cmd, payload) in commands {
+            bytes.extend(cmd.to_le_bytes());
+            bytes.extend((payload.len() as u32 + 8).to_le_bytes());
+            bytes.extend(payload);
+        }
+        let mut data = Vec::new();
+        for value in [
+            MH_MAGIC_64,
+            cpu::CPU_TYPE_ARM64,
+            0,
+            MH_EXECUTE,
+            commands.len() as u32,
+            bytes.len() as u32,
+            0,
+            0,
+        ] {
+            data.extend(value.to_le_bytes());
+        }
+        data.extend(bytes);
+        assert!(
+            data.len() as u64 <= size,
+            "the commands overlap the contents"
+        );
+        data.resize(size as usize, 0);
+        data
+    }
+
+    /// LC_SYMTAB payload and contents of a table of `(name, n_type, n_sect, n_desc, n_value)`
+    /// symbols: the 64-bit entries at `symoff` followed by their names.
+    pub(crate) fn symbol_table(
+        symoff: u32,
+        symbols: &[(&str, u8, u8, u16, u64)],
+    ) -> (Vec<u8>, Vec<u8>) {
+        let mut entries = Vec::new();
+        let mut strings = vec![0];
+        for (name, n_type, n_sect, n_desc, n_value) in symbols {
+            entries.extend((strings.len() as u32).to_le_bytes());
+            entries.extend([*n_type, *n_sect]);
+            entries.extend(n_desc.to_le_bytes());
+            entries.extend(n_value.to_le_bytes());
+            strings.extend(name.as_bytes());
+            strings.push(0);
+        }
+        let stroff = symoff + entries.len() as u32;
+        let mut command = Vec::new();
+        for value in [symoff, symbols.len() as u32, stroff, strings.len() as u32] {
+            command.extend(value.to_le_bytes());
+        }
+        entries.extend(strings);
+        (command, entries)
+    }
+
+    /// Bind opcodes of the `(symbol, offset in the first segment)` pointers bound to the first
+    /// dylib.
+    pub(crate) fn bind_opcodes(binds: &[(&str, u64)]) -> Vec<u8> {
+        let mut opcodes = Vec::new();
+        for (symbol, offset) in binds {
+            opcodes.extend([
+                dyld_info::BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | 1,
+                dyld_info::BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM,
+            ]);
+            opcodes.extend(symbol.as_bytes());
+            opcodes.extend([
+                0,
+                dyld_info::BIND_OPCODE_SET_TYPE_IMM | 1,
+                dyld_info::BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB,
+            ]);
+            // the offsets fit in two ULEB128 bytes
+            assert!(*offset < 1 << 14);
+            opcodes.extend([*offset as u8 | 0x80, (offset >> 7) as u8]);
+            opcodes.push(dyld_info::BIND_OPCODE_DO_BIND);
+        }
+        opcodes.push(dyld_info::BIND_OPCODE_DONE);
+        opcodes
+    }
+
+    /// A 64-bit image of `size` bytes mapped by a single segment at 0x1_0000_0000, with
+    /// `(sectname, file offset, size)` sections and the `(symbol, file offset)` pointers bound to
+    /// the first dylib.
+    pub(crate) fn sectioned_image(
+        segname: &str,
+        sections: &[(&str, u64, u64)],
+        binds: &[(&str, u64)],
+        size: u64,
+    ) -> Vec<u8> {
+        let bind_opcodes = bind_opcodes(binds);
+        let sections: Vec<Section> = sections
+            .iter()
+            .map(|(sectname, offset, section_size)| {
+                section(
+                    segname,
+                    sectname,
+                    0x1_0000_0000 + offset,
+                    *section_size,
+                    *offset as u32,
+                )
+            })
+            .collect();
+        let mut dyld_info = [0u32; 10];
+        dyld_info[2] = size as u32;
+        dyld_info[3] = bind_opcodes.len() as u32;
+        let dyld_info = dyld_info.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let mut data = image_with_commands(
+            &[
+                (
+                    LC_SEGMENT_64,
+                    segment_64(segname, 0x1_0000_0000, 0, size, &sections),
+                ),
+                (load_command::LC_DYLD_INFO_ONLY, dyld_info),
+            ],
+            size,
+        );
+        data.extend(bind_opcodes);
+        data
+    }
+
+    #[test]
+    fn parses_an_image() {
+        let data = image(cpu::CPU_TYPE_ARM64, true, Endian::Little);
+        let binary = MachO::parse(&data).unwrap();
+        assert_eq!(binary.header.cputype, cpu::CPU_TYPE_ARM64);
+        assert_eq!(binary.header.filetype, MH_EXECUTE);
+        assert_eq!(binary.uuid(), Some(UUID));
+        let text = binary.segment("__TEXT").unwrap();
+        assert_eq!(
+            (text.vmaddr, text.vmsize, text.initprot),
+            (0x1_0000_0000, 0x4000, 5)
+        );
+        assert_eq!(binary.raw_load_commands[1].offset, 32 + 24);
+    }
+
+    #[test]
+    fn parses_a_big_endian_32_bit_image() {
+        let data = image(cpu::CPU_TYPE_POWERPC, false, Endian::Big);
+        assert_eq!(&data[..4], b"\xfe\xed\xfa\xce");
+        let binary = MachO::parse(&data).unwrap();
+        assert_eq!(binary.endian, Endian::Big);
+        assert!(!binary.header.is_64());
+        assert_eq!(binary.header.cputype, cpu::CPU_TYPE_POWERPC);
+        assert_eq!(binary.uuid(), Some(UUID));
+        let text = binary.segment("__TEXT").unwrap();
+        assert_eq!(
+            (text.vmaddr, text.vmsize, text.filesize),
+            (0x1000, 0x4000, 108)
+        );
+        assert_eq!(binary.raw_load_commands[1].offset, 28 + 24);
+
+        // the same image written little endian, and a 64-bit one written big endian
+        let little = image(cpu::CPU_TYPE_X86, false, Endian::Little);
+        let binary = MachO::parse(&little).unwrap();
+        assert_eq!(binary.segment("__TEXT"), Some(text));
+        let big_64 = image(cpu::CPU_TYPE_POWERPC64, true, Endian::Big);
+        let binary = MachO::parse(&big_64).unwrap();
+        assert_eq!(binary.endian, Endian::Big);
+        assert_eq!(binary.segment("__TEXT").unwrap().vmaddr, 0x1_0000_0000);
+    }
+
+    #[test]
+    fn truncated_headers() {
+        let data = image(cpu::CPU_TYPE_ARM64, true, Endian::Little);
+        for len in 0..32 {
+            assert_eq!(
+                MachO::parse(&data[..len]).unwrap_err(),
+                MachOError::TruncatedHeader { len },
+                "{len} bytes"
+            );
+        }
+        assert_eq!(
+            MachO::parse(b"\x7fELF\x02\x01\x01\0").unwrap_err(),
+            MachOError::UnknownMagic(0x464c457f)
+        );
+        // the header is complete but the load commands are cut
+        assert_eq!(
+            MachO::parse(&data[..40]).unwrap_err(),
+            MachOError::LoadCommandsOutOfBounds {
+                sizeofcmds: 24 + 72,
+                available: 8
+            }
+        );
+    }
+
+    #[test]
+    fn command_sizes_overrunning_the_commands() {
+        let data = image(cpu::CPU_TYPE_ARM64, true, Endian::Little);
+        let cmdsize_at = |offset: usize, cmdsize: u32| {
+            let mut data = data.clone();
+            data[offset + 4..offset + 8].copy_from_slice(&cmdsize.to_le_bytes());
+            MachO::parse(&data).unwrap_err()
+        };
+        assert_eq!(
+            cmdsize_at(32 + 24, 80),
+            MachOError::CommandSizeOverrun {
+                index: 1,
+                offset: 32 + 24,
+                cmdsize: 80,
+                remaining: 72
+            }
+        );
+        assert_eq!(
+            cmdsize_at(32, 4),
+            MachOError::CommandSizeOverrun {
+                index: 0,
+                offset: 32,
+                cmdsize: 4,
+                remaining: 96
+            }
+        );
+
+        // one more command than sizeofcmds holds
+        let mut data = data.clone();
+        data[16..20].copy_from_slice(&3u32.to_le_bytes());
+        assert_eq!(
+            MachO::parse(&data).unwrap_err(),
+            MachOError::CommandSizeOverrun {
+                index: 2,
+                offset: 32 + 96,
+                cmdsize: 0,
+                remaining: 0
+            }
+        );
+    }
+}
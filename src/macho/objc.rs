@@ -0,0 +1,365 @@
+// Objective-C runtime metadata
+// every class of the image is listed in __objc_classlist and every category in __objc_catlist,
+// the runtime reads them at load time so they survive stripping: the method lists they point to
+// still give the selector and the implementation of every method
+// https://github.com/apple-oss-distributions/objc4/blob/main/runtime/objc-runtime-new.h
+//
+// the structures are made of pointers fixed up by dyld, read through `ImageMemory`: pointers
+// bound to another image (a superclass, the class a category extends) only give the name of their
+// symbol
+
+use super::memory::ImageMemory;
+use super::reader::Reader;
+use super::{MachO, MachOError};
+
+// flags of method_list_t, the entry size is in the other bits
+const METHOD_LIST_FLAGS_MASK: u32 = 0xffff_0003;
+// the entries are 32-bit offsets from themselves instead of pointers
+const SMALL_METHOD_LIST_FLAG: u32 = 0x8000_0000;
+// set by the shared cache builder, the selectors are offsets from a selector table of the cache
+const DIRECT_SELECTORS_FLAG: u32 = 0x4000_0000;
+const SMALL_METHOD_SIZE: usize = 12;
+
+// the low bits of class_t.data are flags of Swift classes
+const CLASS_DATA_MASK: u64 = !0x7;
+
+// prefix of the symbols of the classes, as bound by the images using them
+pub const CLASS_SYMBOL_PREFIX: &str = "_OBJC_CLASS_$_";
+
+/// A method found in the Objective-C metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjcMethod {
+    pub class_name: String,
+    /// Name of the category the method is declared in.
+    pub category: Option<String>,
+    pub selector: String,
+    /// `+` method, from the metaclass or the class methods of a category.
+    pub is_class_method: bool,
+    /// Preferred (unslid) address of the implementation.
+    pub imp: u64,
+}
+
+impl ObjcMethod {
+    /// Name the way the compiler names the function, e.g. `-[NSString(Extras) doThing:]`.
+    pub fn name(&self) -> String {
+        let kind = if self.is_class_method { '+' } else { '-' };
+        match &self.category {
+            Some(category) => format!(
+                "{}[{}({}) {}]",
+                kind, self.class_name, category, self.selector
+            ),
+            None => format!("{}[{} {}]", kind, self.class_name, self.selector),
+        }
+    }
+}
+
+/// The methods of every class and category of `binary` that has an implementation in it.
+pub fn methods(binary: &MachO) -> Result<Vec<ObjcMethod>, MachOError> {
+    let metadata = Metadata::new(binary)?;
+    let mut methods = Vec::new();
+    for class in metadata.memory.pointer_list("__objc_classlist")? {
+        metadata.class_methods(class, &mut methods)?;
+    }
+    for category in metadata.memory.pointer_list("__objc_catlist")? {
+        metadata.category_methods(category, &mut methods)?;
+    }
+    Ok(methods)
+}
+
+/// Reads the metadata through the fixups of the image.
+struct Metadata<'b, 'a> {
+    memory: ImageMemory<'b, 'a>,
+    is_64: bool,
+}
+
+impl<'b, 'a> Metadata<'b, 'a> {
+    fn new(binary: &'b MachO<'a>) -> Result<Metadata<'b, 'a>, MachOError> {
+        let memory = ImageMemory::new(binary)?;
+        Ok(Metadata {
+            is_64: memory.is_64(),
+            memory,
+        })
+    }
+
+    fn pointer_size(&self) -> u64 {
+        self.memory.pointer_size()
+    }
+
+    fn reader(&self, address: u64) -> Result<Reader<'a>, MachOError> {
+        self.memory.reader(address)
+    }
+
+    fn pointer(&self, address: u64) -> Result<Option<u64>, MachOError> {
+        self.memory.pointer(address)
+    }
+
+    fn string(&self, address: u64) -> Result<&'a str, MachOError> {
+        self.memory.string(address)
+    }
+
+    /// Address of the class_ro_t of a class_t.
+    fn class_ro(&self, class: u64) -> Result<Option<u64>, MachOError> {
+        // isa, superclass, cache and vtable come before data
+        let data = self.pointer(class + 4 * self.pointer_size())?;
+        Ok(data.map(|data| data & CLASS_DATA_MASK))
+    }
+
+    /// `(name, base methods)` of a class_ro_t.
+    fn class_ro_fields(&self, ro: u64) -> Result<(&'a str, Option<u64>), MachOError> {
+        // flags, instanceStart and instanceSize, padded to 8 bytes in 64-bit images, then
+        // ivarLayout
+        let name_field = if self.is_64 { 24 } else { 16 };
+        let name = self
+            .pointer(ro + name_field)?
+            .ok_or(MachOError::MalformedObjcMetadata("class without a name"))?;
+        let methods = self.pointer(ro + name_field + self.pointer_size())?;
+        Ok((self.string(name)?, methods))
+    }
+
+    fn class_name(&self, class: u64) -> Result<&'a str, MachOError> {
+        let ro = self
+            .class_ro(class)?
+            .ok_or(MachOError::MalformedObjcMetadata("class without data"))?;
+        Ok(self.class_ro_fields(ro)?.0)
+    }
+
+    /// Instance methods of a class_t, then the class methods of its metaclass.
+    fn class_methods(&self, class: u64, methods: &mut Vec<ObjcMethod>) -> Result<(), MachOError> {
+        let Some(ro) = self.class_ro(class)? else {
+            return Ok(());
+        };
+        let (name, list) = self.class_ro_fields(ro)?;
+        if let Some(list) = list {
+            self.method_list(list, name, None, false, methods)?;
+        }
+        // the metaclass of a root class is bound to another image when it is not defined here
+        let Some(metaclass) = self.pointer(class)? else {
+            return Ok(());
+        };
+        if let Some(ro) = self.class_ro(metaclass)?
+            && let (_, Some(list)) = self.class_ro_fields(ro)?
+        {
+            self.method_list(list, name, None, true, methods)?;
+        }
+        Ok(())
+    }
+
+    /// Methods of a category_t: its name, its class, then the instance and class methods.
+    fn category_methods(
+        &self,
+        category: u64,
+        methods: &mut Vec<ObjcMethod>,
+    ) -> Result<(), MachOError> {
+        let size = self.pointer_size();
+        let name = match self.pointer(category)? {
+            Some(name) => self.string(name)?,
+            None => "",
+        };
+        let class_slot = category + size;
+        let class_name = match self.pointer(class_slot)? {
+            Some(class) => self.class_name(class)?,
+            // a category of a class of another image
+            None => self.memory.bound_symbol(class_slot).map_or("?", |symbol| {
+                symbol.strip_prefix(CLASS_SYMBOL_PREFIX).unwrap_or(symbol)
+            }),
+        };
+        for (field, is_class_method) in [(2, false), (3, true)] {
+            if let Some(list) = self.pointer(category + field * size)? {
+                self.method_list(list, class_name, Some(name), is_class_method, methods)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Entries of a method_list_t, with pointers or relative offsets.
+    fn method_list(
+        &self,
+        list: u64,
+        class_name: &str,
+        category: Option<&str>,
+        is_class_method: bool,
+        methods: &mut Vec<ObjcMethod>,
+    ) -> Result<(), MachOError> {
+        let mut r = self.reader(list)?;
+        let flags = r.u32()?;
+        let count = r.u32()?;
+        let entry_size = (flags & !METHOD_LIST_FLAGS_MASK) as u64;
+        let small = flags & SMALL_METHOD_LIST_FLAG != 0;
+        if small && flags & DIRECT_SELECTORS_FLAG != 0 {
+            // the selector table is in the shared cache, not in the image
+            return Ok(());
+        }
+        let expected = if small {
+            SMALL_METHOD_SIZE as u64
+        } else {
+            3 * self.pointer_size()
+        };
+        if entry_size < expected {
+            return Err(MachOError::MalformedObjcMetadata(
+                "method list entries are too small",
+            ));
+        }
+
+        for index in 0..count as u64 {
+            let entry = list + 8 + index * entry_size;
+            let (selector, imp) = if small {
+                // name, types and imp are offsets from each field, the name points to the
+                // selector reference of the method
+                let mut r = self.reader(entry)?;
+                let name = r.u32()? as i32 as i64;
+                let _types = r.u32()?;
+                let imp = r.u32()? as i32 as i64;
+                let selector_ref = entry.wrapping_add_signed(name);
+                let selector = self.pointer(selector_ref)?;
+                let imp = (imp != 0).then(|| (entry + 8).wrapping_add_signed(imp));
+                (selector, imp)
+            } else {
+                let size = self.pointer_size();
+                (self.pointer(entry)?, self.pointer(entry + 2 * size)?)
+            };
+            let (Some(selector), Some(imp)) = (selector, imp) else {
+                continue;
+            };
+            methods.push(ObjcMethod {
+                class_name: class_name.to_string(),
+                category: category.map(str::to_string),
+                selector: self.string(selector)?.to_string(),
+                is_class_method,
+                imp,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::tests::sectioned_image;
+
+    const BASE: u64 = 0x1_0000_0000;
+
+    fn put(data: &mut [u8], offset: u64, bytes: &[u8]) {
+        data[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn pointer(data: &mut [u8], offset: u64, target: u64) {
+        put(data, offset, &(BASE + target).to_le_bytes());
+    }
+
+    /// A class with a metaclass, a category of it and a category of a class of another image.
+    fn metadata() -> Vec<u8> {
+        let mut data = sectioned_image(
+            "__DATA",
+            &[
+                ("__objc_classlist", 0x400, 8),
+                ("__objc_catlist", 0x408, 16),
+            ],
+            &[("_OBJC_CLASS_$_NSString", 0x648)],
+            0x1000,
+        );
+        pointer(&mut data, 0x400, 0x500);
+        pointer(&mut data, 0x408, 0x600);
+        pointer(&mut data, 0x410, 0x640);
+        for (offset, string) in [
+            (0x700, "Widget"),
+            (0x710, "draw"),
+            (0x718, "hidden"),
+            (0x720, "create"),
+            (0x730, "Extras"),
+            (0x740, "thing:"),
+            (0x750, "Ext"),
+            (0x760, "extra"),
+        ] {
+            put(&mut data, offset, format!("{string}\0").as_bytes());
+        }
+
+        // the class, its data pointer carries the flags of Swift classes
+        pointer(&mut data, 0x500, 0x540);
+        put(&mut data, 0x520, &(BASE + 0x580 + 1).to_le_bytes());
+        pointer(&mut data, 0x560, 0x5c0);
+        for (ro, list) in [(0x580, 0x800), (0x5c0, 0x840)] {
+            pointer(&mut data, ro + 24, 0x700);
+            pointer(&mut data, ro + 32, list);
+        }
+        // instance methods with pointers, the one without an implementation is skipped
+        put(&mut data, 0x800, &[24, 0, 0, 0, 2, 0, 0, 0]);
+        pointer(&mut data, 0x808, 0x710);
+        pointer(&mut data, 0x818, 0xf00);
+        pointer(&mut data, 0x820, 0x718);
+        // class methods with relative offsets, the name points to a selector reference
+        put(
+            &mut data,
+            0x840,
+            &(SMALL_METHOD_LIST_FLAG | 12).to_le_bytes(),
+        );
+        put(&mut data, 0x844, &1u32.to_le_bytes());
+        put(&mut data, 0x848, &(0x880u32 - 0x848).to_le_bytes());
+        put(&mut data, 0x850, &(0xf40u32 - 0x850).to_le_bytes());
+        pointer(&mut data, 0x880, 0x720);
+
+        // a category of the class, its class methods use the selectors of the shared cache
+        pointer(&mut data, 0x600, 0x730);
+        pointer(&mut data, 0x608, 0x500);
+        pointer(&mut data, 0x610, 0x900);
+        pointer(&mut data, 0x618, 0x940);
+        put(&mut data, 0x900, &[24, 0, 0, 0, 1, 0, 0, 0]);
+        pointer(&mut data, 0x908, 0x740);
+        pointer(&mut data, 0x918, 0xf80);
+        let direct = SMALL_METHOD_LIST_FLAG | DIRECT_SELECTORS_FLAG | 12;
+        put(&mut data, 0x940, &direct.to_le_bytes());
+        put(&mut data, 0x944, &1u32.to_le_bytes());
+
+        // a category of a class bound to another image
+        pointer(&mut data, 0x640, 0x750);
+        pointer(&mut data, 0x650, 0x980);
+        put(&mut data, 0x980, &[24, 0, 0, 0, 1, 0, 0, 0]);
+        pointer(&mut data, 0x988, 0x760);
+        pointer(&mut data, 0x998, 0xfc0);
+        data
+    }
+
+    #[test]
+    fn methods_of_classes_and_categories() {
+        let data = metadata();
+        let binary = MachO::parse(&data).unwrap();
+        let methods = methods(&binary).unwrap();
+        let names: Vec<_> = methods
+            .iter()
+            .map(|method| (method.name(), method.imp - BASE))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("-[Widget draw]".to_string(), 0xf00),
+                ("+[Widget create]".to_string(), 0xf40),
+                ("-[Widget(Extras) thing:]".to_string(), 0xf80),
+                ("-[NSString(Ext) extra]".to_string(), 0xfc0),
+            ]
+        );
+        assert_eq!(methods[2].category.as_deref(), Some("Extras"));
+        assert!(methods[1].is_class_method && !methods[3].is_class_method);
+    }
+
+    #[test]
+    fn malformed_metadata() {
+        let methods_of = |patch: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = metadata();
+            patch(&mut data);
+            let binary = MachO::parse(&data).unwrap();
+            methods(&binary).unwrap_err()
+        };
+        assert_eq!(
+            methods_of(&|data| put(data, 0x800, &[16, 0, 0, 0])),
+            MachOError::MalformedObjcMetadata("method list entries are too small")
+        );
+        assert_eq!(
+            methods_of(&|data| put(data, 0x598, &[0; 8])),
+            MachOError::MalformedObjcMetadata("class without a name")
+        );
+        assert_eq!(
+            methods_of(&|data| pointer(data, 0x808, 0x2000)),
+            MachOError::AddressNotMapped(BASE + 0x2000)
+        );
+    }
+}
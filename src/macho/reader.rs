@@ -0,0 +1,218 @@
+// bounds checked cursor over a byte slice
+// every read goes through `take` so nothing is ever read past the end of the buffer
+
+use super::MachOError;
+
+/// Byte order of the file being decoded, detected from the magic number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Little,
+    Big,
+}
+
+/// Returns `data[offset..offset + size]` or an `OutOfBounds` error naming `what` was being read.
+pub fn slice<'a>(
+    data: &'a [u8],
+    offset: usize,
+    size: usize,
+    what: &'static str,
+) -> Result<&'a [u8], MachOError> {
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or(MachOError::OutOfBounds { what, offset, size })
+}
+
+/// Same as `slice` but takes the 64-bit offset/size pairs found in the file.
+pub fn slice64<'a>(
+    data: &'a [u8],
+    offset: u64,
+    size: u64,
+    what: &'static str,
+) -> Result<&'a [u8], MachOError> {
+    let (Ok(offset), Ok(size)) = (usize::try_from(offset), usize::try_from(size)) else {
+        return Err(MachOError::OutOfBounds {
+            what,
+            offset: usize::MAX,
+            size: usize::MAX,
+        });
+    };
+    slice(data, offset, size, what)
+}
+
+/// Reads the NUL terminated string starting at `offset`, the string ends at the end of the
+/// buffer if there is no terminator.
+pub fn cstr(data: &[u8], offset: usize) -> Option<&str> {
+    let bytes = data.get(offset..)?;
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    std::str::from_utf8(&bytes[..end]).ok()
+}
+
+/// Turns the fixed size, NUL padded names used by segments and sections into a `String`.
+pub fn fixed_name(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    endian: Endian,
+    what: &'static str,
+}
+
+impl<'a> Reader<'a> {
+    /// `what` names the structure being decoded, it ends up in the error if the buffer is too short.
+    pub fn new(data: &'a [u8], endian: Endian, what: &'static str) -> Self {
+        Reader {
+            data,
+            offset: 0,
+            endian,
+            what,
+        }
+    }
+
+    pub fn at(data: &'a [u8], offset: usize, endian: Endian, what: &'static str) -> Self {
+        Reader {
+            data,
+            offset,
+            endian,
+            what,
+        }
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    /// Number of bytes left after the cursor.
+    pub fn remaining(&self) -> usize {
+        self.data.len().saturating_sub(self.offset)
+    }
+
+    /// Moves the cursor to `offset`, reads past the end of the data fail.
+    pub fn seek(&mut self, offset: usize) {
+        self.offset = offset;
+    }
+
+    pub fn skip(&mut self, size: usize) -> Result<(), MachOError> {
+        self.take(size).map(|_| ())
+    }
+
+    pub fn take(&mut self, size: usize) -> Result<&'a [u8], MachOError> {
+        let bytes = slice(self.data, self.offset, size, self.what)?;
+        self.offset += size;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], MachOError> {
+        let mut out = [0u8; N];
+        out.copy_from_slice(self.take(N)?);
+        Ok(out)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, MachOError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, MachOError> {
+        let bytes = self.array::<2>()?;
+        Ok(match self.endian {
+            Endian::Little => u16::from_le_bytes(bytes),
+            Endian::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn u32(&mut self) -> Result<u32, MachOError> {
+        let bytes = self.array::<4>()?;
+        Ok(match self.endian {
+            Endian::Little => u32::from_le_bytes(bytes),
+            Endian::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn u64(&mut self) -> Result<u64, MachOError> {
+        let bytes = self.array::<8>()?;
+        Ok(match self.endian {
+            Endian::Little => u64::from_le_bytes(bytes),
+            Endian::Big => u64::from_be_bytes(bytes),
+        })
+    }
+
+    /// Reads a pointer sized field: a u64 in 64-bit images, a u32 in 32-bit ones.
+    pub fn word(&mut self, is_64: bool) -> Result<u64, MachOError> {
+        if is_64 {
+            self.u64()
+        } else {
+            self.u32().map(u64::from)
+        }
+    }
+
+    /// Reads an unsigned LEB128 value (7 bits per byte, high bit set on all but the last byte).
+    pub fn uleb128(&mut self) -> Result<u64, MachOError> {
+        let start = self.offset;
+        let mut value: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 || (shift == 63 && byte & 0x7f > 1) {
+                return Err(MachOError::MalformedLeb128 { offset: start });
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Reads a signed LEB128 value, sign extended from the last byte.
+    pub fn sleb128(&mut self) -> Result<i64, MachOError> {
+        let start = self.offset;
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return Err(MachOError::MalformedLeb128 { offset: start });
+            }
+            value |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value |= -1i64 << shift;
+                }
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Reads a NUL terminated string, the cursor ends up after the terminator.
+    pub fn cstr(&mut self) -> Result<&'a str, MachOError> {
+        let start = self.offset;
+        let bytes = self.data.get(start..).unwrap_or(&[]);
+        let Some(len) = bytes.iter().position(|b| *b == 0) else {
+            return Err(MachOError::OutOfBounds {
+                what: self.what,
+                offset: start,
+                size: bytes.len() + 1,
+            });
+        };
+        self.offset += len + 1;
+        std::str::from_utf8(&bytes[..len]).map_err(|_| MachOError::InvalidString { offset: start })
+    }
+
+    /// Reads a 16 bytes segment or section name.
+    pub fn name16(&mut self) -> Result<String, MachOError> {
+        Ok(fixed_name(self.take(16)?))
+    }
+}
@@ -0,0 +1,321 @@
+// code requirements of a code signature
+// a requirement is a compiled expression in prefix form, e.g. the designated requirement
+// `identifier "com.example.tool" and anchor apple generic` the system checks the code against
+// decompiled back to the text form `codesign -d -r-` prints
+
+use super::MachOError;
+use super::codesign::{CSMAGIC_REQUIREMENT, CSMAGIC_REQUIREMENTS};
+use super::reader::{self, Endian, Reader};
+
+// types of requirements of the requirement set
+pub const HOST_REQUIREMENT_TYPE: u32 = 1;
+pub const GUEST_REQUIREMENT_TYPE: u32 = 2;
+pub const DESIGNATED_REQUIREMENT_TYPE: u32 = 3;
+pub const LIBRARY_REQUIREMENT_TYPE: u32 = 4;
+pub const PLUGIN_REQUIREMENT_TYPE: u32 = 5;
+
+// the only kind of requirement, an expression
+const EXPR_FORM: u32 = 1;
+
+const OP_FALSE: u32 = 0;
+const OP_TRUE: u32 = 1;
+const OP_IDENT: u32 = 2;
+const OP_APPLE_ANCHOR: u32 = 3;
+const OP_ANCHOR_HASH: u32 = 4;
+const OP_INFO_KEY_VALUE: u32 = 5;
+const OP_AND: u32 = 6;
+const OP_OR: u32 = 7;
+const OP_CD_HASH: u32 = 8;
+const OP_NOT: u32 = 9;
+const OP_INFO_KEY_FIELD: u32 = 10;
+const OP_CERT_FIELD: u32 = 11;
+const OP_TRUSTED_CERT: u32 = 12;
+const OP_TRUSTED_CERTS: u32 = 13;
+const OP_CERT_GENERIC: u32 = 14;
+const OP_APPLE_GENERIC_ANCHOR: u32 = 15;
+const OP_ENTITLEMENT_FIELD: u32 = 16;
+const OP_CERT_POLICY: u32 = 17;
+const OP_NAMED_ANCHOR: u32 = 18;
+const OP_NAMED_CODE: u32 = 19;
+const OP_PLATFORM: u32 = 20;
+const OP_NOTARIZED: u32 = 21;
+const OP_CERT_FIELD_DATE: u32 = 22;
+const OP_LEGACY_DEV_ID: u32 = 23;
+// high bits of an opcode telling how to skip an unknown one, not part of the opcode
+const OP_FLAG_MASK: u32 = 0xff000000;
+
+const MATCH_EXISTS: u32 = 0;
+const MATCH_EQUAL: u32 = 1;
+const MATCH_CONTAINS: u32 = 2;
+const MATCH_BEGINS_WITH: u32 = 3;
+const MATCH_ENDS_WITH: u32 = 4;
+const MATCH_LESS_THAN: u32 = 5;
+const MATCH_GREATER_THAN: u32 = 6;
+const MATCH_LESS_EQUAL: u32 = 7;
+const MATCH_GREATER_EQUAL: u32 = 8;
+const MATCH_ON: u32 = 9;
+const MATCH_BEFORE: u32 = 10;
+const MATCH_AFTER: u32 = 11;
+const MATCH_ON_OR_BEFORE: u32 = 12;
+const MATCH_ON_OR_AFTER: u32 = 13;
+const MATCH_ABSENT: u32 = 14;
+
+// certificate slots counted from the leaf, negative ones from the anchor
+const LEAF_CERT: i32 = 0;
+const ANCHOR_CERT: i32 = -1;
+
+// nested expressions deeper than this are rejected rather than overflowing the stack
+const MAX_DEPTH: usize = 64;
+
+/// A requirement of the requirement set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Requirement<'a> {
+    /// One of the `*_REQUIREMENT_TYPE` constants.
+    pub kind: u32,
+    /// The whole requirement blob.
+    pub data: &'a [u8],
+}
+
+impl Requirement<'_> {
+    pub fn kind_name(&self) -> String {
+        match self.kind {
+            HOST_REQUIREMENT_TYPE => "host".to_string(),
+            GUEST_REQUIREMENT_TYPE => "guest".to_string(),
+            DESIGNATED_REQUIREMENT_TYPE => "designated".to_string(),
+            LIBRARY_REQUIREMENT_TYPE => "library".to_string(),
+            PLUGIN_REQUIREMENT_TYPE => "plugin".to_string(),
+            kind => format!("type {kind}"),
+        }
+    }
+
+    /// The expression in the text form of the requirement language.
+    pub fn decompile(&self) -> Result<String, MachOError> {
+        let mut r = Reader::new(self.data, Endian::Big, "requirement");
+        if r.u32()? != CSMAGIC_REQUIREMENT {
+            return Err(MachOError::MalformedCodeSignature("not a requirement"));
+        }
+        let length = r.u32()? as usize;
+        let data = reader::slice(self.data, 0, length, "requirement")?;
+        let mut r = Reader::at(data, 8, Endian::Big, "requirement");
+        if r.u32()? != EXPR_FORM {
+            return Err(MachOError::MalformedCodeSignature(
+                "requirement is not an expression",
+            ));
+        }
+        let (expression, _) = expression(&mut r, 0)?;
+        Ok(expression)
+    }
+}
+
+/// The requirement set of a code signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Requirements<'a> {
+    pub requirements: Vec<Requirement<'a>>,
+}
+
+impl<'a> Requirements<'a> {
+    /// Parses the requirement set blob `data`, magic and length included.
+    pub fn parse(data: &'a [u8]) -> Result<Requirements<'a>, MachOError> {
+        let mut r = Reader::new(data, Endian::Big, "requirements");
+        if r.u32()? != CSMAGIC_REQUIREMENTS {
+            return Err(MachOError::MalformedCodeSignature("not a requirement set"));
+        }
+        let length = r.u32()? as usize;
+        let data = reader::slice(data, 0, length, "requirements")?;
+        let count = r.u32()?;
+        let mut requirements = Vec::new();
+        for _ in 0..count {
+            let kind = r.u32()?;
+            let offset = r.u32()? as usize;
+            let mut blob = Reader::at(data, offset, Endian::Big, "requirement");
+            let _magic = blob.u32()?;
+            let length = blob.u32()? as usize;
+            requirements.push(Requirement {
+                kind,
+                data: reader::slice(data, offset, length, "requirement")?,
+            });
+        }
+        Ok(Requirements { requirements })
+    }
+}
+
+// precedence of the text form, an `or` inside an `and` needs parentheses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Or,
+    And,
+    Primary,
+}
+
+fn expression(r: &mut Reader, depth: usize) -> Result<(String, Precedence), MachOError> {
+    if depth > MAX_DEPTH {
+        return Err(MachOError::MalformedCodeSignature(
+            "requirement nested too deeply",
+        ));
+    }
+    let opcode = r.u32()? & !OP_FLAG_MASK;
+    let primary = |text: String| Ok((text, Precedence::Primary));
+    match opcode {
+        OP_FALSE => primary("never".to_string()),
+        OP_TRUE => primary("always".to_string()),
+        OP_IDENT => primary(format!("identifier {}", quoted(data(r)?))),
+        OP_APPLE_ANCHOR => primary("anchor apple".to_string()),
+        OP_APPLE_GENERIC_ANCHOR => primary("anchor apple generic".to_string()),
+        OP_ANCHOR_HASH => {
+            let slot = cert_slot(r)?;
+            primary(format!("{} = H\"{}\"", slot, hex(data(r)?)))
+        }
+        OP_INFO_KEY_VALUE => {
+            let key = data(r)?;
+            primary(format!("info[{}] = {}", text(key), quoted(data(r)?)))
+        }
+        OP_AND | OP_OR => {
+            let (precedence, word) = match opcode {
+                OP_AND => (Precedence::And, "and"),
+                _ => (Precedence::Or, "or"),
+            };
+            let left = operand(r, depth, precedence)?;
+            let right = operand(r, depth, precedence)?;
+            Ok((format!("{left} {word} {right}"), precedence))
+        }
+        OP_CD_HASH => primary(format!("cdhash H\"{}\"", hex(data(r)?))),
+        OP_NOT => primary(format!("! {}", operand(r, depth, Precedence::Primary)?)),
+        OP_INFO_KEY_FIELD => {
+            let key = data(r)?;
+            primary(format!("info[{}]{}", text(key), match_suffix(r)?))
+        }
+        OP_ENTITLEMENT_FIELD => {
+            let key = data(r)?;
+            primary(format!("entitlement[{}]{}", quoted(key), match_suffix(r)?))
+        }
+        OP_CERT_FIELD => {
+            let slot = cert_slot(r)?;
+            let field = data(r)?;
+            primary(format!("{}[{}]{}", slot, text(field), match_suffix(r)?))
+        }
+        OP_CERT_GENERIC | OP_CERT_POLICY | OP_CERT_FIELD_DATE => {
+            let slot = cert_slot(r)?;
+            let prefix = match opcode {
+                OP_CERT_GENERIC => "field",
+                OP_CERT_POLICY => "policy",
+                _ => "timestamp",
+            };
+            let oid = oid(data(r)?);
+            primary(format!("{}[{}.{}]{}", slot, prefix, oid, match_suffix(r)?))
+        }
+        OP_TRUSTED_CERT => primary(format!("{} trusted", cert_slot(r)?)),
+        OP_TRUSTED_CERTS => primary("anchor trusted".to_string()),
+        OP_NAMED_ANCHOR => primary(format!("anchor apple {}", text(data(r)?))),
+        OP_NAMED_CODE => primary(format!("({})", text(data(r)?))),
+        OP_PLATFORM => primary(format!("platform = {}", r.u32()?)),
+        OP_NOTARIZED => primary("notarized".to_string()),
+        OP_LEGACY_DEV_ID => primary("legacy".to_string()),
+        _ => Err(MachOError::MalformedCodeSignature(
+            "unknown requirement opcode",
+        )),
+    }
+}
+
+// an operand of an operator of `precedence`, parenthesized when it binds more loosely
+fn operand(r: &mut Reader, depth: usize, precedence: Precedence) -> Result<String, MachOError> {
+    let (text, inner) = expression(r, depth + 1)?;
+    Ok(if inner < precedence {
+        format!("({text})")
+    } else {
+        text
+    })
+}
+
+// ` = "value"`, ` exists`...
+fn match_suffix(r: &mut Reader) -> Result<String, MachOError> {
+    let operation = r.u32()?;
+    let op = match operation {
+        MATCH_EXISTS => return Ok(" exists".to_string()),
+        MATCH_ABSENT => return Ok(" absent".to_string()),
+        MATCH_ON | MATCH_BEFORE | MATCH_AFTER | MATCH_ON_OR_BEFORE | MATCH_ON_OR_AFTER => {
+            let op = match operation {
+                MATCH_ON => "=",
+                MATCH_BEFORE => "<",
+                MATCH_AFTER => ">",
+                MATCH_ON_OR_BEFORE => "<=",
+                _ => ">=",
+            };
+            // seconds since 2001-01-01, the reference date of CoreFoundation
+            return Ok(format!(" {} timestamp {}", op, r.u64()? as i64));
+        }
+        MATCH_EQUAL | MATCH_BEGINS_WITH | MATCH_ENDS_WITH => "=",
+        MATCH_CONTAINS => "~",
+        MATCH_LESS_THAN => "<",
+        MATCH_GREATER_THAN => ">",
+        MATCH_LESS_EQUAL => "<=",
+        MATCH_GREATER_EQUAL => ">=",
+        _ => {
+            return Err(MachOError::MalformedCodeSignature(
+                "unknown requirement match operation",
+            ));
+        }
+    };
+    let value = quoted(data(r)?);
+    Ok(match operation {
+        // the wildcards go inside the string: `= "prefix*"`
+        MATCH_BEGINS_WITH => format!(" = {}*\"", value.trim_end_matches('"')),
+        MATCH_ENDS_WITH => format!(" = \"*{}", value.trim_start_matches('"')),
+        _ => format!(" {op} {value}"),
+    })
+}
+
+fn cert_slot(r: &mut Reader) -> Result<String, MachOError> {
+    Ok(match r.u32()? as i32 {
+        LEAF_CERT => "certificate leaf".to_string(),
+        ANCHOR_CERT => "certificate root".to_string(),
+        slot => format!("certificate {slot}"),
+    })
+}
+
+// a length prefixed byte string padded to 4 bytes
+fn data<'a>(r: &mut Reader<'a>) -> Result<&'a [u8], MachOError> {
+    let length = r.u32()? as usize;
+    let bytes = r.take(length)?;
+    r.skip((4 - length % 4) % 4)?;
+    Ok(bytes)
+}
+
+fn text(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    // bare words are printed as is, the rest quoted
+    if !text.is_empty()
+        && text
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_')
+    {
+        text.into_owned()
+    } else {
+        quoted(bytes)
+    }
+}
+
+fn quoted(bytes: &[u8]) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+// dotted form of a DER encoded object identifier
+fn oid(bytes: &[u8]) -> String {
+    let Some((first, rest)) = bytes.split_first() else {
+        return String::new();
+    };
+    let mut parts = vec![(first / 40).to_string(), (first % 40).to_string()];
+    let mut value: u64 = 0;
+    for byte in rest {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            parts.push(value.to_string());
+            value = 0;
+        }
+    }
+    parts.join(".")
+}
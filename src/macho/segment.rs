@@ -0,0 +1,265 @@
+use super::MachOError;
+use super::load_command::{LC_SEGMENT, LC_SEGMENT_64};
+use super::reader::{self, Reader};
+
+/// Size of `segment_command` and `segment_command_64`, header included.
+pub const SEGMENT_COMMAND_SIZE: usize = 56;
+pub const SEGMENT_COMMAND_64_SIZE: usize = 72;
+/// Size of `section` and `section_64`.
+pub const SECTION_SIZE: usize = 68;
+pub const SECTION_64_SIZE: usize = 80;
+
+// section types, stored in the low byte of `flags`
+pub const SECTION_TYPE: u32 = 0x0000_00ff;
+pub const S_REGULAR: u32 = 0x0;
+pub const S_ZEROFILL: u32 = 0x1;
+pub const S_CSTRING_LITERALS: u32 = 0x2;
+pub const S_4BYTE_LITERALS: u32 = 0x3;
+pub const S_8BYTE_LITERALS: u32 = 0x4;
+pub const S_LITERAL_POINTERS: u32 = 0x5;
+pub const S_NON_LAZY_SYMBOL_POINTERS: u32 = 0x6;
+pub const S_LAZY_SYMBOL_POINTERS: u32 = 0x7;
+pub const S_SYMBOL_STUBS: u32 = 0x8;
+pub const S_MOD_INIT_FUNC_POINTERS: u32 = 0x9;
+pub const S_MOD_TERM_FUNC_POINTERS: u32 = 0xa;
+pub const S_COALESCED: u32 = 0xb;
+pub const S_GB_ZEROFILL: u32 = 0xc;
+pub const S_INTERPOSING: u32 = 0xd;
+pub const S_16BYTE_LITERALS: u32 = 0xe;
+pub const S_DTRACE_DOF: u32 = 0xf;
+pub const S_LAZY_DYLIB_SYMBOL_POINTERS: u32 = 0x10;
+pub const S_THREAD_LOCAL_REGULAR: u32 = 0x11;
+pub const S_THREAD_LOCAL_ZEROFILL: u32 = 0x12;
+pub const S_THREAD_LOCAL_VARIABLES: u32 = 0x13;
+pub const S_THREAD_LOCAL_VARIABLE_POINTERS: u32 = 0x14;
+pub const S_THREAD_LOCAL_INIT_FUNCTION_POINTERS: u32 = 0x15;
+pub const S_INIT_FUNC_OFFSETS: u32 = 0x16;
+
+/// Decoded LC_SEGMENT or LC_SEGMENT_64 command, 32-bit values are widened to 64 bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub segname: String,
+    pub vmaddr: u64,
+    pub vmsize: u64,
+    pub fileoff: u64,
+    pub filesize: u64,
+    pub maxprot: u32,
+    pub initprot: u32,
+    pub nsects: u32,
+    pub flags: u32,
+    pub sections: Vec<Section>,
+}
+
+/// Decoded `section` or `section_64`, `reserved3` is 0 for 32-bit images.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub sectname: String,
+    pub segname: String,
+    pub addr: u64,
+    pub size: u64,
+    pub offset: u32,
+    pub align: u32,
+    pub reloff: u32,
+    pub nreloc: u32,
+    pub flags: u32,
+    pub reserved1: u32,
+    pub reserved2: u32,
+    pub reserved3: u32,
+}
+
+impl Segment {
+    /// Parses the segment command body (after `cmd` and `cmdsize`) with its sections and checks
+    /// that the bytes it maps from the file are inside `data`.
+    pub fn parse(
+        r: &mut Reader,
+        data: &[u8],
+        cmdsize: u32,
+        is_64: bool,
+    ) -> Result<Segment, MachOError> {
+        let mut segment = Segment {
+            segname: r.name16()?,
+            vmaddr: r.word(is_64)?,
+            vmsize: r.word(is_64)?,
+            fileoff: r.word(is_64)?,
+            filesize: r.word(is_64)?,
+            maxprot: r.u32()?,
+            initprot: r.u32()?,
+            nsects: r.u32()?,
+            flags: r.u32()?,
+            sections: Vec::new(),
+        };
+        if segment.filesize != 0
+            && reader::slice64(data, segment.fileoff, segment.filesize, "segment").is_err()
+        {
+            return Err(MachOError::SegmentOutOfRange {
+                segname: segment.segname,
+                fileoff: segment.fileoff,
+                filesize: segment.filesize,
+            });
+        }
+
+        let (command_size, section_size) = if is_64 {
+            (SEGMENT_COMMAND_64_SIZE, SECTION_64_SIZE)
+        } else {
+            (SEGMENT_COMMAND_SIZE, SECTION_SIZE)
+        };
+        let expected = command_size + segment.nsects as usize * section_size;
+        if (cmdsize as usize) < expected {
+            return Err(MachOError::CommandTooSmall {
+                cmd: if is_64 { LC_SEGMENT_64 } else { LC_SEGMENT },
+                cmdsize,
+                expected,
+            });
+        }
+        for _ in 0..segment.nsects {
+            segment.sections.push(Section::parse(r, is_64)?);
+        }
+        Ok(segment)
+    }
+}
+
+impl Section {
+    /// One of the `S_*` section types.
+    pub fn section_type(&self) -> u32 {
+        self.flags & SECTION_TYPE
+    }
+
+    /// Zero fill sections only exist in memory, their `offset` is meaningless.
+    pub fn is_zerofill(&self) -> bool {
+        matches!(
+            self.section_type(),
+            S_ZEROFILL | S_GB_ZEROFILL | S_THREAD_LOCAL_ZEROFILL
+        )
+    }
+
+    /// Returns true if `addr` is inside the section's preferred VM range.
+    pub fn contains(&self, addr: u64) -> bool {
+        addr >= self.addr && addr - self.addr < self.size
+    }
+
+    /// End of the section's preferred VM range, clamped for hostile headers.
+    pub fn end(&self) -> u64 {
+        self.addr.saturating_add(self.size)
+    }
+
+    /// Name of the section type as spelled in <mach-o/loader.h>.
+    pub fn type_name(&self) -> &'static str {
+        match self.section_type() {
+            S_REGULAR => "S_REGULAR",
+            S_ZEROFILL => "S_ZEROFILL",
+            S_CSTRING_LITERALS => "S_CSTRING_LITERALS",
+            S_4BYTE_LITERALS => "S_4BYTE_LITERALS",
+            S_8BYTE_LITERALS => "S_8BYTE_LITERALS",
+            S_LITERAL_POINTERS => "S_LITERAL_POINTERS",
+            S_NON_LAZY_SYMBOL_POINTERS => "S_NON_LAZY_SYMBOL_POINTERS",
+            S_LAZY_SYMBOL_POINTERS => "S_LAZY_SYMBOL_POINTERS",
+            S_SYMBOL_STUBS => "S_SYMBOL_STUBS",
+            S_MOD_INIT_FUNC_POINTERS => "S_MOD_INIT_FUNC_POINTERS",
+            S_MOD_TERM_FUNC_POINTERS => "S_MOD_TERM_FUNC_POINTERS",
+            S_COALESCED => "S_COALESCED",
+            S_GB_ZEROFILL => "S_GB_ZEROFILL",
+            S_INTERPOSING => "S_INTERPOSING",
+            S_16BYTE_LITERALS => "S_16BYTE_LITERALS",
+            S_DTRACE_DOF => "S_DTRACE_DOF",
+            S_LAZY_DYLIB_SYMBOL_POINTERS => "S_LAZY_DYLIB_SYMBOL_POINTERS",
+            S_THREAD_LOCAL_REGULAR => "S_THREAD_LOCAL_REGULAR",
+            S_THREAD_LOCAL_ZEROFILL => "S_THREAD_LOCAL_ZEROFILL",
+            S_THREAD_LOCAL_VARIABLES => "S_THREAD_LOCAL_VARIABLES",
+            S_THREAD_LOCAL_VARIABLE_POINTERS => "S_THREAD_LOCAL_VARIABLE_POINTERS",
+            S_THREAD_LOCAL_INIT_FUNCTION_POINTERS => "S_THREAD_LOCAL_INIT_FUNCTION_POINTERS",
+            S_INIT_FUNC_OFFSETS => "S_INIT_FUNC_OFFSETS",
+            _ => "unknown",
+        }
+    }
+
+    pub fn parse(r: &mut Reader, is_64: bool) -> Result<Section, MachOError> {
+        Ok(Section {
+            sectname: r.name16()?,
+            segname: r.name16()?,
+            addr: r.word(is_64)?,
+            size: r.word(is_64)?,
+            offset: r.u32()?,
+            align: r.u32()?,
+            reloff: r.u32()?,
+            nreloc: r.u32()?,
+            flags: r.u32()?,
+            reserved1: r.u32()?,
+            reserved2: r.u32()?,
+            reserved3: if is_64 { r.u32()? } else { 0 },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::MachO;
+    use crate::macho::tests::{image_with_commands, section, segment_64};
+
+    const BASE: u64 = 0x1_0000_0000;
+
+    #[test]
+    fn sections_are_found_by_name() {
+        let text = Section {
+            align: 2,
+            // S_ATTR_PURE_INSTRUCTIONS | S_ATTR_SOME_INSTRUCTIONS
+            flags: 0x8000_0400 | S_REGULAR,
+            ..section("__TEXT", "__text", BASE + 0x400, 0x100, 0x400)
+        };
+        let cstring = Section {
+            flags: S_CSTRING_LITERALS,
+            ..section("__TEXT", "__cstring", BASE + 0x500, 0x20, 0x500)
+        };
+        let sections = [text.clone(), cstring.clone()];
+        let data = image_with_commands(
+            &[(
+                LC_SEGMENT_64,
+                segment_64("__TEXT", BASE, 0, 0x1000, &sections),
+            )],
+            0x1000,
+        );
+        let binary = MachO::parse(&data).unwrap();
+        let segment = binary.segment("__TEXT").unwrap();
+        assert_eq!(
+            (segment.vmaddr, segment.filesize, segment.nsects),
+            (BASE, 0x1000, 2)
+        );
+        assert_eq!(segment.sections, sections);
+
+        assert_eq!(binary.find_section("__TEXT", "__text"), Some(&text));
+        assert_eq!(binary.find_section("__TEXT", "__cstring"), Some(&cstring));
+        assert_eq!(binary.find_section("__TEXT", "__const"), None);
+        assert_eq!(binary.find_section("__DATA", "__text"), None);
+        assert_eq!(binary.section_for_address(BASE + 0x510), Some(&cstring));
+        assert_eq!(binary.section_for_address(BASE + 0x520), None);
+        assert_eq!(cstring.type_name(), "S_CSTRING_LITERALS");
+        assert_eq!(binary.section_data(&cstring).unwrap(), &data[0x500..0x520]);
+    }
+
+    #[test]
+    fn malformed_segments() {
+        let text = section("__TEXT", "__text", BASE, 0x100, 0);
+        let mut payload = segment_64("__TEXT", BASE, 0, 0x1000, &[text]);
+        // a segment mapping more than the file
+        let data = image_with_commands(&[(LC_SEGMENT_64, payload.clone())], 0x800);
+        assert_eq!(
+            MachO::parse(&data).unwrap_err(),
+            MachOError::SegmentOutOfRange {
+                segname: "__TEXT".to_string(),
+                fileoff: 0,
+                filesize: 0x1000
+            }
+        );
+        // two sections announced, one stored
+        payload[56..60].copy_from_slice(&2u32.to_le_bytes());
+        let data = image_with_commands(&[(LC_SEGMENT_64, payload)], 0x1000);
+        assert_eq!(
+            MachO::parse(&data).unwrap_err(),
+            MachOError::CommandTooSmall {
+                cmd: LC_SEGMENT_64,
+                cmdsize: 152,
+                expected: 232
+            }
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// debug map carried by the STAB entries of a linked image
+// when a binary is linked without a dSYM, ld keeps one N_SO/N_OSO group per object file with the
+// N_BNSYM/N_FUN/N_ENSYM ranges of its functions, pointing to the objects holding the DWARF
+
+use super::symtab::{N_BNSYM, N_ENSYM, N_FUN, N_GSYM, N_OSO, N_SO, N_STSYM, SymbolTable};
+
+/// A function of the debug map with its address range in the linked image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugMapFunction {
+    pub name: String,
+    /// Preferred (unslid) address of the function in the linked image.
+    pub address: u64,
+    pub size: u64,
+}
+
+/// A global or static variable of the debug map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugMapVariable {
+    pub name: String,
+    /// Address in the linked image, 0 for N_GSYM entries that only name the symbol.
+    pub address: u64,
+}
+
+/// One compile unit: the object file it was built into and the source file it came from.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugMapObject {
+    /// Path of the object file (or `archive.a(object.o)`).
+    pub object_path: String,
+    /// Modification time of the object recorded by the linker, used to detect stale objects.
+    pub mtime: u64,
+    /// Directory and file of the main source file of the compile unit.
+    pub source: Option<String>,
+    pub functions: Vec<DebugMapFunction>,
+    pub variables: Vec<DebugMapVariable>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DebugMap {
+    pub objects: Vec<DebugMapObject>,
+}
+
+impl DebugMap {
+    /// Walks the stab entries of the symbol table in order, entries of other kinds are skipped.
+    pub fn parse(symtab: &SymbolTable) -> DebugMap {
+        let mut objects = Vec::new();
+        let mut current: Option<DebugMapObject> = None;
+        // N_SO directory waiting for its file name
+        let mut directory = String::new();
+        // N_FUN whose size entry has not been seen yet
+        let mut open_function: Option<(String, u64)> = None;
+
+        for nlist in symtab.entries.iter().filter(|nlist| nlist.is_stab()) {
+            let name = symtab.name(nlist).unwrap_or("");
+            match nlist.n_type {
+                N_SO if name.is_empty() => {
+                    // end of the compile unit
+                    objects.extend(current.take());
+                    directory.clear();
+                }
+                N_SO if name.ends_with('/') => directory = name.to_string(),
+                N_SO => {
+                    objects.extend(current.take());
+                    current = Some(DebugMapObject {
+                        source: Some(format!("{directory}{name}")),
+                        ..DebugMapObject::default()
+                    });
+                }
+                N_OSO => {
+                    let object = current.get_or_insert_with(DebugMapObject::default);
+                    object.object_path = name.to_string();
+                    object.mtime = nlist.n_value;
+                }
+                N_FUN if !name.is_empty() => {
+                    open_function = Some((name.to_string(), nlist.n_value))
+                }
+                N_FUN => {
+                    // the unnamed N_FUN closing a function holds its size
+                    if let (Some((name, address)), Some(object)) =
+                        (open_function.take(), current.as_mut())
+                    {
+                        object.functions.push(DebugMapFunction {
+                            name,
+                            address,
+                            size: nlist.n_value,
+                        });
+                    }
+                }
+                N_STSYM | N_GSYM => {
+                    if let Some(object) = current.as_mut() {
+                        object.variables.push(DebugMapVariable {
+                            name: name.to_string(),
+                            address: nlist.n_value,
+                        });
+                    }
+                }
+                N_BNSYM | N_ENSYM => {}
+                _ => {}
+            }
+        }
+        objects.extend(current);
+        DebugMap { objects }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Finds the function covering the unslid `address` and the compile unit it belongs to.
+    pub fn lookup(&self, address: u64) -> Option<(&DebugMapObject, &DebugMapFunction)> {
+        self.objects.iter().find_map(|object| {
+            object
+                .functions
+                .iter()
+                .find(|f| address >= f.address && address - f.address < f.size)
+                .map(|function| (object, function))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::MachO;
+    use crate::macho::load_command::LC_SYMTAB;
+    use crate::macho::symtab::{N_EXT, N_SECT};
+    use crate::macho::tests::{image_with_commands, symbol_table};
+
+    const TEXT: u64 = 0x1_0000_1000;
+
+    #[test]
+    fn objects_with_their_functions_and_variables() {
+        let (command, contents) = symbol_table(
+            0x100,
+            &[
+                ("/src/", N_SO, 0, 0, 0),
+                ("main.c", N_SO, 0, 0, 0),
+                ("/build/main.o", N_OSO, 0, 1, 0x6000_0000),
+                ("", N_BNSYM, 1, 0, TEXT),
+                ("_main", N_FUN, 1, 0, TEXT),
+                ("", N_FUN, 0, 0, 0x40),
+                ("", N_ENSYM, 1, 0, TEXT),
+                ("_counter", N_STSYM, 2, 0, 0x1_0000_8000),
+                ("", N_SO, 1, 0, 0),
+                // the symbols of the linked image are not part of the map
+                ("_main", N_SECT | N_EXT, 1, 0, TEXT),
+                ("/src/", N_SO, 0, 0, 0),
+                ("util.c", N_SO, 0, 0, 0),
+                ("/build/libutil.a(util.o)", N_OSO, 0, 1, 0x6000_0100),
+                ("_helper", N_FUN, 1, 0, TEXT + 0x40),
+                ("", N_FUN, 0, 0, 0x20),
+                ("_global", N_GSYM, 0, 0, 0),
+                ("", N_SO, 1, 0, 0),
+            ],
+        );
+        let mut data = image_with_commands(&[(LC_SYMTAB, command)], 0x400);
+        data[0x100..0x100 + contents.len()].copy_from_slice(&contents);
+        let binary = MachO::parse(&data).unwrap();
+        let map = DebugMap::parse(binary.symtab.as_ref().unwrap());
+
+        assert_eq!(
+            map.objects,
+            [
+                DebugMapObject {
+                    object_path: "/build/main.o".to_string(),
+                    mtime: 0x6000_0000,
+                    source: Some("/src/main.c".to_string()),
+                    functions: vec![DebugMapFunction {
+                        name: "_main".to_string(),
+                        address: TEXT,
+                        size: 0x40
+                    }],
+                    variables: vec![DebugMapVariable {
+                        name: "_counter".to_string(),
+                        address: 0x1_0000_8000
+                    }],
+                },
+                DebugMapObject {
+                    object_path: "/build/libutil.a(util.o)".to_string(),
+                    mtime: 0x6000_0100,
+                    source: Some("/src/util.c".to_string()),
+                    functions: vec![DebugMapFunction {
+                        name: "_helper".to_string(),
+                        address: TEXT + 0x40,
+                        size: 0x20
+                    }],
+                    variables: vec![DebugMapVariable {
+                        name: "_global".to_string(),
+                        address: 0
+                    }],
+                },
+            ]
+        );
+
+        let (object, function) = map.lookup(TEXT + 0x3f).unwrap();
+        assert_eq!(
+            (object.object_path.as_str(), function.name.as_str()),
+            ("/build/main.o", "_main")
+        );
+        let (object, function) = map.lookup(TEXT + 0x40).unwrap();
+        assert_eq!(
+            (object.source.as_deref(), function.name.as_str()),
+            (Some("/src/util.c"), "_helper")
+        );
+        assert_eq!(map.lookup(TEXT + 0x60), None);
+        assert_eq!(map.lookup(TEXT - 1), None);
+    }
+}
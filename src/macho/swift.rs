@@ -0,0 +1,485 @@
+// Swift runtime metadata
+// the runtime finds the types of an image through __swift5_types and their protocol conformances
+// through __swift5_proto, so both survive stripping: the context descriptors they point to give
+// the name of every type and of the module and types it is nested in, and the address of its
+// type metadata accessor
+// https://github.com/swiftlang/swift/blob/main/include/swift/ABI/Metadata.h
+//
+// the records are 32-bit offsets from themselves rather than pointers, the ones with their low bit
+// set point to a pointer slot instead: a slot fixed up by dyld, or bound to a descriptor of
+// another image whose symbol is the only name we get
+
+use symbolic_common::Name;
+use symbolic_demangle::{Demangle, DemangleOptions};
+
+use super::memory::ImageMemory;
+use super::objc::CLASS_SYMBOL_PREFIX;
+use super::{MachO, MachOError};
+
+// kinds of context descriptors, in the low bits of their flags
+const CONTEXT_KIND_MASK: u32 = 0x1f;
+const CONTEXT_MODULE: u32 = 0;
+const CONTEXT_EXTENSION: u32 = 1;
+const CONTEXT_ANONYMOUS: u32 = 2;
+const CONTEXT_PROTOCOL: u32 = 3;
+const CONTEXT_OPAQUE_TYPE: u32 = 4;
+const CONTEXT_CLASS: u32 = 16;
+const CONTEXT_STRUCT: u32 = 17;
+const CONTEXT_ENUM: u32 = 18;
+
+// types imported from C follow their name with tagged strings, `N` gives the name of the C type
+const TYPE_HAS_IMPORT_INFO: u32 = 1 << 18;
+const IMPORT_INFO_ABI_NAME: char = 'N';
+
+// kinds of type references, in the low bits of the __swift5_types records and in bits 3..5 of
+// the flags of a conformance
+const TYPE_REFERENCE_DIRECT: u32 = 0;
+const TYPE_REFERENCE_INDIRECT: u32 = 1;
+const TYPE_REFERENCE_DIRECT_OBJC_NAME: u32 = 2;
+const TYPE_REFERENCE_INDIRECT_OBJC_CLASS: u32 = 3;
+const CONFORMANCE_TYPE_REFERENCE_SHIFT: u32 = 3;
+
+// the parent chain of a descriptor is short, a longer one is a loop in a malformed image
+const MAX_CONTEXT_DEPTH: usize = 32;
+
+/// Kind of a nominal type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwiftTypeKind {
+    Class,
+    Struct,
+    Enum,
+}
+
+impl SwiftTypeKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            SwiftTypeKind::Class => "class",
+            SwiftTypeKind::Struct => "struct",
+            SwiftTypeKind::Enum => "enum",
+        }
+    }
+}
+
+/// A type listed in __swift5_types.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwiftType {
+    pub kind: SwiftTypeKind,
+    /// Qualified name, e.g. `Module.Outer.Inner`.
+    pub name: String,
+    /// Preferred address of the nominal type descriptor.
+    pub descriptor: u64,
+    /// Preferred address of the type metadata accessor.
+    pub accessor: Option<u64>,
+}
+
+impl SwiftType {
+    /// Name the compiler gives the descriptor, as printed by the Swift demangler.
+    pub fn descriptor_name(&self) -> String {
+        format!("nominal type descriptor for {}", self.name)
+    }
+
+    pub fn accessor_name(&self) -> String {
+        format!("type metadata accessor for {}", self.name)
+    }
+}
+
+/// A conformance listed in __swift5_proto.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwiftConformance {
+    pub type_name: String,
+    pub protocol: String,
+    /// Preferred address of the protocol conformance descriptor.
+    pub descriptor: u64,
+    /// Preferred address of the witness table, or of its pattern for generic types.
+    pub witness_table: Option<u64>,
+}
+
+impl SwiftConformance {
+    pub fn name(&self) -> String {
+        format!(
+            "protocol conformance descriptor for {} : {}",
+            self.type_name, self.protocol
+        )
+    }
+}
+
+/// The nominal types of `binary`, empty when it has no Swift metadata.
+pub fn types(binary: &MachO) -> Result<Vec<SwiftType>, MachOError> {
+    let metadata = Metadata {
+        memory: ImageMemory::new(binary)?,
+    };
+    let mut types = Vec::new();
+    for record in records(binary, "__swift5_types") {
+        let value = metadata.memory.reader(record)?.u32()?;
+        let target = record.wrapping_add_signed((value & !3) as i32 as i64);
+        let descriptor = match value & 3 {
+            TYPE_REFERENCE_DIRECT => Some(target),
+            TYPE_REFERENCE_INDIRECT => metadata.memory.pointer(target)?,
+            // Objective-C classes are named by the objc metadata
+            _ => None,
+        };
+        if let Some(descriptor) = descriptor {
+            types.extend(metadata.nominal_type(descriptor)?);
+        }
+    }
+    Ok(types)
+}
+
+/// The protocol conformances of `binary`, empty when it has no Swift metadata.
+pub fn conformances(binary: &MachO) -> Result<Vec<SwiftConformance>, MachOError> {
+    let metadata = Metadata {
+        memory: ImageMemory::new(binary)?,
+    };
+    let mut conformances = Vec::new();
+    for record in records(binary, "__swift5_proto") {
+        let Some(descriptor) = metadata.memory.relative(record)? else {
+            continue;
+        };
+        conformances.push(metadata.conformance(descriptor)?);
+    }
+    Ok(conformances)
+}
+
+/// Addresses of the 32-bit records of a section, empty when the image has no such section.
+fn records(binary: &MachO, sectname: &str) -> Vec<u64> {
+    binary
+        .sections()
+        .filter(|section| section.segname == "__TEXT" && section.sectname == sectname)
+        .flat_map(|section| (section.addr..section.addr + section.size / 4 * 4).step_by(4))
+        .collect()
+}
+
+/// Name of a descriptor of another image from its symbol, e.g. `Swift.Hashable` for
+/// `_$sSHMp`.
+fn symbol_context_name(symbol: &str) -> String {
+    let symbol = symbol.strip_prefix('_').unwrap_or(symbol);
+    if let Some(class) = symbol.strip_prefix(&CLASS_SYMBOL_PREFIX[1..]) {
+        return class.to_string();
+    }
+    let name = Name::from(symbol);
+    let Some(demangled) = name.demangle(DemangleOptions::complete()) else {
+        return symbol.to_string();
+    };
+    for prefix in [
+        "nominal type descriptor for ",
+        "protocol descriptor for ",
+        "module descriptor ",
+        "type metadata for ",
+        "full type metadata for ",
+    ] {
+        if let Some(name) = demangled.strip_prefix(prefix) {
+            return name.to_string();
+        }
+    }
+    demangled
+}
+
+/// Reads the descriptors through the fixups of the image.
+struct Metadata<'b, 'a> {
+    memory: ImageMemory<'b, 'a>,
+}
+
+impl Metadata<'_, '_> {
+    /// Target of a relative indirectable pointer: the descriptor itself, or the symbol of the
+    /// slot it points to when the descriptor is in another image.
+    fn indirectable(&self, address: u64) -> Result<Option<Context>, MachOError> {
+        let offset = self.memory.reader(address)?.u32()? as i32;
+        if offset == 0 {
+            return Ok(None);
+        }
+        let target = address.wrapping_add_signed((offset & !1) as i64);
+        if offset & 1 == 0 {
+            return Ok(Some(Context::Local(target)));
+        }
+        self.slot(target)
+    }
+
+    /// Descriptor a pointer slot points to.
+    fn slot(&self, slot: u64) -> Result<Option<Context>, MachOError> {
+        if let Some(symbol) = self.memory.bound_symbol(slot) {
+            return Ok(Some(Context::External(symbol_context_name(symbol))));
+        }
+        Ok(self.memory.pointer(slot)?.map(Context::Local))
+    }
+
+    fn context_name(&self, context: Context) -> Result<String, MachOError> {
+        match context {
+            Context::Local(descriptor) => self.qualified_name(descriptor),
+            Context::External(name) => Ok(name),
+        }
+    }
+
+    /// Names of a descriptor and of its parents joined by dots, from the module down.
+    fn qualified_name(&self, descriptor: u64) -> Result<String, MachOError> {
+        let mut components = Vec::new();
+        let mut context = Some(Context::Local(descriptor));
+        while let Some(current) = context.take() {
+            if components.len() == MAX_CONTEXT_DEPTH {
+                return Err(MachOError::MalformedSwiftMetadata(
+                    "context descriptors form a loop",
+                ));
+            }
+            let descriptor = match current {
+                Context::Local(descriptor) => descriptor,
+                Context::External(name) => {
+                    components.push(name);
+                    break;
+                }
+            };
+            let flags = self.memory.reader(descriptor)?.u32()?;
+            match flags & CONTEXT_KIND_MASK {
+                CONTEXT_MODULE | CONTEXT_PROTOCOL | CONTEXT_CLASS | CONTEXT_STRUCT
+                | CONTEXT_ENUM => {
+                    let name = self.memory.relative(descriptor + 8)?.ok_or(
+                        MachOError::MalformedSwiftMetadata("context descriptor without a name"),
+                    )?;
+                    components.push(self.context_component(flags, name)?.to_string());
+                }
+                // an extension only names the type it extends with a mangled name, the types
+                // nested in it are named after the module; private types are nested in an
+                // anonymous context
+                CONTEXT_EXTENSION | CONTEXT_ANONYMOUS | CONTEXT_OPAQUE_TYPE => {}
+                _ => {
+                    return Err(MachOError::MalformedSwiftMetadata(
+                        "unknown context descriptor kind",
+                    ));
+                }
+            }
+            context = self.indirectable(descriptor + 4)?;
+        }
+        components.reverse();
+        Ok(components.join("."))
+    }
+
+    /// Name of a context, the C name of the types imported from C.
+    fn context_component(&self, flags: u32, name: u64) -> Result<&str, MachOError> {
+        let base = self.memory.string(name)?;
+        if flags & CONTEXT_KIND_MASK < CONTEXT_CLASS || flags & TYPE_HAS_IMPORT_INFO == 0 {
+            return Ok(base);
+        }
+        let mut entry = name + base.len() as u64 + 1;
+        loop {
+            let info = self.memory.string(entry)?;
+            if info.is_empty() {
+                return Ok(base);
+            }
+            if let Some(abi_name) = info.strip_prefix(IMPORT_INFO_ABI_NAME) {
+                return Ok(abi_name);
+            }
+            entry += info.len() as u64 + 1;
+        }
+    }
+
+    /// The type of a type context descriptor: flags, parent, name, then the accessor.
+    fn nominal_type(&self, descriptor: u64) -> Result<Option<SwiftType>, MachOError> {
+        let flags = self.memory.reader(descriptor)?.u32()?;
+        let kind = match flags & CONTEXT_KIND_MASK {
+            CONTEXT_CLASS => SwiftTypeKind::Class,
+            CONTEXT_STRUCT => SwiftTypeKind::Struct,
+            CONTEXT_ENUM => SwiftTypeKind::Enum,
+            _ => return Ok(None),
+        };
+        Ok(Some(SwiftType {
+            kind,
+            name: self.qualified_name(descriptor)?,
+            descriptor,
+            accessor: self.memory.relative(descriptor + 12)?,
+        }))
+    }
+
+    /// A protocol conformance descriptor: protocol, type reference, witness table and flags.
+    fn conformance(&self, descriptor: u64) -> Result<SwiftConformance, MachOError> {
+        let protocol = match self.indirectable(descriptor)? {
+            Some(protocol) => self.context_name(protocol)?,
+            None => "?".to_string(),
+        };
+        let flags = self.memory.reader(descriptor + 12)?.u32()?;
+        let type_reference = descriptor + 4;
+        let target = self.memory.relative(type_reference)?;
+        let type_name = match (flags >> CONFORMANCE_TYPE_REFERENCE_SHIFT) & 7 {
+            TYPE_REFERENCE_DIRECT => target.map(Context::Local),
+            TYPE_REFERENCE_INDIRECT => match target {
+                Some(slot) => self.slot(slot)?,
+                None => None,
+            },
+            TYPE_REFERENCE_DIRECT_OBJC_NAME => match target {
+                Some(name) => Some(Context::External(self.memory.string(name)?.to_string())),
+                None => None,
+            },
+            TYPE_REFERENCE_INDIRECT_OBJC_CLASS => match target {
+                Some(slot) => self
+                    .memory
+                    .bound_symbol(slot)
+                    .map(|symbol| Context::External(symbol_context_name(symbol))),
+                None => None,
+            },
+            _ => {
+                return Err(MachOError::MalformedSwiftMetadata(
+                    "unknown conformance type reference kind",
+                ));
+            }
+        };
+        let type_name = match type_name {
+            Some(context) => self.context_name(context)?,
+            None => "?".to_string(),
+        };
+        Ok(SwiftConformance {
+            type_name,
+            protocol,
+            descriptor,
+            witness_table: self.memory.relative(descriptor + 8)?,
+        })
+    }
+}
+
+/// A context descriptor of the image, or the name of one of another image.
+enum Context {
+    Local(u64),
+    External(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::tests::sectioned_image;
+
+    const BASE: u64 = 0x1_0000_0000;
+
+    fn put(data: &mut [u8], offset: u64, bytes: &[u8]) {
+        data[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Stores the offset from `at` to `target`, with `kind` in its low bits.
+    fn relative(data: &mut [u8], at: u64, target: u64, kind: u32) {
+        let offset = (target as i64 - at as i64) as i32 as u32 | kind;
+        put(data, at, &offset.to_le_bytes());
+    }
+
+    /// A context descriptor: flags, parent and name, then the accessor of a type.
+    fn descriptor(data: &mut [u8], at: u64, flags: u32, parent: u64, name: u64) {
+        put(data, at, &flags.to_le_bytes());
+        if parent != 0 {
+            relative(data, at + 4, parent, 0);
+        }
+        relative(data, at + 8, name, 0);
+    }
+
+    /// A module with a struct, a class nested in it, an enum imported from C and a protocol, and
+    /// the conformances of the struct to a protocol of the standard library and of a class of
+    /// another image to the protocol.
+    fn metadata() -> Vec<u8> {
+        let mut data = sectioned_image(
+            "__TEXT",
+            &[("__swift5_types", 0x400, 16), ("__swift5_proto", 0x410, 8)],
+            &[("_$sSHMp", 0x6f0), ("_OBJC_CLASS_$_NSView", 0x6f8)],
+            0x1000,
+        );
+        for (offset, string) in [
+            (0x700, "Shop\0"),
+            (0x710, "Cart\0"),
+            (0x718, "Item\0"),
+            (0x720, "Color\0Rtag\0NColorRef\0\0"),
+            (0x740, "Shape\0"),
+        ] {
+            put(&mut data, offset, string.as_bytes());
+        }
+        descriptor(&mut data, 0x500, CONTEXT_MODULE, 0, 0x700);
+        descriptor(&mut data, 0x520, CONTEXT_STRUCT, 0x500, 0x710);
+        relative(&mut data, 0x52c, 0xf00, 0);
+        descriptor(&mut data, 0x540, CONTEXT_CLASS, 0x520, 0x718);
+        relative(&mut data, 0x54c, 0xf20, 0);
+        let flags = CONTEXT_ENUM | TYPE_HAS_IMPORT_INFO;
+        descriptor(&mut data, 0x560, flags, 0x500, 0x720);
+        descriptor(&mut data, 0x580, CONTEXT_PROTOCOL, 0x500, 0x740);
+
+        // a direct record, an indirect one through a slot, then a class named by the objc
+        // metadata
+        relative(&mut data, 0x400, 0x520, TYPE_REFERENCE_DIRECT);
+        relative(&mut data, 0x404, 0x5f0, TYPE_REFERENCE_INDIRECT);
+        put(&mut data, 0x5f0, &(BASE + 0x540).to_le_bytes());
+        relative(&mut data, 0x408, 0x560, TYPE_REFERENCE_DIRECT);
+        relative(&mut data, 0x40c, 0x740, TYPE_REFERENCE_DIRECT_OBJC_NAME);
+
+        relative(&mut data, 0x410, 0x600, 0);
+        relative(&mut data, 0x600, 0x6f0, 1);
+        relative(&mut data, 0x604, 0x520, 0);
+        relative(&mut data, 0x608, 0xf40, 0);
+        relative(&mut data, 0x414, 0x620, 0);
+        relative(&mut data, 0x620, 0x580, 0);
+        relative(&mut data, 0x624, 0x6f8, 0);
+        let flags = TYPE_REFERENCE_INDIRECT_OBJC_CLASS << CONFORMANCE_TYPE_REFERENCE_SHIFT;
+        put(&mut data, 0x62c, &flags.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn types_are_named_after_their_parents() {
+        let data = metadata();
+        let binary = MachO::parse(&data).unwrap();
+        let types = types(&binary).unwrap();
+        let found: Vec<_> = types
+            .iter()
+            .map(|ty| {
+                (
+                    ty.kind,
+                    ty.name.as_str(),
+                    ty.descriptor - BASE,
+                    ty.accessor.map(|accessor| accessor - BASE),
+                )
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (SwiftTypeKind::Struct, "Shop.Cart", 0x520, Some(0xf00)),
+                (SwiftTypeKind::Class, "Shop.Cart.Item", 0x540, Some(0xf20)),
+                (SwiftTypeKind::Enum, "Shop.ColorRef", 0x560, None),
+            ]
+        );
+        assert_eq!(
+            types[1].accessor_name(),
+            "type metadata accessor for Shop.Cart.Item"
+        );
+    }
+
+    #[test]
+    fn conformances_to_local_and_bound_protocols() {
+        let data = metadata();
+        let binary = MachO::parse(&data).unwrap();
+        let conformances = conformances(&binary).unwrap();
+        let names: Vec<_> = conformances.iter().map(SwiftConformance::name).collect();
+        assert_eq!(
+            names,
+            [
+                "protocol conformance descriptor for Shop.Cart : Swift.Hashable",
+                "protocol conformance descriptor for NSView : Shop.Shape",
+            ]
+        );
+        assert_eq!(conformances[0].witness_table, Some(BASE + 0xf40));
+        assert_eq!(conformances[1].witness_table, None);
+    }
+
+    #[test]
+    fn malformed_metadata() {
+        let with = |patch: &dyn Fn(&mut Vec<u8>)| {
+            let mut data = metadata();
+            patch(&mut data);
+            data
+        };
+        let data = with(&|data| relative(data, 0x504, 0x540, 0));
+        assert_eq!(
+            types(&MachO::parse(&data).unwrap()).unwrap_err(),
+            MachOError::MalformedSwiftMetadata("context descriptors form a loop")
+        );
+        let data = with(&|data| put(data, 0x500, &7u32.to_le_bytes()));
+        assert_eq!(
+            types(&MachO::parse(&data).unwrap()).unwrap_err(),
+            MachOError::MalformedSwiftMetadata("unknown context descriptor kind")
+        );
+        let data = with(&|data| put(data, 0x62c, &(5u32 << 3).to_le_bytes()));
+        assert_eq!(
+            conformances(&MachO::parse(&data).unwrap()).unwrap_err(),
+            MachOError::MalformedSwiftMetadata("unknown conformance type reference kind")
+        );
+    }
+}
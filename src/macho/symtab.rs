@@ -0,0 +1,375 @@
+use super::MachOError;
+use super::reader::{self, Endian, Reader};
+
+// masks of `n_type`
+pub const N_STAB: u8 = 0xe0;
+pub const N_PEXT: u8 = 0x10;
+pub const N_TYPE: u8 = 0x0e;
+pub const N_EXT: u8 = 0x01;
+
+// values of `n_type & N_TYPE`
+pub const N_UNDF: u8 = 0x0;
+pub const N_ABS: u8 = 0x2;
+pub const N_SECT: u8 = 0xe;
+pub const N_PBUD: u8 = 0xc;
+pub const N_INDR: u8 = 0xa;
+
+/// `n_sect` of symbols that are not in any section.
+pub const NO_SECT: u8 = 0;
+
+// flags of `n_desc`
+pub const N_WEAK_REF: u16 = 0x40;
+pub const N_WEAK_DEF: u16 = 0x80;
+pub const N_ARM_THUMB_DEF: u16 = 0x8;
+pub const N_SYMBOL_RESOLVER: u16 = 0x100;
+pub const N_ALT_ENTRY: u16 = 0x200;
+
+// special library ordinals of undefined symbols (high byte of `n_desc`)
+pub const SELF_LIBRARY_ORDINAL: u8 = 0x0;
+pub const DYNAMIC_LOOKUP_ORDINAL: u8 = 0xfe;
+pub const EXECUTABLE_ORDINAL: u8 = 0xff;
+
+// stab types from <mach-o/stab.h>, the whole `n_type` byte
+pub const N_GSYM: u8 = 0x20;
+pub const N_FNAME: u8 = 0x22;
+pub const N_FUN: u8 = 0x24;
+pub const N_STSYM: u8 = 0x26;
+pub const N_LCSYM: u8 = 0x28;
+pub const N_BNSYM: u8 = 0x2e;
+pub const N_AST: u8 = 0x32;
+pub const N_OPT: u8 = 0x3c;
+pub const N_RSYM: u8 = 0x40;
+pub const N_SLINE: u8 = 0x44;
+pub const N_ENSYM: u8 = 0x4e;
+pub const N_SSYM: u8 = 0x60;
+pub const N_SO: u8 = 0x64;
+pub const N_OSO: u8 = 0x66;
+pub const N_LSYM: u8 = 0x80;
+pub const N_BINCL: u8 = 0x82;
+pub const N_SOL: u8 = 0x84;
+pub const N_PARAMS: u8 = 0x86;
+pub const N_VERSION: u8 = 0x88;
+pub const N_OLEVEL: u8 = 0x8a;
+pub const N_PSYM: u8 = 0xa0;
+pub const N_EINCL: u8 = 0xa2;
+pub const N_ENTRY: u8 = 0xa4;
+pub const N_LBRAC: u8 = 0xc0;
+pub const N_EXCL: u8 = 0xc2;
+pub const N_RBRAC: u8 = 0xe0;
+pub const N_BCOMM: u8 = 0xe2;
+pub const N_ECOMM: u8 = 0xe4;
+pub const N_ECOML: u8 = 0xe8;
+pub const N_LENG: u8 = 0xfe;
+
+/// What an `nlist` entry describes, decoded from `n_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Imported symbol, `n_desc` holds the library ordinal.
+    Undefined,
+    /// Symbol with an absolute value, not relocated by the slide.
+    Absolute,
+    /// Symbol defined in section `n_sect`.
+    Section,
+    /// Prebound undefined symbol.
+    Prebound,
+    /// Alias of the symbol whose name is at `n_value` in the string table.
+    Indirect,
+    /// Debugger symbol, one of the `N_*` stab types.
+    Stab(u8),
+    Unknown(u8),
+}
+
+/// Size in bytes of one `nlist` entry.
+pub const NLIST_SIZE: usize = 12;
+/// Size in bytes of one `nlist_64` entry.
+pub const NLIST_64_SIZE: usize = 16;
+
+/// Payload of LC_SYMTAB: where the symbol and string tables live in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SymtabCommand {
+    pub symoff: u32,
+    pub nsyms: u32,
+    pub stroff: u32,
+    pub strsize: u32,
+}
+
+impl SymtabCommand {
+    pub fn parse(r: &mut Reader) -> Result<SymtabCommand, MachOError> {
+        Ok(SymtabCommand {
+            symoff: r.u32()?,
+            nsyms: r.u32()?,
+            stroff: r.u32()?,
+            strsize: r.u32()?,
+        })
+    }
+}
+
+/// One entry of the symbol table, `nlist` values are widened to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nlist {
+    pub n_strx: u32,
+    pub n_type: u8,
+    pub n_sect: u8,
+    pub n_desc: u16,
+    pub n_value: u64,
+}
+
+impl Nlist {
+    /// Debugger symbols use the whole `n_type` byte as their own type.
+    pub fn is_stab(&self) -> bool {
+        self.n_type & N_STAB != 0
+    }
+
+    /// True for symbols defined in one of the image's sections, `n_value` is then their address.
+    pub fn is_defined_in_section(&self) -> bool {
+        !self.is_stab() && self.n_type & N_TYPE == N_SECT && self.n_sect != NO_SECT
+    }
+
+    pub fn is_external(&self) -> bool {
+        self.n_type & N_EXT != 0
+    }
+
+    /// Private external: was external in its object file but made local by the static linker.
+    pub fn is_private_external(&self) -> bool {
+        !self.is_stab() && self.n_type & N_PEXT != 0
+    }
+
+    pub fn kind(&self) -> SymbolKind {
+        if self.is_stab() {
+            return SymbolKind::Stab(self.n_type);
+        }
+        match self.n_type & N_TYPE {
+            N_UNDF => SymbolKind::Undefined,
+            N_ABS => SymbolKind::Absolute,
+            N_SECT => SymbolKind::Section,
+            N_PBUD => SymbolKind::Prebound,
+            N_INDR => SymbolKind::Indirect,
+            other => SymbolKind::Unknown(other),
+        }
+    }
+
+    /// For undefined symbols of a two-level namespace image: 1-based index of the dylib
+    /// (in LC_LOAD_DYLIB order) the symbol is imported from, or one of the special ordinals.
+    pub fn library_ordinal(&self) -> u8 {
+        (self.n_desc >> 8) as u8
+    }
+
+    // the flags below share bits with the library ordinal and are only valid for definitions
+
+    pub fn is_weak_definition(&self) -> bool {
+        self.is_defined_in_section() && self.n_desc & N_WEAK_DEF != 0
+    }
+
+    pub fn is_weak_reference(&self) -> bool {
+        self.kind() == SymbolKind::Undefined && self.n_desc & N_WEAK_REF != 0
+    }
+
+    /// Function defined in thumb mode (32-bit ARM only).
+    pub fn is_thumb(&self) -> bool {
+        self.is_defined_in_section() && self.n_desc & N_ARM_THUMB_DEF != 0
+    }
+
+    /// The symbol is a resolver function returning the address of the real implementation.
+    pub fn is_resolver(&self) -> bool {
+        self.is_defined_in_section() && self.n_desc & N_SYMBOL_RESOLVER != 0
+    }
+
+    pub fn is_alt_entry(&self) -> bool {
+        self.is_defined_in_section() && self.n_desc & N_ALT_ENTRY != 0
+    }
+
+    /// Short description of the type and flags, in the spirit of `nm -m`.
+    pub fn describe(&self) -> String {
+        let mut description = match self.kind() {
+            SymbolKind::Undefined => match self.library_ordinal() {
+                SELF_LIBRARY_ORDINAL => "undefined".to_string(),
+                DYNAMIC_LOOKUP_ORDINAL => "undefined (dynamic lookup)".to_string(),
+                EXECUTABLE_ORDINAL => "undefined (from executable)".to_string(),
+                ordinal => format!("undefined (from dylib #{ordinal})"),
+            },
+            SymbolKind::Absolute => "absolute".to_string(),
+            SymbolKind::Section => format!("section #{}", self.n_sect),
+            SymbolKind::Prebound => "prebound undefined".to_string(),
+            SymbolKind::Indirect => "indirect".to_string(),
+            SymbolKind::Stab(stab) => format!("stab {}", stab_name(stab)),
+            SymbolKind::Unknown(kind) => format!("unknown type {kind:#x}"),
+        };
+        if self.is_stab() {
+            return description;
+        }
+        let flags = [
+            (self.is_external(), "external"),
+            (self.is_private_external(), "private external"),
+            (self.is_weak_definition(), "weak definition"),
+            (self.is_weak_reference(), "weak reference"),
+            (self.is_thumb(), "thumb"),
+            (self.is_resolver(), "resolver"),
+            (self.is_alt_entry(), "alt entry"),
+        ];
+        for (set, flag) in flags {
+            if set {
+                description.push_str(", ");
+                description.push_str(flag);
+            }
+        }
+        description
+    }
+}
+
+/// Name of a stab type as spelled in <mach-o/stab.h>.
+pub fn stab_name(n_type: u8) -> &'static str {
+    match n_type {
+        N_GSYM => "GSYM",
+        N_FNAME => "FNAME",
+        N_FUN => "FUN",
+        N_STSYM => "STSYM",
+        N_LCSYM => "LCSYM",
+        N_BNSYM => "BNSYM",
+        N_AST => "AST",
+        N_OPT => "OPT",
+        N_RSYM => "RSYM",
+        N_SLINE => "SLINE",
+        N_ENSYM => "ENSYM",
+        N_SSYM => "SSYM",
+        N_SO => "SO",
+        N_OSO => "OSO",
+        N_LSYM => "LSYM",
+        N_BINCL => "BINCL",
+        N_SOL => "SOL",
+        N_PARAMS => "PARAMS",
+        N_VERSION => "VERSION",
+        N_OLEVEL => "OLEVEL",
+        N_PSYM => "PSYM",
+        N_EINCL => "EINCL",
+        N_ENTRY => "ENTRY",
+        N_LBRAC => "LBRAC",
+        N_EXCL => "EXCL",
+        N_RBRAC => "RBRAC",
+        N_BCOMM => "BCOMM",
+        N_ECOMM => "ECOMM",
+        N_ECOML => "ECOML",
+        N_LENG => "LENG",
+        _ => "?",
+    }
+}
+
+/// Symbol table of an image with its string table, both validated against the file.
+#[derive(Debug, Clone)]
+pub struct SymbolTable<'a> {
+    pub command: SymtabCommand,
+    pub entries: Vec<Nlist>,
+    strings: &'a [u8],
+}
+
+impl<'a> SymbolTable<'a> {
+    pub fn parse(
+        data: &'a [u8],
+        command: SymtabCommand,
+        endian: Endian,
+        is_64: bool,
+    ) -> Result<SymbolTable<'a>, MachOError> {
+        let entry_size = if is_64 { NLIST_64_SIZE } else { NLIST_SIZE };
+        let symbols_size = (command.nsyms as usize).checked_mul(entry_size);
+        let symbols = symbols_size
+            .and_then(|size| {
+                reader::slice(data, command.symoff as usize, size, "symbol table").ok()
+            })
+            .ok_or(MachOError::SymbolTableOutOfRange {
+                symoff: command.symoff,
+                nsyms: command.nsyms,
+            })?;
+        let strings = reader::slice(
+            data,
+            command.stroff as usize,
+            command.strsize as usize,
+            "string table",
+        )
+        .map_err(|_| MachOError::StringTableOutOfRange {
+            stroff: command.stroff,
+            strsize: command.strsize,
+        })?;
+
+        let mut r = Reader::new(symbols, endian, "symbol table");
+        let mut entries = Vec::with_capacity(command.nsyms as usize);
+        for _ in 0..command.nsyms {
+            entries.push(Nlist {
+                n_strx: r.u32()?,
+                n_type: r.u8()?,
+                n_sect: r.u8()?,
+                n_desc: r.u16()?,
+                n_value: r.word(is_64)?,
+            });
+        }
+
+        Ok(SymbolTable {
+            command,
+            entries,
+            strings,
+        })
+    }
+
+    /// Name of the given entry, checked against the bounds of the string table.
+    pub fn name(&self, nlist: &Nlist) -> Result<&'a str, MachOError> {
+        let out_of_range = MachOError::StringIndexOutOfRange {
+            strx: nlist.n_strx,
+            strsize: self.command.strsize,
+        };
+        if nlist.n_strx as usize >= self.strings.len() {
+            return Err(out_of_range);
+        }
+        reader::cstr(self.strings, nlist.n_strx as usize).ok_or(out_of_range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nlist(n_type: u8, n_sect: u8, n_desc: u16) -> Nlist {
+        Nlist {
+            n_strx: 0,
+            n_type,
+            n_sect,
+            n_desc,
+            n_value: 0x1000,
+        }
+    }
+
+    #[test]
+    fn types_and_flags() {
+        let weak = nlist(N_SECT | N_EXT, 1, N_WEAK_DEF);
+        assert!(weak.is_defined_in_section() && weak.is_external());
+        assert_eq!(weak.kind(), SymbolKind::Section);
+        assert_eq!(weak.describe(), "section #1, external, weak definition");
+        assert_eq!(
+            nlist(N_SECT | N_PEXT, 2, N_ALT_ENTRY).describe(),
+            "section #2, private external, alt entry"
+        );
+
+        // the high byte of n_desc of an import is its library ordinal
+        let import = nlist(N_UNDF | N_EXT, NO_SECT, 2 << 8 | N_WEAK_REF);
+        assert_eq!(import.library_ordinal(), 2);
+        assert!(!import.is_defined_in_section() && !import.is_weak_definition());
+        assert_eq!(
+            import.describe(),
+            "undefined (from dylib #2), external, weak reference"
+        );
+        let flat = nlist(
+            N_UNDF | N_EXT,
+            NO_SECT,
+            (DYNAMIC_LOOKUP_ORDINAL as u16) << 8,
+        );
+        assert_eq!(flat.describe(), "undefined (dynamic lookup), external");
+        assert_eq!(nlist(N_ABS, NO_SECT, 0).kind(), SymbolKind::Absolute);
+        assert_eq!(nlist(N_INDR, NO_SECT, 0).kind(), SymbolKind::Indirect);
+        assert_eq!(nlist(0x6, NO_SECT, 0).kind(), SymbolKind::Unknown(0x6));
+        // a section symbol without a section
+        assert!(!nlist(N_SECT, NO_SECT, 0).is_defined_in_section());
+
+        // stabs use the whole byte, their bits overlap the flags
+        let stab = nlist(N_FUN, 1, N_WEAK_DEF);
+        assert!(stab.is_stab() && !stab.is_defined_in_section());
+        assert_eq!(stab.kind(), SymbolKind::Stab(N_FUN));
+        assert_eq!(stab.describe(), "stab FUN");
+    }
+}
@@ -0,0 +1,368 @@
+// compact unwind information (__TEXT,__unwind_info)
+// a two level index over function offsets: the first level splits the image into pages, each
+// second level page maps function starts to a 32-bit encoding describing how to unwind them
+// https://github.com/llvm/llvm-project/blob/main/libunwind/include/mach-o/compact_unwind_encoding.h
+
+use super::MachOError;
+use super::reader::{Endian, Reader};
+
+pub const UNWIND_SECTION_VERSION: u32 = 1;
+pub const UNWIND_SECOND_LEVEL_REGULAR: u32 = 2;
+pub const UNWIND_SECOND_LEVEL_COMPRESSED: u32 = 3;
+
+// bits shared by every architecture
+pub const UNWIND_HAS_LSDA: u32 = 0x4000_0000;
+pub const UNWIND_PERSONALITY_MASK: u32 = 0x3000_0000;
+
+/// Encoding of one function, with what is needed to find its start and exception data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnwindEntry {
+    /// Offset of the function from the start of the image (its mach header).
+    pub function_offset: u32,
+    /// Offset of the first byte after the function, from the next entry.
+    pub function_end: u32,
+    pub encoding: u32,
+    /// Offset of the language specific data area from the start of the image.
+    pub lsda: Option<u32>,
+    /// Offset from the start of the image of the pointer to the personality routine.
+    pub personality: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FirstLevelEntry {
+    function_offset: u32,
+    second_level_page: u32,
+    lsda_index: u32,
+}
+
+/// Parsed `__unwind_info` section borrowing the section bytes.
+#[derive(Debug, Clone)]
+pub struct UnwindInfo<'a> {
+    data: &'a [u8],
+    endian: Endian,
+    common_encodings: Vec<u32>,
+    personalities: Vec<u32>,
+    // the last entry is a sentinel holding the end of the last function
+    index: Vec<FirstLevelEntry>,
+}
+
+impl<'a> UnwindInfo<'a> {
+    pub fn parse(data: &'a [u8], endian: Endian) -> Result<UnwindInfo<'a>, MachOError> {
+        let mut r = Reader::new(data, endian, "unwind info header");
+        let version = r.u32()?;
+        if version != UNWIND_SECTION_VERSION {
+            return Err(MachOError::UnsupportedUnwindVersion(version));
+        }
+        let common_offset = r.u32()?;
+        let common_count = r.u32()?;
+        let personality_offset = r.u32()?;
+        let personality_count = r.u32()?;
+        let index_offset = r.u32()?;
+        let index_count = r.u32()?;
+
+        let u32_array = |offset: u32, count: u32, what| {
+            let mut r = Reader::at(data, offset as usize, endian, what);
+            (0..count).map(|_| r.u32()).collect::<Result<Vec<_>, _>>()
+        };
+        let common_encodings = u32_array(common_offset, common_count, "common encodings")?;
+        let personalities = u32_array(personality_offset, personality_count, "personalities")?;
+
+        let mut r = Reader::at(data, index_offset as usize, endian, "unwind index");
+        let index = (0..index_count)
+            .map(|_| {
+                Ok(FirstLevelEntry {
+                    function_offset: r.u32()?,
+                    second_level_page: r.u32()?,
+                    lsda_index: r.u32()?,
+                })
+            })
+            .collect::<Result<Vec<_>, MachOError>>()?;
+
+        Ok(UnwindInfo {
+            data,
+            endian,
+            common_encodings,
+            personalities,
+            index,
+        })
+    }
+
+    /// Finds the entry of the function containing `offset` (relative to the start of the image).
+    pub fn lookup(&self, offset: u32) -> Result<Option<UnwindEntry>, MachOError> {
+        // the sentinel only bounds the last page, it has no page of its own
+        let pages = self.index.len().saturating_sub(1);
+        let i = self.index[..pages].partition_point(|e| e.function_offset <= offset);
+        let Some(first_level) = i.checked_sub(1).map(|i| self.index[i]) else {
+            return Ok(None);
+        };
+        let page_end = self.index[i].function_offset;
+        if first_level.second_level_page == 0 || offset >= page_end {
+            return Ok(None);
+        }
+
+        let entries = self.page_entries(&first_level, page_end)?;
+        let j = entries.partition_point(|(start, _)| *start <= offset);
+        let Some(&(function_offset, encoding)) = j.checked_sub(1).and_then(|j| entries.get(j))
+        else {
+            return Ok(None);
+        };
+        let function_end = entries.get(j).map(|(next, _)| *next).unwrap_or(page_end);
+
+        let lsda = if encoding & UNWIND_HAS_LSDA != 0 {
+            self.lsda(i - 1, function_offset)?
+        } else {
+            None
+        };
+        let personality_index = (encoding & UNWIND_PERSONALITY_MASK) >> 28;
+        let personality = personality_index
+            .checked_sub(1)
+            .and_then(|p| self.personalities.get(p as usize).copied());
+
+        Ok(Some(UnwindEntry {
+            function_offset,
+            function_end,
+            encoding,
+            lsda,
+            personality,
+        }))
+    }
+
+    /// Every entry of the table, in increasing function order.
+    pub fn entries(&self) -> Result<Vec<UnwindEntry>, MachOError> {
+        let mut entries = Vec::new();
+        for (i, window) in self.index.windows(2).enumerate() {
+            let (first_level, page_end) = (window[0], window[1].function_offset);
+            if first_level.second_level_page == 0 {
+                continue;
+            }
+            let page = self.page_entries(&first_level, page_end)?;
+            for (j, &(function_offset, encoding)) in page.iter().enumerate() {
+                let lsda = if encoding & UNWIND_HAS_LSDA != 0 {
+                    self.lsda(i, function_offset)?
+                } else {
+                    None
+                };
+                let personality_index = (encoding & UNWIND_PERSONALITY_MASK) >> 28;
+                entries.push(UnwindEntry {
+                    function_offset,
+                    function_end: page.get(j + 1).map(|(next, _)| *next).unwrap_or(page_end),
+                    encoding,
+                    lsda,
+                    personality: personality_index
+                        .checked_sub(1)
+                        .and_then(|p| self.personalities.get(p as usize).copied()),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Decodes a second level page into `(function offset, encoding)` pairs.
+    fn page_entries(
+        &self,
+        first_level: &FirstLevelEntry,
+        page_end: u32,
+    ) -> Result<Vec<(u32, u32)>, MachOError> {
+        let page_offset = first_level.second_level_page as usize;
+        let mut r = Reader::at(self.data, page_offset, self.endian, "unwind page");
+        let kind = r.u32()?;
+        let entry_page_offset = r.u16()? as usize;
+        let entry_count = r.u16()?;
+
+        let mut entries = Vec::with_capacity(entry_count as usize);
+        match kind {
+            UNWIND_SECOND_LEVEL_REGULAR => {
+                let mut r = Reader::at(
+                    self.data,
+                    page_offset + entry_page_offset,
+                    self.endian,
+                    "unwind page entries",
+                );
+                for _ in 0..entry_count {
+                    entries.push((r.u32()?, r.u32()?));
+                }
+            }
+            UNWIND_SECOND_LEVEL_COMPRESSED => {
+                let encodings_page_offset = r.u16()? as usize;
+                let encodings_count = r.u16()?;
+                let mut r = Reader::at(
+                    self.data,
+                    page_offset + encodings_page_offset,
+                    self.endian,
+                    "unwind page encodings",
+                );
+                let page_encodings = (0..encodings_count)
+                    .map(|_| r.u32())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut r = Reader::at(
+                    self.data,
+                    page_offset + entry_page_offset,
+                    self.endian,
+                    "unwind page entries",
+                );
+                for _ in 0..entry_count {
+                    let entry = r.u32()?;
+                    // low 24 bits: offset from the first function of the page
+                    // high 8 bits: index in the common encodings then in the page encodings
+                    let function_offset = first_level
+                        .function_offset
+                        .checked_add(entry & 0x00ff_ffff)
+                        .ok_or(MachOError::MalformedUnwindInfo(
+                            "function offset out of range",
+                        ))?;
+                    let encoding_index = (entry >> 24) as usize;
+                    let encoding =
+                        match encoding_index.checked_sub(self.common_encodings.len()) {
+                            None => self.common_encodings[encoding_index],
+                            Some(local) => *page_encodings.get(local).ok_or(
+                                MachOError::MalformedUnwindInfo("encoding index out of range"),
+                            )?,
+                        };
+                    entries.push((function_offset, encoding));
+                }
+            }
+            _ => {
+                return Err(MachOError::MalformedUnwindInfo(
+                    "unknown second level page kind",
+                ));
+            }
+        }
+        entries.retain(|(start, _)| *start < page_end || page_end == 0);
+        Ok(entries)
+    }
+
+    /// LSDA of the function starting at `function_offset`, searched in the LSDA entries of the
+    /// first level page `page`.
+    fn lsda(&self, page: usize, function_offset: u32) -> Result<Option<u32>, MachOError> {
+        let start = self.index[page].lsda_index as usize;
+        let end = self
+            .index
+            .get(page + 1)
+            .map(|e| e.lsda_index as usize)
+            .unwrap_or(start);
+        let count = end.saturating_sub(start) / 8;
+        let mut r = Reader::at(self.data, start, self.endian, "lsda index");
+        for _ in 0..count {
+            let (function, lsda) = (r.u32()?, r.u32()?);
+            if function == function_offset {
+                return Ok(Some(lsda));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COMMON: [u32; 2] = [0x0400_1000, 0x0200_0000];
+    // has an LSDA, first personality
+    const PAGE_ENCODING: u32 = UNWIND_HAS_LSDA | (1 << 28) | 0x0400_0000;
+
+    // a compressed page for 0x1000..0x3000, a regular one for 0x3000..0x4000
+    fn section(first_page_offset: u32) -> Vec<u8> {
+        let mut words = vec![UNWIND_SECTION_VERSION, 28, 2, 36, 1, 40, 3];
+        words.extend_from_slice(&COMMON);
+        words.push(0x8000);
+        // first level: function offset, page, lsda index, then the sentinel
+        words.extend_from_slice(&[first_page_offset, 84, 76]);
+        words.extend_from_slice(&[0x3000, 112, 84]);
+        words.extend_from_slice(&[0x4000, 0, 84]);
+        // the LSDA of the function at 0x1100
+        words.extend_from_slice(&[0x1100, 0x9000]);
+        // compressed page: entries after 12 bytes, page encodings after 24
+        words.extend_from_slice(&[
+            UNWIND_SECOND_LEVEL_COMPRESSED,
+            12 | (3 << 16),
+            24 | (1 << 16),
+        ]);
+        words.extend_from_slice(&[0, (2 << 24) | 0x100, (1 << 24) | 0x800]);
+        words.push(PAGE_ENCODING);
+        // regular page
+        words.extend_from_slice(&[UNWIND_SECOND_LEVEL_REGULAR, 8 | (1 << 16)]);
+        words.extend_from_slice(&[0x3000, 0x0300_0000]);
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    fn entry(start: u32, end: u32, encoding: u32) -> UnwindEntry {
+        UnwindEntry {
+            function_offset: start,
+            function_end: end,
+            encoding,
+            lsda: None,
+            personality: None,
+        }
+    }
+
+    #[test]
+    fn compressed_and_regular_pages() {
+        let data = section(0x1000);
+        let info = UnwindInfo::parse(&data, Endian::Little).unwrap();
+        let with_lsda = UnwindEntry {
+            lsda: Some(0x9000),
+            personality: Some(0x8000),
+            ..entry(0x1100, 0x1800, PAGE_ENCODING)
+        };
+        assert_eq!(info.lookup(0xfff), Ok(None));
+        assert_eq!(
+            info.lookup(0x1050),
+            Ok(Some(entry(0x1000, 0x1100, COMMON[0])))
+        );
+        assert_eq!(info.lookup(0x1100), Ok(Some(with_lsda)));
+        assert_eq!(
+            info.lookup(0x2fff),
+            Ok(Some(entry(0x1800, 0x3000, COMMON[1])))
+        );
+        assert_eq!(
+            info.lookup(0x3004),
+            Ok(Some(entry(0x3000, 0x4000, 0x0300_0000)))
+        );
+        assert_eq!(info.lookup(0x4000), Ok(None));
+        assert_eq!(
+            info.entries(),
+            Ok(vec![
+                entry(0x1000, 0x1100, COMMON[0]),
+                with_lsda,
+                entry(0x1800, 0x3000, COMMON[1]),
+                entry(0x3000, 0x4000, 0x0300_0000),
+            ])
+        );
+        assert_eq!(info.personalities, vec![0x8000]);
+    }
+
+    #[test]
+    fn malformed_sections() {
+        let mut data = section(0x1000);
+        data[0] = 2;
+        assert_eq!(
+            UnwindInfo::parse(&data, Endian::Little).unwrap_err(),
+            MachOError::UnsupportedUnwindVersion(2)
+        );
+
+        // the offsets of a compressed page are added to the start of the page
+        let data = section(0xffff_ff00);
+        let info = UnwindInfo::parse(&data, Endian::Little).unwrap();
+        assert_eq!(
+            info.entries(),
+            Err(MachOError::MalformedUnwindInfo(
+                "function offset out of range"
+            ))
+        );
+
+        // an encoding index past the common and page encodings
+        let mut data = section(0x1000);
+        data[99] = 9;
+        let info = UnwindInfo::parse(&data, Endian::Little).unwrap();
+        assert_eq!(
+            info.lookup(0x1000),
+            Err(MachOError::MalformedUnwindInfo(
+                "encoding index out of range"
+            ))
+        );
+
+        let data = section(0x1000);
+        assert!(UnwindInfo::parse(&data[..50], Endian::Little).is_err());
+    }
+}
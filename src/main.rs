@@ -1,21 +1,84 @@
+mod coredump;
+mod dwarf;
+mod dyld_cache;
+mod elf;
+mod inspect;
 pub mod logs;
+mod macho;
+mod mapped;
 mod profiler;
+mod symbolize;
+mod unwind;
 pub mod utils;
 
-use std::{env, process::exit};
+use std::{env, path::PathBuf, process::exit};
+
+use symbolize::{DebugSearch, DemangleMode};
 
 // Current version of RustProf
 // if modified and then running update command it will replace
 // your current RustProf installation with the newer version
-const VERSION: &'static str = "0.1.0";
+const VERSION: &str = "0.1.0";
 
 #[derive(Debug, Clone)]
 enum Commands {
-    Run { pid: i32 },
+    // the live profiler is macOS only, the arguments are unused elsewhere
+    #[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+    Run {
+        pid: i32,
+        arch: Option<String>,
+        dsym_paths: Vec<PathBuf>,
+        demangle: DemangleMode,
+    },
+    Inspect {
+        path: String,
+        arch: Option<String>,
+        symbols: bool,
+        imports: bool,
+        codesign: bool,
+        verify: bool,
+    },
+    Symbolize {
+        path: String,
+        arch: Option<String>,
+        load_address: Option<u64>,
+        dsym_paths: Vec<PathBuf>,
+        demangle: DemangleMode,
+        addresses: Vec<u64>,
+    },
+    Core {
+        path: String,
+        binaries: Vec<String>,
+        dsym_paths: Vec<PathBuf>,
+        demangle: DemangleMode,
+    },
+    Cache {
+        path: String,
+        images: Vec<String>,
+        symbols: bool,
+        output: Option<String>,
+    },
+    Cfi {
+        path: String,
+        arch: Option<String>,
+        addresses: Vec<u64>,
+    },
+    SymbolsAdd {
+        paths: Vec<String>,
+        arch: Option<String>,
+        dsym_paths: Vec<PathBuf>,
+    },
+    SymbolsList,
+    SymbolsPrune {
+        days: u64,
+    },
     Version,
     Help,
 }
 
+// entries of the symbol store unused for this long are pruned
+const DEFAULT_PRUNE_DAYS: u64 = 30;
+
 fn main() {
     let args: Vec<String> = env::args().collect();
 
@@ -40,6 +103,81 @@ fn main() {
                     eprintln!("Please provide a valid PID.");
                     exit(1);
                 }),
+            arch: utils::option_value(&args, "--arch"),
+            dsym_paths: utils::path_list(&args, "--dsym-path"),
+            demangle: demangle_mode(&args),
+        },
+        Some("inspect") => Commands::Inspect {
+            path: args.get(2).cloned().unwrap_or_else(|| {
+                eprintln!("Please provide the path of a binary.");
+                exit(1);
+            }),
+            arch: utils::option_value(&args, "--arch"),
+            symbols: utils::has_flag(&args, "--symbols"),
+            imports: utils::has_flag(&args, "--imports"),
+            codesign: utils::has_flag(&args, "--codesign"),
+            verify: utils::has_flag(&args, "--verify"),
+        },
+        Some("symbolize") => Commands::Symbolize {
+            path: args.get(2).cloned().unwrap_or_else(|| {
+                eprintln!("Please provide the path of a binary.");
+                exit(1);
+            }),
+            arch: utils::option_value(&args, "--arch"),
+            load_address: utils::option_value(&args, "--load-address")
+                .and_then(|s| utils::parse_address(&s)),
+            dsym_paths: utils::path_list(&args, "--dsym-path"),
+            demangle: demangle_mode(&args),
+            addresses: utils::positional_args(&args[3..])
+                .iter()
+                .filter_map(|s| utils::parse_address(s))
+                .collect(),
+        },
+        Some("core") => Commands::Core {
+            path: args.get(2).cloned().unwrap_or_else(|| {
+                eprintln!("Please provide the path of a core file.");
+                exit(1);
+            }),
+            binaries: utils::positional_args(&args[3..]),
+            dsym_paths: utils::path_list(&args, "--dsym-path"),
+            demangle: demangle_mode(&args),
+        },
+        Some("cache") => Commands::Cache {
+            path: args.get(2).cloned().unwrap_or_else(|| {
+                eprintln!("Please provide the path of a dyld shared cache.");
+                exit(1);
+            }),
+            images: utils::positional_args(&args[3..]),
+            symbols: utils::has_flag(&args, "--symbols"),
+            output: utils::option_value(&args, "--output"),
+        },
+        Some("cfi") => Commands::Cfi {
+            path: args.get(2).cloned().unwrap_or_else(|| {
+                eprintln!("Please provide the path of a binary.");
+                exit(1);
+            }),
+            arch: utils::option_value(&args, "--arch"),
+            addresses: utils::positional_args(&args[3..])
+                .iter()
+                .filter_map(|s| utils::parse_address(s))
+                .collect(),
+        },
+        Some("symbols") => match args.get(2).map(|s| s.as_str()) {
+            Some("add") => Commands::SymbolsAdd {
+                paths: utils::positional_args(&args[3..]),
+                arch: utils::option_value(&args, "--arch"),
+                dsym_paths: utils::path_list(&args, "--dsym-path"),
+            },
+            Some("list") => Commands::SymbolsList,
+            Some("prune") => Commands::SymbolsPrune {
+                days: utils::option_value(&args, "--older-than")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(DEFAULT_PRUNE_DAYS),
+            },
+            _ => {
+                usage_and_exit("Invalid symbols command".to_string());
+                return;
+            }
         },
         Some("version") => Commands::Version,
         Some("help") => Commands::Help,
@@ -50,14 +188,93 @@ fn main() {
     };
 
     match command {
-        Commands::Run { pid } => profiler::run_profiler(&pid),
+        #[cfg(target_os = "macos")]
+        Commands::Run {
+            pid,
+            arch,
+            dsym_paths,
+            demangle,
+        } => profiler::run_profiler(
+            &pid,
+            arch.as_deref(),
+            &DebugSearch::new(dsym_paths),
+            demangle,
+        ),
+        #[cfg(not(target_os = "macos"))]
+        Commands::Run { pid, .. } => {
+            logs::error_log(format!(
+                "Cannot profile process {}: live profiling is only supported on macOS",
+                pid
+            ));
+            exit(1);
+        }
+        Commands::Inspect {
+            path,
+            arch,
+            symbols,
+            imports,
+            codesign,
+            verify,
+        } => inspect::run_inspect(&path, arch.as_deref(), symbols, imports, codesign, verify),
+        Commands::Symbolize {
+            path,
+            arch,
+            load_address,
+            dsym_paths,
+            demangle,
+            addresses,
+        } => inspect::run_symbolize(
+            &path,
+            arch.as_deref(),
+            load_address,
+            &DebugSearch::new(dsym_paths),
+            demangle,
+            &addresses,
+        ),
+        Commands::Core {
+            path,
+            binaries,
+            dsym_paths,
+            demangle,
+        } => inspect::run_core(&path, &binaries, &DebugSearch::new(dsym_paths), demangle),
+        Commands::Cache {
+            path,
+            images,
+            symbols,
+            output,
+        } => inspect::run_cache(&path, &images, symbols, output.as_deref()),
+        Commands::Cfi {
+            path,
+            arch,
+            addresses,
+        } => inspect::run_cfi(&path, arch.as_deref(), &addresses),
+        Commands::SymbolsAdd {
+            paths,
+            arch,
+            dsym_paths,
+        } => inspect::run_symbols_add(&paths, arch.as_deref(), &DebugSearch::new(dsym_paths)),
+        Commands::SymbolsList => inspect::run_symbols_list(&DebugSearch::new(Vec::new())),
+        Commands::SymbolsPrune { days } => {
+            inspect::run_symbols_prune(&DebugSearch::new(Vec::new()), days)
+        }
         Commands::Version => utils::command_usage(&rustprof_version()),
         Commands::Help => utils::rustprof_usage(),
     }
 }
 
+/// Mode given to `--demangle`, simplified names by default.
+fn demangle_mode(args: &[String]) -> DemangleMode {
+    let Some(mode) = utils::option_value(args, "--demangle") else {
+        return DemangleMode::default();
+    };
+    mode.parse().unwrap_or_else(|e: String| {
+        eprintln!("{}", e);
+        exit(1);
+    })
+}
+
 fn usage_and_exit(msg: String) {
-    if msg != "" {
+    if !msg.is_empty() {
         eprintln!("{}", msg);
     }
 
@@ -67,6 +284,5 @@ fn usage_and_exit(msg: String) {
 }
 
 pub fn rustprof_version() -> String {
-    let usage = format!("rustprof {VERSION}");
-    usage
+    format!("rustprof {VERSION}")
 }
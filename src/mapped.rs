@@ -0,0 +1,140 @@
+// memory-mapped files
+// binaries are mapped instead of read so opening one only costs the pages the parsers touch,
+// every parser borrows its structures from the mapping
+// images rebuilt from the memory of a core file have no file and are kept in a buffer instead,
+// like small files where mapping saves nothing
+//
+// a mapped file must not be truncated while it is open: pages past the new end of the file
+// raise SIGBUS when touched and the process dies, rewriting it in place only gives garbage that
+// the bounds checked readers reject. Compilers and package managers replace binaries with a new
+// file instead, which leaves the mapping intact.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::ops::{Deref, Range};
+use std::path::Path;
+
+use memmap2::Mmap;
+
+// files up to this size are read instead of mapped
+const READ_LIMIT: u64 = 1 << 20;
+
+/// A read-only view of a whole file.
+#[derive(Debug)]
+pub struct MappedFile {
+    contents: Contents,
+}
+
+#[derive(Debug)]
+enum Contents {
+    // empty files cannot be mapped
+    Empty,
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl MappedFile {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<MappedFile> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if size == 0 {
+            return Ok(MappedFile {
+                contents: Contents::Empty,
+            });
+        }
+        if size <= READ_LIMIT {
+            let mut bytes = Vec::with_capacity(size as usize);
+            file.read_to_end(&mut bytes)?;
+            return Ok(Self::from_bytes(bytes));
+        }
+        // SAFETY: the mapping is read-only and the slices it hands out live no longer than it
+        // does. Another process truncating the file while it is mapped makes the reads past
+        // the new end fault with SIGBUS, which is not prevented here: see the top of the file.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(MappedFile {
+            contents: Contents::Mapped(map),
+        })
+    }
+
+    /// Contents built in memory, parsed like a file.
+    pub fn from_bytes(bytes: Vec<u8>) -> MappedFile {
+        MappedFile {
+            contents: Contents::Owned(bytes),
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match &self.contents {
+            Contents::Empty => &[],
+            Contents::Mapped(map) => map,
+            Contents::Owned(bytes) => bytes,
+        }
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data()
+    }
+}
+
+/// Position of `inner` in `outer`, for slices borrowed from the same mapping. Slices from
+/// elsewhere give an empty range.
+pub fn range_in(outer: &[u8], inner: &[u8]) -> Range<usize> {
+    let start = (inner.as_ptr() as usize).wrapping_sub(outer.as_ptr() as usize);
+    if inner.is_empty() || start > outer.len() || outer.len() - start < inner.len() {
+        return 0..0;
+    }
+    start..start + inner.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::macho::{MachO, MachOError};
+
+    fn temp_file(name: &str, contents: &[u8]) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("rustprof-mapped-{}-{}", name, std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn files_are_read_or_mapped() {
+        // read below the limit, mapped above it
+        for size in [0x100, READ_LIMIT as usize + 1] {
+            let contents: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let path = temp_file(&format!("{size}"), &contents);
+            let file = MappedFile::open(&path).unwrap();
+            assert_eq!(
+                matches!(file.contents, Contents::Mapped(_)),
+                size as u64 > READ_LIMIT
+            );
+            assert_eq!(&file[..], &contents[..]);
+            assert_eq!(range_in(&file, &file[0x10..0x20]), 0x10..0x20);
+            assert_eq!(range_in(&file, &contents[0x10..0x20]), 0..0);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_and_missing_files() {
+        let path = temp_file("empty", &[]);
+        let file = MappedFile::open(&path).unwrap();
+        assert!(file.is_empty());
+        assert_eq!(
+            MachO::parse(&file).unwrap_err(),
+            MachOError::TruncatedHeader { len: 0 }
+        );
+        fs::remove_file(&path).unwrap();
+
+        let error = MappedFile::open(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,17 +1,49 @@
 #![allow(unused_variables)]
 #![allow(non_snake_case)]
 
-use core::panic;
-
+// live profiling goes through the mach API and is only available on macOS
+// the binary parsing does not depend on it and builds everywhere
+#[cfg(target_os = "macos")]
 use crate::logs;
+#[cfg(target_os = "macos")]
+use crate::macho::cpu::Arch;
+#[cfg(target_os = "macos")]
 use libc::exit;
+#[cfg(target_os = "macos")]
 use mach2::traps::mach_task_self;
+#[cfg(target_os = "macos")]
 use mach2::traps::task_for_pid;
+#[cfg(target_os = "macos")]
 use read_process_memory::*;
+#[cfg(target_os = "macos")]
 embed_plist::embed_info_plist!("../../Info.plist");
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
 mod parser;
 
-pub fn run_profiler(pid: &i32) {
+#[cfg(target_os = "macos")]
+use crate::symbolize::{DebugSearch, DemangleMode};
+#[cfg(target_os = "macos")]
+use crate::unwind::Registers;
+
+// ARM_THREAD_STATE64 and x86_THREAD_STATE64
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+const THREAD_STATE_FLAVOR: i32 = 6;
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+const THREAD_STATE_FLAVOR: i32 = 4;
+
+/// Registers of the thread state returned by `thread_get_state` for `THREAD_STATE_FLAVOR`.
+#[cfg(all(target_os = "macos", target_arch = "aarch64"))]
+fn thread_registers(state: &[u64]) -> Registers {
+    Registers::from_arm64_thread_state(state)
+}
+
+#[cfg(all(target_os = "macos", target_arch = "x86_64"))]
+fn thread_registers(state: &[u64]) -> Registers {
+    Registers::from_x86_64_thread_state(state)
+}
+
+#[cfg(target_os = "macos")]
+pub fn run_profiler(pid: &i32, arch: Option<&str>, search: &DebugSearch, demangle: DemangleMode) {
     logs::rp_log("Start running the profiler...");
     let mut task: u32 = 0;
 
@@ -29,23 +61,11 @@ pub fn run_profiler(pid: &i32) {
     let mut new_state_count: u32 = 129;
     // backtrace
 
-    // buffers
-    let mut process_address_bytes_buffer = [0; 128];
-    let mut next_fp_bytes_vec = [0u8; 8];
-    let mut next_lr_bytes_vec = [0u8; 8];
-
-    // used to read the process memory address
-    let array: [u64; 128] = [0; 128];
-    let size: usize = array.len();
-
     // flavor
     let thread_basic_info: u32 = 3;
     let thread_id_info: u32 = 4;
     let thread_extended_info: u32 = 5;
 
-    //unwind loop
-    let mut addresses: Vec<u64> = Vec::new();
-
     // binary based address
     #[allow(unused_assignments)]
     let mut bin_loaded_addr: u64 = 0;
@@ -102,7 +122,7 @@ pub fn run_profiler(pid: &i32) {
 
         let thread_state = mach2::thread_act::thread_get_state(
             *thread_list,
-            6,
+            THREAD_STATE_FLAVOR,
             new_state.as_mut_ptr() as *mut _,
             &mut new_state_count,
         );
@@ -110,51 +130,21 @@ pub fn run_profiler(pid: &i32) {
         if thread_state != kernel_success {
             panic!("Thread_State error: {}", thread_state);
         }
-        // addr pointer
-        let mut FP = new_state[29];
-        // link register (return addr)
-        let LR = new_state[30];
-        // stack pointer
-        let SP = new_state[31];
-        // program pointer
-        let PC = new_state[32];
-
-        if FP == 0 {
-            panic!("FP is 0 cannot unreferenced");
-        }
-
-        let fp_ptr = FP as *const u64;
+        let registers = thread_registers(&new_state);
 
         let pid_i32 = *pid as i32;
-        if !fp_ptr.is_null() {
-            loop {
-                let read_process_address = read_process_address(
-                    pid_i32,
-                    FP as usize,
-                    size,
-                    &mut process_address_bytes_buffer,
-                )
-                .unwrap();
-
-                // load the specific chunk of the buffer into vector
-                // used to get the next_fp and next_lr addresses
-                next_fp_bytes_vec.copy_from_slice(&process_address_bytes_buffer[..8]);
-                next_lr_bytes_vec.copy_from_slice(&process_address_bytes_buffer[8..16]);
-                let next_fp = u64::from_le_bytes(next_fp_bytes_vec);
-                let next_lr = u64::from_le_bytes(next_lr_bytes_vec);
-                addresses.push(next_lr);
-                let current_fp = FP;
-                FP = next_fp;
-                // println!("Next FP: {:#x}, Next LR: {:#x}", current_fp, next_lr);
-                if next_fp == 0 {
-                    break;
-                }
-            }
-        }
-        #[allow(unused_assignments)]
-        let mut read_loaded_addr: usize = 0;
-        (bin_loaded_addr, read_loaded_addr) = get_binary_based_addr(task);
-        parser::parse_bin_file(*pid, addresses, bin_loaded_addr, read_loaded_addr);
+        // the architecture of the executable slice the process runs
+        let target;
+        (bin_loaded_addr, target) = get_binary_based_addr(task, pid_i32);
+        parser::parse_bin_file(
+            *pid,
+            registers,
+            bin_loaded_addr,
+            target,
+            arch,
+            search,
+            demangle,
+        );
     }
     //data output
     println!("binary loaded at: {:#x}", bin_loaded_addr);
@@ -191,6 +181,7 @@ pub fn run_profiler(pid: &i32) {
 /// Returns:
 ///
 /// The function `read_process_address` is returning a `std::io::Result<()>`.
+#[cfg(target_os = "macos")]
 fn read_process_address(
     pid: Pid,
     address: usize,
@@ -207,49 +198,66 @@ fn read_process_address(
     Ok(())
 }
 
-/// The function `get_binary_based_addr` retrieves the base address of a binary in a target task using
-/// low-level Rust code.
+/// The function `get_binary_based_addr` retrieves the load address of the main executable in a
+/// target task by walking its VM regions until one starts with a Mach-O header of type `MH_EXECUTE`.
 ///
 /// Arguments:
 ///
 /// * `target_task`: The `target_task` parameter is the task for which you want to retrieve the
 /// binary-based address. This task is typically a process or application running on the system. The
 /// function `get_binary_based_addr` uses this parameter to query the virtual memory region information
-/// of the specified task and extract the base address
+/// of the specified task.
+/// * `pid`: The `pid` of the same process, used to read the start of each region.
 ///
 /// Returns:
 ///
-/// The function `get_binary_based_addr` returns the memory address of the specified target task in
-/// binary format as a `u64` value.
-fn get_binary_based_addr(target_task: u32) -> (u64, usize) {
-    // get base addr
-    let mut address: mach2::vm_types::mach_vm_address_t = 0;
-    let mut size: mach2::vm_types::mach_vm_size_t = 0;
+/// The function `get_binary_based_addr` returns the address the executable header is mapped at
+/// with the architecture in that header, or the start of the first VM region and no architecture
+/// if no header was found.
+#[cfg(target_os = "macos")]
+fn get_binary_based_addr(target_task: u32, pid: Pid) -> (u64, Option<Arch>) {
+    // MH_EXECUTE file type in the mach header
+    let mh_execute: u32 = 2;
     let flavor: i32 = 9;
-    let mut info: mach2::vm_region::vm_region_basic_info_64 = unsafe { std::mem::zeroed() };
-    let mut infoCnt: mach2::message::mach_msg_type_number_t =
-        std::mem::size_of::<mach2::vm_region::vm_region_basic_info_64>() as u32;
-    let mut object_name: mach2::port::mach_port_t = 0;
+    let mut header_buffer = [0u8; 128];
+    let mut first_region: Option<u64> = None;
+    let mut address: mach2::vm_types::mach_vm_address_t = 0;
 
-    // read base addr content
-    let mut data: usize = 0;
-    let mut dataCnt: mach2::message::mach_msg_type_number_t = 0;
+    loop {
+        let mut size: mach2::vm_types::mach_vm_size_t = 0;
+        let mut info: mach2::vm_region::vm_region_basic_info_64 = unsafe { std::mem::zeroed() };
+        let mut infoCnt: mach2::message::mach_msg_type_number_t =
+            std::mem::size_of::<mach2::vm_region::vm_region_basic_info_64>() as u32;
+        let mut object_name: mach2::port::mach_port_t = 0;
 
-    unsafe {
-        let base_addr = mach2::vm::mach_vm_region(
-            target_task,
-            &mut address,
-            &mut size,
-            flavor,
-            &mut info as *mut _ as *mut _,
-            &mut infoCnt,
-            &mut object_name,
-        );
-    }
-    unsafe {
-        let read_addr =
-            mach2::vm::mach_vm_read(target_task, address, size, &mut data, &mut dataCnt);
+        let region = unsafe {
+            mach2::vm::mach_vm_region(
+                target_task,
+                &mut address,
+                &mut size,
+                flavor,
+                &mut info as *mut _ as *mut _,
+                &mut infoCnt,
+                &mut object_name,
+            )
+        };
+        // no region left after `address`
+        if region != 0 {
+            break;
+        }
+        first_region.get_or_insert(address);
+
+        if read_process_address(pid, address as usize, 128, &mut header_buffer).is_ok() {
+            let magic = u32::from_le_bytes(header_buffer[..4].try_into().unwrap());
+            let filetype = u32::from_le_bytes(header_buffer[12..16].try_into().unwrap());
+            if (magic == 0xfeedfacf || magic == 0xfeedface) && filetype == mh_execute {
+                let cputype = u32::from_le_bytes(header_buffer[4..8].try_into().unwrap());
+                let cpusubtype = u32::from_le_bytes(header_buffer[8..12].try_into().unwrap());
+                return (address, Some(Arch::exact(cputype, cpusubtype)));
+            }
+        }
+        address += size;
     }
 
-    return (address, data);
+    (first_region.unwrap_or(0), None)
 }
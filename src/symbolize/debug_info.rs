@@ -0,0 +1,378 @@
+// DWARF of a symbolized image
+// the line program and the functions of a unit are only parsed once an address of the unit is
+// symbolized, the DWARF of the other units stays untouched in the mapping of the file
+// parsing every unit for the symbol store is detached from the lazy units, so it can run on
+// another thread
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::dwarf::inline::FunctionTable;
+use crate::dwarf::interval::IntervalIndex;
+use crate::dwarf::line::LineTable;
+use crate::dwarf::unit::{Unit, UnitHeader};
+use crate::dwarf::{DwarfError, DwarfSections};
+use crate::elf::Elf;
+use crate::logs;
+use crate::macho::MachO;
+use crate::macho::reader::Endian;
+use crate::mapped::{self, MappedFile};
+
+/// Line table and functions of a unit, or of the whole image.
+#[derive(Debug, Clone, Default)]
+pub struct UnitInfo {
+    pub lines: LineTable,
+    pub functions: FunctionTable,
+}
+
+/// The debug information of an image, parsed up front or one unit at a time.
+#[derive(Debug, Clone)]
+pub struct DebugInfo {
+    source: Source,
+}
+
+#[derive(Debug, Clone)]
+enum Source {
+    Parsed(Rc<UnitInfo>),
+    Lazy(Box<LazyUnits>),
+}
+
+// a DwarfSections turned into ranges of the mapping, so it can be rebuilt without borrowing
+#[derive(Debug, Clone)]
+pub struct SectionRanges {
+    endian: Endian,
+    debug_info: Range<usize>,
+    debug_abbrev: Range<usize>,
+    debug_line: Range<usize>,
+    debug_line_str: Range<usize>,
+    debug_str: Range<usize>,
+    debug_str_offsets: Range<usize>,
+    debug_addr: Range<usize>,
+    debug_ranges: Range<usize>,
+    debug_rnglists: Range<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct LazyUnits {
+    // name of the image, for the errors
+    name: String,
+    file: Arc<MappedFile>,
+    sections: SectionRanges,
+    headers: Vec<UnitHeader>,
+    // index of the header of the unit by address range
+    ranges: IntervalIndex,
+    // units without an address range, only parsed when no other unit has the address
+    unranged: Vec<usize>,
+    parsed: RefCell<HashMap<usize, Rc<UnitInfo>>>,
+}
+
+impl DebugInfo {
+    pub fn empty() -> DebugInfo {
+        Self::from_tables(LineTable::default(), FunctionTable::default())
+    }
+
+    /// Debug information already parsed, from the symbol store.
+    pub fn from_tables(lines: LineTable, functions: FunctionTable) -> DebugInfo {
+        DebugInfo {
+            source: Source::Parsed(Rc::new(UnitInfo { lines, functions })),
+        }
+    }
+
+    /// Indexes the units of the __DWARF segment of `binary`, an image borrowed from `file`.
+    ///
+    /// Only the unit headers and the first entry of each unit are read here.
+    pub fn from_macho(name: &str, file: Arc<MappedFile>, binary: &MachO) -> DebugInfo {
+        Self::from_sections(name, file, &DwarfSections::from_macho(binary))
+    }
+
+    /// Indexes the units of the .debug_* sections of `binary`, an ELF file borrowed from `file`.
+    pub fn from_elf(name: &str, file: Arc<MappedFile>, binary: &Elf) -> DebugInfo {
+        Self::from_sections(name, file, &DwarfSections::from_elf(binary))
+    }
+
+    fn from_sections(name: &str, file: Arc<MappedFile>, sections: &DwarfSections) -> DebugInfo {
+        if sections.debug_info.is_empty() {
+            return Self::empty();
+        }
+        match LazyUnits::index(name, file, sections) {
+            Ok(units) => DebugInfo {
+                source: Source::Lazy(Box::new(units)),
+            },
+            Err(e) => {
+                logs::error_log(format!(
+                    "Cannot read the debug information of {}: {}",
+                    name, e
+                ));
+                Self::empty()
+            }
+        }
+    }
+
+    /// Line table and functions of the unit covering `address`, an address of the file.
+    pub fn lookup(&self, address: u64) -> Option<Rc<UnitInfo>> {
+        match &self.source {
+            Source::Parsed(info) => Some(info.clone()),
+            Source::Lazy(units) => units.lookup(address),
+        }
+    }
+
+    /// Whether the image had any DWARF to read.
+    pub fn has_dwarf(&self) -> bool {
+        match &self.source {
+            Source::Parsed(info) => !info.lines.is_empty() || !info.functions.is_empty(),
+            Source::Lazy(_) => true,
+        }
+    }
+
+    /// Line table and functions of every unit, what the symbol store keeps.
+    pub fn tables(&self) -> UnitInfo {
+        self.detached_tables().parse()
+    }
+
+    /// What `tables` reads, owned and `Send`, to parse every unit on another thread.
+    pub fn detached_tables(&self) -> DetachedTables {
+        match &self.source {
+            Source::Parsed(info) => DetachedTables::Parsed(UnitInfo::clone(info)),
+            Source::Lazy(units) => DetachedTables::Lazy {
+                name: units.name.clone(),
+                file: units.file.clone(),
+                sections: units.sections.clone(),
+            },
+        }
+    }
+}
+
+/// The DWARF of an image without the units already parsed, see `DebugInfo::detached_tables`.
+#[derive(Debug)]
+pub enum DetachedTables {
+    Parsed(UnitInfo),
+    Lazy {
+        name: String,
+        file: Arc<MappedFile>,
+        sections: SectionRanges,
+    },
+}
+
+impl DetachedTables {
+    /// Line table and functions of every unit.
+    pub fn parse(self) -> UnitInfo {
+        let (name, file, sections) = match self {
+            DetachedTables::Parsed(info) => return info,
+            DetachedTables::Lazy {
+                name,
+                file,
+                sections,
+            } => (name, file, sections),
+        };
+        let sections = sections.resolve(file.data());
+        let lines = LineTable::parse(&sections).unwrap_or_else(|e| {
+            logs::error_log(format!("Cannot read the line table of {}: {}", name, e));
+            LineTable::default()
+        });
+        let functions = FunctionTable::parse(&sections, &lines).unwrap_or_else(|e| {
+            logs::error_log(format!("Cannot read the functions of {}: {}", name, e));
+            FunctionTable::default()
+        });
+        UnitInfo { lines, functions }
+    }
+}
+
+impl LazyUnits {
+    fn index(
+        name: &str,
+        file: Arc<MappedFile>,
+        sections: &DwarfSections,
+    ) -> Result<LazyUnits, DwarfError> {
+        let headers = UnitHeader::all(sections)?;
+        let mut ranges = Vec::new();
+        let mut unranged = Vec::new();
+        for (index, header) in headers.iter().enumerate() {
+            let unit = Unit::parse(sections, *header)?;
+            let unit_ranges = match unit.entry_at(header.entries_offset)? {
+                Some(root) => unit.ranges(&root)?,
+                None => Vec::new(),
+            };
+            if unit_ranges.is_empty() {
+                unranged.push(index);
+            }
+            ranges.extend(unit_ranges.into_iter().map(|range| (range, index)));
+        }
+        let sections = SectionRanges::new(file.data(), sections);
+        Ok(LazyUnits {
+            name: name.to_string(),
+            file,
+            sections,
+            headers,
+            ranges: IntervalIndex::new(ranges),
+            unranged,
+            parsed: RefCell::new(HashMap::new()),
+        })
+    }
+
+    fn lookup(&self, address: u64) -> Option<Rc<UnitInfo>> {
+        if let Some(index) = self.ranges.lookup(address) {
+            return Some(self.unit(index));
+        }
+        self.unranged
+            .iter()
+            .map(|index| self.unit(*index))
+            .find(|info| info.lines.lookup(address).is_some())
+    }
+
+    fn unit(&self, index: usize) -> Rc<UnitInfo> {
+        if let Some(info) = self.parsed.borrow().get(&index) {
+            return info.clone();
+        }
+        let sections = self.sections.resolve(self.file.data());
+        let info = match self.parse_unit(&sections, index) {
+            Ok(info) => info,
+            Err(e) => {
+                logs::error_log(format!(
+                    "Cannot read the unit at {:#x} of {}: {}",
+                    self.headers[index].offset, self.name, e
+                ));
+                UnitInfo::default()
+            }
+        };
+        let info = Rc::new(info);
+        self.parsed.borrow_mut().insert(index, info.clone());
+        info
+    }
+
+    fn parse_unit(&self, sections: &DwarfSections, index: usize) -> Result<UnitInfo, DwarfError> {
+        let unit = Unit::parse(sections, self.headers[index])?;
+        let lines = match unit.stmt_list {
+            Some(offset) => LineTable::parse_program(sections, offset as usize, unit.comp_dir)?,
+            None => LineTable::default(),
+        };
+        let functions = FunctionTable::parse_unit(&self.headers, &unit, &lines)?;
+        Ok(UnitInfo { lines, functions })
+    }
+}
+
+impl SectionRanges {
+    fn new(data: &[u8], sections: &DwarfSections) -> SectionRanges {
+        let range = |section: &[u8]| mapped::range_in(data, section);
+        SectionRanges {
+            endian: sections.endian,
+            debug_info: range(sections.debug_info),
+            debug_abbrev: range(sections.debug_abbrev),
+            debug_line: range(sections.debug_line),
+            debug_line_str: range(sections.debug_line_str),
+            debug_str: range(sections.debug_str),
+            debug_str_offsets: range(sections.debug_str_offsets),
+            debug_addr: range(sections.debug_addr),
+            debug_ranges: range(sections.debug_ranges),
+            debug_rnglists: range(sections.debug_rnglists),
+        }
+    }
+
+    fn resolve<'a>(&self, data: &'a [u8]) -> DwarfSections<'a> {
+        let section = |range: &Range<usize>| &data[range.clone()];
+        DwarfSections {
+            endian: self.endian,
+            debug_info: section(&self.debug_info),
+            debug_abbrev: section(&self.debug_abbrev),
+            debug_line: section(&self.debug_line),
+            debug_line_str: section(&self.debug_line_str),
+            debug_str: section(&self.debug_str),
+            debug_str_offsets: section(&self.debug_str_offsets),
+            debug_addr: section(&self.debug_addr),
+            debug_ranges: section(&self.debug_ranges),
+            debug_rnglists: section(&self.debug_rnglists),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::dwarf::line::{DW_LNS_ADVANCE_PC, DW_LNS_COPY};
+    use crate::dwarf::tests::{END_SEQUENCE, line_program, set_address, unit_4};
+
+    const ABBREVIATIONS: &[u8] = &[
+        1, 0x11, 1, // compile unit with children
+        0x10, 0x17, // DW_AT_stmt_list, DW_FORM_sec_offset
+        0x11, 0x01, // DW_AT_low_pc, DW_FORM_addr
+        0x12, 0x06, // DW_AT_high_pc, DW_FORM_data4
+        0, 0, //
+        2, 0x2e, 0, // subprogram without children
+        0x03, 0x08, // DW_AT_name, DW_FORM_string
+        0x11, 0x01, // DW_AT_low_pc, DW_FORM_addr
+        0x12, 0x06, // DW_AT_high_pc, DW_FORM_data4
+        0, 0, 0,
+    ];
+
+    // a file holding the abbreviations, the line program and the unit of main at 0x1000..0x1020
+    fn write_dwarf(path: &std::path::Path) -> [Range<usize>; 3] {
+        let mut entries = vec![1];
+        entries.extend(0u32.to_le_bytes());
+        entries.extend(0x1000u64.to_le_bytes());
+        entries.extend(0x20u32.to_le_bytes());
+        entries.extend(b"\x02main\0");
+        entries.extend(0x1000u64.to_le_bytes());
+        entries.extend(0x20u32.to_le_bytes());
+        entries.push(0);
+
+        let mut opcodes = set_address(0x1000);
+        opcodes.extend_from_slice(&[DW_LNS_COPY, DW_LNS_ADVANCE_PC, 0x20]);
+        opcodes.extend_from_slice(&END_SEQUENCE);
+
+        // the sections start past a header, like in an image
+        let mut data = vec![0xcc; 0x40];
+        let mut ranges = Vec::new();
+        for section in [
+            ABBREVIATIONS.to_vec(),
+            line_program(1, &opcodes),
+            unit_4(&entries),
+        ] {
+            ranges.push(data.len()..data.len() + section.len());
+            data.extend(section);
+        }
+        fs::write(path, data).unwrap();
+        ranges.try_into().unwrap()
+    }
+
+    #[test]
+    fn units_are_read_from_the_mapping() {
+        let path = std::env::temp_dir().join(format!("rustprof-debug-info-{}", std::process::id()));
+        let [abbrev, line, info] = write_dwarf(&path);
+        let file = Arc::new(MappedFile::open(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+        let data = file.data();
+        let sections = DwarfSections {
+            endian: Endian::Little,
+            debug_info: &data[info],
+            debug_abbrev: &data[abbrev],
+            debug_line: &data[line],
+            debug_line_str: &[],
+            debug_str: &[],
+            debug_str_offsets: &[],
+            debug_addr: &[],
+            debug_ranges: &[],
+            debug_rnglists: &[],
+        };
+
+        let debug_info = DebugInfo::from_sections("image", file.clone(), &sections);
+        assert!(debug_info.has_dwarf());
+        assert!(debug_info.lookup(0xfff).is_none());
+        assert!(debug_info.lookup(0x1020).is_none());
+
+        let unit = debug_info.lookup(0x1010).unwrap();
+        let location = unit.lines.lookup(0x1010).unwrap();
+        assert_eq!((location.file, location.line), ("src/main.c", 1));
+        let function = unit.functions.lookup(0x1010).unwrap();
+        assert_eq!(function.name.as_deref(), Some("main"));
+        // the unit is parsed once
+        assert!(Rc::ptr_eq(&unit, &debug_info.lookup(0x1000).unwrap()));
+
+        let tables = debug_info.detached_tables().parse();
+        assert_eq!(tables.lines, unit.lines);
+        assert_eq!(tables.functions, unit.functions);
+    }
+}
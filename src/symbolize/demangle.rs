@@ -0,0 +1,202 @@
+// demangling of symbol names
+// C++ and Rust names go through symbolic-demangle as is; Swift names need some help:
+// - the simplified names drop the markers of the async and merged functions, every partial
+//   function of an async function would be named after the function itself
+// - the suffixes added by LLVM when it splits a function (`.cold.1`, `.resume.0`) are kept in
+//   parentheses, `foo (.cold.1)`, instead of the "with unmangled suffix" of the full name
+// - Embedded Swift mangles with `$e` instead of `$s`, which the demangler does not detect
+
+use std::fmt;
+use std::str::FromStr;
+
+use symbolic_common::{Language, Name};
+use symbolic_demangle::{Demangle, DemangleOptions};
+
+/// How much of a demangled name to keep.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DemangleMode {
+    /// The function name and the types it is nested in, e.g. `Foo.bar`.
+    #[default]
+    Simplified,
+    /// The module, parameters and return type as well, e.g. `main.Foo.bar(Swift.Int) -> ()`.
+    Full,
+}
+
+impl FromStr for DemangleMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DemangleMode, String> {
+        match s {
+            "simple" | "simplified" => Ok(DemangleMode::Simplified),
+            "full" => Ok(DemangleMode::Full),
+            _ => Err(format!("unknown demangling mode {s} (simple, full)")),
+        }
+    }
+}
+
+impl fmt::Display for DemangleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DemangleMode::Simplified => write!(f, "simple"),
+            DemangleMode::Full => write!(f, "full"),
+        }
+    }
+}
+
+// markers of the Swift demangler the simplified names lose, the partial functions of an async
+// function are numbered: `(1) await resume partial function for ...`
+const SWIFT_MARKERS: &[&str] = &[
+    "await resume partial function for ",
+    "suspend resume partial function for ",
+    "merged ",
+];
+
+// how the Swift demangler prints the suffixes it does not know
+const UNMANGLED_SUFFIX: &str = " with unmangled suffix \"";
+
+/// Demangles a symbol name, names in an unknown mangling are returned unchanged.
+pub fn demangle(name: &str, mode: DemangleMode) -> String {
+    let embedded = embedded_swift(name);
+    let symbol = Name::from(embedded.as_deref().unwrap_or(name));
+    let options = match mode {
+        DemangleMode::Simplified => DemangleOptions::name_only(),
+        DemangleMode::Full => DemangleOptions::complete(),
+    };
+    let Some(demangled) = symbol.demangle(options) else {
+        return name.to_string();
+    };
+    if mode == DemangleMode::Full || symbol.detect_language() != Language::Swift {
+        return demangled;
+    }
+    let Some(complete) = symbol.demangle(DemangleOptions::complete()) else {
+        return demangled;
+    };
+    simplified_swift(demangled, &complete)
+}
+
+/// `$s` spelling of an Embedded Swift name.
+fn embedded_swift(name: &str) -> Option<String> {
+    let (underscore, mangled) = match name.strip_prefix('_') {
+        Some(mangled) => ("_", mangled),
+        None => ("", name),
+    };
+    let mangled = mangled.strip_prefix("$e")?;
+    Some(format!("{underscore}$s{mangled}"))
+}
+
+/// The simplified name with the marker and the suffix of the complete name it lost.
+fn simplified_swift(mut simplified: String, complete: &str) -> String {
+    // `(1) await resume partial function for ...`
+    let unnumbered = complete
+        .strip_prefix('(')
+        .and_then(|rest| rest.split_once(") "))
+        .filter(|(number, _)| number.bytes().all(|b| b.is_ascii_digit()))
+        .map_or(complete, |(_, rest)| rest);
+    if let Some(marker) = SWIFT_MARKERS
+        .iter()
+        .find(|marker| unnumbered.starts_with(*marker) && !simplified.starts_with(*marker))
+    {
+        let number = &complete[..complete.len() - unnumbered.len()];
+        simplified = format!("{number}{marker}{simplified}");
+    }
+    if let Some((_, suffix)) = complete.rsplit_once(UNMANGLED_SUFFIX)
+        && let Some(suffix) = suffix.strip_suffix('"')
+    {
+        simplified = format!("{simplified} ({suffix})");
+    }
+    simplified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn both(name: &str) -> (String, String) {
+        (
+            demangle(name, DemangleMode::Simplified),
+            demangle(name, DemangleMode::Full),
+        )
+    }
+
+    #[test]
+    fn modes_parse_and_print() {
+        assert_eq!("simple".parse(), Ok(DemangleMode::Simplified));
+        assert_eq!("simplified".parse(), Ok(DemangleMode::Simplified));
+        assert_eq!("full".parse(), Ok(DemangleMode::Full));
+        assert!("short".parse::<DemangleMode>().is_err());
+        assert_eq!(DemangleMode::Simplified.to_string(), "simple");
+        assert_eq!(DemangleMode::Full.to_string(), "full");
+    }
+
+    #[test]
+    fn swift_names() {
+        assert_eq!(
+            both("$s4main3FooV3baryyF"),
+            ("Foo.bar".to_string(), "main.Foo.bar() -> ()".to_string())
+        );
+        assert_eq!(
+            demangle("_$s4main3FooV3baryyF", DemangleMode::Simplified),
+            "Foo.bar"
+        );
+    }
+
+    #[test]
+    fn swift_markers_are_kept() {
+        assert_eq!(
+            demangle("$s4main3fooyyYaFTQ0_", DemangleMode::Simplified),
+            "(1) await resume partial function for foo"
+        );
+        assert_eq!(
+            demangle("$s4main3fooyyYaFTY1_", DemangleMode::Simplified),
+            "(2) suspend resume partial function for foo"
+        );
+        assert_eq!(
+            demangle("$s4main3fooyyFTm", DemangleMode::Simplified),
+            "merged foo"
+        );
+    }
+
+    #[test]
+    fn swift_split_functions_keep_their_suffix() {
+        assert_eq!(
+            both("$s4main3fooyyF.cold.1"),
+            (
+                "foo (.cold.1)".to_string(),
+                "main.foo() -> () with unmangled suffix \".cold.1\"".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn embedded_swift_names() {
+        assert_eq!(both("$e4main3FooV3baryyF"), both("$s4main3FooV3baryyF"));
+        assert_eq!(
+            embedded_swift("_$e4main3fooyyF").as_deref(),
+            Some("_$s4main3fooyyF")
+        );
+        assert_eq!(embedded_swift("$s4main3fooyyF"), None);
+    }
+
+    #[test]
+    fn other_languages() {
+        assert_eq!(
+            both("_ZN3foo3barEv"),
+            ("foo::bar".to_string(), "foo::bar()".to_string())
+        );
+        assert_eq!(
+            demangle(
+                "_ZN4core3ptr13drop_in_place17h0123456789abcdefE",
+                DemangleMode::Simplified
+            ),
+            "core::ptr::drop_in_place"
+        );
+        assert_eq!(
+            demangle("_RNvCs1234_7mycrate3foo", DemangleMode::Full),
+            "mycrate::foo"
+        );
+        assert_eq!(
+            both("plain_c"),
+            ("plain_c".to_string(), "plain_c".to_string())
+        );
+    }
+}
@@ -0,0 +1,257 @@
+// debug files of stripped images
+// the DWARF of a release build is moved into a dSYM bundle by dsymutil, the bundle belongs to
+// the image whose LC_UUID it shares: <name>.dSYM/Contents/Resources/DWARF/<name>
+
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::logs;
+use crate::macho::MachO;
+use crate::macho::fat::{FatBinary, is_fat};
+use crate::macho::load_command::format_uuid;
+use crate::mapped::MappedFile;
+
+/// Environment variable enabling the local symbol store, set to its root.
+pub const SYMBOL_STORE_ENV: &str = "RUSTPROF_SYMBOL_STORE";
+
+/// Where to look for the debug file of an image, besides next to the image itself.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DebugSearch {
+    /// Directories holding dSYM bundles, from `--dsym-path`.
+    pub directories: Vec<PathBuf>,
+    /// Root of the local symbol store, one directory per UUID.
+    pub store: Option<PathBuf>,
+}
+
+impl DebugSearch {
+    pub fn new(directories: Vec<PathBuf>) -> DebugSearch {
+        DebugSearch {
+            directories,
+            store: default_symbol_store(),
+        }
+    }
+}
+
+/// `$RUSTPROF_SYMBOL_STORE`, the store is not used when it is not set.
+pub fn default_symbol_store() -> Option<PathBuf> {
+    std::env::var_os(SYMBOL_STORE_ENV)
+        .filter(|store| !store.is_empty())
+        .map(PathBuf::from)
+}
+
+/// A debug file whose UUID matches the image.
+#[derive(Debug, Clone)]
+pub struct DebugFile {
+    pub path: PathBuf,
+    file: Arc<MappedFile>,
+    // the matching slice of a universal file
+    slice: Range<usize>,
+}
+
+impl DebugFile {
+    /// The Mach-O image with the DWARF of the image.
+    pub fn image(&self) -> &[u8] {
+        &self.file[self.slice.clone()]
+    }
+
+    /// The mapping of the whole debug file.
+    pub fn file(&self) -> Arc<MappedFile> {
+        self.file.clone()
+    }
+}
+
+/// Finds the debug file of the image at `path` with LC_UUID `uuid`.
+///
+/// dSYM bundles next to the image come first, then the ones of the configured directories and
+/// last the symbol store. Files with another UUID are skipped with a warning.
+pub fn find_debug_file(path: &Path, uuid: &[u8; 16], search: &DebugSearch) -> Option<DebugFile> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let mut directories = Vec::new();
+    if let Some(parent) = path.parent() {
+        directories.push(parent.to_path_buf());
+    }
+    directories.extend(search.directories.iter().cloned());
+
+    let mut candidates = Vec::new();
+    for directory in &directories {
+        // the bundle named after the image first, then any other bundle of the directory
+        candidates.extend(bundle_files(&directory.join(format!("{}.dSYM", name))));
+        for bundle in dsym_bundles(directory) {
+            candidates.extend(bundle_files(&bundle));
+        }
+    }
+    if let Some(store) = &search.store {
+        let entry = store.join(format_uuid(uuid));
+        for bundle in dsym_bundles(&entry) {
+            candidates.extend(bundle_files(&bundle));
+        }
+        candidates.extend(files(&entry));
+    }
+
+    let mut seen = HashSet::new();
+    for candidate in candidates {
+        if !seen.insert(candidate.clone()) {
+            continue;
+        }
+        if let Some(file) = open_debug_file(&candidate, path, uuid) {
+            return Some(file);
+        }
+    }
+    None
+}
+
+/// Maps `candidate` and keeps it when it has a slice with `uuid`.
+fn open_debug_file(candidate: &Path, image: &Path, uuid: &[u8; 16]) -> Option<DebugFile> {
+    let data = MappedFile::open(candidate).ok()?;
+    let slices = slices(&data);
+    let mut uuids = Vec::new();
+    for slice in slices {
+        let Some(found) = MachO::parse(&data[slice.clone()])
+            .ok()
+            .and_then(|binary| binary.uuid())
+        else {
+            continue;
+        };
+        if found == *uuid {
+            return Some(DebugFile {
+                path: candidate.to_path_buf(),
+                file: Arc::new(data),
+                slice,
+            });
+        }
+        uuids.push(format_uuid(&found));
+    }
+    if !uuids.is_empty() {
+        logs::warning_log(format!(
+            "Ignoring {}: its UUID {} does not match the UUID {} of {}",
+            candidate.display(),
+            uuids.join(", "),
+            format_uuid(uuid),
+            image.display()
+        ));
+    }
+    None
+}
+
+// every slice of a universal file, or the whole file
+fn slices(data: &[u8]) -> Vec<Range<usize>> {
+    if !is_fat(data) {
+        return std::iter::once(0..data.len()).collect();
+    }
+    match FatBinary::parse(data) {
+        Ok(fat) => fat
+            .arches
+            .iter()
+            .map(|arch| arch.offset as usize..(arch.offset + arch.size) as usize)
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+// the DWARF files of a bundle
+fn bundle_files(bundle: &Path) -> Vec<PathBuf> {
+    files(&bundle.join("Contents").join("Resources").join("DWARF"))
+}
+
+fn dsym_bundles(directory: &Path) -> Vec<PathBuf> {
+    let mut bundles: Vec<PathBuf> = read_dir(directory)
+        .into_iter()
+        .filter(|path| path.extension().is_some_and(|e| e == "dSYM") && path.is_dir())
+        .collect();
+    bundles.sort();
+    bundles
+}
+
+fn files(directory: &Path) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = read_dir(directory)
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    files
+}
+
+fn read_dir(directory: &Path) -> Vec<PathBuf> {
+    match fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Finds the debug file of `binary`, logging where the DWARF comes from.
+pub fn load_debug_file(path: &Path, binary: &MachO, search: &DebugSearch) -> Option<DebugFile> {
+    let Some(uuid) = binary.uuid() else {
+        logs::warning_log(format!(
+            "{} has no LC_UUID, its dSYM cannot be verified and is not searched",
+            path.display()
+        ));
+        return None;
+    };
+    let file = find_debug_file(path, &uuid, search)?;
+    logs::info_log(format!(
+        "Using the debug information of {}",
+        file.path.display()
+    ));
+    Some(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::Endian;
+    use crate::macho::cpu::CPU_TYPE_ARM64;
+    use crate::macho::tests::{UUID, image};
+
+    // an image next to its dSYM bundle, the DWARF file holding `data`
+    fn bundle(name: &str, data: &[u8]) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustprof-dsym-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let dwarf = dir.join("app.dSYM/Contents/Resources/DWARF");
+        fs::create_dir_all(&dwarf).unwrap();
+        fs::write(dwarf.join("app"), data).unwrap();
+        fs::write(dir.join("app"), b"image").unwrap();
+        dir
+    }
+
+    #[test]
+    fn bundles_with_the_uuid_of_the_image() {
+        let data = image(CPU_TYPE_ARM64, true, Endian::Little);
+        let dir = bundle("match", &data);
+        let file = find_debug_file(&dir.join("app"), &UUID, &DebugSearch::default()).unwrap();
+        assert_eq!(file.path, dir.join("app.dSYM/Contents/Resources/DWARF/app"));
+        assert_eq!(file.image(), &data[..]);
+
+        // a bundle of another directory, found by its UUID whatever its name
+        let elsewhere =
+            std::env::temp_dir().join(format!("rustprof-dsym-elsewhere-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&elsewhere);
+        fs::create_dir_all(&elsewhere).unwrap();
+        let search = DebugSearch {
+            directories: vec![dir.clone()],
+            store: None,
+        };
+        let file = find_debug_file(&elsewhere.join("renamed"), &UUID, &search).unwrap();
+        assert_eq!(file.path, dir.join("app.dSYM/Contents/Resources/DWARF/app"));
+
+        let _ = fs::remove_dir_all(&dir);
+        let _ = fs::remove_dir_all(&elsewhere);
+    }
+
+    #[test]
+    fn bundles_with_another_uuid_are_skipped() {
+        let dir = bundle("mismatch", &image(CPU_TYPE_ARM64, true, Endian::Little));
+        let mut other = UUID;
+        other[0] ^= 0xff;
+        assert!(find_debug_file(&dir.join("app"), &other, &DebugSearch::default()).is_none());
+        let _ = fs::remove_dir_all(&dir);
+
+        // neither are files that are not Mach-O images
+        let dir = bundle("garbage", b"not an image");
+        assert!(find_debug_file(&dir.join("app"), &UUID, &DebugSearch::default()).is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
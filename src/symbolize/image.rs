@@ -0,0 +1,214 @@
+// images loaded in a process
+// an image is mapped at its load address instead of the preferred address of its __TEXT segment,
+// the difference (the slide) has to be removed before looking anything up in the file
+
+use std::fmt;
+
+use crate::elf::{Elf, PT_LOAD};
+use crate::macho::MachO;
+
+/// Where an image is mapped in the target process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedImage {
+    /// File name of the image, used to print `image+0xoffset`.
+    pub name: String,
+    pub path: String,
+    /// Address the image header is mapped at.
+    pub load_address: u64,
+    /// Preferred address of the __TEXT segment.
+    pub vmaddr: u64,
+    /// Size of the VM range covered by the image segments.
+    pub vmsize: u64,
+    /// `load_address - vmaddr`, wrapping for images loaded below their preferred address.
+    pub slide: u64,
+}
+
+impl LoadedImage {
+    pub fn new(path: &str, load_address: u64, binary: &MachO) -> LoadedImage {
+        let vmaddr = binary
+            .segment("__TEXT")
+            .map(|text| text.vmaddr)
+            .unwrap_or(0);
+        // __PAGEZERO is not part of the image, it only reserves the low memory
+        let end = binary
+            .segments()
+            .filter(|segment| segment.segname != "__PAGEZERO")
+            .map(|segment| segment.vmaddr.saturating_add(segment.vmsize))
+            .max()
+            .unwrap_or(vmaddr);
+        LoadedImage {
+            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+            path: path.to_string(),
+            load_address,
+            vmaddr,
+            vmsize: end.saturating_sub(vmaddr),
+            slide: load_address.wrapping_sub(vmaddr),
+        }
+    }
+
+    /// An ELF file mapped from `load_address`, the address of its first byte. The preferred
+    /// address is the one of the first loadable segment, 0 for position independent files.
+    pub fn from_elf(path: &str, load_address: u64, binary: &Elf) -> LoadedImage {
+        let vmaddr = binary.base_address();
+        let end = binary
+            .program_headers
+            .iter()
+            .filter(|ph| ph.p_type == PT_LOAD)
+            .map(|ph| ph.vaddr.saturating_add(ph.memsz))
+            .max()
+            .unwrap_or(vmaddr);
+        LoadedImage {
+            name: path.rsplit('/').next().unwrap_or(path).to_string(),
+            path: path.to_string(),
+            load_address,
+            vmaddr,
+            vmsize: end.saturating_sub(vmaddr),
+            slide: load_address.wrapping_sub(vmaddr),
+        }
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.load_address && address - self.load_address < self.vmsize
+    }
+
+    /// Converts a runtime address into the preferred address used by the file.
+    pub fn unslide(&self, address: u64) -> u64 {
+        address.wrapping_sub(self.slide)
+    }
+
+    /// Offset of a runtime address from the start of the image.
+    pub fn offset(&self, address: u64) -> u64 {
+        address.wrapping_sub(self.load_address)
+    }
+}
+
+/// Source position of a frame, `line` and `column` are 0 when unknown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file)?;
+        if self.line != 0 {
+            write!(f, ":{}", self.line)?;
+        }
+        if self.column != 0 {
+            write!(f, ":{}", self.column)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sampled address with everything known about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// Absolute address in the target process.
+    pub address: u64,
+    /// `(image name, offset in the image)` when the address belongs to a known image.
+    pub image: Option<(String, u64)>,
+    /// `(symbol name, offset in the symbol)` when the address could be symbolized.
+    pub symbol: Option<(String, u64)>,
+    pub location: Option<SourceLocation>,
+    /// Object file the code was compiled into, from the debug map.
+    pub object: Option<String>,
+    /// The frame is a call inlined at the address, not a function with its own stack frame.
+    pub inlined: bool,
+}
+
+impl Frame {
+    pub fn new(address: u64) -> Frame {
+        Frame {
+            address,
+            image: None,
+            symbol: None,
+            location: None,
+            object: None,
+            inlined: false,
+        }
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.address)?;
+        if let Some((image, offset)) = &self.image {
+            write!(f, " {}+{:#x}", image, offset)?;
+        }
+        match &self.symbol {
+            // an inlined call has no code of its own to be an offset in
+            Some((symbol, _)) if self.inlined => write!(f, " {} [inlined]", symbol)?,
+            Some((symbol, offset)) => write!(f, " {}+{:#x}", symbol, offset)?,
+            None => write!(f, " ???")?,
+        }
+        if let Some(location) = &self.location {
+            write!(f, " at {}", location)?;
+        }
+        if let Some(object) = &self.object {
+            write!(f, " ({})", object.rsplit('/').next().unwrap_or(object))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::load_command::LC_SEGMENT_64;
+    use crate::macho::tests::{image_with_commands, segment_64};
+
+    const BASE: u64 = 0x1_0000_0000;
+
+    fn loaded_at(load_address: u64) -> LoadedImage {
+        let data = image_with_commands(
+            &[
+                (LC_SEGMENT_64, segment_64("__PAGEZERO", 0, 0, 0, &[])),
+                (LC_SEGMENT_64, segment_64("__TEXT", BASE, 0, 0x1000, &[])),
+                (
+                    LC_SEGMENT_64,
+                    segment_64("__DATA", BASE + 0x1000, 0x1000, 0x1000, &[]),
+                ),
+            ],
+            0x2000,
+        );
+        let binary = MachO::parse(&data).unwrap();
+        LoadedImage::new("/usr/lib/libfoo.dylib", load_address, &binary)
+    }
+
+    #[test]
+    fn slid_images() {
+        let image = loaded_at(BASE + 0x800_0000);
+        assert_eq!(image.name, "libfoo.dylib");
+        assert_eq!((image.vmaddr, image.vmsize), (BASE, 0x2000));
+        assert_eq!(image.slide, 0x800_0000);
+        let address = BASE + 0x800_1010;
+        assert!(image.contains(address));
+        assert_eq!(image.unslide(address), BASE + 0x1010);
+        assert_eq!(image.offset(address), 0x1010);
+    }
+
+    #[test]
+    fn images_at_their_preferred_address() {
+        let image = loaded_at(BASE);
+        assert_eq!(image.slide, 0);
+        assert_eq!(image.unslide(BASE + 0x10), BASE + 0x10);
+        assert_eq!(image.offset(BASE + 0x10), 0x10);
+
+        // loaded below the preferred address, the slide wraps
+        let image = loaded_at(0x10_0000);
+        assert_eq!(image.unslide(0x10_0010), BASE + 0x10);
+    }
+
+    #[test]
+    fn addresses_outside_the_image() {
+        let image = loaded_at(BASE + 0x800_0000);
+        assert!(!image.contains(BASE + 0x7ff_ffff));
+        assert!(!image.contains(BASE + 0x800_2000));
+        assert!(image.contains(BASE + 0x800_1fff));
+        assert!(!image.contains(0));
+        assert!(!image.contains(u64::MAX));
+    }
+}
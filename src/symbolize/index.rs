@@ -0,0 +1,432 @@
+// address to symbol index
+// built once per image from its symbol table, sorted by address and queried by binary search
+
+use crate::elf::Elf;
+use crate::elf::symtab::{STB_LOCAL, STT_FILE, STT_SECTION, STT_TLS};
+use crate::macho::{MachO, Section, dysymtab, exports, fixups, function_starts, objc, swift};
+
+/// A function or data symbol with the address range it covers in the image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    /// Preferred (unslid) address of the symbol.
+    pub address: u64,
+    /// Distance to the next symbol, or to the end of the section for the last one.
+    pub size: u64,
+}
+
+impl Symbol {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.address && address - self.address < self.size
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SymbolIndex {
+    symbols: Vec<Symbol>,
+}
+
+// when several sources name the same address the lowest priority wins
+const PRIORITY_EXTERNAL: u8 = 0;
+const PRIORITY_LOCAL: u8 = 1;
+const PRIORITY_INDIRECT: u8 = 2;
+const PRIORITY_BIND: u8 = 3;
+const PRIORITY_EXPORT: u8 = 4;
+const PRIORITY_OBJC_METHOD: u8 = 5;
+const PRIORITY_SWIFT_METADATA: u8 = 6;
+const PRIORITY_FUNCTION_START: u8 = 9;
+
+/// A symbol waiting for the index to be sorted to know its size.
+#[derive(Debug, Clone)]
+struct Candidate {
+    address: u64,
+    /// The symbol cannot extend past this address (end of its section or of its slot).
+    end: u64,
+    priority: u8,
+    name: String,
+}
+
+impl SymbolIndex {
+    /// Builds the index from every source of names of the image: the defined `N_SECT` symbols,
+    /// the stubs and pointer slots of the indirect symbol table, the slots bound by dyld, the
+    /// export trie, the Objective-C method lists, the Swift type and conformance records and the
+    /// function starts.
+    pub fn from_macho(binary: &MachO) -> SymbolIndex {
+        let mut candidates = Vec::new();
+
+        if let Some(symtab) = &binary.symtab {
+            // n_sect is a 1-based index over every section of the image
+            let sections: Vec<&Section> = binary.sections().collect();
+            candidates.extend(
+                symtab
+                    .entries
+                    .iter()
+                    .filter(|nlist| nlist.is_defined_in_section())
+                    .filter_map(|nlist| {
+                        let section = sections.get(nlist.n_sect as usize - 1)?;
+                        let name = symtab.name(nlist).ok().filter(|name| !name.is_empty())?;
+                        Some(Candidate {
+                            address: nlist.n_value,
+                            end: section.end(),
+                            // external symbols win over local aliases at the same address
+                            priority: if nlist.is_external() {
+                                PRIORITY_EXTERNAL
+                            } else {
+                                PRIORITY_LOCAL
+                            },
+                            name: name.to_string(),
+                        })
+                    }),
+            );
+        }
+
+        // stubs have no symbol of their own, name them after the symbol they jump to
+        if let Ok(entries) = dysymtab::indirect_entries(binary) {
+            candidates.extend(entries.iter().filter_map(|entry| {
+                Some(Candidate {
+                    address: entry.address,
+                    end: entry.address.checked_add(entry.size)?,
+                    priority: PRIORITY_INDIRECT,
+                    name: entry.label(),
+                })
+            }));
+        }
+
+        // other slots bound to imports, pointers in data or the GOT of images without a
+        // dynamic symbol table
+        if let Ok(fixups) = fixups::fixups(binary) {
+            let pointer_size = if binary.header.is_64() { 8 } else { 4 };
+            candidates.extend(fixups.binds.iter().filter_map(|bind| {
+                Some(Candidate {
+                    address: bind.address,
+                    end: bind.address.checked_add(pointer_size)?,
+                    priority: PRIORITY_BIND,
+                    name: fixups.label(bind),
+                })
+            }));
+        }
+
+        // the export trie still names the public functions of a dylib stripped of its locals
+        if let Ok(exports) = exports::exports(binary) {
+            let base = binary.segment("__TEXT").map_or(0, |text| text.vmaddr);
+            candidates.extend(exports.into_iter().filter_map(|export| {
+                let address = base.checked_add(export.offset()?)?;
+                let section = binary.section_for_address(address)?;
+                Some(Candidate {
+                    address,
+                    end: section.end(),
+                    priority: PRIORITY_EXPORT,
+                    name: export.name,
+                })
+            }));
+        }
+
+        // the Objective-C metadata names the methods of stripped images
+        if let Ok(methods) = objc::methods(binary) {
+            candidates.extend(methods.into_iter().filter_map(|method| {
+                let section = binary.section_for_address(method.imp)?;
+                Some(Candidate {
+                    address: method.imp,
+                    end: section.end(),
+                    priority: PRIORITY_OBJC_METHOD,
+                    name: method.name(),
+                })
+            }));
+        }
+
+        // the Swift type records name the descriptors and metadata accessors of stripped images
+        let mut swift_names = Vec::new();
+        if let Ok(types) = swift::types(binary) {
+            for swift_type in types {
+                if let Some(accessor) = swift_type.accessor {
+                    swift_names.push((accessor, swift_type.accessor_name()));
+                }
+                swift_names.push((swift_type.descriptor, swift_type.descriptor_name()));
+            }
+        }
+        if let Ok(conformances) = swift::conformances(binary) {
+            swift_names.extend(
+                conformances
+                    .into_iter()
+                    .map(|conformance| (conformance.descriptor, conformance.name())),
+            );
+        }
+        candidates.extend(swift_names.into_iter().filter_map(|(address, name)| {
+            let section = binary.section_for_address(address)?;
+            Some(Candidate {
+                address,
+                end: section.end(),
+                priority: PRIORITY_SWIFT_METADATA,
+                name,
+            })
+        }));
+
+        // stripped functions get a synthetic name, they still bound the symbol before them
+        if let Ok(starts) = function_starts::function_starts(binary) {
+            candidates.extend(starts.into_iter().filter_map(|address| {
+                let section = binary.section_for_address(address)?;
+                Some(Candidate {
+                    address,
+                    end: section.end(),
+                    priority: PRIORITY_FUNCTION_START,
+                    name: synthetic_name(address),
+                })
+            }));
+        }
+
+        SymbolIndex::from_candidates(candidates)
+    }
+
+    /// Builds the index from the defined symbols of .symtab, or .dynsym for stripped files.
+    pub fn from_elf(binary: &Elf) -> SymbolIndex {
+        let symbols = binary.symbols().unwrap_or_default();
+        let candidates = symbols
+            .iter()
+            .filter(|symbol| symbol.is_defined() && !symbol.name.is_empty())
+            .filter(|symbol| !matches!(symbol.symbol_type(), STT_SECTION | STT_FILE | STT_TLS))
+            // $x and $d mark code and data in arm64 sections
+            .filter(|symbol| !symbol.name.starts_with('$'))
+            .filter_map(|symbol| {
+                let section = binary.sections.get(symbol.shndx as usize)?;
+                let section_end = section.addr.saturating_add(section.size);
+                // a sized symbol does not cover the padding after it
+                let end = if symbol.size != 0 {
+                    symbol.value.saturating_add(symbol.size).min(section_end)
+                } else {
+                    section_end
+                };
+                Some(Candidate {
+                    address: symbol.value,
+                    end,
+                    priority: if symbol.binding() == STB_LOCAL {
+                        PRIORITY_LOCAL
+                    } else {
+                        PRIORITY_EXTERNAL
+                    },
+                    name: symbol.name.to_string(),
+                })
+            })
+            .collect();
+        SymbolIndex::from_candidates(candidates)
+    }
+
+    /// Index of symbols already sorted by address, as stored in the symbol store.
+    pub fn from_symbols(symbols: Vec<Symbol>) -> SymbolIndex {
+        SymbolIndex { symbols }
+    }
+
+    fn from_candidates(mut candidates: Vec<Candidate>) -> SymbolIndex {
+        candidates.sort_by_key(|c| (c.address, c.priority));
+        candidates.dedup_by_key(|c| c.address);
+
+        let ends: Vec<u64> = candidates
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                candidates
+                    .get(i + 1)
+                    .map(|next| next.address.min(c.end))
+                    .unwrap_or(c.end)
+            })
+            .collect();
+        let symbols = candidates
+            .into_iter()
+            .zip(ends)
+            .map(|(c, end)| Symbol {
+                size: end.saturating_sub(c.address),
+                address: c.address,
+                name: c.name,
+            })
+            .collect();
+        SymbolIndex { symbols }
+    }
+
+    /// Finds the symbol containing the unslid `address` and the offset of the address in it.
+    pub fn lookup(&self, address: u64) -> Option<(&Symbol, u64)> {
+        let i = self.symbols.partition_point(|s| s.address <= address);
+        let symbol = self.symbols.get(i.checked_sub(1)?)?;
+        symbol
+            .contains(address)
+            .then(|| (symbol, address - symbol.address))
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+}
+
+/// Name given to functions only known from LC_FUNCTION_STARTS, from their unslid address so it
+/// can be matched later against a dSYM.
+pub fn synthetic_name(address: u64) -> String {
+    format!("func_{:#x}", address)
+}
+
+/// True for names made up by `synthetic_name`.
+pub fn is_synthetic(name: &str) -> bool {
+    name.starts_with("func_0x")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::macho::dysymtab::tests::indirect_image;
+    use crate::macho::load_command::{LC_DYLD_INFO_ONLY, LC_SEGMENT_64};
+    use crate::macho::tests::{bind_opcodes, image_with_commands, section, segment_64};
+
+    fn candidate(address: u64, end: u64, priority: u8, name: &str) -> Candidate {
+        Candidate {
+            address,
+            end,
+            priority,
+            name: name.to_string(),
+        }
+    }
+
+    fn sizes(index: &SymbolIndex) -> Vec<(&str, u64, u64)> {
+        index
+            .symbols()
+            .iter()
+            .map(|symbol| (symbol.name.as_str(), symbol.address, symbol.size))
+            .collect()
+    }
+
+    #[test]
+    fn symbols_are_sized_by_the_next_one_or_their_section() {
+        let index = SymbolIndex::from_candidates(vec![
+            candidate(0x1010, 0x2000, PRIORITY_EXTERNAL, "_second"),
+            candidate(0x1000, 0x2000, PRIORITY_EXTERNAL, "_first"),
+            // the last one of __text, then a slot of __got
+            candidate(0x1f00, 0x2000, PRIORITY_LOCAL, "_last"),
+            candidate(0x3000, 0x3008, PRIORITY_BIND, "pointer for _malloc"),
+        ]);
+        assert_eq!(
+            sizes(&index),
+            [
+                ("_first", 0x1000, 0x10),
+                ("_second", 0x1010, 0xef0),
+                ("_last", 0x1f00, 0x100),
+                ("pointer for _malloc", 0x3000, 8),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_lowest_priority_names_an_address() {
+        for candidates in [
+            vec![
+                candidate(0x1000, 0x2000, PRIORITY_FUNCTION_START, "func_0x1000"),
+                candidate(0x1000, 0x2000, PRIORITY_LOCAL, "_alias"),
+                candidate(0x1000, 0x2000, PRIORITY_EXTERNAL, "_main"),
+            ],
+            vec![
+                candidate(0x1000, 0x2000, PRIORITY_EXTERNAL, "_main"),
+                candidate(0x1000, 0x2000, PRIORITY_EXPORT, "_main"),
+            ],
+        ] {
+            let index = SymbolIndex::from_candidates(candidates);
+            assert_eq!(sizes(&index), [("_main", 0x1000, 0x1000)]);
+        }
+    }
+
+    #[test]
+    fn lookups_find_the_containing_symbol() {
+        let index = SymbolIndex::from_candidates(vec![
+            candidate(0x1000, 0x1010, PRIORITY_EXTERNAL, "_sized"),
+            candidate(0x1020, 0x1040, PRIORITY_EXTERNAL, "_after_a_gap"),
+        ]);
+        let found = |address| {
+            index
+                .lookup(address)
+                .map(|(symbol, offset)| (symbol.name.as_str(), offset))
+        };
+        assert_eq!(found(0x1000), Some(("_sized", 0)));
+        assert_eq!(found(0x100f), Some(("_sized", 0xf)));
+        assert_eq!(found(0x1024), Some(("_after_a_gap", 4)));
+        // before the first symbol, in the padding after a sized one, past the last one
+        assert_eq!(found(0xfff), None);
+        assert_eq!(found(0x1010), None);
+        assert_eq!(found(0x1040), None);
+        assert_eq!(SymbolIndex::default().lookup(0x1000), None);
+    }
+
+    #[test]
+    fn stubs_are_named_after_their_symbol() {
+        let data = indirect_image(0x1_0000_0000, [0, 2, 5], &[0, 1, 1, 0, 0, 0]);
+        let index = SymbolIndex::from_macho(&MachO::parse(&data).unwrap());
+        let found = index.lookup(0x1_0000_0410).unwrap();
+        assert_eq!((found.0.name.as_str(), found.1), ("stub for _free", 4));
+        assert_eq!(index.len(), 6);
+
+        // the lazy pointer ends past the address space
+        let base = u64::MAX - 0x43f;
+        let data = indirect_image(base, [0, 2, 5], &[0, 1, 1, 0, 0, 0]);
+        let index = SymbolIndex::from_macho(&MachO::parse(&data).unwrap());
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.lookup(base + 0x438), None);
+    }
+
+    #[test]
+    fn bound_slots_past_the_address_space_are_skipped() {
+        let base = u64::MAX - 0xfff;
+        let opcodes = bind_opcodes(&[("_malloc", 0x10), ("_free", 0xff8)]);
+        let mut dyld_info = [0u32; 10];
+        dyld_info[2] = 0x1000;
+        dyld_info[3] = opcodes.len() as u32;
+        let mut data = image_with_commands(
+            &[
+                (LC_SEGMENT_64, segment_64("__DATA", base, 0, 0x1000, &[])),
+                (
+                    LC_DYLD_INFO_ONLY,
+                    dyld_info.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ),
+            ],
+            0x1000,
+        );
+        data.extend(opcodes);
+        let index = SymbolIndex::from_macho(&MachO::parse(&data).unwrap());
+        assert_eq!(sizes(&index), [("pointer for _malloc", base + 0x10, 8)]);
+    }
+
+    #[test]
+    fn exports_past_the_address_space_are_skipped() {
+        const BASE: u64 = 0x1_0000_0000;
+        // _big at u64::MAX and _main at 0x1000
+        let mut trie = vec![0, 2];
+        trie.extend(b"_big\0");
+        trie.push(15);
+        trie.extend(b"_main\0");
+        trie.push(28);
+        trie.extend([
+            11, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 1, 0,
+        ]);
+        trie.extend([3, 0, 0x80, 0x20, 0]);
+        let mut dyld_info = [0u32; 10];
+        dyld_info[8] = 0x800;
+        dyld_info[9] = trie.len() as u32;
+        let text = section("__TEXT", "__text", BASE + 0x1000, 0x1000, 0x1000);
+        let mut data = image_with_commands(
+            &[
+                (
+                    LC_SEGMENT_64,
+                    segment_64("__TEXT", BASE, 0, 0x2000, &[text]),
+                ),
+                (
+                    LC_DYLD_INFO_ONLY,
+                    dyld_info.iter().flat_map(|v| v.to_le_bytes()).collect(),
+                ),
+            ],
+            0x2000,
+        );
+        data[0x800..0x800 + trie.len()].copy_from_slice(&trie);
+        let index = SymbolIndex::from_macho(&MachO::parse(&data).unwrap());
+        assert_eq!(sizes(&index), [("_main", BASE + 0x1000, 0x1000)]);
+    }
+}
@@ -0,0 +1,383 @@
+// symbolization of sampled addresses
+// everything needed to go from a return address to a readable frame
+
+pub mod debug_info;
+pub mod demangle;
+pub mod dsym;
+pub mod image;
+pub mod index;
+pub mod store;
+
+pub use debug_info::{DebugInfo, UnitInfo};
+pub use demangle::{DemangleMode, demangle};
+pub use dsym::DebugSearch;
+pub use image::{Frame, LoadedImage, SourceLocation};
+pub use index::SymbolIndex;
+pub use store::{CachedSymbols, SymbolStore};
+
+use std::path::Path;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::dwarf::DwarfSections;
+use crate::elf::{self, Elf};
+use crate::logs;
+use crate::macho::{DebugMap, MachO, cpu};
+use crate::mapped::MappedFile;
+
+/// Everything known about one loaded image to turn its addresses into frames.
+#[derive(Debug)]
+pub struct Symbolizer {
+    pub image: LoadedImage,
+    index: SymbolIndex,
+    debug_map: DebugMap,
+    debug_info: DebugInfo,
+    demangle: DemangleMode,
+    // saving the symbols to the store, joined when the symbolizer is dropped
+    store_write: Option<JoinHandle<()>>,
+}
+
+impl Drop for Symbolizer {
+    fn drop(&mut self) {
+        if let Some(write) = self.store_write.take() {
+            let _ = write.join();
+        }
+    }
+}
+
+impl Symbolizer {
+    /// Symbolizer of `binary`, an image borrowed from `file`, reading its own DWARF.
+    pub fn new(image: LoadedImage, file: &Arc<MappedFile>, binary: &MachO) -> Symbolizer {
+        let debug_info = DebugInfo::from_macho(&image.name, file.clone(), binary);
+        Self::with_debug_info(image, binary, debug_info)
+    }
+
+    /// Symbolizer of `binary` from the symbol store, or built with `build` and added to the store
+    /// for the next runs.
+    ///
+    /// A stored entry without DWARF is only used while no dSYM or DWARF of the image is found.
+    pub fn load(
+        image: LoadedImage,
+        file: &Arc<MappedFile>,
+        binary: &MachO,
+        search: &DebugSearch,
+    ) -> Symbolizer {
+        let store = search.store.as_deref().map(SymbolStore::new);
+        let key = binary.uuid().map(|uuid| store::macho_key(&uuid));
+        let (Some(store), Some(key)) = (store, key) else {
+            return Self::build(image, file, binary, search);
+        };
+        let mut debug_file = None;
+        match store.get(&key) {
+            Ok(Some(cached)) if cached.has_debug_info => {
+                logs::info_log(format!(
+                    "Symbols of {} loaded from the symbol store",
+                    image.name
+                ));
+                return Self::from_cached(image, binary, cached);
+            }
+            Ok(Some(cached)) => {
+                debug_file = dsym::load_debug_file(Path::new(&image.path), binary, search);
+                if debug_file.is_none() && DwarfSections::from_macho(binary).debug_info.is_empty() {
+                    logs::info_log(format!(
+                        "Symbols of {} loaded from the symbol store",
+                        image.name
+                    ));
+                    return Self::from_cached(image, binary, cached);
+                }
+                logs::info_log(format!(
+                    "The stored symbols of {} have no DWARF, rebuilding them",
+                    image.name
+                ));
+            }
+            Ok(None) => {
+                debug_file = dsym::load_debug_file(Path::new(&image.path), binary, search);
+            }
+            Err(e) => logs::warning_log(format!("Ignoring the stored symbols of {}: {}", key, e)),
+        }
+        let mut symbolizer = Self::with_debug_file(image, file, binary, debug_file);
+        let arch = cpu::arch_name(binary.header.cputype, binary.header.cpusubtype);
+        symbolizer.store_in_background(store, key, arch);
+        symbolizer
+    }
+
+    /// Symbolizer of `binary` reading the DWARF of its dSYM when one is found, the DWARF of the
+    /// image itself otherwise.
+    pub fn build(
+        image: LoadedImage,
+        file: &Arc<MappedFile>,
+        binary: &MachO,
+        search: &DebugSearch,
+    ) -> Symbolizer {
+        let debug_file = dsym::load_debug_file(Path::new(&image.path), binary, search);
+        Self::with_debug_file(image, file, binary, debug_file)
+    }
+
+    /// Symbolizer of `binary` reading the DWARF of `debug_file`, or of the image itself.
+    fn with_debug_file(
+        image: LoadedImage,
+        file: &Arc<MappedFile>,
+        binary: &MachO,
+        debug_file: Option<dsym::DebugFile>,
+    ) -> Symbolizer {
+        let Some(debug_file) = debug_file else {
+            return Self::new(image, file, binary);
+        };
+        match MachO::parse(debug_file.image()) {
+            Ok(debug) => {
+                let debug_info = DebugInfo::from_macho(&image.name, debug_file.file(), &debug);
+                Self::with_debug_info(image, binary, debug_info)
+            }
+            Err(e) => {
+                logs::error_log(format!(
+                    "Cannot parse the debug file {}: {}",
+                    debug_file.path.display(),
+                    e
+                ));
+                Self::new(image, file, binary)
+            }
+        }
+    }
+
+    /// Symbolizer of `binary` with the DWARF of `debug_info`, found in the image or in its dSYM.
+    pub fn with_debug_info(
+        image: LoadedImage,
+        binary: &MachO,
+        debug_info: DebugInfo,
+    ) -> Symbolizer {
+        Symbolizer {
+            index: SymbolIndex::from_macho(binary),
+            debug_map: binary
+                .symtab
+                .as_ref()
+                .map(DebugMap::parse)
+                .unwrap_or_default(),
+            debug_info,
+            image,
+            demangle: DemangleMode::default(),
+            store_write: None,
+        }
+    }
+
+    /// Symbolizer of an ELF file borrowed from `file`, from its symbol table and its own DWARF.
+    pub fn from_elf(image: LoadedImage, file: &Arc<MappedFile>, binary: &Elf) -> Symbolizer {
+        Symbolizer {
+            index: SymbolIndex::from_elf(binary),
+            debug_map: DebugMap::default(),
+            debug_info: DebugInfo::from_elf(&image.name, file.clone(), binary),
+            image,
+            demangle: DemangleMode::default(),
+            store_write: None,
+        }
+    }
+
+    /// Symbolizer of an ELF file from the symbol store, or built and added to the store when
+    /// the file has a build-id.
+    pub fn load_elf(
+        image: LoadedImage,
+        file: &Arc<MappedFile>,
+        binary: &Elf,
+        search: &DebugSearch,
+    ) -> Symbolizer {
+        let store = search.store.as_deref().map(SymbolStore::new);
+        let key = binary.build_id().map(store::elf_key);
+        let (Some(store), Some(key)) = (store, key) else {
+            return Self::from_elf(image, file, binary);
+        };
+        match store.get(&key) {
+            // the file may have been stripped when it was stored
+            Ok(Some(cached))
+                if cached.has_debug_info
+                    || DwarfSections::from_elf(binary).debug_info.is_empty() =>
+            {
+                logs::info_log(format!(
+                    "Symbols of {} loaded from the symbol store",
+                    image.name
+                ));
+                return Symbolizer {
+                    index: cached.index,
+                    debug_map: DebugMap::default(),
+                    debug_info: DebugInfo::from_tables(cached.lines, cached.functions),
+                    image,
+                    demangle: DemangleMode::default(),
+                    store_write: None,
+                };
+            }
+            Ok(_) => {}
+            Err(e) => logs::warning_log(format!("Ignoring the stored symbols of {}: {}", key, e)),
+        }
+        let mut symbolizer = Self::from_elf(image, file, binary);
+        symbolizer.store_in_background(store, key, elf::machine_name(binary.machine));
+        symbolizer
+    }
+
+    /// Symbolizer from stored symbols, only the debug map is read from `binary`.
+    pub fn from_cached(image: LoadedImage, binary: &MachO, cached: CachedSymbols) -> Symbolizer {
+        Symbolizer {
+            index: cached.index,
+            debug_map: binary
+                .symtab
+                .as_ref()
+                .map(DebugMap::parse)
+                .unwrap_or_default(),
+            debug_info: DebugInfo::from_tables(cached.lines, cached.functions),
+            image,
+            demangle: DemangleMode::default(),
+            store_write: None,
+        }
+    }
+
+    /// What the symbol store keeps of the symbolizer, every unit of the DWARF gets parsed.
+    pub fn cached(&self, arch: &str) -> CachedSymbols {
+        let tables = self.debug_info.tables();
+        CachedSymbols {
+            name: self.image.name.clone(),
+            arch: arch.to_string(),
+            has_debug_info: self.debug_info.has_dwarf(),
+            index: self.index.clone(),
+            lines: tables.lines,
+            functions: tables.functions,
+        }
+    }
+
+    /// Saves what `cached` returns under `key` from another thread, the symbolizer itself keeps
+    /// parsing the units on demand.
+    fn store_in_background(&mut self, store: SymbolStore, key: String, arch: String) {
+        let tables = self.debug_info.detached_tables();
+        let mut symbols = CachedSymbols {
+            name: self.image.name.clone(),
+            arch,
+            has_debug_info: self.debug_info.has_dwarf(),
+            index: self.index.clone(),
+            ..CachedSymbols::default()
+        };
+        let write = thread::Builder::new()
+            .name(format!("store {}", key))
+            .spawn(move || {
+                let tables = tables.parse();
+                symbols.lines = tables.lines;
+                symbols.functions = tables.functions;
+                if let Err(e) = store.insert(&key, &symbols) {
+                    logs::warning_log(format!("Cannot save the symbols of {}: {}", key, e));
+                }
+            });
+        match write {
+            Ok(write) => self.store_write = Some(write),
+            Err(e) => logs::warning_log(format!(
+                "Cannot save the symbols of {}: {}",
+                self.image.name, e
+            )),
+        }
+    }
+
+    /// How the names of the frames are demangled, `Simplified` by default.
+    pub fn set_demangle_mode(&mut self, mode: DemangleMode) {
+        self.demangle = mode;
+    }
+
+    pub fn index(&self) -> &SymbolIndex {
+        &self.index
+    }
+
+    /// Builds the frames of a runtime `address`, with its position in the image, its symbol and
+    /// its source location when the address belongs to the image.
+    ///
+    /// Calls inlined at the address come first, innermost first, the last frame is the function
+    /// the code belongs to.
+    pub fn symbolize(&self, address: u64) -> Vec<Frame> {
+        self.symbolize_at(address, address)
+    }
+
+    /// Same as `symbolize` for a return address: the source location is the one of the call,
+    /// the instruction before the address, which may even be in another function.
+    pub fn symbolize_return_address(&self, address: u64) -> Vec<Frame> {
+        self.symbolize_at(address, address.saturating_sub(1))
+    }
+
+    fn symbolize_at(&self, address: u64, lookup: u64) -> Vec<Frame> {
+        if !self.image.contains(lookup) {
+            return vec![Frame::new(address)];
+        }
+        let unslid = self.image.unslide(lookup);
+        let unit = self.debug_info.lookup(unslid);
+        let mut frame = self.physical_frame(address, lookup, unit.as_deref());
+        let Some(function) = unit.as_ref().and_then(|unit| unit.functions.lookup(unslid)) else {
+            return vec![frame];
+        };
+        // the symbol table of a stripped image only has names made up from the function starts
+        if frame
+            .symbol
+            .as_ref()
+            .is_none_or(|(name, _)| index::is_synthetic(name))
+            && let Some(name) = &function.name
+        {
+            let start = function
+                .ranges
+                .iter()
+                .find(|range| range.contains(&unslid))
+                .map_or(unslid, |range| range.start);
+            frame.symbol = Some((
+                demangle(name, self.demangle),
+                unslid - start + address - lookup,
+            ));
+        }
+
+        // each inlined call is located by the line table for the innermost one, by the call
+        // site of the call it contains for the others
+        let mut frames = Vec::new();
+        let mut location = frame.location.take();
+        for inlinee in function.inlined_at(unslid).into_iter().rev() {
+            frames.push(Frame {
+                symbol: inlinee
+                    .name
+                    .as_deref()
+                    .map(|name| (demangle(name, self.demangle), 0)),
+                location,
+                inlined: true,
+                ..frame.clone()
+            });
+            location = inlinee.call_file.as_ref().map(|file| SourceLocation {
+                file: file.clone(),
+                line: inlinee.call_line,
+                column: inlinee.call_column,
+            });
+        }
+        frame.location = location;
+        frames.push(frame);
+        frames
+    }
+
+    fn physical_frame(&self, address: u64, lookup: u64, unit: Option<&UnitInfo>) -> Frame {
+        let mut frame = Frame::new(address);
+        frame.image = Some((self.image.name.clone(), self.image.offset(address)));
+        let unslid = self.image.unslide(lookup);
+        // offsets are relative to the address itself, not the one looked up
+        let delta = address - lookup;
+        let symbol = self.index.lookup(unslid);
+        frame.symbol =
+            symbol.map(|(symbol, offset)| (demangle(&symbol.name, self.demangle), offset + delta));
+
+        if let Some((object, function)) = self.debug_map.lookup(unslid) {
+            if symbol.is_none_or(|(symbol, _)| index::is_synthetic(&symbol.name)) {
+                frame.symbol = Some((
+                    demangle(&function.name, self.demangle),
+                    unslid - function.address + delta,
+                ));
+            }
+            frame.location = object.source.as_ref().map(|file| SourceLocation {
+                file: file.clone(),
+                line: 0,
+                column: 0,
+            });
+            frame.object = Some(object.object_path.clone());
+        }
+        if let Some(line) = unit.and_then(|unit| unit.lines.lookup(unslid)) {
+            frame.location = Some(SourceLocation {
+                file: line.file.to_string(),
+                line: line.line,
+                column: line.column,
+            });
+        }
+        frame
+    }
+}
@@ -0,0 +1,619 @@
+// local symbol store
+// building the symbols, line table and functions of an image means reading all of its DWARF,
+// they are saved once per build as <store>/<key>/symbols and loaded back by later runs
+// the key is the LC_UUID of a Mach-O image or the GNU build-id of an ELF file
+//
+// the file is little endian: a header with the counts, then the symbols, the line table and the
+// functions, strings are prefixed with their length
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use super::index::{Symbol, SymbolIndex};
+use crate::dwarf::DwarfSections;
+use crate::dwarf::inline::{Function, FunctionTable, Inlinee};
+use crate::dwarf::line::{LineRow, LineSequence, LineTable};
+use crate::elf::Elf;
+use crate::elf::note::format_build_id;
+use crate::macho::MachOError;
+use crate::macho::reader::{Endian, Reader};
+
+/// Name of the index file in the directory of a key.
+pub const INDEX_FILE: &str = "symbols";
+const MAGIC: &[u8; 8] = b"RPSYMBOL";
+// also bumped when the index gains a source of names, so older entries get rebuilt
+const FORMAT_VERSION: u32 = 3;
+
+/// Every way the store can fail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StoreError {
+    /// Reading or writing a file of the store failed.
+    Io { path: PathBuf, error: String },
+    /// An index file does not start with the magic of the store.
+    NotAnIndex(PathBuf),
+    /// An index file was written by another version of the store.
+    UnsupportedVersion(u32),
+    /// An index file is truncated.
+    Read(MachOError),
+    /// A string of an index file is not UTF-8.
+    InvalidString,
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            StoreError::NotAnIndex(path) => {
+                write!(f, "{} is not a symbol index", path.display())
+            }
+            StoreError::UnsupportedVersion(version) => {
+                write!(f, "unsupported symbol index version {version}")
+            }
+            StoreError::Read(e) => write!(f, "{e}"),
+            StoreError::InvalidString => write!(f, "symbol index string is not UTF-8"),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<MachOError> for StoreError {
+    fn from(e: MachOError) -> StoreError {
+        StoreError::Read(e)
+    }
+}
+
+fn io_error(path: &Path, error: io::Error) -> StoreError {
+    StoreError::Io {
+        path: path.to_path_buf(),
+        error: error.to_string(),
+    }
+}
+
+/// What the symbolizer keeps of an image, without the image itself.
+#[derive(Debug, Clone, Default)]
+pub struct CachedSymbols {
+    /// File name of the image the symbols were built from.
+    pub name: String,
+    pub arch: String,
+    /// Whether the image or its debug file had DWARF, entries without it are rebuilt once the
+    /// debug file shows up.
+    pub has_debug_info: bool,
+    pub index: SymbolIndex,
+    pub lines: LineTable,
+    pub functions: FunctionTable,
+}
+
+impl CachedSymbols {
+    /// Symbols of an ELF file from its symbol table and DWARF.
+    pub fn from_elf(name: &str, arch: &str, binary: &Elf) -> CachedSymbols {
+        let sections = DwarfSections::from_elf(binary);
+        // an image without usable DWARF is still worth its symbols
+        let lines = LineTable::parse(&sections).unwrap_or_default();
+        let functions = FunctionTable::parse(&sections, &lines).unwrap_or_default();
+        CachedSymbols {
+            name: name.to_string(),
+            arch: arch.to_string(),
+            has_debug_info: !sections.debug_info.is_empty(),
+            index: SymbolIndex::from_elf(binary),
+            lines,
+            functions,
+        }
+    }
+}
+
+/// Store key of a Mach-O image, its formatted LC_UUID.
+pub fn macho_key(uuid: &[u8; 16]) -> String {
+    crate::macho::load_command::format_uuid(uuid)
+}
+
+/// Store key of an ELF file, its hexadecimal build-id.
+pub fn elf_key(build_id: &[u8]) -> String {
+    format_build_id(build_id)
+}
+
+/// An index of the store, as listed by `symbols list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreEntry {
+    pub key: String,
+    pub path: PathBuf,
+    pub name: String,
+    pub arch: String,
+    pub symbols: usize,
+    pub sequences: usize,
+    pub functions: usize,
+    /// Size of the index file.
+    pub size: u64,
+    /// Last time the index was written or loaded.
+    pub last_used: SystemTime,
+}
+
+/// An index of the store, or why its header cannot be read.
+pub type EntryResult = Result<StoreEntry, StoreError>;
+
+/// The store rooted at a directory, created on the first insertion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolStore {
+    root: PathBuf,
+}
+
+impl SymbolStore {
+    pub fn new(root: &Path) -> SymbolStore {
+        SymbolStore {
+            root: root.to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn index_path(&self, key: &str) -> PathBuf {
+        self.root.join(key).join(INDEX_FILE)
+    }
+
+    /// Loads the symbols stored for `key`, `None` when there are none.
+    pub fn get(&self, key: &str) -> Result<Option<CachedSymbols>, StoreError> {
+        let path = self.index_path(key);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(io_error(&path, e)),
+        };
+        let symbols = decode(&path, &data)?;
+        // the modification time tells `prune` the index is still in use
+        if let Ok(file) = fs::File::options().append(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Ok(Some(symbols))
+    }
+
+    /// Saves the symbols of `key`, replacing the previous ones.
+    pub fn insert(&self, key: &str, symbols: &CachedSymbols) -> Result<PathBuf, StoreError> {
+        let directory = self.root.join(key);
+        fs::create_dir_all(&directory).map_err(|e| io_error(&directory, e))?;
+        let path = directory.join(INDEX_FILE);
+        // written aside and renamed so a concurrent run never reads half an index
+        let partial = directory.join(format!("{}.{}.tmp", INDEX_FILE, std::process::id()));
+        fs::write(&partial, encode(symbols)).map_err(|e| io_error(&partial, e))?;
+        fs::rename(&partial, &path).map_err(|e| io_error(&path, e))?;
+        Ok(path)
+    }
+
+    /// Every index of the store with the result of reading its header, sorted by key.
+    pub fn entries(&self) -> Result<Vec<(String, EntryResult)>, StoreError> {
+        let directories = match fs::read_dir(&self.root) {
+            Ok(directories) => directories,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(io_error(&self.root, e)),
+        };
+        let mut entries = Vec::new();
+        for directory in directories.filter_map(|e| e.ok()) {
+            let key = directory.file_name().to_string_lossy().into_owned();
+            let path = directory.path().join(INDEX_FILE);
+            if path.is_file() {
+                entries.push((key.clone(), self.entry(&key, &path)));
+            }
+        }
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(entries)
+    }
+
+    fn entry(&self, key: &str, path: &Path) -> Result<StoreEntry, StoreError> {
+        let metadata = fs::metadata(path).map_err(|e| io_error(path, e))?;
+        let data = fs::read(path).map_err(|e| io_error(path, e))?;
+        let mut r = Reader::new(&data, Endian::Little, "symbol index header");
+        let header = read_header(path, &mut r)?;
+        Ok(StoreEntry {
+            key: key.to_string(),
+            path: path.to_path_buf(),
+            name: header.name,
+            arch: header.arch,
+            symbols: header.symbols,
+            sequences: header.sequences,
+            functions: header.functions,
+            size: metadata.len(),
+            last_used: metadata.modified().map_err(|e| io_error(path, e))?,
+        })
+    }
+
+    /// Removes the index of `key` with the files stored next to it.
+    pub fn remove(&self, key: &str) -> Result<(), StoreError> {
+        let directory = self.root.join(key);
+        fs::remove_dir_all(&directory).map_err(|e| io_error(&directory, e))
+    }
+
+    /// Removes the indexes unused for `max_age` and the ones that cannot be read, returns the
+    /// removed keys.
+    pub fn prune(&self, max_age: Duration) -> Result<Vec<String>, StoreError> {
+        let now = SystemTime::now();
+        let mut removed = Vec::new();
+        for (key, entry) in self.entries()? {
+            let expired = match &entry {
+                Ok(entry) => now
+                    .duration_since(entry.last_used)
+                    .is_ok_and(|age| age > max_age),
+                Err(_) => true,
+            };
+            if expired {
+                self.remove(&key)?;
+                removed.push(key);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+struct Header {
+    name: String,
+    arch: String,
+    has_debug_info: bool,
+    symbols: usize,
+    sequences: usize,
+    functions: usize,
+}
+
+fn read_header(path: &Path, r: &mut Reader) -> Result<Header, StoreError> {
+    if r.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(StoreError::NotAnIndex(path.to_path_buf()));
+    }
+    let version = r.u32()?;
+    if version != FORMAT_VERSION {
+        return Err(StoreError::UnsupportedVersion(version));
+    }
+    Ok(Header {
+        name: read_string(r)?.to_string(),
+        arch: read_string(r)?.to_string(),
+        has_debug_info: r.u8()? != 0,
+        symbols: r.u32()? as usize,
+        sequences: r.u32()? as usize,
+        functions: r.u32()? as usize,
+    })
+}
+
+fn decode(path: &Path, data: &[u8]) -> Result<CachedSymbols, StoreError> {
+    let mut r = Reader::new(data, Endian::Little, "symbol index");
+    let header = read_header(path, &mut r)?;
+
+    let mut symbols = Vec::with_capacity(capacity(&r, header.symbols, SYMBOL_SIZE));
+    for _ in 0..header.symbols {
+        symbols.push(Symbol {
+            address: r.u64()?,
+            size: r.u64()?,
+            name: read_string(&mut r)?.to_string(),
+        });
+    }
+
+    let files = (0..r.u32()?)
+        .map(|_| read_string(&mut r).map(str::to_string))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut sequences = Vec::with_capacity(capacity(&r, header.sequences, SEQUENCE_SIZE));
+    for _ in 0..header.sequences {
+        let start = r.u64()?;
+        let end = r.u64()?;
+        let rows = (0..r.u32()?)
+            .map(|_| {
+                Ok(LineRow {
+                    address: r.u64()?,
+                    file: r.u32()? as usize,
+                    line: r.u32()?,
+                    column: r.u32()?,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        // a row pointing past the files would make lookups panic
+        if rows.iter().any(|row| row.file >= files.len()) {
+            return Err(StoreError::NotAnIndex(path.to_path_buf()));
+        }
+        sequences.push(LineSequence { start, end, rows });
+    }
+
+    let mut functions = Vec::with_capacity(capacity(&r, header.functions, FUNCTION_SIZE));
+    for _ in 0..header.functions {
+        let ranges = read_ranges(&mut r)?;
+        let name = read_optional_string(&mut r)?;
+        let inlinees = (0..r.u32()?)
+            .map(|_| {
+                Ok(Inlinee {
+                    depth: r.u32()? as usize,
+                    ranges: read_ranges(&mut r)?,
+                    name: read_optional_string(&mut r)?,
+                    call_file: read_optional_string(&mut r)?,
+                    call_line: r.u32()?,
+                    call_column: r.u32()?,
+                })
+            })
+            .collect::<Result<Vec<_>, StoreError>>()?;
+        functions.push(Function {
+            ranges,
+            name,
+            inlinees,
+        });
+    }
+
+    Ok(CachedSymbols {
+        name: header.name,
+        arch: header.arch,
+        has_debug_info: header.has_debug_info,
+        index: SymbolIndex::from_symbols(symbols),
+        lines: LineTable::from_sequences(files, sequences),
+        functions: FunctionTable::from_functions(functions),
+    })
+}
+
+// smallest encoding of a symbol, a line sequence and a function
+const SYMBOL_SIZE: usize = 8 + 8 + 4;
+const SEQUENCE_SIZE: usize = 8 + 8 + 4;
+const FUNCTION_SIZE: usize = 4 + 1 + 4;
+
+/// Room for `count` records, no more than the rest of the file can hold: the counts of a corrupt
+/// index are not trusted.
+fn capacity(r: &Reader, count: usize, record_size: usize) -> usize {
+    count.min(r.remaining() / record_size)
+}
+
+fn read_string<'a>(r: &mut Reader<'a>) -> Result<&'a str, StoreError> {
+    let length = r.u32()? as usize;
+    std::str::from_utf8(r.take(length)?).map_err(|_| StoreError::InvalidString)
+}
+
+fn read_optional_string(r: &mut Reader) -> Result<Option<String>, StoreError> {
+    match r.u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(read_string(r)?.to_string())),
+    }
+}
+
+fn read_ranges(r: &mut Reader) -> Result<Vec<std::ops::Range<u64>>, StoreError> {
+    (0..r.u32()?).map(|_| Ok(r.u64()?..r.u64()?)).collect()
+}
+
+fn encode(symbols: &CachedSymbols) -> Vec<u8> {
+    let mut w = Writer::default();
+    w.bytes(MAGIC);
+    w.u32(FORMAT_VERSION);
+    w.string(&symbols.name);
+    w.string(&symbols.arch);
+    w.data.push(symbols.has_debug_info as u8);
+    w.u32(symbols.index.len() as u32);
+    w.u32(symbols.lines.sequences().len() as u32);
+    w.u32(symbols.functions.functions().len() as u32);
+
+    for symbol in symbols.index.symbols() {
+        w.u64(symbol.address);
+        w.u64(symbol.size);
+        w.string(&symbol.name);
+    }
+
+    w.u32(symbols.lines.files.len() as u32);
+    for file in &symbols.lines.files {
+        w.string(file);
+    }
+    for sequence in symbols.lines.sequences() {
+        w.u64(sequence.start);
+        w.u64(sequence.end);
+        w.u32(sequence.rows.len() as u32);
+        for row in &sequence.rows {
+            w.u64(row.address);
+            w.u32(row.file as u32);
+            w.u32(row.line);
+            w.u32(row.column);
+        }
+    }
+
+    for function in symbols.functions.functions() {
+        w.ranges(&function.ranges);
+        w.optional_string(function.name.as_deref());
+        w.u32(function.inlinees.len() as u32);
+        for inlinee in &function.inlinees {
+            w.u32(inlinee.depth as u32);
+            w.ranges(&inlinee.ranges);
+            w.optional_string(inlinee.name.as_deref());
+            w.optional_string(inlinee.call_file.as_deref());
+            w.u32(inlinee.call_line);
+            w.u32(inlinee.call_column);
+        }
+    }
+    w.data
+}
+
+#[derive(Default)]
+struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes(value.as_bytes());
+    }
+
+    fn optional_string(&mut self, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                self.data.push(1);
+                self.string(value);
+            }
+            None => self.data.push(0),
+        }
+    }
+
+    fn ranges(&mut self, ranges: &[std::ops::Range<u64>]) {
+        self.u32(ranges.len() as u32);
+        for range in ranges {
+            self.u64(range.start);
+            self.u64(range.end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> CachedSymbols {
+        let inlinee = Inlinee {
+            depth: 1,
+            ranges: vec![0x1010..0x1018, 0x1030..0x1038],
+            name: Some("inlined".to_string()),
+            call_file: None,
+            call_line: 12,
+            call_column: 5,
+        };
+        CachedSymbols {
+            name: "image".to_string(),
+            arch: "arm64".to_string(),
+            has_debug_info: true,
+            index: SymbolIndex::from_symbols(vec![
+                Symbol {
+                    address: 0x1000,
+                    size: 0x40,
+                    name: "_main".to_string(),
+                },
+                Symbol {
+                    address: 0x1040,
+                    size: 0x10,
+                    name: "_helper".to_string(),
+                },
+            ]),
+            lines: LineTable::from_sequences(
+                vec!["main.c".to_string(), "helper.h".to_string()],
+                vec![LineSequence {
+                    start: 0x1000,
+                    end: 0x1050,
+                    rows: vec![
+                        LineRow {
+                            address: 0x1000,
+                            file: 0,
+                            line: 10,
+                            column: 1,
+                        },
+                        LineRow {
+                            address: 0x1010,
+                            file: 1,
+                            line: 3,
+                            column: 0,
+                        },
+                    ],
+                }],
+            ),
+            functions: FunctionTable::from_functions(vec![Function {
+                ranges: vec![0x1000..0x1040, 0x2000..0x2010],
+                name: Some("main".to_string()),
+                inlinees: vec![inlinee],
+            }]),
+        }
+    }
+
+    fn assert_same(a: &CachedSymbols, b: &CachedSymbols) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.arch, b.arch);
+        assert_eq!(a.has_debug_info, b.has_debug_info);
+        assert_eq!(a.index.symbols(), b.index.symbols());
+        assert_eq!(a.lines, b.lines);
+        assert_eq!(a.functions, b.functions);
+    }
+
+    fn temp_store(name: &str) -> SymbolStore {
+        let root =
+            std::env::temp_dir().join(format!("rustprof-store-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        SymbolStore::new(&root)
+    }
+
+    #[test]
+    fn stored_symbols_load_back() {
+        let store = temp_store("round-trip");
+        let symbols = symbols();
+        assert!(store.get("key").unwrap().is_none());
+        store.insert("key", &symbols).unwrap();
+        assert_same(&store.get("key").unwrap().unwrap(), &symbols);
+
+        let entries = store.entries().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0].1.as_ref().unwrap();
+        assert_eq!(
+            (entry.name.as_str(), entry.arch.as_str()),
+            ("image", "arm64")
+        );
+        assert_eq!((entry.symbols, entry.sequences, entry.functions), (2, 1, 1));
+
+        store.remove("key").unwrap();
+        assert!(store.get("key").unwrap().is_none());
+        let _ = fs::remove_dir_all(store.root());
+    }
+
+    #[test]
+    fn corrupt_indexes_are_rejected() {
+        let path = Path::new("symbols");
+        let data = encode(&symbols());
+        assert_same(&decode(path, &data).unwrap(), &symbols());
+
+        // every truncation fails to decode instead of panicking
+        for length in 0..data.len() {
+            assert!(decode(path, &data[..length]).is_err(), "{length} bytes");
+        }
+
+        let mut other = data.clone();
+        other[0] = b'X';
+        assert_eq!(
+            decode(path, &other).unwrap_err(),
+            StoreError::NotAnIndex(path.to_path_buf())
+        );
+
+        let mut other = data.clone();
+        other[MAGIC.len()..MAGIC.len() + 4].copy_from_slice(&1u32.to_le_bytes());
+        assert_eq!(
+            decode(path, &other).unwrap_err(),
+            StoreError::UnsupportedVersion(1)
+        );
+
+        // a line row naming a file past the file names
+        let mut symbols = symbols();
+        symbols.lines = LineTable::from_sequences(
+            vec!["main.c".to_string()],
+            symbols.lines.sequences().to_vec(),
+        );
+        assert_eq!(
+            decode(path, &encode(&symbols)).unwrap_err(),
+            StoreError::NotAnIndex(path.to_path_buf())
+        );
+    }
+
+    #[test]
+    fn counts_are_not_trusted() {
+        let mut w = Writer::default();
+        w.bytes(MAGIC);
+        w.u32(FORMAT_VERSION);
+        w.string("image");
+        w.string("x86_64");
+        w.data.push(0);
+        for _ in 0..3 {
+            w.u32(u32::MAX);
+        }
+        w.u64(0x1000);
+        let mut r = Reader::new(&w.data, Endian::Little, "symbol index");
+        let header = read_header(Path::new("symbols"), &mut r).unwrap();
+        assert_eq!(header.symbols, u32::MAX as usize);
+        assert_eq!(capacity(&r, header.symbols, SYMBOL_SIZE), 0);
+        assert!(matches!(
+            decode(Path::new("symbols"), &w.data),
+            Err(StoreError::Read(_))
+        ));
+    }
+}
@@ -0,0 +1,390 @@
+// compact unwind encodings
+// applies the 32-bit encoding of a function to the registers of its frame to get the caller's
+// https://github.com/llvm/llvm-project/blob/main/libunwind/src/CompactUnwinder.hpp
+
+use super::memory::Memory;
+use super::registers::*;
+
+pub const UNWIND_ARM64_MODE_MASK: u32 = 0x0f00_0000;
+pub const UNWIND_ARM64_MODE_FRAMELESS: u32 = 0x0200_0000;
+pub const UNWIND_ARM64_MODE_DWARF: u32 = 0x0300_0000;
+pub const UNWIND_ARM64_MODE_FRAME: u32 = 0x0400_0000;
+pub const UNWIND_ARM64_FRAME_X19_X20_PAIR: u32 = 0x0000_0001;
+pub const UNWIND_ARM64_FRAME_X21_X22_PAIR: u32 = 0x0000_0002;
+pub const UNWIND_ARM64_FRAME_X23_X24_PAIR: u32 = 0x0000_0004;
+pub const UNWIND_ARM64_FRAME_X25_X26_PAIR: u32 = 0x0000_0008;
+pub const UNWIND_ARM64_FRAME_X27_X28_PAIR: u32 = 0x0000_0010;
+pub const UNWIND_ARM64_FRAMELESS_STACK_SIZE_MASK: u32 = 0x00ff_f000;
+pub const UNWIND_ARM64_DWARF_SECTION_OFFSET: u32 = 0x00ff_ffff;
+
+pub const UNWIND_X86_64_MODE_MASK: u32 = 0x0f00_0000;
+pub const UNWIND_X86_64_MODE_RBP_FRAME: u32 = 0x0100_0000;
+pub const UNWIND_X86_64_MODE_STACK_IMMD: u32 = 0x0200_0000;
+pub const UNWIND_X86_64_MODE_STACK_IND: u32 = 0x0300_0000;
+pub const UNWIND_X86_64_MODE_DWARF: u32 = 0x0400_0000;
+pub const UNWIND_X86_64_RBP_FRAME_REGISTERS: u32 = 0x0000_7fff;
+pub const UNWIND_X86_64_RBP_FRAME_OFFSET: u32 = 0x00ff_0000;
+pub const UNWIND_X86_64_FRAMELESS_STACK_SIZE: u32 = 0x00ff_0000;
+pub const UNWIND_X86_64_FRAMELESS_STACK_ADJUST: u32 = 0x0000_e000;
+pub const UNWIND_X86_64_FRAMELESS_STACK_REG_COUNT: u32 = 0x0000_1c00;
+pub const UNWIND_X86_64_FRAMELESS_STACK_REG_PERMUTATION: u32 = 0x0000_03ff;
+pub const UNWIND_X86_64_DWARF_SECTION_OFFSET: u32 = 0x00ff_ffff;
+
+// register numbers used in the x86_64 encodings
+const UNWIND_X86_64_REG_NONE: u32 = 0;
+const UNWIND_X86_64_REG_RBX: u32 = 1;
+const UNWIND_X86_64_REG_R12: u32 = 2;
+const UNWIND_X86_64_REG_R13: u32 = 3;
+const UNWIND_X86_64_REG_R14: u32 = 4;
+const UNWIND_X86_64_REG_R15: u32 = 5;
+const UNWIND_X86_64_REG_RBP: u32 = 6;
+
+/// What an encoding says about the caller of a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompactStep {
+    /// Registers of the caller, its pc is the return address.
+    Caller(Box<Registers>),
+    /// The function is described by the FDE at this offset of `__eh_frame`.
+    Dwarf { fde_offset: u32 },
+}
+
+/// Unwinds one frame of a function with the compact `encoding`, `function_start` is the runtime
+/// address of the function. Returns `None` when the encoding is empty, the stack unreadable or
+/// the registers point where no frame can be.
+pub fn step(
+    encoding: u32,
+    function_start: u64,
+    registers: &Registers,
+    memory: &dyn Memory,
+) -> Option<CompactStep> {
+    // a zero encoding means the function has no unwind information
+    if encoding == 0 {
+        return None;
+    }
+    match registers.family {
+        CpuFamily::Arm64 => step_arm64(encoding, registers, memory),
+        CpuFamily::X86_64 => step_x86_64(encoding, function_start, registers, memory),
+    }
+}
+
+fn step_arm64(encoding: u32, registers: &Registers, memory: &dyn Memory) -> Option<CompactStep> {
+    let mut caller = registers.clone();
+    match encoding & UNWIND_ARM64_MODE_MASK {
+        UNWIND_ARM64_MODE_FRAMELESS => {
+            // the return address is still in lr, the registers are saved from the top of the frame
+            let stack_size = 16 * ((encoding & UNWIND_ARM64_FRAMELESS_STACK_SIZE_MASK) >> 12);
+            let caller_sp = registers.sp()?.checked_add(stack_size as u64)?;
+            restore_arm64_pairs(encoding, caller_sp.checked_sub(8)?, &mut caller, memory)?;
+            caller.set_sp(caller_sp);
+            caller.set_pc(registers.get(ARM64_LR)?);
+            // lr is consumed by the return, only leaf functions are frameless
+            caller.clear(ARM64_LR);
+        }
+        UNWIND_ARM64_MODE_FRAME => {
+            // fp points to the saved fp and lr pair, the other registers are saved below it
+            let fp = registers.fp()?;
+            restore_arm64_pairs(encoding, fp.checked_sub(8)?, &mut caller, memory)?;
+            caller.set_fp(memory.read_u64(fp)?);
+            caller.set_sp(fp.checked_add(16)?);
+            caller.set_pc(memory.read_u64(fp.checked_add(8)?)?);
+            // the caller's lr was overwritten by the call
+            caller.clear(ARM64_LR);
+        }
+        UNWIND_ARM64_MODE_DWARF => {
+            return Some(CompactStep::Dwarf {
+                fde_offset: encoding & UNWIND_ARM64_DWARF_SECTION_OFFSET,
+            });
+        }
+        _ => return None,
+    }
+    Some(CompactStep::Caller(Box::new(caller)))
+}
+
+/// Restores the callee saved pairs of an arm64 encoding, reading down from `location`.
+fn restore_arm64_pairs(
+    encoding: u32,
+    mut location: u64,
+    registers: &mut Registers,
+    memory: &dyn Memory,
+) -> Option<()> {
+    const PAIRS: [(u32, u16); 5] = [
+        (UNWIND_ARM64_FRAME_X19_X20_PAIR, 19),
+        (UNWIND_ARM64_FRAME_X21_X22_PAIR, 21),
+        (UNWIND_ARM64_FRAME_X23_X24_PAIR, 23),
+        (UNWIND_ARM64_FRAME_X25_X26_PAIR, 25),
+        (UNWIND_ARM64_FRAME_X27_X28_PAIR, 27),
+    ];
+    for (flag, first) in PAIRS {
+        if encoding & flag != 0 {
+            registers.set(first, memory.read_u64(location)?);
+            registers.set(first + 1, memory.read_u64(location.checked_sub(8)?)?);
+            location = location.checked_sub(16)?;
+        }
+    }
+    // the floating point pairs are saved below the integer ones and are not tracked
+    Some(())
+}
+
+fn step_x86_64(
+    encoding: u32,
+    function_start: u64,
+    registers: &Registers,
+    memory: &dyn Memory,
+) -> Option<CompactStep> {
+    let mut caller = registers.clone();
+    match encoding & UNWIND_X86_64_MODE_MASK {
+        UNWIND_X86_64_MODE_RBP_FRAME => {
+            let rbp = registers.fp()?;
+            let offset = (encoding & UNWIND_X86_64_RBP_FRAME_OFFSET) >> 16;
+            let mut saved = rbp.checked_sub(8 * offset as u64)?;
+            let mut locations = encoding & UNWIND_X86_64_RBP_FRAME_REGISTERS;
+            for _ in 0..5 {
+                let reg = locations & 0x7;
+                if reg != UNWIND_X86_64_REG_NONE {
+                    caller.set(x86_64_register(reg)?, memory.read_u64(saved)?);
+                }
+                saved = saved.checked_add(8)?;
+                locations >>= 3;
+            }
+            caller.set_fp(memory.read_u64(rbp)?);
+            caller.set_sp(rbp.checked_add(16)?);
+            caller.set_pc(memory.read_u64(rbp.checked_add(8)?)?);
+        }
+        mode @ (UNWIND_X86_64_MODE_STACK_IMMD | UNWIND_X86_64_MODE_STACK_IND) => {
+            let size = (encoding & UNWIND_X86_64_FRAMELESS_STACK_SIZE) >> 16;
+            let stack_size = if mode == UNWIND_X86_64_MODE_STACK_IMMD {
+                8 * size as u64
+            } else {
+                // the size is the immediate of the `sub rsp` instruction at `size` in the function
+                let adjust = (encoding & UNWIND_X86_64_FRAMELESS_STACK_ADJUST) >> 13;
+                let immediate = function_start.checked_add(size as u64)?;
+                memory.read_u32(immediate)? as u64 + 8 * adjust as u64
+            };
+            let count = (encoding & UNWIND_X86_64_FRAMELESS_STACK_REG_COUNT) >> 10;
+            let permutation = encoding & UNWIND_X86_64_FRAMELESS_STACK_REG_PERMUTATION;
+            let saved_registers = decode_permutation(count, permutation)?;
+
+            // the return address is at the top of the frame, the saved registers below it
+            let caller_sp = registers.sp()?.checked_add(stack_size)?;
+            let return_address = caller_sp.checked_sub(8)?;
+            let mut saved = return_address.checked_sub(8 * count as u64)?;
+            for reg in saved_registers {
+                caller.set(x86_64_register(reg)?, memory.read_u64(saved)?);
+                saved = saved.checked_add(8)?;
+            }
+            caller.set_pc(memory.read_u64(return_address)?);
+            caller.set_sp(caller_sp);
+        }
+        UNWIND_X86_64_MODE_DWARF => {
+            return Some(CompactStep::Dwarf {
+                fde_offset: encoding & UNWIND_X86_64_DWARF_SECTION_OFFSET,
+            });
+        }
+        _ => return None,
+    }
+    Some(CompactStep::Caller(Box::new(caller)))
+}
+
+/// Decodes the saved registers of a frameless x86_64 function, stored as the index of their
+/// permutation in the 6!/(6-count)! possible orders of `count` registers.
+fn decode_permutation(count: u32, mut permutation: u32) -> Option<Vec<u32>> {
+    // factors of the positional number system of each count
+    let factors: &[u32] = match count {
+        0 => &[],
+        1 => &[1],
+        2 => &[5, 1],
+        3 => &[20, 4, 1],
+        4 => &[60, 12, 3, 1],
+        5 | 6 => &[120, 24, 6, 2, 1],
+        _ => return None,
+    };
+    let mut indices = Vec::with_capacity(count as usize);
+    for factor in factors {
+        indices.push(permutation / factor);
+        permutation %= factor;
+    }
+    // with 6 registers the last one is the only register left
+    if count == 6 {
+        indices.push(0);
+    }
+
+    // each index counts the registers not used yet
+    let mut used = [false; 7];
+    let mut registers = Vec::with_capacity(count as usize);
+    for index in indices {
+        let reg = (1..7).filter(|reg| !used[*reg]).nth(index as usize)?;
+        used[reg] = true;
+        registers.push(reg as u32);
+    }
+    Some(registers)
+}
+
+fn x86_64_register(reg: u32) -> Option<u16> {
+    match reg {
+        UNWIND_X86_64_REG_RBX => Some(X86_64_RBX),
+        UNWIND_X86_64_REG_R12 => Some(X86_64_R12),
+        UNWIND_X86_64_REG_R13 => Some(X86_64_R13),
+        UNWIND_X86_64_REG_R14 => Some(X86_64_R14),
+        UNWIND_X86_64_REG_R15 => Some(X86_64_R15),
+        UNWIND_X86_64_REG_RBP => Some(X86_64_RBP),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // words of a stack from `base`, every other address is unreadable
+    struct Stack {
+        base: u64,
+        words: Vec<u64>,
+    }
+
+    impl Memory for Stack {
+        fn read(&self, address: u64, buf: &mut [u8]) -> bool {
+            let bytes: Vec<u8> = self.words.iter().flat_map(|w| w.to_le_bytes()).collect();
+            let Some(start) = address.checked_sub(self.base) else {
+                return false;
+            };
+            match bytes.get(start as usize..start as usize + buf.len()) {
+                Some(data) => {
+                    buf.copy_from_slice(data);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    // memory readable everywhere, only the arithmetic can fail
+    struct Zeros;
+
+    impl Memory for Zeros {
+        fn read(&self, _: u64, buf: &mut [u8]) -> bool {
+            buf.fill(0);
+            true
+        }
+    }
+
+    const BASE: u64 = 0x7000_0000;
+
+    fn caller(step: Option<CompactStep>) -> Registers {
+        match step {
+            Some(CompactStep::Caller(registers)) => *registers,
+            other => panic!("no caller registers: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn arm64_frame() {
+        // x20, x19, then the saved fp and lr pair fp points to
+        let stack = Stack {
+            base: BASE,
+            words: vec![20, 19, 0x7000_1000, 0x1_0000_4000],
+        };
+        let mut registers = Registers::new(CpuFamily::Arm64);
+        registers.set_fp(BASE + 16);
+        registers.set_sp(BASE);
+        registers.set(ARM64_LR, 0x1_0000_2000);
+        let encoding = UNWIND_ARM64_MODE_FRAME | UNWIND_ARM64_FRAME_X19_X20_PAIR;
+        let caller = caller(step(encoding, 0, &registers, &stack));
+        assert_eq!(caller.fp(), Some(0x7000_1000));
+        assert_eq!(caller.sp(), Some(BASE + 32));
+        assert_eq!(caller.pc(), Some(0x1_0000_4000));
+        assert_eq!((caller.get(19), caller.get(20)), (Some(19), Some(20)));
+        assert_eq!(caller.get(ARM64_LR), None);
+    }
+
+    #[test]
+    fn arm64_frameless() {
+        let stack = Stack {
+            base: BASE,
+            words: vec![0, 0, 20, 19],
+        };
+        let mut registers = Registers::new(CpuFamily::Arm64);
+        registers.set_sp(BASE);
+        registers.set(ARM64_LR, 0x1_0000_2000);
+        let encoding = UNWIND_ARM64_MODE_FRAMELESS | (2 << 12) | UNWIND_ARM64_FRAME_X19_X20_PAIR;
+        let caller = caller(step(encoding, 0, &registers, &stack));
+        assert_eq!(caller.sp(), Some(BASE + 32));
+        assert_eq!(caller.pc(), Some(0x1_0000_2000));
+        assert_eq!((caller.get(19), caller.get(20)), (Some(19), Some(20)));
+    }
+
+    #[test]
+    fn x86_64_rbp_frame() {
+        // rbx and r12 saved 2 words below rbp, then the saved rbp and the return address
+        let stack = Stack {
+            base: BASE,
+            words: vec![3, 12, 0x7000_1000, 0x1_0000_4000],
+        };
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        registers.set_fp(BASE + 16);
+        registers.set_sp(BASE);
+        let locations = UNWIND_X86_64_REG_RBX | (UNWIND_X86_64_REG_R12 << 3);
+        let encoding = UNWIND_X86_64_MODE_RBP_FRAME | (2 << 16) | locations;
+        let caller = caller(step(encoding, 0, &registers, &stack));
+        assert_eq!(caller.fp(), Some(0x7000_1000));
+        assert_eq!(caller.sp(), Some(BASE + 32));
+        assert_eq!(caller.pc(), Some(0x1_0000_4000));
+        assert_eq!(caller.get(X86_64_RBX), Some(3));
+        assert_eq!(caller.get(X86_64_R12), Some(12));
+    }
+
+    #[test]
+    fn x86_64_frameless() {
+        // 4 words: a local, rbx, r12 and the return address
+        let stack = Stack {
+            base: BASE,
+            words: vec![0, 3, 12, 0x1_0000_4000],
+        };
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        registers.set_sp(BASE);
+        let encoding = UNWIND_X86_64_MODE_STACK_IMMD | (4 << 16) | (2 << 10);
+        let caller = caller(step(encoding, 0, &registers, &stack));
+        assert_eq!(caller.sp(), Some(BASE + 32));
+        assert_eq!(caller.pc(), Some(0x1_0000_4000));
+        assert_eq!(caller.get(X86_64_RBX), Some(3));
+        assert_eq!(caller.get(X86_64_R12), Some(12));
+    }
+
+    #[test]
+    fn dwarf_and_empty_encodings() {
+        let registers = Registers::new(CpuFamily::X86_64);
+        assert_eq!(step(0, 0, &registers, &Zeros), None);
+        assert_eq!(
+            step(UNWIND_X86_64_MODE_DWARF | 0x1234, 0, &registers, &Zeros),
+            Some(CompactStep::Dwarf { fde_offset: 0x1234 })
+        );
+    }
+
+    #[test]
+    fn permutations() {
+        assert_eq!(decode_permutation(6, 0), Some(vec![1, 2, 3, 4, 5, 6]));
+        // r15, rbx, r14: index 4 of 6, 0 of 5 and 2 of 4
+        assert_eq!(decode_permutation(3, 4 * 20 + 2), Some(vec![5, 1, 4]));
+        assert_eq!(decode_permutation(7, 0), None);
+    }
+
+    #[test]
+    fn frames_out_of_the_address_space_stop_the_unwinding() {
+        let mut registers = Registers::new(CpuFamily::Arm64);
+        registers.set_fp(0);
+        registers.set_sp(u64::MAX - 8);
+        let frame = UNWIND_ARM64_MODE_FRAME | UNWIND_ARM64_FRAME_X19_X20_PAIR;
+        assert_eq!(step(frame, 0, &registers, &Zeros), None);
+        let frameless = UNWIND_ARM64_MODE_FRAMELESS | (2 << 12);
+        assert_eq!(step(frameless, 0, &registers, &Zeros), None);
+
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        registers.set_fp(8);
+        registers.set_sp(u64::MAX - 8);
+        let rbp_frame = UNWIND_X86_64_MODE_RBP_FRAME | (2 << 16) | UNWIND_X86_64_REG_RBX;
+        assert_eq!(step(rbp_frame, 0, &registers, &Zeros), None);
+        let frameless = UNWIND_X86_64_MODE_STACK_IMMD | (4 << 16);
+        assert_eq!(step(frameless, 0, &registers, &Zeros), None);
+        let indirect = UNWIND_X86_64_MODE_STACK_IND | (4 << 16);
+        assert_eq!(step(indirect, u64::MAX - 2, &registers, &Zeros), None);
+    }
+}
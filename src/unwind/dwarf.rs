@@ -0,0 +1,114 @@
+// unwinding with DWARF call frame information
+// evaluates the rules of the row covering the pc to get the caller's registers
+
+use super::memory::Memory;
+use super::registers::{ARM64_LR, CpuFamily, Registers};
+use crate::dwarf::cfi::{CfaRule, CfiIndex, CfiSection, Fde, RegisterRule, UnwindRow};
+use crate::dwarf::{DwarfError, expression};
+
+/// A call frame section with the index of its FDEs.
+#[derive(Debug, Clone)]
+pub struct DwarfCfi<'a> {
+    pub section: CfiSection<'a>,
+    index: CfiIndex,
+}
+
+impl<'a> DwarfCfi<'a> {
+    pub fn new(section: CfiSection<'a>) -> Result<DwarfCfi<'a>, DwarfError> {
+        Ok(DwarfCfi {
+            index: section.index()?,
+            section,
+        })
+    }
+
+    /// The FDE covering `address`, an address of the file.
+    pub fn find_fde(&self, address: u64) -> Option<Fde<'a>> {
+        let range = self.index.lookup(address)?;
+        self.section.fde_at(range.offset).ok()
+    }
+}
+
+/// Unwinds the frame described by `registers` with the rules of `fde` at `address`, an
+/// address of the file while the registers hold runtime values.
+pub fn step(
+    section: &CfiSection,
+    fde: &Fde,
+    address: u64,
+    registers: &Registers,
+    memory: &dyn Memory,
+) -> Result<Option<Registers>, DwarfError> {
+    let row = fde.unwind_row(section, address)?;
+    apply_row(
+        section,
+        &row,
+        fde.cie.return_address_register,
+        registers,
+        memory,
+    )
+}
+
+/// Computes the caller's registers from a row, `None` when the return address is undefined,
+/// which marks the outermost frame.
+pub fn apply_row(
+    section: &CfiSection,
+    row: &UnwindRow,
+    return_address_register: u16,
+    registers: &Registers,
+    memory: &dyn Memory,
+) -> Result<Option<Registers>, DwarfError> {
+    let evaluate = |expression: &[u8], initial: &[u64]| {
+        expression::evaluate(
+            expression,
+            section.endian,
+            section.address_size,
+            registers,
+            memory,
+            initial,
+        )
+    };
+    let read = |address: u64| memory.read_u64(address).ok_or(DwarfError::UnavailableValue);
+
+    let cfa = match row.cfa {
+        CfaRule::RegisterOffset { register, offset } => registers
+            .get(register)
+            .ok_or(DwarfError::UnavailableValue)?
+            .wrapping_add(offset as u64),
+        CfaRule::Expression(expression) => evaluate(expression, &[])?,
+    };
+
+    let mut caller = registers.clone();
+    // the return address column of x86_64 is the pc itself
+    caller.clear(registers.family.pc_register());
+    for (register, rule) in &row.registers {
+        let value = match *rule {
+            RegisterRule::Undefined => None,
+            RegisterRule::SameValue => registers.get(*register),
+            RegisterRule::Offset(offset) => Some(read(cfa.wrapping_add(offset as u64))?),
+            RegisterRule::ValOffset(offset) => Some(cfa.wrapping_add(offset as u64)),
+            RegisterRule::Register(source) => registers.get(source),
+            RegisterRule::Expression(expression) => Some(read(evaluate(expression, &[cfa])?)?),
+            RegisterRule::ValExpression(expression) => Some(evaluate(expression, &[cfa])?),
+        };
+        match value {
+            Some(value) => caller.set(*register, value),
+            None => caller.clear(*register),
+        }
+    }
+
+    let return_address = match row.rule(return_address_register) {
+        Some(RegisterRule::Undefined) => return Ok(None),
+        Some(_) => caller.get(return_address_register),
+        // without a rule the return address register keeps its value, like lr in a leaf
+        None => registers.get(return_address_register),
+    };
+    let Some(return_address) = return_address else {
+        return Ok(None);
+    };
+    caller.set_sp(cfa);
+    caller.set_pc(return_address);
+    if registers.family == CpuFamily::Arm64 {
+        // the caller's lr was overwritten by the call
+        caller.clear(ARM64_LR);
+    }
+    Ok(Some(caller))
+}
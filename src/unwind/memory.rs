@@ -0,0 +1,41 @@
+// access to the memory of the unwound process
+// the stack is read through this trait so a live process and a core file unwind the same way
+
+use read_process_memory::{CopyAddress, Pid, ProcessHandle};
+
+/// Little endian reads of the memory of a process.
+pub trait Memory {
+    /// Fills `buf` with the bytes at `address`, returns false if they cannot all be read.
+    fn read(&self, address: u64, buf: &mut [u8]) -> bool;
+
+    fn read_u32(&self, address: u64) -> Option<u32> {
+        let mut buf = [0u8; 4];
+        self.read(address, &mut buf)
+            .then(|| u32::from_le_bytes(buf))
+    }
+
+    fn read_u64(&self, address: u64) -> Option<u64> {
+        let mut buf = [0u8; 8];
+        self.read(address, &mut buf)
+            .then(|| u64::from_le_bytes(buf))
+    }
+}
+
+/// Memory of a running process.
+pub struct ProcessMemory {
+    handle: ProcessHandle,
+}
+
+impl ProcessMemory {
+    pub fn new(pid: Pid) -> std::io::Result<ProcessMemory> {
+        Ok(ProcessMemory {
+            handle: pid.try_into()?,
+        })
+    }
+}
+
+impl Memory for ProcessMemory {
+    fn read(&self, address: u64, buf: &mut [u8]) -> bool {
+        self.handle.copy_address(address as usize, buf).is_ok()
+    }
+}
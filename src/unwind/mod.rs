@@ -0,0 +1,262 @@
+// stack unwinding
+// recovers the return addresses of a thread from its registers and the memory of its process,
+// using the compact unwind tables of the images, their DWARF call frame information and
+// following frame pointers otherwise
+
+pub mod compact;
+pub mod dwarf;
+pub mod memory;
+pub mod registers;
+
+pub use compact::CompactStep;
+pub use dwarf::DwarfCfi;
+pub use memory::{Memory, ProcessMemory};
+pub use registers::{CpuFamily, Registers};
+
+use crate::dwarf::cfi::{CfiKind, CfiSection};
+use crate::elf::Elf;
+use crate::logs;
+use crate::macho::MachO;
+use crate::macho::unwind_info::UnwindInfo;
+use crate::symbolize::LoadedImage;
+
+/// Deepest stack the unwinder walks, protects against corrupted stacks.
+pub const MAX_FRAMES: usize = 512;
+
+/// The unwind tables of an image mapped in the unwound process.
+#[derive(Debug, Clone)]
+pub struct UnwindImage<'a> {
+    pub image: LoadedImage,
+    unwind_info: Option<UnwindInfo<'a>>,
+    eh_frame: Option<DwarfCfi<'a>>,
+}
+
+impl<'a> UnwindImage<'a> {
+    pub fn new(image: LoadedImage, binary: &MachO<'a>) -> UnwindImage<'a> {
+        let unwind_info = binary
+            .find_section("__TEXT", "__unwind_info")
+            .map(|section| binary.section_data(section))
+            .and_then(
+                |data| match data.and_then(|d| UnwindInfo::parse(d, binary.endian)) {
+                    Ok(info) => Some(info),
+                    Err(e) => {
+                        logs::error_log(format!(
+                            "Cannot read the unwind info of {}: {}",
+                            image.name, e
+                        ));
+                        None
+                    }
+                },
+            );
+        let eh_frame = binary
+            .find_section("__TEXT", "__eh_frame")
+            .and_then(|section| {
+                let data = binary.section_data(section).ok()?;
+                let address_size = if binary.header.is_64() { 8 } else { 4 };
+                let cfi = CfiSection::new(
+                    CfiKind::EhFrame,
+                    data,
+                    section.addr,
+                    binary.endian,
+                    address_size,
+                );
+                match DwarfCfi::new(cfi) {
+                    Ok(cfi) => Some(cfi),
+                    Err(e) => {
+                        logs::error_log(format!(
+                            "Cannot read the eh_frame of {}: {}",
+                            image.name, e
+                        ));
+                        None
+                    }
+                }
+            });
+        UnwindImage {
+            image,
+            unwind_info,
+            eh_frame,
+        }
+    }
+
+    /// An ELF file, unwound with its .eh_frame, or its .debug_frame when it has none.
+    pub fn from_elf(image: LoadedImage, binary: &Elf<'a>) -> UnwindImage<'a> {
+        let address_size = if binary.is_64 { 8 } else { 4 };
+        let eh_frame = [
+            (".eh_frame", CfiKind::EhFrame),
+            (".debug_frame", CfiKind::DebugFrame),
+        ]
+        .into_iter()
+        .find_map(|(name, kind)| {
+            let section = binary.section(name)?;
+            let data = binary.section_data(section).ok()?;
+            let cfi = CfiSection::new(kind, data, section.addr, binary.endian, address_size);
+            match DwarfCfi::new(cfi) {
+                Ok(cfi) => Some(cfi),
+                Err(e) => {
+                    logs::error_log(format!("Cannot read the {} of {}: {}", name, image.name, e));
+                    None
+                }
+            }
+        });
+        UnwindImage {
+            image,
+            unwind_info: None,
+            eh_frame,
+        }
+    }
+
+    /// Uses the compact unwind entry of the function containing `lookup_pc`.
+    fn compact_step(
+        &self,
+        lookup_pc: u64,
+        registers: &Registers,
+        memory: &dyn Memory,
+    ) -> Option<CompactStep> {
+        let info = self.unwind_info.as_ref()?;
+        let offset = u32::try_from(self.image.offset(lookup_pc)).ok()?;
+        let entry = info.lookup(offset).ok()??;
+        let function_start = self.image.load_address + entry.function_offset as u64;
+        compact::step(entry.encoding, function_start, registers, memory)
+    }
+
+    /// Uses the FDE at `fde_offset` of the eh_frame, or the one covering `lookup_pc`.
+    fn dwarf_step(
+        &self,
+        lookup_pc: u64,
+        fde_offset: Option<u32>,
+        registers: &Registers,
+        memory: &dyn Memory,
+    ) -> Option<Registers> {
+        let cfi = self.eh_frame.as_ref()?;
+        let address = self.image.unslide(lookup_pc);
+        let fde = match fde_offset {
+            Some(offset) => cfi.section.fde_at(offset as usize).ok()?,
+            None => cfi.find_fde(address)?,
+        };
+        dwarf::step(&cfi.section, &fde, address, registers, memory)
+            .ok()
+            .flatten()
+    }
+
+    /// Compact unwind first, then the eh_frame when the encoding asks for it or is missing.
+    fn step(
+        &self,
+        lookup_pc: u64,
+        registers: &Registers,
+        memory: &dyn Memory,
+    ) -> Option<Registers> {
+        match self.compact_step(lookup_pc, registers, memory) {
+            Some(CompactStep::Caller(caller)) => Some(*caller),
+            Some(CompactStep::Dwarf { fde_offset }) => {
+                self.dwarf_step(lookup_pc, Some(fde_offset), registers, memory)
+            }
+            None => self.dwarf_step(lookup_pc, None, registers, memory),
+        }
+    }
+}
+
+/// Walks the stacks of threads running code of a set of images.
+#[derive(Debug, Clone, Default)]
+pub struct Unwinder<'a> {
+    images: Vec<UnwindImage<'a>>,
+}
+
+impl<'a> Unwinder<'a> {
+    pub fn new(images: Vec<UnwindImage<'a>>) -> Unwinder<'a> {
+        Unwinder { images }
+    }
+
+    /// Returns the pc of the thread followed by the return address of each frame, stops at the
+    /// first frame that cannot be unwound or after `max_frames` frames.
+    pub fn unwind(&self, registers: Registers, memory: &dyn Memory, max_frames: usize) -> Vec<u64> {
+        let mut registers = registers;
+        let Some(pc) = registers.pc() else {
+            return Vec::new();
+        };
+        let mut addresses = vec![pc];
+        while addresses.len() < max_frames {
+            let Some(caller) = self.step(&registers, memory, addresses.len() == 1) else {
+                break;
+            };
+            let (Some(pc), Some(sp)) = (caller.pc(), caller.sp()) else {
+                break;
+            };
+            // the stack grows down, a caller frame below its callee means the stack is corrupted
+            if pc == 0 || registers.sp().is_some_and(|callee_sp| sp < callee_sp) {
+                break;
+            }
+            if caller == registers {
+                break;
+            }
+            addresses.push(pc);
+            registers = caller;
+        }
+        addresses
+    }
+
+    /// Registers of the caller of the frame described by `registers`.
+    fn step(&self, registers: &Registers, memory: &dyn Memory, first: bool) -> Option<Registers> {
+        let pc = registers.pc()?;
+        // return addresses point after the call, which may be the first byte of another function
+        let lookup_pc = if first { pc } else { pc - 1 };
+        let image = self.images.iter().find(|i| i.image.contains(lookup_pc));
+        image
+            .and_then(|image| image.step(lookup_pc, registers, memory))
+            .or_else(|| frame_pointer_step(registers, memory))
+    }
+}
+
+/// Follows the frame pointer: the caller's fp and the return address are saved at fp.
+pub fn frame_pointer_step(registers: &Registers, memory: &dyn Memory) -> Option<Registers> {
+    let fp = registers.fp()?;
+    if fp == 0 {
+        return None;
+    }
+    // a corrupt frame pointer at the top of the address space ends the walk
+    let return_address = fp.checked_add(8)?;
+    let sp = fp.checked_add(16)?;
+    let mut caller = registers.clone();
+    caller.set_fp(memory.read_u64(fp)?);
+    caller.set_pc(memory.read_u64(return_address)?);
+    caller.set_sp(sp);
+    if registers.family == CpuFamily::Arm64 {
+        caller.clear(registers::ARM64_LR);
+    }
+    Some(caller)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // memory readable everywhere, each word holds its own address
+    struct Addresses;
+
+    impl Memory for Addresses {
+        fn read(&self, address: u64, buf: &mut [u8]) -> bool {
+            let bytes = address.to_le_bytes();
+            buf.copy_from_slice(&bytes[..buf.len()]);
+            true
+        }
+    }
+
+    #[test]
+    fn frame_pointers_are_followed() {
+        let mut registers = Registers::new(CpuFamily::Arm64);
+        registers.set_fp(0x7000_1000);
+        registers.set(registers::ARM64_LR, 0x1_0000_2000);
+        let caller = frame_pointer_step(&registers, &Addresses).unwrap();
+        assert_eq!(caller.fp(), Some(0x7000_1000));
+        assert_eq!(caller.pc(), Some(0x7000_1008));
+        assert_eq!(caller.sp(), Some(0x7000_1010));
+        assert_eq!(caller.get(registers::ARM64_LR), None);
+
+        for fp in [0, u64::MAX - 7, u64::MAX - 15] {
+            registers.set_fp(fp);
+            assert!(
+                frame_pointer_step(&registers, &Addresses).is_none(),
+                "{fp:#x}"
+            );
+        }
+    }
+}
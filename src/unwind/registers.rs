@@ -0,0 +1,222 @@
+// register sets of the unwound frames
+// registers are numbered like in DWARF so the compact and CFI unwinders share one representation
+
+use crate::macho::cpu::{CPU_TYPE_ARM64, CPU_TYPE_X86_64};
+
+// arm64: x0-x30 are 0-30, x29 is the frame pointer and x30 the link register
+pub const ARM64_FP: u16 = 29;
+pub const ARM64_LR: u16 = 30;
+pub const ARM64_SP: u16 = 31;
+// not a DWARF register, the pc is tracked in the first free slot
+pub const ARM64_PC: u16 = 32;
+
+pub const X86_64_RAX: u16 = 0;
+pub const X86_64_RDX: u16 = 1;
+pub const X86_64_RCX: u16 = 2;
+pub const X86_64_RBX: u16 = 3;
+pub const X86_64_RSI: u16 = 4;
+pub const X86_64_RDI: u16 = 5;
+pub const X86_64_RBP: u16 = 6;
+pub const X86_64_RSP: u16 = 7;
+pub const X86_64_R12: u16 = 12;
+pub const X86_64_R13: u16 = 13;
+pub const X86_64_R14: u16 = 14;
+pub const X86_64_R15: u16 = 15;
+// return address column, holds the rip
+pub const X86_64_RIP: u16 = 16;
+
+pub const REGISTER_COUNT: usize = 33;
+
+// return addresses signed with pointer authentication carry the signature above the 47 bits
+// of user space addresses
+const ARM64_ADDRESS_MASK: u64 = 0x0000_7fff_ffff_ffff;
+
+/// Architectures the unwinder knows the registers of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFamily {
+    Arm64,
+    X86_64,
+}
+
+impl CpuFamily {
+    pub fn from_cputype(cputype: u32) -> Option<CpuFamily> {
+        match cputype {
+            CPU_TYPE_ARM64 => Some(CpuFamily::Arm64),
+            CPU_TYPE_X86_64 => Some(CpuFamily::X86_64),
+            _ => None,
+        }
+    }
+
+    pub fn from_elf_machine(machine: u16) -> Option<CpuFamily> {
+        match machine {
+            crate::elf::EM_AARCH64 => Some(CpuFamily::Arm64),
+            crate::elf::EM_X86_64 => Some(CpuFamily::X86_64),
+            _ => None,
+        }
+    }
+
+    pub fn pc_register(self) -> u16 {
+        match self {
+            CpuFamily::Arm64 => ARM64_PC,
+            CpuFamily::X86_64 => X86_64_RIP,
+        }
+    }
+
+    pub fn sp_register(self) -> u16 {
+        match self {
+            CpuFamily::Arm64 => ARM64_SP,
+            CpuFamily::X86_64 => X86_64_RSP,
+        }
+    }
+
+    pub fn fp_register(self) -> u16 {
+        match self {
+            CpuFamily::Arm64 => ARM64_FP,
+            CpuFamily::X86_64 => X86_64_RBP,
+        }
+    }
+
+    /// Removes the pointer authentication bits of a code address.
+    pub fn strip_pointer(self, address: u64) -> u64 {
+        match self {
+            CpuFamily::Arm64 => address & ARM64_ADDRESS_MASK,
+            CpuFamily::X86_64 => address,
+        }
+    }
+}
+
+/// Name of a DWARF register number, for display.
+pub fn register_name(family: Option<CpuFamily>, reg: u16) -> String {
+    const X86_64: [&str; 17] = [
+        "rax", "rdx", "rcx", "rbx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12",
+        "r13", "r14", "r15", "rip",
+    ];
+    match (family, reg) {
+        (Some(CpuFamily::Arm64), ARM64_FP) => "fp".to_string(),
+        (Some(CpuFamily::Arm64), ARM64_LR) => "lr".to_string(),
+        (Some(CpuFamily::Arm64), ARM64_SP) => "sp".to_string(),
+        (Some(CpuFamily::Arm64), ARM64_PC) => "pc".to_string(),
+        (Some(CpuFamily::Arm64), 0..=28) => format!("x{reg}"),
+        (Some(CpuFamily::Arm64), 64..=95) => format!("v{}", reg - 64),
+        (Some(CpuFamily::X86_64), 0..=16) => X86_64[reg as usize].to_string(),
+        _ => format!("r{reg}"),
+    }
+}
+
+/// Values of the registers of one frame, unknown registers are `None`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Registers {
+    pub family: CpuFamily,
+    values: [Option<u64>; REGISTER_COUNT],
+}
+
+impl Registers {
+    pub fn new(family: CpuFamily) -> Registers {
+        Registers {
+            family,
+            values: [None; REGISTER_COUNT],
+        }
+    }
+
+    /// Registers of an `ARM_THREAD_STATE64`: x0-x28, fp, lr, sp then pc, also the layout of the
+    /// Linux `user_pt_regs`.
+    pub fn from_arm64_thread_state(state: &[u64]) -> Registers {
+        let mut registers = Registers::new(CpuFamily::Arm64);
+        for (reg, value) in state.iter().take(ARM64_PC as usize + 1).enumerate() {
+            registers.set(reg as u16, *value);
+        }
+        // the pc of an arm64e thread is signed
+        if let Some(pc) = registers.pc() {
+            registers.set_pc(pc);
+        }
+        registers
+    }
+
+    /// Registers of an `x86_THREAD_STATE64`: rax, rbx, rcx, rdx, rdi, rsi, rbp, rsp, r8-r15, rip.
+    pub fn from_x86_64_thread_state(state: &[u64]) -> Registers {
+        const ORDER: [u16; 17] = [
+            X86_64_RAX, X86_64_RBX, X86_64_RCX, X86_64_RDX, X86_64_RDI, X86_64_RSI, X86_64_RBP,
+            X86_64_RSP, 8, 9, 10, 11, X86_64_R12, X86_64_R13, X86_64_R14, X86_64_R15, X86_64_RIP,
+        ];
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        for (reg, value) in ORDER.iter().zip(state) {
+            registers.set(*reg, *value);
+        }
+        registers
+    }
+
+    /// Registers of a Linux x86_64 `user_regs_struct`: r15, r14, r13, r12, rbp, rbx, r11-r8, rax,
+    /// rcx, rdx, rsi, rdi, orig_rax, rip, cs, eflags then rsp.
+    pub fn from_x86_64_user_regs(regs: &[u64]) -> Registers {
+        const ORDER: [Option<u16>; 20] = [
+            Some(X86_64_R15),
+            Some(X86_64_R14),
+            Some(X86_64_R13),
+            Some(X86_64_R12),
+            Some(X86_64_RBP),
+            Some(X86_64_RBX),
+            Some(11),
+            Some(10),
+            Some(9),
+            Some(8),
+            Some(X86_64_RAX),
+            Some(X86_64_RCX),
+            Some(X86_64_RDX),
+            Some(X86_64_RSI),
+            Some(X86_64_RDI),
+            None,
+            Some(X86_64_RIP),
+            None,
+            None,
+            Some(X86_64_RSP),
+        ];
+        let mut registers = Registers::new(CpuFamily::X86_64);
+        for (reg, value) in ORDER.iter().zip(regs) {
+            if let Some(reg) = reg {
+                registers.set(*reg, *value);
+            }
+        }
+        registers
+    }
+
+    pub fn get(&self, reg: u16) -> Option<u64> {
+        self.values.get(reg as usize).copied().flatten()
+    }
+
+    pub fn set(&mut self, reg: u16, value: u64) {
+        if let Some(slot) = self.values.get_mut(reg as usize) {
+            *slot = Some(value);
+        }
+    }
+
+    pub fn clear(&mut self, reg: u16) {
+        if let Some(slot) = self.values.get_mut(reg as usize) {
+            *slot = None;
+        }
+    }
+
+    pub fn pc(&self) -> Option<u64> {
+        self.get(self.family.pc_register())
+    }
+
+    pub fn sp(&self) -> Option<u64> {
+        self.get(self.family.sp_register())
+    }
+
+    pub fn fp(&self) -> Option<u64> {
+        self.get(self.family.fp_register())
+    }
+
+    pub fn set_pc(&mut self, value: u64) {
+        let value = self.family.strip_pointer(value);
+        self.set(self.family.pc_register(), value);
+    }
+
+    pub fn set_sp(&mut self, value: u64) {
+        self.set(self.family.sp_register(), value);
+    }
+
+    pub fn set_fp(&mut self, value: u64) {
+        self.set(self.family.fp_register(), value);
+    }
+}
@@ -1,3 +1,5 @@
+use std::env;
+use std::path::PathBuf;
 use std::process::{Command, exit};
 
 pub fn command_usage(usage: &str) {
@@ -15,10 +17,34 @@ Usage: RustProf command [options]
 
 Commands:
     run             Run the profiler process
+    inspect         Parse a binary file and print its structure
+    symbolize       Resolve addresses of a binary file: symbolize <path> <addr>...
+    core            Unwind and symbolize the threads of a Mach-O or ELF core file: core <path> [<binary>...]
+    cache           List the images of a dyld shared cache or extract them: cache <path> [<install name>...]
+    cfi             Print the call frame rules of addresses of a Mach-O or ELF file: cfi <path> <addr>...
+    symbols         Manage the local symbol store, used when $RUSTPROF_SYMBOL_STORE is set:
+                      symbols add <path>...   store the symbols of Mach-O or ELF files
+                      symbols list            list the stored images
+                      symbols prune           remove the images unused for --older-than days (30)
     help            Show this help message
 
 Options:
 
+    --arch <name>   Slice to use in a universal binary (arm64, x86_64...)
+    --symbols       Dump the symbol table, export trie and debug map (inspect, cache)
+    --imports       List the imported symbols and the slots bound to them (inspect)
+    --codesign      Show the code signature, entitlements and requirements (inspect)
+    --verify        Check the page hashes of the code signature against the file (inspect)
+    --load-address <addr>
+                    Address the image is loaded at, to symbolize runtime addresses
+    --dsym-path <dir>[:<dir>...]
+                    Directories to search for dSYM bundles (run, symbolize, core, symbols add)
+    --demangle <mode>
+                    simple: names of the functions only (default), full: with their module,
+                    parameters and return type (run, symbolize, core)
+    --output <dir>  Directory to write the images extracted from a dyld shared cache to
+    --older-than <days>
+                    Age of the store entries removed by symbols prune
     -h, --help      Show command usage
     -v, --version   Show the current version of RustySpider
 ";
@@ -26,6 +52,54 @@ Options:
     println!("{}", usage);
 }
 
+/// Returns the value following `name` in the command line arguments, if any.
+pub fn option_value(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+/// Directories given to `name` as a `:` separated list, like `PATH`.
+pub fn path_list(args: &[String], name: &str) -> Vec<PathBuf> {
+    option_value(args, name)
+        .map(|value| env::split_paths(&value).collect())
+        .unwrap_or_default()
+}
+
+// options that do not take a value
+const FLAGS: &[&str] = &["--symbols", "--imports", "--codesign", "--verify"];
+
+/// Returns true if the flag is present in the command line arguments.
+pub fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+/// Arguments that are not an option or the value of an option.
+pub fn positional_args(args: &[String]) -> Vec<String> {
+    let mut positional = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if FLAGS.contains(&arg.as_str()) {
+            continue;
+        }
+        if arg.starts_with("--") {
+            iter.next();
+        } else {
+            positional.push(arg.clone());
+        }
+    }
+    positional
+}
+
+/// Parses an address given in hexadecimal (`0x1234`) or decimal.
+pub fn parse_address(arg: &str) -> Option<u64> {
+    match arg.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => arg.parse().ok(),
+    }
+}
+
 pub fn get_bin_path(pid: i32) -> String {
     let output = Command::new("sh")
         .arg("-c")